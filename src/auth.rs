@@ -8,10 +8,15 @@ use std::path::{Path, PathBuf};
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use toml_edit::{table, value, Document, Item};
+use url::Url;
 
 const DEFAULT_AUTH_TOML: &str = r#"
 # This is where Wally stores details for authenticating with registries.
 # It can be updated using `wally login` and `wally logout`.
+#
+# Tokens are keyed by a registry's API URL, or by its host to cover every
+# registry served from it. `default-token` is used for any registry without a
+# token of its own.
 
 [tokens]
 
@@ -20,6 +25,14 @@ const DEFAULT_AUTH_TOML: &str = r#"
 #[derive(Serialize, Deserialize)]
 pub struct AuthStore {
     pub tokens: HashMap<String, String>,
+
+    /// The token for registries that don't have one in `tokens`.
+    #[serde(
+        default,
+        rename = "default-token",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub default_token: Option<String>,
 }
 
 impl AuthStore {
@@ -45,6 +58,31 @@ impl AuthStore {
         Ok(Self::load()?.tokens.remove(key))
     }
 
+    /// The token to use for the registry whose API is at `api`, trying its
+    /// full URL, then its host with and without a port, then the default
+    /// token.
+    pub fn token_for(&self, api: &Url) -> Option<&str> {
+        let mut keys = vec![api.as_str().to_owned()];
+
+        if let Some(host) = api.host_str() {
+            if let Some(port) = api.port() {
+                keys.push(format!("{}:{}", host, port));
+            }
+
+            keys.push(host.to_owned());
+        }
+
+        keys.iter()
+            .find_map(|key| self.tokens.get(key))
+            .or(self.default_token.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Simplifies the usecase of AuthStore::load()?.token_for(api)
+    pub fn get_token_for(api: &Url) -> anyhow::Result<Option<String>> {
+        Ok(Self::load()?.token_for(api).map(str::to_owned))
+    }
+
     pub fn set_token(key: &str, token: Option<&str>) -> anyhow::Result<()> {
         let path = file_path()?;
         let contents = Self::contents(&path)?;
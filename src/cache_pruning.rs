@@ -0,0 +1,127 @@
+//! Evicts entries from Wally's caches when they haven't been used for a while
+//! or the caches have grown too big.
+//!
+//! Caches record when each of their entries was last used by setting the
+//! entry's modification time whenever it's read, so the least recently used
+//! entries can be evicted first.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use fs_err as fs;
+use walkdir::WalkDir;
+
+/// An entry in one of Wally's caches, along with what's needed to decide
+/// whether to evict it.
+#[derive(Debug, Clone)]
+pub struct CachedEntry {
+    /// The file or directory holding the entry.
+    pub path: PathBuf,
+
+    /// When the entry was last read or written.
+    pub last_used: SystemTime,
+
+    /// How many bytes the entry takes up on disk.
+    pub size: u64,
+}
+
+impl CachedEntry {
+    /// Describe the entry at `path`, whose last use was recorded on
+    /// `marker_path` by [`record_use`].
+    pub(crate) fn read(path: PathBuf, marker_path: &Path) -> anyhow::Result<Self> {
+        let last_used = fs::metadata(marker_path)?.modified()?;
+
+        let mut size = 0;
+        for entry in WalkDir::new(&path) {
+            let metadata = entry?.metadata()?;
+            if metadata.is_file() {
+                size += metadata.len();
+            }
+        }
+
+        Ok(Self {
+            path,
+            last_used,
+            size,
+        })
+    }
+
+    fn remove(&self) -> anyhow::Result<()> {
+        if self.path.is_dir() {
+            fs::remove_dir_all(&self.path)?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What pruning a cache removed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PruneSummary {
+    /// How many entries were removed.
+    pub removed: usize,
+
+    /// How many bytes removing them freed.
+    pub freed: u64,
+}
+
+/// Record that the cache entry marked by `marker_path` was just used. Failing
+/// to record it only means the entry may be evicted sooner, so errors are
+/// logged rather than returned.
+pub(crate) fn record_use(marker_path: &Path) {
+    let result = std::fs::File::options()
+        .append(true)
+        .open(marker_path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+
+    if let Err(err) = result {
+        log::debug!(
+            "Failed to record use of cache entry {}: {}",
+            marker_path.display(),
+            err
+        );
+    }
+}
+
+/// Remove every entry that hasn't been used within `max_age`, then remove the
+/// least recently used entries until the rest take up no more than `max_size`
+/// bytes. Without either limit, every entry is removed.
+pub fn prune(
+    mut entries: Vec<CachedEntry>,
+    max_age: Option<Duration>,
+    max_size: Option<u64>,
+) -> anyhow::Result<PruneSummary> {
+    entries.sort_by_key(|entry| entry.last_used);
+
+    let now = SystemTime::now();
+    let mut remaining: u64 = entries.iter().map(|entry| entry.size).sum();
+    let mut summary = PruneSummary::default();
+
+    for entry in &entries {
+        let too_old = match max_age {
+            // Entries used "in the future" by a clock that's since gone back
+            // count as just used.
+            Some(max_age) => now
+                .duration_since(entry.last_used)
+                .is_ok_and(|age| age > max_age),
+            None => false,
+        };
+        let too_big = match max_size {
+            Some(max_size) => remaining > max_size,
+            None => false,
+        };
+
+        if too_old || too_big || (max_age.is_none() && max_size.is_none()) {
+            log::debug!("Removing cache entry {}", entry.path.display());
+            entry.remove()?;
+
+            remaining -= entry.size;
+            summary.removed += 1;
+            summary.freed += entry.size;
+        }
+    }
+
+    Ok(summary)
+}
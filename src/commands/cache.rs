@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use structopt::StructOpt;
+use ubyte::ByteUnit;
+
+use crate::cache_pruning::prune;
+
+use super::GlobalOptions;
+
+/// Manage the caches of packages and parsed types that are shared between
+/// projects on this machine.
+#[derive(Debug, StructOpt)]
+pub struct CacheSubcommand {
+    #[structopt(subcommand)]
+    pub command: CacheCommand,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CacheCommand {
+    Clean(CacheCleanSubcommand),
+}
+
+/// Remove the least recently used entries from the caches. Without
+/// `--max-age` or `--max-size`, the caches are emptied.
+#[derive(Debug, StructOpt)]
+pub struct CacheCleanSubcommand {
+    /// Remove entries that haven't been used in this many days.
+    #[structopt(long = "max-age")]
+    pub max_age: Option<u64>,
+
+    /// Remove the least recently used entries until the caches take up no
+    /// more than this, like "500MiB" or "2GB".
+    #[structopt(long = "max-size")]
+    pub max_size: Option<ByteUnit>,
+}
+
+impl CacheSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        match self.command {
+            CacheCommand::Clean(subcommand) => subcommand.run(global),
+        }
+    }
+}
+
+impl CacheCleanSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let mut entries = Vec::new();
+        if let Some(package_cache) = global.package_cache()? {
+            entries.extend(package_cache.entries()?);
+        }
+        if let Some(type_cache) = global.type_cache()? {
+            entries.extend(type_cache.entries()?);
+        }
+
+        let max_age = self
+            .max_age
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+        let max_size = self.max_size.map(|max_size| max_size.as_u64());
+        let summary = prune(entries, max_age, max_size)?;
+
+        if !global.quiet {
+            println!(
+                "Removed {} cache {}, freeing {}",
+                summary.removed,
+                if summary.removed == 1 {
+                    "entry"
+                } else {
+                    "entries"
+                },
+                ByteUnit::from(summary.freed)
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::installation::InstallationContext;
+use crate::manifest::{Manifest, Realm};
+
+/// Remove the folders that this project's packages are installed into,
+/// without installing them again.
+#[derive(Debug, StructOpt)]
+pub struct CleanSubcommand {
+    /// Path to the project to clean.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Remove the shared packages folder. Without any of `--shared`,
+    /// `--server` or `--dev`, every realm's folder is removed.
+    #[structopt(long = "shared")]
+    pub shared: bool,
+
+    /// Remove the server packages folder.
+    #[structopt(long = "server")]
+    pub server: bool,
+
+    /// Remove the dev packages folder.
+    #[structopt(long = "dev")]
+    pub dev: bool,
+}
+
+impl CleanSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+        let installation = InstallationContext::new(&self.project_path, &manifest.place);
+
+        let every_realm = !(self.shared || self.server || self.dev);
+        let realms = [
+            (Realm::Shared, self.shared),
+            (Realm::Server, self.server),
+            (Realm::Dev, self.dev),
+        ];
+
+        for (realm, selected) in realms {
+            if every_realm || selected {
+                installation.clean_realm(realm)?;
+            }
+        }
+
+        Ok(())
+    }
+}
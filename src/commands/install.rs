@@ -1,23 +1,26 @@
 use std::collections::BTreeSet;
 
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
 use std::time::Duration;
 
-use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
-use indicatif::{ProgressBar, ProgressStyle};
+use anyhow::{bail, format_err, Context};
+
+use crossterm::style::{Color, SetForegroundColor};
 
 use structopt::StructOpt;
 
 use crate::installation::InstallationContext;
-use crate::lockfile::Lockfile;
-use crate::manifest::Manifest;
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::{Layout, Manifest, Scripts, MANIFEST_FILE_NAME};
 use crate::package_id::PackageId;
-use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
-use crate::resolution::resolve;
+use crate::package_name::PackageName;
+use crate::package_source::PackageSourceMap;
+use crate::resolution::{resolve, resolve_from_lockfile, resolve_with_explanation, Resolve};
 
-use super::utils::{generate_dependency_changes, render_update_difference};
-use super::GlobalOptions;
+use super::utils::{render_locked_lockfile_error, render_lockfile_changes};
+use super::{FeatureOptions, GlobalOptions, MessageFormat};
 
 /// Install all of the dependencies of this project.
 #[derive(Debug, StructOpt)]
@@ -26,38 +29,162 @@ pub struct InstallSubcommand {
     #[structopt(long = "project-path", default_value = ".")]
     pub project_path: PathBuf,
 
+    /// Read the manifest from this file instead of the project's
+    /// `wally.toml`. Packages and the lockfile are still written to the
+    /// project, while path dependencies are relative to the manifest.
+    #[structopt(long = "manifest-path")]
+    pub manifest_path: Option<PathBuf>,
+
     /// Flag to error if the lockfile does not match with the latest dependencies.
     #[structopt(long = "locked")]
     pub locked: bool,
+
+    /// Resolve dependencies and report what would be installed without
+    /// writing any files.
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Remove all installed packages and download them again, instead of
+    /// reusing packages that are already installed.
+    #[structopt(long = "clean")]
+    pub clean: bool,
+
+    /// Install exactly what the lockfile describes without accessing the
+    /// network, erroring if any package isn't already installed.
+    #[structopt(long = "frozen", conflicts_with = "clean")]
+    pub frozen: bool,
+
+    /// Don't install dev dependencies, removing any that are already
+    /// installed.
+    #[structopt(long = "no-dev")]
+    pub no_dev: bool,
+
+    /// Warn about Lua files in the package directories that weren't produced
+    /// by the install, such as hand-edited links.
+    #[structopt(long = "check-orphans")]
+    pub check_orphans: bool,
+
+    /// Warn about Lua files outside of the dev packages folder, in the
+    /// project or its links, that require something inside of it and so
+    /// would break a build without dev dependencies.
+    #[structopt(long = "check-dev-requires")]
+    pub check_dev_requires: bool,
+
+    /// Fail if any package exports no types for links to forward, listing
+    /// every package that doesn't.
+    #[structopt(long = "require-types")]
+    pub require_types: bool,
+
+    /// Check that every generated link requires a module that exists once
+    /// the install has finished, failing if any of them don't.
+    #[structopt(long = "verify")]
+    pub verify: bool,
+
+    /// Stop at the first package that fails to install, instead of trying
+    /// every package and reporting all of the failures at the end.
+    #[structopt(long = "fail-fast")]
+    pub fail_fast: bool,
+
+    /// Write a JSON report describing the installed packages and generated
+    /// links to this path.
+    #[structopt(long = "report")]
+    pub report: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    pub features: FeatureOptions,
+
+    /// How to report errors: `human`, or `json` to also write them to
+    /// stdout as JSON.
+    #[structopt(long = "message-format", default_value = "human")]
+    pub message_format: MessageFormat,
+
+    /// Only download and relink this package and the dependencies that
+    /// nothing else needs, leaving every other installed package untouched.
+    /// Other packages' links aren't checked, so this can't be combined with
+    /// options that describe the whole install.
+    #[structopt(
+        long = "package",
+        conflicts_with_all = &["clean", "frozen", "check-orphans", "report"]
+    )]
+    pub package: Option<PackageName>,
+
+    /// Only download packages into the `_Index` folders, without generating
+    /// any links to them, for tools that link packages themselves.
+    #[structopt(
+        long = "no-links",
+        conflicts_with_all = &["check-orphans", "verify", "package"]
+    )]
+    pub no_links: bool,
+
+    /// Write a `.gitignore` into each packages folder that ignores everything
+    /// installed there, like `gitignore = true` in `[place]` does.
+    #[structopt(long = "gitignore")]
+    pub gitignore: bool,
+
+    /// Replace a packages folder's `.gitignore` even if it was changed by
+    /// hand.
+    #[structopt(long = "force-gitignore")]
+    pub force_gitignore: bool,
+
+    /// Install for a release build: the same as `--no-dev --locked
+    /// --fail-fast`, and every package from a registry must have a checksum
+    /// in the lockfile to be verified against.
+    #[structopt(long = "production")]
+    pub production: bool,
+
+    /// How to lay out installed packages: `index`, or `flat` to install each
+    /// package directly into its packages folder. Overrides `layout` under
+    /// `[place]`.
+    #[structopt(long = "layout")]
+    pub layout: Option<Layout>,
+
+    /// Describe how each package's version was chosen: the candidates that
+    /// were considered, the requirements on it, and why the chosen version
+    /// won. Written to stderr, or to a file if a path is given.
+    #[structopt(long = "explain-resolution")]
+    pub explain_resolution: Option<Option<PathBuf>>,
+
+    /// Run the `postinstall` script from the manifest's `[scripts]` once the
+    /// install has finished. Without this, it's skipped with a warning.
+    #[structopt(long = "allow-scripts")]
+    pub allow_scripts: bool,
 }
 
 impl InstallSubcommand {
     pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
+        let message_format = self.message_format;
+        message_format.report(self.install(global))
+    }
+
+    fn install(mut self, global: GlobalOptions) -> anyhow::Result<()> {
+        // --production only turns other options on, so passing any of them
+        // alongside it changes nothing.
+        if self.production {
+            self.no_dev = true;
+            self.locked = true;
+            self.fail_fast = true;
+        }
+
+        let mut manifest = Manifest::load_file(&self.manifest_path())?;
+        self.features.enable(&mut manifest)?;
+
+        if self.frozen {
+            return self.run_frozen(manifest, global);
+        }
 
         let lockfile = Lockfile::load(&self.project_path)?
             .unwrap_or_else(|| Lockfile::from_manifest(&manifest));
 
-        let default_registry: Box<PackageSource> = if global.test_registry {
-            Box::new(PackageSource::TestRegistry(TestRegistry::new(
-                &manifest.package.registry,
-            )))
-        } else {
-            Box::new(PackageSource::Registry(Registry::from_registry_spec(
-                &manifest.package.registry,
-            )?))
-        };
-
-        let mut package_sources = PackageSourceMap::new(default_registry);
-        package_sources.add_fallbacks()?;
+        let package_sources =
+            global.project_sources(self.manifest_dir(), &manifest, &lockfile.git_commits())?;
 
         let try_to_use = lockfile.as_ids().collect();
 
-        let progress = ProgressBar::new(0).with_style(
-            ProgressStyle::with_template("{spinner:.cyan}{wide_msg}")?.tick_chars("⠁⠈⠐⠠⠄⠂ "),
-        );
+        let progress = global.progress_bar()?;
 
-        progress.enable_steady_tick(Duration::from_millis(100));
+        if !progress.is_hidden() {
+            progress.enable_steady_tick(Duration::from_millis(100));
+        }
 
         if self.locked {
             progress.println(format!(
@@ -71,32 +198,10 @@ impl InstallSubcommand {
             if try_to_use != latest_graph.activated {
                 progress.finish_and_clear();
 
-                let old_dependencies = &try_to_use;
-
-                let changes =
-                    generate_dependency_changes(old_dependencies, &latest_graph.activated);
-                let mut error_output = Vec::new();
-
-                writeln!(
-                    error_output,
-                    "{} The Lockfile is out of date and wasn't changed due to --locked{}",
-                    SetForegroundColor(Color::Yellow),
-                    SetForegroundColor(Color::Reset)
-                )?;
-
-                render_update_difference(&changes, &mut error_output)?;
-
-                writeln!(
-                    error_output,
-                    "{}{} Suggestion{}{} try running wally update",
-                    SetAttribute(Attribute::Bold),
-                    SetForegroundColor(Color::DarkGreen),
-                    SetForegroundColor(Color::Reset),
-                    SetAttribute(Attribute::Reset)
-                )?;
-
-                anyhow::bail!(String::from_utf8(error_output)
-                    .expect("output from render_update_difference should always be utf-8"));
+                anyhow::bail!(render_locked_lockfile_error(
+                    &try_to_use,
+                    &latest_graph.activated
+                )?);
             }
 
             progress.println(format!(
@@ -113,7 +218,14 @@ impl InstallSubcommand {
             SetForegroundColor(Color::Reset)
         ));
 
-        let resolved = resolve(&manifest, &try_to_use, &package_sources)?;
+        let (resolved, explanation) =
+            resolve_with_explanation(&manifest, &try_to_use, &package_sources)?;
+
+        match &self.explain_resolution {
+            Some(Some(path)) => fs_err::write(path, explanation.to_string())?,
+            Some(None) => progress.suspend(|| eprint!("{}", explanation)),
+            None => {}
+        }
 
         progress.println(format!(
             "{}   Resolved {}{} dependencies",
@@ -122,37 +234,250 @@ impl InstallSubcommand {
             resolved.activated.len() - 1
         ));
 
-        let new_lockfile = Lockfile::from_resolve(&resolved);
-        new_lockfile.save(&self.project_path)?;
+        if !global.quiet {
+            progress.suspend(|| {
+                render_lockfile_changes(&lockfile, &resolved, &mut std::io::stdout())
+            })?;
+        }
 
-        progress.println(format!(
-            "{}  Generated {}lockfile",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
+        if self.production {
+            if let Err(err) = check_locked_checksums(&lockfile, &resolved, &manifest.package_id()) {
+                progress.finish_and_clear();
+                return Err(err);
+            }
+        }
 
-        progress.set_message(format!(
-            "{}  Cleaning {}package destination...",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
+        let installation = self.installation_context(&global, &manifest, &lockfile)?;
         let root_package_id = PackageId::new(manifest.package.name, manifest.package.version);
-        let installation = InstallationContext::new(
-            &self.project_path,
-            manifest.place.shared_packages,
-            manifest.place.server_packages,
+
+        if self.dry_run {
+            progress.finish_and_clear();
+            installation.install(package_sources, root_package_id, resolved)?;
+
+            return Ok(());
+        }
+
+        let new_lockfile = Lockfile::from_resolve(&resolved);
+
+        if self.clean {
+            progress.set_message(format!(
+                "{}  Cleaning {}package destination...",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            ));
+
+            installation.clean()?;
+            progress.println(format!(
+                "{}    Cleaned {}package destination",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            ));
+        }
+
+        progress.finish_and_clear();
+
+        let observer = Arc::new(global.install_observer());
+        let checksums = installation.with_observer(observer.clone()).install(
+            package_sources,
+            root_package_id,
+            resolved,
         );
+        observer.finish();
+        let checksums = checksums?;
+
+        // The lockfile is only saved once every package has been downloaded so
+        // that it can record their checksums.
+        new_lockfile
+            .with_checksums(&checksums)
+            .save(&self.project_path)?;
+
+        if !global.quiet {
+            println!(
+                "{}  Generated {}lockfile",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            );
+        }
 
-        installation.clean()?;
-        progress.println(format!(
-            "{}    Cleaned {}package destination",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
+        run_postinstall(&self.project_path, &manifest.scripts, self.allow_scripts)
+    }
+
+    /// The installation that this subcommand's flags and `global` describe,
+    /// shared by normal and frozen installs so that they can't drift apart.
+    fn installation_context(
+        &self,
+        global: &GlobalOptions,
+        manifest: &Manifest,
+        lockfile: &Lockfile,
+    ) -> anyhow::Result<InstallationContext> {
+        Ok(
+            InstallationContext::new(&self.project_path, &manifest.place)
+                .with_dry_run(self.dry_run)
+                .with_gitignore(self.gitignore || manifest.place.gitignore)
+                .with_force_gitignore(self.force_gitignore)
+                .with_layout(self.layout.unwrap_or(manifest.place.layout))
+                .with_no_dev(self.no_dev)
+                .with_check_orphans(self.check_orphans)
+                .with_check_dev_requires(self.check_dev_requires)
+                .with_require_types(self.require_types)
+                .with_verify(self.verify)
+                .with_no_links(self.no_links)
+                .with_fail_fast(self.fail_fast)
+                .with_report_path(self.report.clone())
+                .with_target(self.package.clone())
+                .with_concurrency(global.concurrency)
+                .with_package_cache(global.package_cache()?)
+                .with_link_cache(global.link_cache)
+                .with_type_cache(global.type_cache()?)
+                .with_download_retries(global.retries)
+                .with_locked_checksums(lockfile.checksums()),
+        )
+    }
+
+    /// The manifest to install from, which is the project's own unless
+    /// `--manifest-path` is given.
+    pub fn manifest_path(&self) -> PathBuf {
+        match &self.manifest_path {
+            Some(manifest_path) => manifest_path.clone(),
+            None => self.project_path.join(MANIFEST_FILE_NAME),
+        }
+    }
+
+    /// The directory that the manifest's path dependencies are relative to.
+    fn manifest_dir(&self) -> &Path {
+        match &self.manifest_path {
+            Some(manifest_path) => manifest_path.parent().unwrap_or_else(|| Path::new(".")),
+            None => &self.project_path,
+        }
+    }
+
+    fn run_frozen(self, manifest: Manifest, global: GlobalOptions) -> anyhow::Result<()> {
+        let lockfile = Lockfile::load(&self.project_path)?.ok_or_else(|| {
+            format_err!(
+                "--frozen requires a lockfile, but {} has none",
+                self.project_path.display()
+            )
+        })?;
+
+        let resolved = resolve_from_lockfile(&manifest, &lockfile)?;
+
+        if self.production {
+            check_locked_checksums(&lockfile, &resolved, &manifest.package_id())?;
+        }
+
+        if !global.quiet {
+            println!(
+                "{}   Verified {}lockfile is up-to-date",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            );
+        }
+
+        // Nothing is queried or downloaded in frozen mode, so fallback sources
+        // are never looked up, as that could require the network.
+        let package_sources = PackageSourceMap::new(Box::new(
+            global.registry_source(&manifest.package.registry)?,
         ));
-        progress.finish_and_clear();
 
-        installation.install(package_sources, root_package_id, resolved)?;
+        let root_package_id = manifest.package_id();
+        let observer = Arc::new(global.install_observer());
+        let result = self
+            .installation_context(&global, &manifest, &lockfile)?
+            .with_frozen(true)
+            .with_observer(observer.clone())
+            .install(package_sources, root_package_id, resolved);
+        observer.finish();
+        result?;
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        run_postinstall(&self.project_path, &manifest.scripts, self.allow_scripts)
+    }
+}
+
+/// Run the project's `postinstall` script, if it has one, in the project
+/// directory. Scripts can run anything, so they only run when the user has
+/// allowed them.
+fn run_postinstall(
+    project_path: &Path,
+    scripts: &Scripts,
+    allow_scripts: bool,
+) -> anyhow::Result<()> {
+    let script = match &scripts.postinstall {
+        Some(script) => script,
+        None => return Ok(()),
+    };
+
+    if !allow_scripts {
+        log::warn!(
+            "Skipped the postinstall script `{}`. Pass --allow-scripts to run it.",
+            script
+        );
+        return Ok(());
+    }
+
+    log::info!("Running the postinstall script `{}`", script);
+
+    let status = shell_command(script)
+        .current_dir(project_path)
+        .status()
+        .with_context(|| format!("failed to run the postinstall script `{}`", script))?;
 
-        Ok(())
+    if !status.success() {
+        bail!("The postinstall script `{}` failed ({})", script, status);
     }
+
+    Ok(())
+}
+
+/// A command that runs `script` with the platform's shell.
+fn shell_command(script: &str) -> Command {
+    let mut command;
+
+    if cfg!(windows) {
+        command = Command::new("cmd");
+        command.arg("/C");
+    } else {
+        command = Command::new("sh");
+        command.arg("-c");
+    }
+
+    command.arg(script);
+    command
+}
+
+/// Fail unless the lockfile records a checksum for every package from a
+/// registry that installing `resolved` without dev dependencies would
+/// download, so that all of them are verified.
+fn check_locked_checksums(
+    lockfile: &Lockfile,
+    resolved: &Resolve,
+    root_package_id: &PackageId,
+) -> anyhow::Result<()> {
+    let installed = resolved.without_dev_dependencies().activated;
+
+    let missing: Vec<String> = lockfile
+        .packages
+        .iter()
+        .filter_map(|lock_package| match lock_package {
+            LockPackage::Registry(lock_package) if lock_package.checksum.is_none() => Some(
+                PackageId::new(lock_package.name.clone(), lock_package.version.clone()),
+            ),
+            _ => None,
+        })
+        .filter(|package_id| package_id != root_package_id && installed.contains(package_id))
+        .map(|package_id| package_id.to_string())
+        .collect();
+
+    if !missing.is_empty() {
+        bail!(
+            "--production requires the lockfile to record the checksum of every package, but it \
+             has none for {}. Run `wally install` without --production to record them.",
+            missing.join(", ")
+        );
+    }
+
+    Ok(())
 }
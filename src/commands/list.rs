@@ -0,0 +1,176 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::format_err;
+use structopt::StructOpt;
+
+use crate::install_report::{ReportPackage, ResolvedFrom};
+use crate::lockfile::Lockfile;
+use crate::manifest::{Manifest, Realm};
+use crate::package_id::PackageId;
+use crate::resolution::{resolve_from_lockfile, Resolve};
+
+use super::FeatureOptions;
+
+/// List the packages installed by this project's lockfile, grouped by realm.
+#[derive(Debug, StructOpt)]
+pub struct ListSubcommand {
+    /// Path to the project to list the packages of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Print the packages as JSON instead.
+    #[structopt(long = "json")]
+    pub json: bool,
+
+    #[structopt(flatten)]
+    pub features: FeatureOptions,
+}
+
+impl ListSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let mut manifest = Manifest::load(&self.project_path)?;
+        self.features.enable(&mut manifest)?;
+
+        let lockfile = Lockfile::load(&self.project_path)?.ok_or_else(|| {
+            format_err!(
+                "{} has no lockfile yet. Run `wally install` or `wally resolve` to create one.",
+                self.project_path.display()
+            )
+        })?;
+
+        let resolved = resolve_from_lockfile(&manifest, &lockfile)?;
+
+        let stdout = io::stdout();
+        let mut output = stdout.lock();
+
+        if self.json {
+            render_list_json(&resolved, &manifest.package_id(), &mut output)
+        } else {
+            render_list(&resolved, &manifest.package_id(), &mut output)
+        }
+    }
+}
+
+/// Write every package in a resolved dependency graph other than
+/// `root_package_id`, grouped by the realm it's installed into.
+pub fn render_list(
+    resolved: &Resolve,
+    root_package_id: &PackageId,
+    output: &mut impl Write,
+) -> anyhow::Result<()> {
+    let packages = ReportPackage::all(root_package_id, resolved);
+
+    if packages.is_empty() {
+        writeln!(output, "{} has no dependencies", root_package_id)?;
+        return Ok(());
+    }
+
+    for realm in [Realm::Shared, Realm::Server, Realm::Dev].iter() {
+        let in_realm: Vec<_> = packages
+            .iter()
+            .filter(|package| package.realm == *realm)
+            .collect();
+
+        if in_realm.is_empty() {
+            continue;
+        }
+
+        writeln!(output, "{}:", realm)?;
+
+        for package in in_realm {
+            let resolved_from = match package.resolved_from {
+                ResolvedFrom::Direct => "direct",
+                ResolvedFrom::Transitive => "transitive",
+            };
+
+            writeln!(output, "  {} ({})", package.id, resolved_from)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the same packages as `render_list`, as a JSON array.
+pub fn render_list_json(
+    resolved: &Resolve,
+    root_package_id: &PackageId,
+    output: &mut impl Write,
+) -> anyhow::Result<()> {
+    let packages = ReportPackage::all(root_package_id, resolved);
+
+    serde_json::to_writer_pretty(&mut *output, &packages)?;
+    writeln!(output)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use crate::package_source::PackageSourceId;
+    use crate::resolution::ResolvePackageMetadata;
+
+    /// A project with a shared dependency `a`, which depends on `b`, and a
+    /// server dependency `c`.
+    fn graph() -> (PackageId, Resolve) {
+        let id = |spec: &str| PackageId::from_str(spec).unwrap();
+        let root = id("biff/root@0.1.0");
+
+        let mut resolved = Resolve::default();
+        for (package, realm, dependencies) in [
+            (
+                "biff/root@0.1.0",
+                Realm::Server,
+                vec!["biff/a@0.1.0", "biff/c@1.0.0"],
+            ),
+            ("biff/a@0.1.0", Realm::Shared, vec!["biff/b@0.2.0"]),
+            ("biff/b@0.2.0", Realm::Shared, vec![]),
+            ("biff/c@1.0.0", Realm::Server, vec![]),
+        ] {
+            resolved.activated.insert(id(package));
+            resolved.metadata.insert(
+                id(package),
+                ResolvePackageMetadata {
+                    realm,
+                    origin_realm: realm,
+                    source_registry: PackageSourceId::DefaultRegistry,
+                    git_commit: None,
+                    checksum: None,
+                },
+            );
+            resolved.shared_dependencies.insert(
+                id(package),
+                dependencies
+                    .into_iter()
+                    .map(|dependency| (dependency.to_owned(), id(dependency)))
+                    .collect(),
+            );
+        }
+
+        (root, resolved)
+    }
+
+    #[test]
+    fn packages_are_grouped_by_realm() {
+        let (root, resolved) = graph();
+
+        let mut output = Vec::new();
+        render_list(&resolved, &root, &mut output).unwrap();
+
+        insta::assert_snapshot!(String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn packages_as_json() {
+        let (root, resolved) = graph();
+
+        let mut output = Vec::new();
+        render_list_json(&resolved, &root, &mut output).unwrap();
+
+        insta::assert_snapshot!(String::from_utf8(output).unwrap());
+    }
+}
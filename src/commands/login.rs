@@ -15,6 +15,8 @@ use crate::{
     package_index::{PackageIndex, PackageIndexConfig},
 };
 
+use super::GlobalOptions;
+
 /// Log into a registry.
 #[derive(Debug, StructOpt)]
 pub struct LoginSubcommand {
@@ -110,24 +112,27 @@ fn prompt_github_auth(api: url::Url, github_oauth_id: &str) -> anyhow::Result<()
     AuthStore::set_token(api.as_str(), Some(&auth.access_token))
 }
 
-fn fetch_package_index_config(project_path: &Path) -> anyhow::Result<PackageIndexConfig> {
+fn fetch_package_index_config(
+    project_path: &Path,
+    global: &GlobalOptions,
+) -> anyhow::Result<PackageIndexConfig> {
     let manifest = Manifest::load(project_path)?;
     let registry = Url::parse(&manifest.package.registry)?;
-    let package_index = PackageIndex::new(&registry, None)?;
+    let package_index = PackageIndex::new(&global.cache_dir()?, &registry, None)?;
     package_index.config()
 }
 
 impl LoginSubcommand {
-    pub fn run(self) -> anyhow::Result<()> {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
         match (self.token, self.api) {
             (Some(token), Some(api)) => AuthStore::set_token(&api, Some(&token)),
             (Some(token), None) => {
-                let config = fetch_package_index_config(&self.project_path)?;
+                let config = fetch_package_index_config(&self.project_path, &global)?;
 
                 AuthStore::set_token(config.api.as_str(), Some(&token))
             }
             (None, _) => {
-                let config = fetch_package_index_config(&self.project_path)?;
+                let config = fetch_package_index_config(&self.project_path, &global)?;
 
                 match config.github_oauth_id {
                     None => prompt_api_key(config.api),
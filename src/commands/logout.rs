@@ -4,6 +4,8 @@ use structopt::StructOpt;
 
 use crate::{auth::AuthStore, manifest::Manifest, package_index::PackageIndex};
 
+use super::GlobalOptions;
+
 /// Log out of a registry.
 #[derive(Debug, StructOpt)]
 pub struct LogoutSubcommand {
@@ -13,10 +15,10 @@ pub struct LogoutSubcommand {
 }
 
 impl LogoutSubcommand {
-    pub fn run(self) -> anyhow::Result<()> {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
         let manifest = Manifest::load(&self.project_path)?;
         let registry = url::Url::parse(&manifest.package.registry)?;
-        let package_index = PackageIndex::new(&registry, None)?;
+        let package_index = PackageIndex::new(&global.cache_dir()?, &registry, None)?;
         let api = package_index.config()?.api;
 
         AuthStore::set_token(api.as_str(), None)?;
@@ -1,26 +1,61 @@
+mod cache;
+mod clean;
 mod init;
 mod install;
+mod list;
 mod login;
 mod logout;
 mod manifest_to_json;
+mod outdated;
 mod package;
 mod publish;
+mod resolve;
 mod search;
+mod tree;
 mod update;
 mod utils;
+mod verify_manifest;
+mod why;
 
+pub use cache::{CacheCleanSubcommand, CacheCommand, CacheSubcommand};
+pub use clean::CleanSubcommand;
 pub use init::InitSubcommand;
 pub use install::InstallSubcommand;
+pub use list::ListSubcommand;
 pub use login::LoginSubcommand;
 pub use logout::LogoutSubcommand;
 pub use manifest_to_json::ManifestToJsonSubcommand;
+pub use outdated::OutdatedSubcommand;
 pub use package::PackageSubcommand;
 pub use publish::PublishSubcommand;
+pub use resolve::ResolveSubcommand;
 pub use search::SearchSubcommand;
+pub use tree::TreeSubcommand;
 pub use update::{PackageSpec, UpdateSubcommand};
+pub use verify_manifest::VerifyManifestSubcommand;
+pub use why::WhySubcommand;
 
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::bail;
+use indicatif::{ProgressBar, ProgressStyle};
 use structopt::StructOpt;
 
+use crate::global_config::{cache_dir_from_env, GlobalConfig, CACHE_DIR_ENV_VAR};
+use crate::install_observer::ProgressBarObserver;
+use crate::manifest::{check_wally_version, GitSpec, Manifest, PatchSource, MANIFEST_FILE_NAME};
+use crate::package_cache::PackageCache;
+use crate::package_source::{
+    GitSource, PackageSource, PackageSourceId, PackageSourceMap, PathSource, Registry, TestRegistry,
+};
+use crate::type_cache::TypeCache;
+
+use utils::render_error_json;
+
 #[derive(Debug, StructOpt)]
 #[structopt(about = env!("CARGO_PKG_DESCRIPTION"))]
 pub struct Args {
@@ -33,16 +68,32 @@ pub struct Args {
 
 impl Args {
     pub fn run(self) -> anyhow::Result<()> {
+        if self.global.offline && self.subcommand.requires_network() {
+            bail!("This command needs to access the network, so it cannot be used with --offline");
+        }
+
+        if let Some(manifest_path) = self.subcommand.manifest_path() {
+            check_wally_version(&manifest_path)?;
+        }
+
         match self.subcommand {
             Subcommand::Publish(subcommand) => subcommand.run(self.global),
             Subcommand::Init(subcommand) => subcommand.run(),
-            Subcommand::Login(subcommand) => subcommand.run(),
-            Subcommand::Logout(subcommand) => subcommand.run(),
+            Subcommand::Login(subcommand) => subcommand.run(self.global),
+            Subcommand::Logout(subcommand) => subcommand.run(self.global),
             Subcommand::Update(subcommand) => subcommand.run(self.global),
-            Subcommand::Search(subcommand) => subcommand.run(),
+            Subcommand::Search(subcommand) => subcommand.run(self.global),
             Subcommand::Package(subcommand) => subcommand.run(),
             Subcommand::Install(subcommand) => subcommand.run(self.global),
             Subcommand::ManifestToJson(subcommand) => subcommand.run(),
+            Subcommand::Outdated(subcommand) => subcommand.run(self.global),
+            Subcommand::Tree(subcommand) => subcommand.run(self.global),
+            Subcommand::Why(subcommand) => subcommand.run(self.global),
+            Subcommand::Resolve(subcommand) => subcommand.run(self.global),
+            Subcommand::List(subcommand) => subcommand.run(),
+            Subcommand::VerifyManifest(subcommand) => subcommand.run(),
+            Subcommand::Clean(subcommand) => subcommand.run(),
+            Subcommand::Cache(subcommand) => subcommand.run(self.global),
         }
     }
 }
@@ -55,6 +106,48 @@ pub struct GlobalOptions {
     #[structopt(global = true, parse(from_occurrences), long = "verbose", short)]
     pub verbosity: u8,
 
+    /// Only show errors: no progress bars, status messages or warnings.
+    #[structopt(global = true, long = "quiet", short, conflicts_with = "verbosity")]
+    pub quiet: bool,
+
+    /// Number of packages to download in parallel. Defaults to the number of
+    /// available CPUs, with a minimum of 4.
+    #[structopt(global = true, long = "jobs", short = "j")]
+    pub concurrency: Option<usize>,
+
+    /// Number of times to retry a package download that failed due to a
+    /// network error.
+    #[structopt(global = true, long = "retries", default_value = "3")]
+    pub retries: u32,
+
+    /// Number of seconds a single attempt at downloading a package may take
+    /// before it's abandoned. Timed out downloads are retried like any other
+    /// network error, each attempt getting the full timeout again.
+    #[structopt(global = true, long = "download-timeout", default_value = "60")]
+    pub download_timeout: u64,
+
+    /// Always download packages instead of reusing copies from the package
+    /// cache shared between projects.
+    #[structopt(global = true, long = "no-cache")]
+    pub no_cache: bool,
+
+    /// Link installed packages to their copies in the package cache instead
+    /// of copying them into each project, where the platform allows it.
+    #[structopt(global = true, long = "link-cache", conflicts_with = "no-cache")]
+    pub link_cache: bool,
+
+    /// Never access the network. Registries are read from the copies already
+    /// on this machine and packages are only installed from the package cache,
+    /// failing if anything that's needed isn't available locally.
+    #[structopt(global = true, long = "offline")]
+    pub offline: bool,
+
+    /// Directory to keep registry indexes, Git repositories and cached
+    /// packages in. Defaults to `WALLY_CACHE_DIR` if it's set, or to `wally`
+    /// in the user's cache directory.
+    #[structopt(global = true, long = "cache-dir", parse(from_os_str))]
+    pub cache_dir: Option<PathBuf>,
+
     /// Flag to indidate if we will be using a test registry. Usable only by tests.
     #[structopt(skip)]
     pub test_registry: bool,
@@ -66,15 +159,302 @@ pub struct GlobalOptions {
     /// Specify if a specific auth token should be provided. Usable only by tests.
     #[structopt(skip)]
     pub check_token: Option<String>,
+
+    /// Specify a directory to use as the package cache. Usable only by tests.
+    #[structopt(skip)]
+    pub package_cache_path: Option<PathBuf>,
+
+    /// Specify a file to use as the global config. Usable only by tests.
+    #[structopt(skip)]
+    pub global_config_path: Option<PathBuf>,
+}
+
+impl GlobalOptions {
+    /// The directory that registry indexes, Git repositories and cached
+    /// packages are kept in. `--cache-dir` takes precedence over
+    /// `WALLY_CACHE_DIR`, which takes precedence over the platform's default.
+    pub(crate) fn cache_dir(&self) -> anyhow::Result<PathBuf> {
+        self.cache_dir_with_env(std::env::var_os(CACHE_DIR_ENV_VAR))
+    }
+
+    /// [`GlobalOptions::cache_dir`] when `WALLY_CACHE_DIR` is set to
+    /// `env_dir`.
+    fn cache_dir_with_env(&self, env_dir: Option<OsString>) -> anyhow::Result<PathBuf> {
+        match &self.cache_dir {
+            Some(cache_dir) => Ok(cache_dir.clone()),
+            None => cache_dir_from_env(env_dir),
+        }
+    }
+
+    /// A spinner showing which step a command is on, which draws nothing
+    /// with `--quiet`.
+    pub(crate) fn progress_bar(&self) -> anyhow::Result<ProgressBar> {
+        if self.quiet {
+            return Ok(ProgressBar::hidden());
+        }
+
+        Ok(ProgressBar::new(0).with_style(
+            ProgressStyle::with_template("{spinner:.cyan}{wide_msg}")?.tick_chars("⠁⠈⠐⠠⠄⠂ "),
+        ))
+    }
+
+    /// The observer that shows an install's progress, which draws nothing
+    /// with `--quiet` and lists every downloaded package with `--verbose`.
+    pub(crate) fn install_observer(&self) -> ProgressBarObserver {
+        if self.quiet {
+            ProgressBarObserver::hidden()
+        } else {
+            ProgressBarObserver::new().with_verbose(self.verbosity > 0)
+        }
+    }
+
+    /// The package cache to install packages through, if caching is enabled.
+    pub(crate) fn package_cache(&self) -> anyhow::Result<Option<PackageCache>> {
+        if self.no_cache {
+            return Ok(None);
+        }
+
+        match &self.package_cache_path {
+            Some(path) => Ok(Some(PackageCache::at(path))),
+            // Test registries are already on disk, so caching them gains
+            // nothing and would leave test packages in the user's cache.
+            None if self.test_registry => Ok(None),
+            None => Ok(Some(PackageCache::new(&self.cache_dir()?))),
+        }
+    }
+
+    /// The cache of types parsed from packages' main modules, if caching is
+    /// enabled. It lives next to the package cache when that's overridden.
+    pub(crate) fn type_cache(&self) -> anyhow::Result<Option<TypeCache>> {
+        if self.no_cache {
+            return Ok(None);
+        }
+
+        match &self.package_cache_path {
+            Some(path) => Ok(Some(TypeCache::at(path.join(".types")))),
+            None if self.test_registry => Ok(None),
+            None => Ok(Some(TypeCache::new(&self.cache_dir()?))),
+        }
+    }
+
+    fn global_config(&self) -> anyhow::Result<GlobalConfig> {
+        match &self.global_config_path {
+            Some(path) => GlobalConfig::load_from(path),
+            // Tests shouldn't be affected by the user's own config.
+            None if self.test_registry => Ok(GlobalConfig::default()),
+            None => GlobalConfig::load(),
+        }
+    }
+
+    /// The mirrors from the global config, keyed by the scope they serve.
+    pub(crate) fn package_mirrors(&self) -> anyhow::Result<HashMap<String, PackageSource>> {
+        self.global_config()?
+            .mirrors
+            .into_iter()
+            .map(|(scope, spec)| Ok((scope, self.registry_source(&spec)?)))
+            .collect()
+    }
+
+    fn download_timeout(&self) -> Duration {
+        Duration::from_secs(self.download_timeout)
+    }
+
+    /// The source for the registry described by `spec`, usually the
+    /// `registry` field of a manifest.
+    pub(crate) fn registry_source(&self, spec: &str) -> anyhow::Result<PackageSource> {
+        if self.test_registry {
+            Ok(PackageSource::TestRegistry(
+                TestRegistry::new(spec).with_offline(self.offline),
+            ))
+        } else {
+            let mut registry = Registry::from_registry_spec(spec)?
+                .with_offline(self.offline)
+                .with_download_timeout(self.download_timeout())
+                .with_cache_dir(self.cache_dir()?);
+            if let Some(headers) = self.global_config()?.headers.get(spec) {
+                registry = registry.with_headers(headers)?;
+            }
+
+            Ok(PackageSource::Registry(registry))
+        }
+    }
+
+    /// Every source that packages can come from for a project using the
+    /// registry described by `spec`, including mirrors and fallbacks.
+    /// `registries` are searched first, in order, as `[[registries]]` in a
+    /// manifest are.
+    pub(crate) fn package_sources(
+        &self,
+        spec: &str,
+        registries: &[String],
+    ) -> anyhow::Result<PackageSourceMap> {
+        let mut package_sources = PackageSourceMap::new(Box::new(self.registry_source(spec)?))
+            .with_offline(self.offline)
+            .with_download_timeout(self.download_timeout())
+            .with_cache_dir(self.cache_dir()?)
+            .with_registry_headers(self.global_config()?.headers);
+        package_sources.add_mirrors(self.package_mirrors()?);
+
+        if !registries.is_empty() {
+            let registries = registries
+                .iter()
+                .map(|registry| {
+                    if registry == spec {
+                        return Ok((PackageSourceId::DefaultRegistry, None));
+                    }
+
+                    // Registries are identified the same way as fallbacks, so
+                    // that one which is also a fallback is only searched once.
+                    let id = if self.test_registry {
+                        let path = PathBuf::from(registry);
+                        PackageSourceId::Path(path.canonicalize().unwrap_or(path))
+                    } else {
+                        PackageSourceId::Git(registry.clone())
+                    };
+
+                    Ok((id, Some(self.registry_source(registry)?)))
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            package_sources.add_registries(registries);
+        }
+
+        package_sources.add_fallbacks()?;
+
+        Ok(package_sources)
+    }
+
+    /// Every source that packages can come from for the project at
+    /// `project_path` described by `manifest`: those from `package_sources`,
+    /// plus the Git repositories and local directories it depends on or
+    /// patches packages with.
+    /// Repositories with a commit in `locked_commits` are checked out at that
+    /// commit instead of the latest commit of their `rev`.
+    pub(crate) fn project_sources(
+        &self,
+        project_path: &Path,
+        manifest: &Manifest,
+        locked_commits: &HashMap<GitSpec, String>,
+    ) -> anyhow::Result<PackageSourceMap> {
+        let mut package_sources =
+            self.package_sources(&manifest.package.registry, &manifest.registries)?;
+
+        for dependency in manifest.git_dependencies.values() {
+            package_sources.add_git_source(self.git_source(&dependency.source, locked_commits)?);
+        }
+
+        for dependency in manifest.path_dependencies.values() {
+            package_sources.add_path_source(PathSource::new(project_path, &dependency.path)?);
+        }
+
+        for patch in manifest.patches.values() {
+            match patch {
+                PatchSource::Git(spec) => {
+                    package_sources.add_git_source(self.git_source(spec, locked_commits)?)
+                }
+                PatchSource::Path { path } => {
+                    package_sources.add_path_source(PathSource::new(project_path, path)?)
+                }
+            }
+        }
+
+        Ok(package_sources)
+    }
+
+    fn git_source(
+        &self,
+        spec: &GitSpec,
+        locked_commits: &HashMap<GitSpec, String>,
+    ) -> anyhow::Result<GitSource> {
+        let locked_commit = locked_commits.get(spec).map(String::as_str);
+
+        if self.offline {
+            GitSource::open_offline(&self.cache_dir()?, spec, locked_commit)
+        } else if self.test_registry {
+            // Tests shouldn't leave their repositories in the user's cache.
+            GitSource::new_temp(spec, locked_commit)
+        } else {
+            GitSource::new(&self.cache_dir()?, spec, locked_commit)
+        }
+    }
 }
 
 impl Default for GlobalOptions {
     fn default() -> Self {
         Self {
             verbosity: 0,
+            quiet: false,
+            concurrency: None,
+            retries: 3,
+            download_timeout: 60,
+            no_cache: false,
+            link_cache: false,
+            offline: false,
+            cache_dir: None,
             test_registry: false,
             use_temp_index: false,
             check_token: None,
+            package_cache_path: None,
+            global_config_path: None,
+        }
+    }
+}
+
+/// Options for choosing which of a project's optional dependencies are
+/// resolved and installed.
+#[derive(Debug, Default, StructOpt)]
+pub struct FeatureOptions {
+    /// Comma-separated list of the project's features to enable.
+    #[structopt(long = "features", use_delimiter = true)]
+    pub features: Vec<String>,
+
+    /// Enable every feature of the project.
+    #[structopt(long = "all-features")]
+    pub all_features: bool,
+}
+
+impl FeatureOptions {
+    /// Add the optional dependencies enabled by these options to `manifest`.
+    pub(crate) fn enable(&self, manifest: &mut Manifest) -> anyhow::Result<()> {
+        manifest.enable_features(&self.features, self.all_features)
+    }
+}
+
+/// How a subcommand reports the error it fails with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Errors are only returned, to be printed for a person to read.
+    #[default]
+    Human,
+
+    /// Errors are also written to stdout as a JSON object, for tools that
+    /// run Wally to read.
+    Json,
+}
+
+impl MessageFormat {
+    /// Pass through the result of running a subcommand, writing its error to
+    /// stdout first if errors should be reported as JSON.
+    pub(crate) fn report(self, result: anyhow::Result<()>) -> anyhow::Result<()> {
+        if let (MessageFormat::Json, Err(err)) = (self, &result) {
+            render_error_json(err, &mut std::io::stdout())?;
+        }
+
+        result
+    }
+}
+
+impl FromStr for MessageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => bail!(
+                "Unknown message format '{}', expected 'human' or 'json'",
+                value
+            ),
         }
     }
 }
@@ -90,4 +470,87 @@ pub enum Subcommand {
     Search(SearchSubcommand),
     Package(PackageSubcommand),
     ManifestToJson(ManifestToJsonSubcommand),
+    Outdated(OutdatedSubcommand),
+    Tree(TreeSubcommand),
+    Why(WhySubcommand),
+    Resolve(ResolveSubcommand),
+    List(ListSubcommand),
+    VerifyManifest(VerifyManifestSubcommand),
+    Clean(CleanSubcommand),
+    Cache(CacheSubcommand),
+}
+
+impl Subcommand {
+    /// Whether this subcommand can't do anything useful without the network.
+    fn requires_network(&self) -> bool {
+        matches!(
+            self,
+            Subcommand::Publish(_) | Subcommand::Login(_) | Subcommand::Search(_)
+        )
+    }
+
+    /// The manifest this subcommand reads, if any.
+    fn manifest_path(&self) -> Option<PathBuf> {
+        let project_path = match self {
+            Subcommand::Init(_) | Subcommand::Cache(_) => return None,
+            Subcommand::Install(subcommand) => return Some(subcommand.manifest_path()),
+            Subcommand::Update(subcommand) => &subcommand.project_path,
+            Subcommand::Publish(subcommand) => &subcommand.project_path,
+            Subcommand::Login(subcommand) => &subcommand.project_path,
+            Subcommand::Logout(subcommand) => &subcommand.project_path,
+            Subcommand::Search(subcommand) => &subcommand.project_path,
+            Subcommand::Package(subcommand) => &subcommand.project_path,
+            Subcommand::ManifestToJson(subcommand) => &subcommand.project_path,
+            Subcommand::Outdated(subcommand) => &subcommand.project_path,
+            Subcommand::Tree(subcommand) => &subcommand.project_path,
+            Subcommand::Why(subcommand) => &subcommand.project_path,
+            Subcommand::Resolve(subcommand) => &subcommand.project_path,
+            Subcommand::List(subcommand) => &subcommand.project_path,
+            Subcommand::VerifyManifest(subcommand) => &subcommand.project_path,
+            Subcommand::Clean(subcommand) => &subcommand.project_path,
+        };
+
+        Some(project_path.join(MANIFEST_FILE_NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::package_id::PackageId;
+
+    #[test]
+    fn cache_dir_can_be_moved() {
+        let env_dir = tempfile::tempdir().unwrap();
+        let flag_dir = tempfile::tempdir().unwrap();
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let env_value = || Some(env_dir.path().as_os_str().to_owned());
+
+        let global = GlobalOptions::default();
+        assert_eq!(
+            global.cache_dir_with_env(env_value()).unwrap(),
+            env_dir.path()
+        );
+
+        // An empty variable is the same as one that isn't set.
+        assert_eq!(
+            global.cache_dir_with_env(Some(OsString::new())).unwrap(),
+            global.cache_dir_with_env(None).unwrap()
+        );
+
+        // The flag wins over the environment variable.
+        let global = GlobalOptions {
+            cache_dir: Some(flag_dir.path().to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            global.cache_dir_with_env(env_value()).unwrap(),
+            flag_dir.path()
+        );
+
+        let package_cache = global.package_cache().unwrap().unwrap();
+        let entry = package_cache.start_insert(&package_id).unwrap().unwrap();
+        assert!(entry.contents_path().starts_with(flag_dir.path()));
+    }
 }
@@ -0,0 +1,250 @@
+use std::path::PathBuf;
+
+use anyhow::format_err;
+use crossterm::style::{Color, SetForegroundColor};
+use semver::{Version, VersionReq};
+use structopt::StructOpt;
+
+use crate::lockfile::Lockfile;
+use crate::manifest::{Manifest, Realm};
+use crate::package_req::PackageReq;
+use crate::package_source::PackageSourceMap;
+use crate::resolution::VersionListings;
+
+use super::GlobalOptions;
+
+/// List dependencies that have newer versions available.
+#[derive(Debug, StructOpt)]
+pub struct OutdatedSubcommand {
+    /// Path to the project to check dependencies for.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Exit with an error if any dependency is outdated.
+    #[structopt(long = "exit-code")]
+    pub exit_code: bool,
+}
+
+impl OutdatedSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+
+        // Without a lockfile there's nothing installed to be out of date, so
+        // reporting every dependency as up-to-date would be misleading.
+        let lockfile = Lockfile::load(&self.project_path)?.ok_or_else(|| {
+            format_err!(
+                "{} has no lockfile yet. Run `wally install` or `wally resolve` to create one.",
+                self.project_path.display()
+            )
+        })?;
+
+        let package_sources =
+            global.package_sources(&manifest.package.registry, &manifest.registries)?;
+
+        let dependencies = find_outdated(&manifest, &lockfile, &package_sources)?;
+        let outdated: Vec<_> = dependencies
+            .iter()
+            .filter(|dependency| dependency.is_outdated())
+            .collect();
+
+        if outdated.is_empty() {
+            println!(
+                "{}  Up-to-date {}all dependencies are on their latest version",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            );
+
+            return Ok(());
+        }
+
+        let rows: Vec<[String; 4]> = outdated
+            .iter()
+            .map(|dependency| {
+                [
+                    dependency.package_req.name().to_string(),
+                    display_version(&dependency.current),
+                    display_version(&dependency.compatible),
+                    display_version(&dependency.latest),
+                ]
+            })
+            .collect();
+
+        let header = ["Name", "Current", "Compatible", "Latest"];
+        let mut widths = header.map(str::len);
+
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        println!(
+            "{}{:<w0$}  {:<w1$}  {:<w2$}  {}{}",
+            SetForegroundColor(Color::DarkGreen),
+            header[0],
+            header[1],
+            header[2],
+            header[3],
+            SetForegroundColor(Color::Reset),
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+
+        for row in &rows {
+            println!(
+                "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+            );
+        }
+
+        if self.exit_code {
+            anyhow::bail!("{} dependencies are outdated", outdated.len());
+        }
+
+        Ok(())
+    }
+}
+
+/// The versions available for one of a project's direct dependencies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutdatedDependency {
+    pub package_req: PackageReq,
+    pub realm: Realm,
+
+    /// The version currently locked in the lockfile, if any.
+    pub current: Option<Version>,
+
+    /// The newest version that satisfies the manifest's requirement.
+    pub compatible: Option<Version>,
+
+    /// The newest version available at all.
+    pub latest: Option<Version>,
+}
+
+impl OutdatedDependency {
+    pub fn is_outdated(&self) -> bool {
+        match (&self.current, &self.latest) {
+            (Some(current), Some(latest)) => current < latest,
+            _ => false,
+        }
+    }
+}
+
+/// Query the package sources for the newest versions of each of the
+/// manifest's direct dependencies, only considering versions that are valid in
+/// the realm each dependency is declared in. Like resolving, versions come from
+/// the highest priority source that has any that match.
+pub fn find_outdated(
+    manifest: &Manifest,
+    lockfile: &Lockfile,
+    package_sources: &PackageSourceMap,
+) -> anyhow::Result<Vec<OutdatedDependency>> {
+    let dependencies = manifest
+        .dependencies
+        .values()
+        .map(|req| (req, Realm::Shared))
+        .chain(
+            manifest
+                .server_dependencies
+                .values()
+                .map(|req| (req, Realm::Server)),
+        )
+        .chain(
+            manifest
+                .dev_dependencies
+                .values()
+                .map(|req| (req, Realm::Dev)),
+        );
+
+    let listings = VersionListings::new(package_sources);
+    let mut outdated = Vec::new();
+
+    for (package_req, realm) in dependencies {
+        let any_version = PackageReq::new(package_req.name().clone(), VersionReq::any());
+
+        let newest = |package_req: &PackageReq| -> anyhow::Result<Option<Version>> {
+            let (_, candidates) = listings
+                .query(package_req)
+                .ok_or_else(|| format_err!("Failed to find a source for {}", package_req))?;
+
+            Ok(candidates
+                .into_iter()
+                .filter(|candidate| Realm::is_dependency_valid(realm, candidate.package.realm))
+                .map(|candidate| candidate.package.version)
+                .max())
+        };
+
+        let latest = newest(&any_version)?;
+        let compatible = newest(package_req)?;
+
+        let current = lockfile
+            .as_ids()
+            .filter(|package_id| package_req.matches_id(package_id))
+            .map(|package_id| package_id.version().clone())
+            .max();
+
+        outdated.push(OutdatedDependency {
+            package_req: package_req.clone(),
+            realm,
+            current,
+            compatible,
+            latest,
+        });
+    }
+
+    Ok(outdated)
+}
+
+fn display_version(version: &Option<Version>) -> String {
+    version
+        .as_ref()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::package_source::{InMemoryRegistry, PackageSourceId};
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    #[test]
+    fn versions_from_later_registries() {
+        let default_registry = InMemoryRegistry::new();
+        let other_registry = InMemoryRegistry::new();
+        other_registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+        other_registry.publish(PackageBuilder::new("biff/minimal@0.2.0"));
+
+        // The default registry is searched first, and lists no versions.
+        let mut sources = PackageSourceMap::new(Box::new(default_registry.source()));
+        sources.add_registries(vec![
+            (PackageSourceId::DefaultRegistry, None),
+            (
+                PackageSourceId::Git("https://example.com/other-index".to_owned()),
+                Some(other_registry.source()),
+            ),
+        ]);
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .into_manifest();
+        let lockfile =
+            Lockfile::from_resolve(&resolve(&root, &Default::default(), &sources).unwrap());
+
+        let outdated = find_outdated(&root, &lockfile, &sources).unwrap();
+
+        assert_eq!(outdated.len(), 1);
+        assert_eq!(outdated[0].current, Some(Version::new(0, 1, 0)));
+        assert_eq!(outdated[0].compatible, Some(Version::new(0, 1, 0)));
+        assert_eq!(outdated[0].latest, Some(Version::new(0, 2, 0)));
+    }
+}
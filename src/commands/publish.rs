@@ -32,6 +32,14 @@ impl PublishSubcommand {
             bail!("Cannot publish private package.");
         }
 
+        if !manifest.git_dependencies.is_empty() {
+            bail!("Cannot publish a package with dependencies on Git repositories.");
+        }
+
+        if !manifest.path_dependencies.is_empty() {
+            bail!("Cannot publish a package with dependencies on local paths.");
+        }
+
         let index_url = if global.test_registry {
             let index_path = Path::new(&manifest.package.registry)
                 .join("index")
@@ -45,7 +53,7 @@ impl PublishSubcommand {
         let package_index = if global.use_temp_index {
             PackageIndex::new_temp(&index_url, None)?
         } else {
-            PackageIndex::new(&index_url, None)?
+            PackageIndex::new(&global.cache_dir()?, &index_url, None)?
         };
 
         let api = package_index.config()?.api;
@@ -57,7 +65,7 @@ impl PublishSubcommand {
 
         let auth = match self.token {
             Some(token) => token,
-            None => AuthStore::get_token(api.as_str())?
+            None => AuthStore::get_token_for(&api)?
                 .with_context(|| "Authentication is required to publish, use `wally login`")?,
         };
 
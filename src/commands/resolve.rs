@@ -0,0 +1,80 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use crossterm::style::{Color, SetForegroundColor};
+use structopt::StructOpt;
+
+use crate::lockfile::Lockfile;
+use crate::manifest::Manifest;
+use crate::resolution::resolve;
+
+use super::utils::{render_locked_lockfile_error, render_lockfile_changes};
+use super::{FeatureOptions, GlobalOptions, MessageFormat};
+
+/// Resolve the dependencies of this project and write the lockfile, without
+/// downloading or installing any packages.
+#[derive(Debug, StructOpt)]
+pub struct ResolveSubcommand {
+    /// Path to the project to resolve dependencies for.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Flag to error if the lockfile does not match with the latest dependencies.
+    #[structopt(long = "locked")]
+    pub locked: bool,
+
+    #[structopt(flatten)]
+    pub features: FeatureOptions,
+
+    /// How to report errors: `human`, or `json` to also write them to
+    /// stdout as JSON.
+    #[structopt(long = "message-format", default_value = "human")]
+    pub message_format: MessageFormat,
+}
+
+impl ResolveSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let message_format = self.message_format;
+        message_format.report(self.write_lockfile(global))
+    }
+
+    fn write_lockfile(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let mut manifest = Manifest::load(&self.project_path)?;
+        self.features.enable(&mut manifest)?;
+
+        let lockfile = Lockfile::load(&self.project_path)?
+            .unwrap_or_else(|| Lockfile::from_manifest(&manifest));
+
+        let package_sources =
+            global.project_sources(&self.project_path, &manifest, &lockfile.git_commits())?;
+        let try_to_use = lockfile.as_ids().collect();
+
+        if self.locked {
+            let latest_graph = resolve(&manifest, &BTreeSet::new(), &package_sources)?;
+
+            if try_to_use != latest_graph.activated {
+                anyhow::bail!(render_locked_lockfile_error(
+                    &try_to_use,
+                    &latest_graph.activated
+                )?);
+            }
+        }
+
+        let resolved = resolve(&manifest, &try_to_use, &package_sources)?;
+        render_lockfile_changes(&lockfile, &resolved, &mut std::io::stdout())?;
+
+        // Nothing is downloaded, so only the checksums of packages that were
+        // already locked are known.
+        Lockfile::from_resolve(&resolved)
+            .with_checksums(&lockfile.checksums())
+            .save(&self.project_path)?;
+
+        println!(
+            "{}  Generated {}lockfile",
+            SetForegroundColor(Color::DarkGreen),
+            SetForegroundColor(Color::Reset)
+        );
+
+        Ok(())
+    }
+}
@@ -3,12 +3,14 @@ use std::path::PathBuf;
 use anyhow::bail;
 use crossterm::style::Color;
 use crossterm::style::SetForegroundColor;
-use reqwest::{blocking::Client, header::AUTHORIZATION};
+use reqwest::blocking::Client;
 use serde::Deserialize;
 use structopt::StructOpt;
 
 use crate::{auth::AuthStore, manifest::Manifest, package_index::PackageIndex};
 
+use super::GlobalOptions;
+
 /// Search a registry for packages matching a query.
 #[derive(Debug, StructOpt)]
 pub struct SearchSubcommand {
@@ -21,14 +23,14 @@ pub struct SearchSubcommand {
 }
 
 impl SearchSubcommand {
-    pub fn run(self) -> anyhow::Result<()> {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
         let manifest = Manifest::load(&self.project_path)?;
         let registry = url::Url::parse(&manifest.package.registry)?;
         let auth_store = AuthStore::load()?;
-        let package_index = PackageIndex::new(&registry, None)?;
+        let package_index = PackageIndex::new(&global.cache_dir()?, &registry, None)?;
         let api = package_index.config()?.api;
 
-        let auth = auth_store.tokens.get(api.as_str());
+        let auth = auth_store.token_for(&api);
 
         let client = Client::new();
         let mut request = client
@@ -36,7 +38,7 @@ impl SearchSubcommand {
             .query(&[("query", &self.query)]);
 
         if let Some(auth) = auth {
-            request = request.header(AUTHORIZATION, format!("Bearer {}", auth));
+            request = request.bearer_auth(auth);
         }
 
         let response = request.send()?;
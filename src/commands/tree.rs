@@ -0,0 +1,289 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::bail;
+use structopt::StructOpt;
+
+use crate::lockfile::Lockfile;
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::resolution::{resolve, Resolve};
+
+use super::GlobalOptions;
+
+/// Print the resolved dependency graph of this project as a tree.
+#[derive(Debug, StructOpt)]
+pub struct TreeSubcommand {
+    /// Path to the project to show the dependency tree of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Only show dependencies up to this many levels below the project.
+    #[structopt(long = "depth")]
+    pub depth: Option<usize>,
+
+    /// Only show the paths that lead to the package with this name, such as
+    /// `biff/minimal`.
+    #[structopt(long = "package")]
+    pub package: Option<PackageName>,
+}
+
+impl TreeSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+
+        let lockfile = Lockfile::load(&self.project_path)?
+            .unwrap_or_else(|| Lockfile::from_manifest(&manifest));
+
+        let package_sources =
+            global.project_sources(&self.project_path, &manifest, &lockfile.git_commits())?;
+
+        let try_to_use = lockfile.as_ids().collect();
+        let resolved = resolve(&manifest, &try_to_use, &package_sources)?;
+
+        let stdout = io::stdout();
+        render_tree(
+            &resolved,
+            &manifest.package_id(),
+            self.depth,
+            self.package.as_ref(),
+            &mut stdout.lock(),
+        )
+    }
+}
+
+/// Write a resolved dependency graph as an indented tree rooted at
+/// `root_package_id`.
+///
+/// Packages whose dependencies have already been shown elsewhere in the tree
+/// are marked with `(*)` instead of being expanded again. When `package` is
+/// given, only dependencies that lead to a package with that name are shown.
+pub fn render_tree(
+    resolved: &Resolve,
+    root_package_id: &PackageId,
+    depth: Option<usize>,
+    package: Option<&PackageName>,
+    output: &mut impl Write,
+) -> anyhow::Result<()> {
+    let leads_to = package.map(|name| packages_leading_to(resolved, name));
+
+    if let (Some(name), Some(leads_to)) = (package, &leads_to) {
+        if !leads_to.contains(root_package_id) {
+            bail!("{} is not part of the dependency graph", name);
+        }
+    }
+
+    writeln!(
+        output,
+        "{} ({})",
+        root_package_id, resolved.metadata[root_package_id].origin_realm
+    )?;
+
+    let mut printer = TreePrinter {
+        resolved,
+        max_depth: depth,
+        package,
+        leads_to,
+        expanded: BTreeSet::new(),
+        output,
+    };
+
+    printer.write_dependencies(root_package_id, "", 0, package.is_some())?;
+
+    Ok(())
+}
+
+struct TreePrinter<'a, W> {
+    resolved: &'a Resolve,
+    max_depth: Option<usize>,
+    package: Option<&'a PackageName>,
+    leads_to: Option<BTreeSet<PackageId>>,
+    expanded: BTreeSet<PackageId>,
+    output: W,
+}
+
+impl<W: Write> TreePrinter<'_, W> {
+    /// Write the dependencies of a package that is `depth` levels below the
+    /// root. While `filtering`, only dependencies that lead to the package
+    /// being searched for are written.
+    fn write_dependencies(
+        &mut self,
+        package_id: &PackageId,
+        prefix: &str,
+        depth: usize,
+        filtering: bool,
+    ) -> io::Result<()> {
+        let dependencies: Vec<&PackageId> = dependencies_of(self.resolved, package_id)
+            .into_iter()
+            .filter(|dependency| !filtering || self.is_on_filtered_path(dependency))
+            .collect();
+
+        let expand_dependencies = self.max_depth.is_none_or(|max| depth + 1 < max);
+
+        for (index, dependency) in dependencies.iter().enumerate() {
+            let (branch, indent) = if index == dependencies.len() - 1 {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+
+            let has_dependencies = !dependencies_of(self.resolved, dependency).is_empty();
+            let repeated = has_dependencies
+                && expand_dependencies
+                && !self.expanded.insert((*dependency).clone());
+
+            writeln!(
+                self.output,
+                "{}{}{} ({}){}",
+                prefix,
+                branch,
+                dependency,
+                self.resolved.metadata[*dependency].origin_realm,
+                if repeated { " (*)" } else { "" }
+            )?;
+
+            if expand_dependencies && !repeated {
+                let is_target = self.package == Some(dependency.name());
+
+                self.write_dependencies(
+                    dependency,
+                    &format!("{}{}", prefix, indent),
+                    depth + 1,
+                    filtering && !is_target,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_on_filtered_path(&self, package_id: &PackageId) -> bool {
+        self.leads_to
+            .as_ref()
+            .is_none_or(|leads_to| leads_to.contains(package_id))
+    }
+}
+
+/// Every package that depends on a package named `name`, directly or
+/// indirectly, along with those packages themselves.
+fn packages_leading_to(resolved: &Resolve, name: &PackageName) -> BTreeSet<PackageId> {
+    let mut leads_to: BTreeSet<PackageId> = resolved
+        .activated
+        .iter()
+        .filter(|package_id| package_id.name() == name)
+        .cloned()
+        .collect();
+
+    loop {
+        let newly_found: Vec<PackageId> = resolved
+            .activated
+            .iter()
+            .filter(|package_id| !leads_to.contains(*package_id))
+            .filter(|package_id| {
+                dependencies_of(resolved, package_id)
+                    .iter()
+                    .any(|dependency| leads_to.contains(*dependency))
+            })
+            .cloned()
+            .collect();
+
+        if newly_found.is_empty() {
+            return leads_to;
+        }
+
+        leads_to.extend(newly_found);
+    }
+}
+
+/// The direct dependencies of a package across all realms, in a stable order.
+fn dependencies_of<'a>(resolved: &'a Resolve, package_id: &PackageId) -> BTreeSet<&'a PackageId> {
+    [
+        &resolved.shared_dependencies,
+        &resolved.server_dependencies,
+        &resolved.dev_dependencies,
+    ]
+    .iter()
+    .filter_map(|graph| graph.get(package_id))
+    .flat_map(|dependencies| dependencies.values())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use crate::manifest::Realm;
+    use crate::package_source::PackageSourceId;
+    use crate::resolution::ResolvePackageMetadata;
+
+    /// A graph where the root depends on `a` and `b`, which both depend on
+    /// `c`, which depends on `d`.
+    fn diamond() -> (PackageId, Resolve) {
+        let id = |spec: &str| PackageId::from_str(spec).unwrap();
+        let root = id("biff/root@0.1.0");
+
+        let mut resolved = Resolve::default();
+        for (package, dependencies) in [
+            ("biff/root@0.1.0", vec!["biff/a@0.1.0", "biff/b@0.1.0"]),
+            ("biff/a@0.1.0", vec!["biff/c@0.1.0"]),
+            ("biff/b@0.1.0", vec!["biff/c@0.1.0"]),
+            ("biff/c@0.1.0", vec!["biff/d@0.1.0"]),
+            ("biff/d@0.1.0", vec![]),
+        ] {
+            resolved.activated.insert(id(package));
+            resolved.metadata.insert(
+                id(package),
+                ResolvePackageMetadata {
+                    realm: Realm::Shared,
+                    origin_realm: Realm::Shared,
+                    source_registry: PackageSourceId::DefaultRegistry,
+                    git_commit: None,
+                    checksum: None,
+                },
+            );
+            resolved.shared_dependencies.insert(
+                id(package),
+                dependencies
+                    .into_iter()
+                    .map(|dependency| (dependency.to_owned(), id(dependency)))
+                    .collect(),
+            );
+        }
+
+        (root, resolved)
+    }
+
+    fn render(depth: Option<usize>, package: Option<&str>) -> anyhow::Result<String> {
+        let (root, resolved) = diamond();
+        let package = package.map(|name| PackageName::from_str(name).unwrap());
+
+        let mut output = Vec::new();
+        render_tree(&resolved, &root, depth, package.as_ref(), &mut output)?;
+
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn repeated_subtrees_are_marked() {
+        insta::assert_snapshot!(render(None, None).unwrap());
+    }
+
+    #[test]
+    fn depth_limits_the_tree() {
+        insta::assert_snapshot!(render(Some(1), None).unwrap());
+    }
+
+    #[test]
+    fn package_filter_only_shows_paths_to_the_package() {
+        insta::assert_snapshot!(render(None, Some("biff/a")).unwrap());
+    }
+
+    #[test]
+    fn package_filter_fails_for_missing_packages() {
+        assert!(render(None, Some("biff/missing")).is_err());
+    }
+}
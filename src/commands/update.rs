@@ -1,19 +1,18 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::installation::InstallationContext;
-use crate::lockfile::Lockfile;
-use crate::manifest::Manifest;
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::{GitSpec, Manifest};
 use crate::package_id::PackageId;
 use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
-use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
-use crate::{resolution, GlobalOptions};
+use crate::{resolution, FeatureOptions, GlobalOptions};
 use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
-use indicatif::{ProgressBar, ProgressStyle};
 use structopt::StructOpt;
 
 use super::utils::{generate_dependency_changes, render_update_difference};
@@ -28,38 +27,54 @@ pub struct UpdateSubcommand {
     /// An optional list of dependencies to update.
     /// They must be valid package name with an optional version requirement.
     pub package_specs: Vec<PackageSpec>,
+
+    #[structopt(flatten)]
+    pub features: FeatureOptions,
 }
 
 impl UpdateSubcommand {
     pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
+        let mut manifest = Manifest::load(&self.project_path)?;
+        self.features.enable(&mut manifest)?;
 
         let lockfile = match Lockfile::load(&self.project_path)? {
             Some(lockfile) => lockfile,
             None => Lockfile::from_manifest(&manifest),
         };
 
-        let default_registry: Box<PackageSource> = if global.test_registry {
-            Box::new(PackageSource::TestRegistry(TestRegistry::new(
-                &manifest.package.registry,
-            )))
+        // Git packages that are being updated are fetched again at the latest
+        // commit of their rev, while the rest stay at their locked commit.
+        let locked_commits: HashMap<GitSpec, String> = if self.package_specs.is_empty() {
+            HashMap::new()
         } else {
-            Box::new(PackageSource::Registry(Registry::from_registry_spec(
-                &manifest.package.registry,
-            )?))
+            lockfile
+                .packages
+                .iter()
+                .filter_map(|lock_package| match lock_package {
+                    LockPackage::Git(lock_package)
+                        if !self
+                            .given_package_id_satisifies_targets(&lock_package.package_id()) =>
+                    {
+                        Some((lock_package.spec(), lock_package.commit.clone()))
+                    }
+                    _ => None,
+                })
+                .collect()
         };
 
-        let mut package_sources = PackageSourceMap::new(default_registry);
-        package_sources.add_fallbacks()?;
+        let package_sources =
+            global.project_sources(&self.project_path, &manifest, &locked_commits)?;
 
         // If the user didn't specify any targets, then update all of the packages.
         // Otherwise, find the target packages to update.
         let try_to_use = if self.package_specs.is_empty() {
-            println!(
-                "{}   Selected {} all dependencies to try update",
-                SetForegroundColor(Color::DarkGreen),
-                SetForegroundColor(Color::Reset)
-            );
+            if !global.quiet {
+                println!(
+                    "{}   Selected {} all dependencies to try update",
+                    SetForegroundColor(Color::DarkGreen),
+                    SetForegroundColor(Color::Reset)
+                );
+            }
 
             BTreeSet::new()
         } else {
@@ -69,25 +84,23 @@ impl UpdateSubcommand {
                 .filter(|package_id| !self.given_package_id_satisifies_targets(package_id))
                 .collect();
 
-            println!(
-                "{}   Selected {}{} dependencies to try update",
-                SetForegroundColor(Color::DarkGreen),
-                SetForegroundColor(Color::Reset),
-                lockfile.packages.len() - try_to_use.len(),
-            );
+            if !global.quiet {
+                println!(
+                    "{}   Selected {}{} dependencies to try update",
+                    SetForegroundColor(Color::DarkGreen),
+                    SetForegroundColor(Color::Reset),
+                    lockfile.packages.len() - try_to_use.len(),
+                );
+            }
 
             try_to_use
         };
 
-        let progress = ProgressBar::new(0)
-            .with_style(
-                ProgressStyle::with_template("{spinner:.cyan}{wide_msg}")?.tick_chars("⠁⠈⠐⠠⠄⠂ "),
-            )
-            .with_message(format!(
-                "{} Resolving {}new dependencies...",
-                SetForegroundColor(Color::DarkGreen),
-                SetForegroundColor(Color::Reset)
-            ));
+        let progress = global.progress_bar()?.with_message(format!(
+            "{} Resolving {}new dependencies...",
+            SetForegroundColor(Color::DarkGreen),
+            SetForegroundColor(Color::Reset)
+        ));
 
         let resolved_graph = resolution::resolve(&manifest, &try_to_use, &package_sources)?;
 
@@ -98,29 +111,27 @@ impl UpdateSubcommand {
             resolved_graph.activated.len() - 1
         ));
 
-        progress.enable_steady_tick(Duration::from_millis(100));
-        progress.suspend(|| {
-            let dependency_changes = generate_dependency_changes(
-                &lockfile.as_ids().collect(),
-                &resolved_graph.activated,
-            );
-            render_update_difference(&dependency_changes, &mut std::io::stdout()).unwrap();
-        });
-
-        Lockfile::from_resolve(&resolved_graph).save(&self.project_path)?;
+        if !progress.is_hidden() {
+            progress.enable_steady_tick(Duration::from_millis(100));
+            progress.suspend(|| {
+                let dependency_changes = generate_dependency_changes(
+                    &lockfile.as_ids().collect(),
+                    &resolved_graph.activated,
+                );
+                render_update_difference(&dependency_changes, &mut std::io::stdout()).unwrap();
+            });
+        }
 
-        progress.println(format!(
-            "{}    Updated {}lockfile",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
+        let new_lockfile = Lockfile::from_resolve(&resolved_graph);
 
         let root_package_id = manifest.package_id();
-        let installation_context = InstallationContext::new(
-            &self.project_path,
-            manifest.place.shared_packages,
-            manifest.place.server_packages,
-        );
+        let installation_context = InstallationContext::new(&self.project_path, &manifest.place)
+            .with_concurrency(global.concurrency)
+            .with_package_cache(global.package_cache()?)
+            .with_link_cache(global.link_cache)
+            .with_type_cache(global.type_cache()?)
+            .with_download_retries(global.retries)
+            .with_locked_checksums(lockfile.checksums());
 
         progress.set_message(format!(
             "{}  Cleaning {}package destination...",
@@ -143,7 +154,24 @@ impl UpdateSubcommand {
             SetForegroundColor(Color::Reset)
         ));
 
-        installation_context.install(package_sources, root_package_id, resolved_graph)?;
+        let observer = Arc::new(global.install_observer());
+        let checksums = installation_context
+            .with_observer(observer.clone())
+            .install(package_sources, root_package_id, resolved_graph);
+        observer.finish();
+        let checksums = checksums?;
+
+        new_lockfile
+            .with_checksums(&checksums)
+            .save(&self.project_path)?;
+
+        if !global.quiet {
+            println!(
+                "{}    Updated {}lockfile",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            );
+        }
 
         Ok(())
     }
@@ -1,5 +1,10 @@
-use crate::{package_id::PackageId, package_name::PackageName};
-use crossterm::style::{Color, SetForegroundColor};
+use crate::{
+    lockfile::Lockfile,
+    package_id::PackageId,
+    package_name::PackageName,
+    resolution::{compatible, Resolve, ResolveError},
+};
+use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
 use serde::Serialize;
 use std::{collections::BTreeSet, io::Write};
 
@@ -32,19 +37,42 @@ pub(crate) fn generate_dependency_changes(
         let mut new_matches = new_dependencies.iter().filter(match_package_ids_by_name);
         let total_new_matches = new_matches.clone().count();
 
-        // If there's more than one new or old matches, then we do the simple route of listing the exact versions removed/added.
+        // If there's more than one new or old matches, only versions that are
+        // compatible with each other can be told apart as one copy of the
+        // package changing. Every other version is listed as removed/added.
         if total_new_matches > 1 || total_old_matches > 1 {
-            dependency
-                .extend(old_matches.map(|package| DependencyChange::Removed(package.clone())));
-            dependency.extend(new_matches.map(|package| DependencyChange::Added(package.clone())));
+            let mut unpaired_new: Vec<&PackageId> = new_matches.collect();
+            let mut unpaired_old = Vec::new();
+
+            for old in old_matches {
+                let paired = unpaired_new
+                    .iter()
+                    .position(|new| compatible(old.version(), new.version()));
+
+                match paired {
+                    Some(index) => {
+                        let new = unpaired_new.remove(index);
+                        dependency.push(version_change(old.clone(), new.clone()));
+                    }
+                    None => unpaired_old.push(old),
+                }
+            }
+
+            dependency.extend(
+                unpaired_old
+                    .into_iter()
+                    .map(|package| DependencyChange::Removed(package.clone())),
+            );
+            dependency.extend(
+                unpaired_new
+                    .into_iter()
+                    .map(|package| DependencyChange::Added(package.clone())),
+            );
         } else {
             // Otherwise, we can try being more specific about what changed.
             dependency.push(
                 match (old_matches.next().cloned(), new_matches.next().cloned()) {
-                    (Some(old), Some(new)) if old.le(&new) => {
-                        DependencyChange::Upgraded { from: old, to: new }
-                    }
-                    (Some(old), Some(new)) => DependencyChange::Downgraded { from: old, to: new },
+                    (Some(old), Some(new)) => version_change(old, new),
 
                     // Or, there's been a singular removal/addition.
                     (Some(old), None) => DependencyChange::Removed(old),
@@ -62,6 +90,14 @@ pub(crate) fn generate_dependency_changes(
     dependency
 }
 
+fn version_change(from: PackageId, to: PackageId) -> DependencyChange {
+    if from.le(&to) {
+        DependencyChange::Upgraded { from, to }
+    } else {
+        DependencyChange::Downgraded { from, to }
+    }
+}
+
 pub(crate) fn render_update_difference(
     dependency_changes: &[DependencyChange],
     writer: &mut impl Write,
@@ -126,11 +162,93 @@ pub(crate) fn render_update_difference(
     Ok(())
 }
 
+/// Describe how a lockfile that was required to be up-to-date with `--locked`
+/// differs from the latest resolve, suitable for returning as an error.
+pub(crate) fn render_locked_lockfile_error(
+    old_dependencies: &BTreeSet<PackageId>,
+    new_dependencies: &BTreeSet<PackageId>,
+) -> anyhow::Result<String> {
+    let changes = generate_dependency_changes(old_dependencies, new_dependencies);
+    let mut error_output = Vec::new();
+
+    writeln!(
+        error_output,
+        "{} The Lockfile is out of date and wasn't changed due to --locked{}",
+        SetForegroundColor(Color::Yellow),
+        SetForegroundColor(Color::Reset)
+    )?;
+
+    render_update_difference(&changes, &mut error_output)?;
+
+    writeln!(
+        error_output,
+        "{}{} Suggestion{}{} try running wally update",
+        SetAttribute(Attribute::Bold),
+        SetForegroundColor(Color::DarkGreen),
+        SetForegroundColor(Color::Reset),
+        SetAttribute(Attribute::Reset)
+    )?;
+
+    Ok(String::from_utf8(error_output)
+        .expect("output from render_update_difference should always be utf-8"))
+}
+
+/// Render the changes between a previously saved lockfile and a newly resolved
+/// graph. Nothing is written if the graph is unchanged.
+pub(crate) fn render_lockfile_changes(
+    lockfile: &Lockfile,
+    resolved: &Resolve,
+    writer: &mut impl Write,
+) -> anyhow::Result<()> {
+    let changes = generate_dependency_changes(&lockfile.as_ids().collect(), &resolved.activated);
+
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    render_update_difference(&changes, writer)
+}
+
+/// Write an error as a single line of JSON. Resolution errors keep their
+/// structure so tools can tell which package conflicted and why; any other
+/// error is written as its message.
+pub(crate) fn render_error_json(
+    err: &anyhow::Error,
+    writer: &mut impl Write,
+) -> anyhow::Result<()> {
+    let resolve_error = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ResolveError>());
+
+    match resolve_error {
+        Some(resolve_error) => serde_json::to_writer(&mut *writer, resolve_error)?,
+        None => serde_json::to_writer(
+            &mut *writer,
+            &serde_json::json!({
+                "kind": "error",
+                "message": format!("{:#}", err),
+            }),
+        )?,
+    }
+
+    writeln!(writer)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
-    use std::{collections::BTreeSet, str::FromStr};
-
-    use super::{generate_dependency_changes, render_update_difference};
+    use std::{collections::BTreeSet, path::Path, str::FromStr};
+
+    use super::{
+        generate_dependency_changes, render_error_json, render_lockfile_changes,
+        render_update_difference,
+    };
+    use crate::{
+        lockfile::Lockfile,
+        manifest::Manifest,
+        package_source::{PackageSource, PackageSourceMap, TestRegistry},
+        resolution::resolve,
+    };
     use insta::assert_snapshot;
 
     macro_rules! package_id {
@@ -275,6 +393,35 @@ mod test {
         )
     }
 
+    #[test]
+    fn pair_compatible_versions_if_multiple() {
+        let old_dependencies = BTreeSet::from([
+            package_id!("biff/package-a@0.1.0"),
+            package_id!("biff/package-a@0.2.0"),
+            package_id!("biff/package-a@1.0.0"),
+        ]);
+
+        let new_dependencies = BTreeSet::from([
+            package_id!("biff/package-a@0.1.1"),
+            package_id!("biff/package-a@0.2.1"),
+            package_id!("biff/package-a@2.0.0"),
+        ]);
+
+        let changes = generate_dependency_changes(&old_dependencies, &new_dependencies);
+
+        assert!(changes.len() == 4, "Expected four changes.");
+        assert!(
+            upgraded_change!(changes, "biff/package-a@0.1.0", "biff/package-a@0.1.1")
+                && upgraded_change!(changes, "biff/package-a@0.2.0", "biff/package-a@0.2.1"),
+            "Expected compatible versions to be paired up."
+        );
+        assert!(
+            removed_change!(changes, "biff/package-a@1.0.0")
+                && added_change!(changes, "biff/package-a@2.0.0"),
+            "Expected incompatible versions to be decomposed."
+        );
+    }
+
     #[test]
     fn snapshot_output_when_no_changes() {
         let changes = Vec::new();
@@ -284,4 +431,71 @@ mod test {
 
         assert_snapshot!(String::from_utf8(writer).unwrap());
     }
+
+    #[test]
+    fn lockfile_changes_list_version_bump() {
+        let project_path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-projects/diamond-graph/root/dated"
+        ));
+
+        let mut manifest = Manifest::load(project_path).unwrap();
+        manifest.server_dependencies.insert(
+            "A".to_owned(),
+            "diamond-graph/direct-dependency-a@0.1.1".parse().unwrap(),
+        );
+        let lockfile = Lockfile::load(project_path).unwrap().unwrap();
+
+        let package_sources = PackageSourceMap::new(Box::new(PackageSource::TestRegistry(
+            TestRegistry::new(&manifest.package.registry),
+        )));
+        let resolved = resolve(&manifest, &lockfile.as_ids().collect(), &package_sources).unwrap();
+
+        let mut writer = Vec::new();
+        render_lockfile_changes(&lockfile, &resolved, &mut writer).unwrap();
+        let output = String::from_utf8(writer).unwrap();
+
+        assert!(
+            output.contains("diamond-graph/direct-dependency-a from v0.1.0 to v0.1.1"),
+            "Expected a version bump in the summary. Instead we got: {}",
+            output
+        );
+    }
+
+    #[test]
+    fn lockfile_changes_empty_when_unchanged() {
+        let project_path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-projects/diamond-graph/root/dated"
+        ));
+
+        let manifest = Manifest::load(project_path).unwrap();
+        let lockfile = Lockfile::load(project_path).unwrap().unwrap();
+
+        let package_sources = PackageSourceMap::new(Box::new(PackageSource::TestRegistry(
+            TestRegistry::new(&manifest.package.registry),
+        )));
+        let resolved = resolve(&manifest, &lockfile.as_ids().collect(), &package_sources).unwrap();
+
+        let mut writer = Vec::new();
+        render_lockfile_changes(&lockfile, &resolved, &mut writer).unwrap();
+
+        assert!(
+            writer.is_empty(),
+            "Expected no output for an unchanged lockfile."
+        );
+    }
+
+    #[test]
+    fn other_errors_as_json() {
+        let err = anyhow::format_err!("the registry is down").context("Failed to update index");
+
+        let mut writer = Vec::new();
+        render_error_json(&err, &mut writer).unwrap();
+
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "{\"kind\":\"error\",\"message\":\"Failed to update index: the registry is down\"}\n"
+        );
+    }
 }
@@ -0,0 +1,224 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use crossterm::style::{Color, SetForegroundColor};
+use fs_err as fs;
+use structopt::StructOpt;
+
+use crate::manifest::{find_problems, MANIFEST_FILE_NAME};
+
+/// Check that this project's wally.toml is valid, reporting every problem
+/// with it, without accessing the network.
+#[derive(Debug, StructOpt)]
+pub struct VerifyManifestSubcommand {
+    /// Path to the project to check the manifest of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+}
+
+impl VerifyManifestSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let manifest_path = self.project_path.join(MANIFEST_FILE_NAME);
+        let content = fs::read_to_string(&manifest_path)
+            .with_context(|| format!("could not read {}", manifest_path.display()))?;
+
+        let problems = find_problems(&content);
+        if !problems.is_empty() {
+            bail!(render_problems(
+                &manifest_path.display().to_string(),
+                &problems
+            ));
+        }
+
+        println!(
+            "{}  Verified {}{}",
+            SetForegroundColor(Color::DarkGreen),
+            SetForegroundColor(Color::Reset),
+            manifest_path.display()
+        );
+
+        Ok(())
+    }
+}
+
+/// Describe every problem found in a manifest, suitable for returning as an
+/// error.
+fn render_problems(manifest_path: &str, problems: &[String]) -> String {
+    let mut output = format!("{} has {} problems:", manifest_path, problems.len());
+
+    for problem in problems {
+        output.push_str("\n    ");
+        output.push_str(problem);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependency_in_two_realms() {
+        let problems = find_problems(
+            r#"
+            [package]
+            name = "biff/two-realms"
+            version = "0.1.0"
+            registry = "test-registries/primary-registry"
+            realm = "server"
+
+            [dependencies]
+            Minimal = "biff/minimal@0.1.0"
+
+            [server-dependencies]
+            Minimal = "biff/minimal@0.1.0"
+            "#,
+        );
+
+        assert_eq!(
+            problems,
+            vec!["Minimal is declared in both [dependencies] and [server-dependencies]"]
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let problems = find_problems(
+            r#"
+            [package]
+            name = "biff/broken"
+            version = "0.1"
+            registry = "test-registries/primary-registry"
+            realm = "shared"
+
+            [dependencies]
+            Minimal = "biff/minimal"
+            Server = { package = "biff/server@0.1.0", realm = "client" }
+
+            [dev-dependencies]
+            TestEZ = "roblox/testez@0.4.1"
+
+            [features]
+            testing = ["TestEZ"]
+            "#,
+        );
+
+        insta::assert_snapshot!(render_problems("wally.toml", &problems));
+    }
+
+    #[test]
+    fn malformed_place_path() {
+        let manifest = |place: &str| {
+            format!(
+                "[package]\n\
+                 name = \"biff/place\"\n\
+                 version = \"0.1.0\"\n\
+                 registry = \"test-registries/primary-registry\"\n\
+                 realm = \"shared\"\n\
+                 [place]\n\
+                 shared-packages = {:?}\n",
+                place
+            )
+        };
+
+        for valid in [
+            "game.ReplicatedStorage.Packages",
+            "game:GetService(\"ReplicatedStorage\").Packages",
+            "script.Parent['My Packages']",
+            "game.ReplicatedStorage:WaitForChild(\"Packages\")",
+        ] {
+            assert_eq!(
+                find_problems(&manifest(valid)),
+                Vec::<String>::new(),
+                "{}",
+                valid
+            );
+        }
+
+        assert_eq!(
+            find_problems(&manifest("game.ReplicatedStorage.Packages;")),
+            vec![
+                "[place] shared-packages = \"game.ReplicatedStorage.Packages;\" is not a path to \
+                 a Roblox instance: unexpected `;` at character 32. Expected something like \
+                 \"game.ReplicatedStorage.Packages\""
+            ]
+        );
+
+        for invalid in [
+            "",
+            "game.",
+            "game..Packages",
+            "game.ReplicatedStorage Packages",
+            "game[\"Packages]",
+            "game:GetService(ReplicatedStorage)",
+            "require(game.Packages)",
+            "1game.Packages",
+        ] {
+            assert_eq!(find_problems(&manifest(invalid)).len(), 1, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn unsafe_packages_dirs() {
+        let manifest = |place: &str| {
+            format!(
+                "[package]\n\
+                 name = \"biff/place\"\n\
+                 version = \"0.1.0\"\n\
+                 registry = \"test-registries/primary-registry\"\n\
+                 realm = \"shared\"\n\
+                 [place]\n\
+                 {}\n",
+                place
+            )
+        };
+
+        for invalid in [".", "..", "/abs", "a/b", "a\\b", "", "_Index"] {
+            let place = format!("shared-packages-dir = {:?}", invalid);
+            assert_eq!(find_problems(&manifest(&place)).len(), 1, "{}", invalid);
+        }
+
+        assert_eq!(
+            find_problems(&manifest("shared-packages-dir = \"..\"")),
+            vec![
+                "[place] shared-packages-dir = \"..\" is not a folder in the project: it must be \
+                 relative to the project and can't be `.` or `..`. Expected the name of a single \
+                 folder, like \"Packages\""
+            ]
+        );
+
+        // The defaults count too, and so do names that only differ by case.
+        assert_eq!(
+            find_problems(&manifest("server-packages-dir = \"packages\"")),
+            vec![
+                "[place] shared-packages-dir and server-packages-dir are both \"packages\", so \
+                 installing packages into one would remove the packages in the other"
+            ]
+        );
+        assert_eq!(
+            find_problems(&manifest(
+                "shared-packages-dir = \"Deps\"\ndev-packages-dir = \"Deps\""
+            ))
+            .len(),
+            1
+        );
+        assert_eq!(
+            find_problems(&manifest(
+                "shared-packages-dir = \"Deps\"\nserver-packages-dir = \"ServerDeps\""
+            )),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn valid_manifest_has_no_problems() {
+        let content = fs::read_to_string(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-projects/optional-dependency/wally.toml"
+        ))
+        .unwrap();
+
+        assert_eq!(find_problems(&content), Vec::<String>::new());
+    }
+}
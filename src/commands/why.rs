@@ -0,0 +1,258 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::bail;
+use structopt::StructOpt;
+
+use crate::lockfile::Lockfile;
+use crate::manifest::{Manifest, Realm};
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::resolution::{resolve, resolve_from_lockfile, Resolve};
+
+use super::GlobalOptions;
+
+/// Explain why a package is part of this project's dependency graph.
+#[derive(Debug, StructOpt)]
+pub struct WhySubcommand {
+    /// Path to the project to explain the dependencies of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The package to explain, such as `biff/minimal`.
+    pub package: PackageName,
+}
+
+impl WhySubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+        let lockfile = Lockfile::load(&self.project_path)?;
+
+        // An up-to-date lockfile already describes the whole graph, so the
+        // registry only needs to be consulted when there isn't one.
+        let locked = lockfile
+            .as_ref()
+            .and_then(|lockfile| resolve_from_lockfile(&manifest, lockfile).ok());
+
+        let resolved = match locked {
+            Some(resolved) => resolved,
+            None => {
+                let lockfile = lockfile.unwrap_or_else(|| Lockfile::from_manifest(&manifest));
+
+                let package_sources = global.project_sources(
+                    &self.project_path,
+                    &manifest,
+                    &lockfile.git_commits(),
+                )?;
+
+                let try_to_use = lockfile.as_ids().collect();
+                resolve(&manifest, &try_to_use, &package_sources)?
+            }
+        };
+
+        let stdout = io::stdout();
+        render_why(
+            &resolved,
+            &manifest.package_id(),
+            &self.package,
+            &mut stdout.lock(),
+        )
+    }
+}
+
+/// One step along a path through the dependency graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyStep {
+    /// The name that the previous package in the path gave this dependency.
+    pub alias: String,
+
+    /// The realm of the dependency table this dependency was declared in.
+    pub dependency_realm: Realm,
+
+    pub package_id: PackageId,
+}
+
+/// Find every path from `root_package_id` to a package named `name`. Each
+/// path lists the dependencies that were followed, starting just after the
+/// root and ending at the package.
+pub fn find_dependency_paths(
+    resolved: &Resolve,
+    root_package_id: &PackageId,
+    name: &PackageName,
+) -> Vec<Vec<DependencyStep>> {
+    let mut paths = Vec::new();
+    let mut current = Vec::new();
+
+    collect_paths(resolved, root_package_id, name, &mut current, &mut paths);
+
+    paths
+}
+
+fn collect_paths(
+    resolved: &Resolve,
+    package_id: &PackageId,
+    name: &PackageName,
+    current: &mut Vec<DependencyStep>,
+    paths: &mut Vec<Vec<DependencyStep>>,
+) {
+    for (dependency_realm, graph) in [
+        (Realm::Shared, &resolved.shared_dependencies),
+        (Realm::Server, &resolved.server_dependencies),
+        (Realm::Dev, &resolved.dev_dependencies),
+    ] {
+        let dependencies = match graph.get(package_id) {
+            Some(dependencies) => dependencies,
+            None => continue,
+        };
+
+        for (alias, dependency) in dependencies {
+            // The graph shouldn't contain cycles, but guard against them so a
+            // bad resolve can't recurse forever.
+            if current.iter().any(|step| &step.package_id == dependency) {
+                continue;
+            }
+
+            current.push(DependencyStep {
+                alias: alias.clone(),
+                dependency_realm,
+                package_id: dependency.clone(),
+            });
+
+            if dependency.name() == name {
+                paths.push(current.clone());
+            } else {
+                collect_paths(resolved, dependency, name, current, paths);
+            }
+
+            current.pop();
+        }
+    }
+}
+
+/// Write every path from the root package to the packages named `name`,
+/// showing the realm of each package and which dependency table pulled it in.
+pub fn render_why(
+    resolved: &Resolve,
+    root_package_id: &PackageId,
+    name: &PackageName,
+    output: &mut impl Write,
+) -> anyhow::Result<()> {
+    let paths = find_dependency_paths(resolved, root_package_id, name);
+
+    if paths.is_empty() {
+        bail!("{} is not part of the dependency graph", name);
+    }
+
+    for (index, path) in paths.iter().enumerate() {
+        if index > 0 {
+            writeln!(output)?;
+        }
+
+        writeln!(
+            output,
+            "{} ({})",
+            root_package_id, resolved.metadata[root_package_id].realm
+        )?;
+
+        for (depth, step) in path.iter().enumerate() {
+            writeln!(
+                output,
+                "{}└── {} = {} ({}, via {})",
+                "    ".repeat(depth),
+                step.alias,
+                step.package_id,
+                resolved.metadata[&step.package_id].realm,
+                dependency_table_name(step.dependency_realm)
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn dependency_table_name(realm: Realm) -> &'static str {
+    match realm {
+        Realm::Shared => "dependencies",
+        Realm::Server => "server-dependencies",
+        Realm::Dev => "dev-dependencies",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use crate::package_source::PackageSourceId;
+    use crate::resolution::ResolvePackageMetadata;
+
+    /// A server package with a shared dependency on `a` and a dev dependency on
+    /// `b`, which both depend on the shared package `c`.
+    fn graph() -> (PackageId, Resolve) {
+        let id = |spec: &str| PackageId::from_str(spec).unwrap();
+        let root = id("biff/root@0.1.0");
+
+        let mut resolved = Resolve::default();
+        for (package, realm) in [
+            ("biff/root@0.1.0", Realm::Server),
+            ("biff/a@0.1.0", Realm::Shared),
+            ("biff/b@0.1.0", Realm::Server),
+            ("biff/c@0.1.0", Realm::Shared),
+        ] {
+            resolved.activated.insert(id(package));
+            resolved.metadata.insert(
+                id(package),
+                ResolvePackageMetadata {
+                    realm,
+                    origin_realm: realm,
+                    source_registry: PackageSourceId::DefaultRegistry,
+                    git_commit: None,
+                    checksum: None,
+                },
+            );
+        }
+
+        let edges = |edges: &[(&str, &str)]| {
+            edges
+                .iter()
+                .map(|(alias, dependency)| (alias.to_string(), id(dependency)))
+                .collect()
+        };
+
+        resolved
+            .shared_dependencies
+            .insert(root.clone(), edges(&[("A", "biff/a@0.1.0")]));
+        resolved
+            .dev_dependencies
+            .insert(root.clone(), edges(&[("B", "biff/b@0.1.0")]));
+        resolved
+            .shared_dependencies
+            .insert(id("biff/a@0.1.0"), edges(&[("C", "biff/c@0.1.0")]));
+        resolved
+            .shared_dependencies
+            .insert(id("biff/b@0.1.0"), edges(&[("C", "biff/c@0.1.0")]));
+
+        (root, resolved)
+    }
+
+    #[test]
+    fn every_path_is_shown() {
+        let (root, resolved) = graph();
+        let name = PackageName::from_str("biff/c").unwrap();
+
+        let mut output = Vec::new();
+        render_why(&resolved, &root, &name, &mut output).unwrap();
+
+        insta::assert_snapshot!(String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn missing_packages_are_an_error() {
+        let (root, resolved) = graph();
+        let name = PackageName::from_str("biff/missing").unwrap();
+
+        let mut output = Vec::new();
+        assert!(render_why(&resolved, &root, &name, &mut output).is_err());
+    }
+}
@@ -1,8 +1,14 @@
 use std::collections::BTreeSet;
-use std::fs;
-use std::path::PathBuf;
-use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
 use std::mem::take;
+use std::ops::Range;
+
+use crate::install_sink::{FilesystemSink, InstallSink};
+use crate::manifest::MANIFEST_FILE_NAME;
+use crate::type_cache::TypeCache;
 
 #[derive(Deserialize)]
 struct ProjectFile {
@@ -15,11 +21,32 @@ struct Tree {
     path: String,
 }
 
+#[derive(Deserialize)]
+struct ManifestHint {
+    package: Option<PackageHint>,
+}
+
+#[derive(Deserialize)]
+struct PackageHint {
+    lib: Option<String>,
+}
+
+/// The character that starts at byte `at`. Lua source often has non-ASCII
+/// text in its strings and comments, so indices are always kept on character
+/// boundaries by stepping over each character's full length. Returns '\0' past
+/// the end of the code.
 fn get(code: &str, at: usize) -> char {
-    if at >= code.len() {
-        return '\0';
-    }
-    code.as_bytes()[at] as char
+    code.get(at..)
+        .and_then(|rest| rest.chars().next())
+        .unwrap_or('\0')
+}
+
+/// The character that ends just before byte `at`, or '\0' at the start of the
+/// code.
+fn get_before(code: &str, at: usize) -> char {
+    code.get(..at)
+        .and_then(|before| before.chars().next_back())
+        .unwrap_or('\0')
 }
 
 fn is_end_of_block(code: &str, at: usize, level: usize) -> bool {
@@ -50,14 +77,39 @@ enum LexState {
     BlockComment(usize), // --[=[ ]=]
 }
 
+/// The code after anything at the start of a file that isn't Lua: a byte
+/// order mark, which editors on Windows often save files with, and a shebang
+/// line, which Luau skips.
+fn without_preamble(lua_code: &str) -> &str {
+    let lua_code = lua_code.strip_prefix('\u{feff}').unwrap_or(lua_code);
+
+    if lua_code.starts_with("#!") {
+        lua_code.find('\n').map_or("", |end| &lua_code[end..])
+    } else {
+        lua_code
+    }
+}
+
 fn strip_comments_and_strings(lua_code: &str) -> String {
-    // Buffer to write out to charater by character
-    let mut output = String::new();
+    code_ranges(lua_code)
+        .into_iter()
+        .map(|range| &lua_code[range])
+        .collect()
+}
+
+/// The byte ranges of `lua_code` that are code, leaving out its comments,
+/// strings and preamble, so that code can be searched without matching text
+/// that only looks like it.
+pub(crate) fn code_ranges(lua_code: &str) -> Vec<Range<usize>> {
+    let offset = lua_code.len() - without_preamble(lua_code).len();
+    let lua_code = &lua_code[offset..];
+
+    let mut ranges: Vec<Range<usize>> = Vec::new();
     let mut index = 0;
     let mut state = LexState::Code;
     while index < lua_code.len() {
         let c = get(lua_code, index);
-        let peek = get(lua_code, index + 1);
+        let peek = get(lua_code, index + c.len_utf8());
         match (state.clone(), c, peek) {
             // Checks to enter one of the states to ignore
             (LexState::Code, '`', _) => {
@@ -107,8 +159,12 @@ fn strip_comments_and_strings(lua_code: &str) -> String {
 
             // Useful contents to not ignore
             (LexState::Code, _, _) => {
-                output.push(c);
-                index += 1;
+                let end = index + c.len_utf8();
+                match ranges.last_mut() {
+                    Some(range) if range.end == offset + index => range.end = offset + end,
+                    _ => ranges.push(offset + index..offset + end),
+                }
+                index = end;
             }
 
             // Template string
@@ -165,19 +221,20 @@ fn strip_comments_and_strings(lua_code: &str) -> String {
 
             // Other character to ignore
             (_, _, _) => {
-                index += 1;
+                index += c.len_utf8();
             }
         }
     }
 
-    output
+    ranges
 }
 
 #[derive(Clone, PartialEq, Debug)]
 enum ParseState {
     Code, // expect "export"
     Export, // expect "type"
-    Type, // expect type name
+    Type, // expect type name, or "function"
+    TypeFunction, // expect type function name
     StartTypeParamList, // optionally expect '<'
     TypeParam, // expect type param name
     TypePack, // optionally expect "..."
@@ -186,7 +243,7 @@ enum ParseState {
     NextTypeParam, // optionally expect ',' or '>'
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TypeParam {
     name: String,
     is_pack: bool,
@@ -201,6 +258,21 @@ impl TypeParam {
             default: None,
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether this is a type pack, written `T...`.
+    pub fn is_pack(&self) -> bool {
+        self.is_pack
+    }
+
+    /// The type used when the parameter isn't given, as it's written in the
+    /// module. Defaults naming types the module doesn't export are left out.
+    pub fn default(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
 }
 
 impl Default for TypeParam {
@@ -209,9 +281,11 @@ impl Default for TypeParam {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct ExportStatement {
     name: String,
     is_exported: bool,
+    is_function: bool,
     type_params: Vec<TypeParam>,
 }
 
@@ -220,12 +294,34 @@ impl ExportStatement {
         ExportStatement {
             name: String::new(),
             is_exported: false,
+            is_function: false,
             type_params: Vec::new(),
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether this is a type function, which can't be forwarded.
+    pub fn is_function(&self) -> bool {
+        self.is_function
+    }
+
+    pub fn type_params(&self) -> &[TypeParam] {
+        &self.type_params
+    }
+
+    /// A statement that re-exports this type from `module_name`, which is
+    /// written in front of the type's name as-is. Callers choose how the type
+    /// is reached, whether through a local holding the required module or a
+    /// longer dotted path like `MODULE.Types`.
     pub fn to_forwarding_statement(&self, module_name: &str) -> String {
-        if self.type_params.len() == 0 {
+        if self.is_function {
+            // Type functions can't be referenced through a module, so the best
+            // we can do is let the user know why the type is missing.
+            format!("-- type function {} cannot be forwarded", self.name)
+        } else if self.type_params.len() == 0 {
             format!("export type {} = {}.{}", self.name, module_name, self.name)
         } else {
             let params: Vec<String> = self.type_params.iter().map(|param| {
@@ -257,17 +353,77 @@ impl Default for ExportStatement {
     }
 }
 
+/// A type parameter default that was dropped from an exported type because
+/// it names a type the module doesn't export, so links can't refer to it.
+#[derive(Serialize, Deserialize)]
+pub struct DroppedDefault {
+    type_name: String,
+    param: String,
+    default: String,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct ExtractTypesResult {
     statements: Vec<ExportStatement>,
+    submodule: Option<Vec<String>>,
+
+    #[serde(default)]
+    dropped_defaults: Vec<DroppedDefault>,
+
+    /// Exported types that weren't forwarded because their type parameters
+    /// use syntax that isn't understood, like constraints.
+    #[serde(default)]
+    skipped_types: Vec<String>,
 }
 
 impl ExtractTypesResult {
     pub fn new() -> Self {
         ExtractTypesResult {
             statements: Vec::new(),
+            submodule: None,
+            dropped_defaults: Vec::new(),
+            skipped_types: Vec::new(),
         }
     }
 
+    /// A warning for each type parameter default that was dropped from the
+    /// types of `package`, and each type that couldn't be forwarded at all,
+    /// for the package's author to fix.
+    pub fn warnings(&self, package: &dyn fmt::Display) -> Vec<String> {
+        let dropped_defaults = self.dropped_defaults.iter().map(|dropped| {
+            format!(
+                "{}: exported type {} has the default {} = {}, which is not exported, so links \
+                 to the package leave the default out",
+                package, dropped.type_name, dropped.param, dropped.default
+            )
+        });
+        let skipped_types = self.skipped_types.iter().map(|type_name| {
+            format!(
+                "{}: exported type {} has type parameters that Wally doesn't understand, so \
+                 links to the package don't forward it",
+                package, type_name
+            )
+        });
+
+        dropped_defaults.chain(skipped_types).collect()
+    }
+
+    /// The types the module exports, in the order they're declared.
+    pub fn statements(&self) -> &[ExportStatement] {
+        &self.statements
+    }
+
+    /// The path below the main module, as child names, of the module the
+    /// statements were read from when the main module only re-exports it.
+    /// Luau doesn't carry exported types through a `return require(...)`, so
+    /// links have to require this module directly to forward them.
+    pub fn submodule(&self) -> Option<&[String]> {
+        self.submodule.as_deref()
+    }
+
+    /// Every statement's forwarding statement, one per line. See
+    /// [`ExportStatement::to_forwarding_statement`] for what `module_name`
+    /// can be.
     pub fn format_forwarding_statements(&self, module_name: &str) -> String {
         self.statements.iter().map(|stmt| {
             stmt.to_forwarding_statement(module_name)
@@ -285,6 +441,104 @@ impl ExtractTypesResult {
     }
 }
 
+impl Default for ExtractTypesResult {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `code` starts with `keyword` as a whole word.
+fn starts_with_keyword(code: &str, keyword: &str) -> bool {
+    code.starts_with(keyword) && !is_identifier_char(get(code, keyword.len()))
+}
+
+/// Whether `keyword` appears as a whole word at `at`, rather than as part of a
+/// longer identifier like `exported` or `mytype`. An `at` inside of a
+/// multibyte character never matches.
+fn is_keyword_at(code: &str, at: usize, keyword: &str) -> bool {
+    !is_identifier_char(get_before(code, at))
+        && code
+            .get(at..)
+            .is_some_and(|rest| starts_with_keyword(rest, keyword))
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Whether `c` can begin an identifier, which unlike the rest of one can't be
+/// a digit.
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+/// Find the end of a type parameter default starting at `at`, which is the
+/// first ',' or '>' that isn't nested inside of another type. Defaults can be
+/// arbitrary types like `(string, number)` or `(T) -> ()`, not just names.
+fn end_of_type_default(code: &str, at: usize) -> usize {
+    let mut index = at;
+    let mut depth = 0;
+    while index < code.len() {
+        let c = get(code, index);
+        match (c, get(code, index + c.len_utf8())) {
+            ('-', '>') => index += 1,
+            ('(' | '{' | '<', _) => depth += 1,
+            (')' | '}', _) => depth -= 1,
+            ('>', _) if depth > 0 => depth -= 1,
+            (',' | '>', _) if depth == 0 => break,
+            _ => {}
+        }
+        index += c.len_utf8();
+    }
+    index
+}
+
+/// Find the end of a part of a type parameter list that isn't understood, like
+/// a constraint, which is the first ',' or '>' that isn't nested inside of
+/// brackets. Returns `None` if the list's brackets don't balance or the code
+/// ends first, in which case it wasn't a type parameter list after all.
+fn end_of_unknown_type_param(code: &str, at: usize) -> Option<usize> {
+    let mut index = at;
+    let mut depth = 0;
+    while index < code.len() {
+        let c = get(code, index);
+        match (c, get(code, index + c.len_utf8())) {
+            ('-', '>') => index += 1,
+            ('(' | '{' | '[' | '<', _) => depth += 1,
+            (')' | '}' | ']', _) if depth == 0 => return None,
+            (')' | '}' | ']', _) => depth -= 1,
+            ('>', _) if depth > 0 => depth -= 1,
+            (',' | '>', _) if depth == 0 => return Some(index),
+            _ => {}
+        }
+        index += c.len_utf8();
+    }
+    None
+}
+
+/// Find the end of the `(...)` or `{...}` group that opens at `at`, returning
+/// the index just past its closing bracket. Returns `None` if the group is
+/// never closed, so that a stray bracket can't hide the rest of the file.
+fn end_of_group(code: &str, at: usize) -> Option<usize> {
+    let mut index = at;
+    let mut depth = 0;
+    while index < code.len() {
+        let c = get(code, index);
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index + 1);
+                }
+            }
+            _ => {}
+        }
+        index += c.len_utf8();
+    }
+    None
+}
+
 fn parse_types(lua_code: &str) -> ExtractTypesResult {
     // First strip any comments / strings which could have extraneous "export type" text in them.
     let lua_code = strip_comments_and_strings(lua_code);
@@ -294,6 +548,7 @@ fn parse_types(lua_code: &str) -> ExtractTypesResult {
     let mut state = ParseState::Code;
     let mut current_export_statement = ExportStatement::new();
     let mut current_type_param = TypeParam::new();
+    let mut has_unknown_type_params = false;
     let mut result = ExtractTypesResult::new();
     let mut non_exported_types: BTreeSet<String> = BTreeSet::new();
     while index < lua_code.len() {
@@ -308,7 +563,7 @@ fn parse_types(lua_code: &str) -> ExtractTypesResult {
         }
         match (state.clone(), c) {
             (ParseState::Code, 'e') => {
-                if lua_code[index..].starts_with("export") {
+                if is_keyword_at(&lua_code, index, "export") {
                     state = ParseState::Export;
                     current_export_statement.is_exported = true;
                     index += "export".len();
@@ -317,7 +572,7 @@ fn parse_types(lua_code: &str) -> ExtractTypesResult {
                 }
             }
             (ParseState::Code, 't') => {
-                if lua_code[index..].starts_with("type") {
+                if is_keyword_at(&lua_code, index, "type") {
                     state = ParseState::Type;
                     current_export_statement.is_exported = false;
                     index += "type".len();
@@ -325,14 +580,42 @@ fn parse_types(lua_code: &str) -> ExtractTypesResult {
                     index += 1;
                 }
             }
-            (ParseState::Export, 't') => {
-                if lua_code[index..].starts_with("type") {
+            (ParseState::Code, '(' | '{') => {
+                // Type declarations can't appear inside of parentheses or
+                // braces, so fields or arguments named `type` or `export` in
+                // them are skipped over rather than parsed.
+                index = end_of_group(&lua_code, index).unwrap_or(index + 1);
+            }
+            (ParseState::Export, _) => {
+                // Anything other than `type` after `export` isn't a type
+                // export, so it's handled as regular code instead.
+                if is_keyword_at(&lua_code, index, "type") {
                     state = ParseState::Type;
                     index += "type".len();
                 } else {
                     state = ParseState::Code;
                 }
             }
+            (ParseState::Type, 'f') if starts_with_keyword(&lua_code[index..], "function") => {
+                current_export_statement.is_function = true;
+                index += "function".len();
+                state = ParseState::TypeFunction;
+            }
+            (ParseState::TypeFunction | ParseState::Type, c) if !is_identifier_start(c) => {
+                // Not a type declaration after all, such as `local type = 5`
+                // where `type` is a variable, or a name like `1Foo`.
+                current_export_statement = ExportStatement::new();
+                state = ParseState::Code;
+            }
+            (ParseState::TypeFunction, _) => {
+                let start = index;
+                while get(&lua_code, index).is_ascii_alphanumeric() || get(&lua_code, index) == '_' {
+                    index += 1;
+                }
+                current_export_statement.name = lua_code[start..index].to_string();
+                result.add_statement(take(&mut current_export_statement));
+                state = ParseState::Code;
+            }
             (ParseState::Type, _) => {
                 let start = index;
                 while get(&lua_code, index).is_ascii_alphanumeric() || get(&lua_code, index) == '_' {
@@ -359,7 +642,15 @@ fn parse_types(lua_code: &str) -> ExtractTypesResult {
                     index += 1;
                 }
                 let param_name = &lua_code[start..index];
-                assert!(param_name.len() > 0);
+                if param_name.is_empty() {
+                    // Not a type parameter list after all, such as `type < (x)`
+                    // where `type` is a variable being compared.
+                    current_export_statement = ExportStatement::new();
+                    current_type_param = TypeParam::new();
+                    has_unknown_type_params = false;
+                    state = ParseState::Code;
+                    continue;
+                }
                 current_type_param.name = param_name.to_string();
                 state = ParseState::TypePack;
             }
@@ -383,12 +674,11 @@ fn parse_types(lua_code: &str) -> ExtractTypesResult {
             }
             (ParseState::TypeDefaultName, _) => {
                 let start = index;
-                while get(&lua_code, index).is_ascii_alphanumeric() || get(&lua_code, index) == '_' {
-                    index += 1;
+                index = end_of_type_default(&lua_code, index);
+                let default_name = lua_code[start..index].trim();
+                if !default_name.is_empty() {
+                    current_type_param.default = Some(default_name.to_string());
                 }
-                let default_name = &lua_code[start..index];
-                assert!(default_name.len() > 0);
-                current_type_param.default = Some(default_name.to_string());
                 current_export_statement.type_params.push(take(&mut current_type_param));
                 state = ParseState::NextTypeParam;
             }
@@ -397,12 +687,39 @@ fn parse_types(lua_code: &str) -> ExtractTypesResult {
                 state = ParseState::TypeParam;
             }
             (ParseState::NextTypeParam, '>') => {
-                result.add_statement(take(&mut current_export_statement));
+                let statement = take(&mut current_export_statement);
+                if !has_unknown_type_params {
+                    result.add_statement(statement);
+                } else if statement.is_exported {
+                    // Forwarding the type without what wasn't understood could
+                    // change what it means, so it's left out instead.
+                    result.skipped_types.push(statement.name);
+                }
+                has_unknown_type_params = false;
                 index += 1;
                 state = ParseState::Code;
             }
+            (ParseState::NextTypeParam, _) => {
+                // Syntax that isn't understood, like a constraint, is skipped
+                // up to the next parameter, keeping track of brackets so that
+                // anything nested inside of it doesn't end the list early.
+                match end_of_unknown_type_param(&lua_code, index) {
+                    Some(end) => {
+                        has_unknown_type_params = true;
+                        index = end;
+                    }
+                    None => {
+                        // Not a type parameter list, so the rest of the file
+                        // is parsed as code rather than skipped along with it.
+                        current_export_statement = ExportStatement::new();
+                        has_unknown_type_params = false;
+                        index += c.len_utf8();
+                        state = ParseState::Code;
+                    }
+                }
+            }
             _ => {
-                index += 1;
+                index += c.len_utf8();
             }
         }
     }
@@ -414,6 +731,11 @@ fn parse_types(lua_code: &str) -> ExtractTypesResult {
         for param in statement.type_params.iter_mut() {
             if let Some(default) = &param.default {
                 if non_exported_types.contains(default) {
+                    result.dropped_defaults.push(DroppedDefault {
+                        type_name: statement.name.clone(),
+                        param: param.name.clone(),
+                        default: default.clone(),
+                    });
                     param.default = None;
                 }
             }
@@ -423,17 +745,250 @@ fn parse_types(lua_code: &str) -> ExtractTypesResult {
     result
 }
 
-pub fn extract_types(package_path: &PathBuf) -> ExtractTypesResult {
+/// Join a `$path` from a project file onto the package path. Project files
+/// may be written on Windows, so both separators are accepted, `.` components
+/// are dropped and `..` components are applied.
+///
+/// Returns `None` if the path would leave the package, so that one package's
+/// project file can't be used to read files outside of it.
+fn resolve_tree_path(package_path: &Path, tree_path: &str) -> Option<PathBuf> {
+    let mut components = Vec::new();
+
+    for component in tree_path.split(&['/', '\\'][..]) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            component => components.push(component),
+        }
+    }
+
+    let mut path = package_path.to_path_buf();
+    path.extend(components);
+
+    Some(path)
+}
+
+/// Find the types exported by the main module of the package unpacked at
+/// `package_path`, such as an installed package or one in the package cache,
+/// for tools that want to know a package's types without installing it.
+pub fn package_types(package_path: &Path) -> ExtractTypesResult {
+    extract_types(&FilesystemSink, package_path, None)
+}
+
+/// Find the types exported by the main module of a package that has been
+/// installed to `package_path` through `sink`, reusing the types parsed from
+/// identical modules before if a `cache` is given.
+pub fn extract_types(
+    sink: &dyn InstallSink,
+    package_path: &Path,
+    cache: Option<&TypeCache>,
+) -> ExtractTypesResult {
     log::debug!("Processing types for package at {}", package_path.display());
 
+    let init_path = match entry_module_path(sink, package_path) {
+        Some(path) => path,
+        None => return ExtractTypesResult::new(),
+    };
+
+    let init_contents = match read_to_string(sink, &init_path) {
+        Ok(c) => c,
+        Err(err) => {
+            log::warn!(
+                "Failed to read {}: {}",
+                init_path.display(),
+                err
+            );
+            return ExtractTypesResult::new();
+        }
+    };
+
+    let types = parse_types_cached(&init_contents, cache);
+
+    if types.is_empty() {
+        if let Some(submodule) = reexported_submodule(&init_contents) {
+            if let Some(types) = extract_submodule_types(sink, &init_path, submodule, cache) {
+                return types;
+            }
+        }
+    }
+
+    types
+}
+
+/// Read the types of a module that the main module at `init_path` re-exports.
+/// Only one level is followed: whatever the submodule itself re-exports is
+/// not, so packages whose modules require each other can't loop forever.
+fn extract_submodule_types(
+    sink: &dyn InstallSink,
+    init_path: &Path,
+    submodule: Vec<String>,
+    cache: Option<&TypeCache>,
+) -> Option<ExtractTypesResult> {
+    // Only an init script has children on disk; a single module script's
+    // `script` has none.
+    let is_init = matches!(
+        init_path.file_stem().and_then(|stem| stem.to_str()),
+        Some("init")
+    );
+    if !is_init {
+        return None;
+    }
+
+    let mut base = init_path.parent()?.to_path_buf();
+    base.extend(&submodule);
+
+    let file_name = base.file_name()?.to_str()?.to_owned();
+    let candidates = [
+        base.with_file_name(format!("{}.lua", file_name)),
+        base.with_file_name(format!("{}.luau", file_name)),
+        base.join("init.lua"),
+        base.join("init.luau"),
+    ];
+    let path = candidates.iter().find(|path| sink.is_file(path))?;
+
+    log::debug!(
+        "Following re-export from {} to {}",
+        init_path.display(),
+        path.display()
+    );
+
+    let contents = match read_to_string(sink, path) {
+        Ok(c) => c,
+        Err(err) => {
+            log::warn!("Failed to read {}: {}", path.display(), err);
+            return None;
+        }
+    };
+
+    let mut types = parse_types_cached(&contents, cache);
+    if types.is_empty() {
+        return None;
+    }
+
+    types.submodule = Some(submodule);
+    Some(types)
+}
+
+/// Like `parse_types`, but reads the result from `cache` when the same
+/// contents were parsed before and stores it there when they weren't.
+fn parse_types_cached(lua_code: &str, cache: Option<&TypeCache>) -> ExtractTypesResult {
+    let cache = match cache {
+        Some(cache) => cache,
+        None => return parse_types(lua_code),
+    };
+
+    if let Some(types) = cache.get(lua_code) {
+        return types;
+    }
+
+    let types = parse_types(lua_code);
+    cache.insert(lua_code, &types);
+    types
+}
+
+/// If a main module only returns one of its children, in the form
+/// `return require(script.Foo)` or `local Foo = require(script.Foo)` followed
+/// by `return Foo`, the names on the path from `script` to that child.
+fn reexported_submodule(lua_code: &str) -> Option<Vec<String>> {
+    let code = strip_comments_and_strings(lua_code);
+
+    let return_at = (0..code.len())
+        .rev()
+        .find(|&index| is_keyword_at(&code, index, "return"))?;
+    let returned = code[return_at + "return".len()..].trim();
+    let returned = returned.strip_suffix(';').unwrap_or(returned).trim_end();
+
+    if let Some((path, rest)) = parse_script_require(returned) {
+        return if rest.trim().is_empty() { Some(path) } else { None };
+    }
+
+    let (name, rest) = parse_identifier(returned)?;
+    if !rest.trim().is_empty() {
+        return None;
+    }
+
+    // Find the `local <name> = require(script...)` that the returned name
+    // was bound by.
+    (0..return_at)
+        .filter(|&index| is_keyword_at(&code, index, "local"))
+        .find_map(|index| {
+            let rest = code[index + "local".len()..].trim_start();
+            let (local, rest) = parse_identifier(rest)?;
+            if local != name {
+                return None;
+            }
+
+            let rest = rest.trim_start().strip_prefix('=')?;
+            parse_script_require(rest.trim_start()).map(|(path, _)| path)
+        })
+}
+
+/// Parse `require(script.A.B)` from the start of `code`, returning the child
+/// names and the remaining code.
+fn parse_script_require(code: &str) -> Option<(Vec<String>, &str)> {
+    let rest = code.strip_prefix("require")?.trim_start();
+    let rest = rest.strip_prefix('(')?.trim_start();
+    let (root, mut rest) = parse_identifier(rest)?;
+    if root != "script" {
+        return None;
+    }
+
+    let mut path = Vec::new();
+    while let Some(after_dot) = rest.trim_start().strip_prefix('.') {
+        let (child, after_child) = parse_identifier(after_dot.trim_start())?;
+        path.push(child.to_owned());
+        rest = after_child;
+    }
+
+    let rest = rest.trim_start().strip_prefix(')')?;
+    if path.is_empty() {
+        None
+    } else {
+        Some((path, rest))
+    }
+}
+
+fn parse_identifier(code: &str) -> Option<(&str, &str)> {
+    let end = code
+        .find(|c: char| !is_identifier_char(c))
+        .unwrap_or(code.len());
+    if end == 0 || code.as_bytes()[0].is_ascii_digit() {
+        return None;
+    }
+
+    Some(code.split_at(end))
+}
+
+/// The path of the module that a package's exported types are read from.
+/// This is the tree of its `default.project.json` if it has one. Otherwise
+/// it's the `lib` named in its `wally.toml`, falling back to `src/init.lua` or
+/// `src/init.luau`.
+pub(crate) fn entry_module_path(sink: &dyn InstallSink, package_path: &Path) -> Option<PathBuf> {
     let project_file_path = package_path.join("default.project.json");
 
-    if !project_file_path.exists() {
-        log::debug!("No default.project.json found for package at {}", package_path.display());
-        return ExtractTypesResult::new();
+    if sink.is_file(&project_file_path) {
+        return project_entry_module_path(sink, package_path, &project_file_path);
+    }
+
+    log::debug!("No default.project.json found for package at {}", package_path.display());
+
+    if let Some(path) = manifest_entry_module_path(sink, package_path) {
+        return Some(path);
     }
 
-    let project_contents = match fs::read_to_string(&project_file_path) {
+    module_at(sink, &package_path.join("src"))
+}
+
+/// The module that the tree of the project file at `project_file_path` points
+/// at.
+fn project_entry_module_path(
+    sink: &dyn InstallSink,
+    package_path: &Path,
+    project_file_path: &Path,
+) -> Option<PathBuf> {
+    let project_contents = match read_to_string(sink, project_file_path) {
         Ok(c) => c,
         Err(err) => {
             log::warn!(
@@ -441,7 +996,7 @@ pub fn extract_types(package_path: &PathBuf) -> ExtractTypesResult {
                 project_file_path.display(),
                 err
             );
-            return ExtractTypesResult::new();
+            return None;
         }
     };
 
@@ -453,52 +1008,127 @@ pub fn extract_types(package_path: &PathBuf) -> ExtractTypesResult {
                 project_file_path.display(),
                 err
             );
-            return ExtractTypesResult::new();
+            return None;
         }
     };
 
-    let tree_path = match project.tree {
-        Some(tree) => package_path.join(tree.path),
+    let tree = match project.tree {
+        Some(tree) => tree,
         None => {
             log::debug!("default.project.json has no tree path");
-            return ExtractTypesResult::new();
+            return None;
         }
     };
 
-    let init_lua = tree_path.join("init.lua");
-    let init_luau = tree_path.join("init.luau");
-
-    let init_path = if init_lua.exists() {
-        init_lua
-    } else if init_luau.exists() {
-        init_luau
-    } else {
-        log::debug!(
-            "No init.lua or init.luau found under {}",
-            tree_path.display()
-        );
-        return ExtractTypesResult::new();
+    let tree_path = match resolve_tree_path(package_path, &tree.path) {
+        Some(path) => path,
+        None => {
+            log::warn!(
+                "The tree path {} in {} points outside of the package, so its types will not be \
+                 forwarded",
+                tree.path,
+                project_file_path.display()
+            );
+            return None;
+        }
     };
 
-    let init_contents = match fs::read_to_string(&init_path) {
-        Ok(c) => c,
+    module_at(sink, &tree_path)
+}
+
+/// The module named by the `lib` field of a package's `wally.toml`, if it has
+/// one and it exists.
+fn manifest_entry_module_path(sink: &dyn InstallSink, package_path: &Path) -> Option<PathBuf> {
+    let manifest_path = package_path.join(MANIFEST_FILE_NAME);
+    if !sink.is_file(&manifest_path) {
+        return None;
+    }
+
+    // Only the one field is read, so that a manifest written by a newer
+    // version of Wally still gives up its entry point.
+    let manifest: ManifestHint = match read_to_string(sink, &manifest_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|contents| Ok(toml::from_str(&contents)?))
+    {
+        Ok(manifest) => manifest,
         Err(err) => {
+            log::warn!("Failed to read {}: {}", manifest_path.display(), err);
+            return None;
+        }
+    };
+
+    let lib = manifest.package?.lib?;
+    let lib_path = match resolve_tree_path(package_path, &lib) {
+        Some(path) => path,
+        None => {
             log::warn!(
-                "Failed to read {}: {}",
-                init_path.display(),
-                err
+                "The lib {} in {} points outside of the package, so its types will not be \
+                 forwarded",
+                lib,
+                manifest_path.display()
             );
-            return ExtractTypesResult::new();
+            return None;
         }
     };
 
-    parse_types(&init_contents)
+    let path = module_at(sink, &lib_path);
+    if path.is_none() {
+        log::debug!(
+            "The lib {} in {} is not a module, falling back to src",
+            lib,
+            manifest_path.display()
+        );
+    }
+
+    path
+}
+
+/// The module at `path`, which can be either a folder with an init script or
+/// a single module script.
+fn module_at(sink: &dyn InstallSink, path: &Path) -> Option<PathBuf> {
+    if sink.is_file(path) {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("lua") | Some("luau") => Some(path.to_path_buf()),
+            _ => {
+                log::debug!("{} is not a Lua module", path.display());
+                None
+            }
+        }
+    } else {
+        let init_lua = path.join("init.lua");
+        let init_luau = path.join("init.luau");
+
+        if sink.is_file(&init_lua) {
+            Some(init_lua)
+        } else if sink.is_file(&init_luau) {
+            Some(init_luau)
+        } else {
+            log::debug!("No init.lua or init.luau found under {}", path.display());
+            None
+        }
+    }
+}
+
+/// Read a file as UTF-8 without its byte order mark, if it has one, which
+/// neither the JSON nor the TOML parser accepts.
+fn read_to_string(sink: &dyn InstallSink, path: &Path) -> io::Result<String> {
+    let contents = String::from_utf8(sink.read_file(path)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    match contents.strip_prefix('\u{feff}') {
+        Some(without_bom) => Ok(without_bom.to_owned()),
+        None => Ok(contents),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::fs;
+
+    use crate::install_sink::FilesystemSink;
+
     #[test]
     fn test_strip_line_comment() {
         let input = "code -- comment\nmore code";
@@ -562,6 +1192,40 @@ mod tests {
         assert_eq!(strip_comments_and_strings(input), expected);
     }
 
+    #[test]
+    fn test_strip_keeps_multibyte_characters_in_code() {
+        let input = "local s = \"héllo\" -- ça va 👋\nlocal π = 3.14";
+        assert_eq!(strip_comments_and_strings(input), "local s =  \nlocal π = 3.14");
+    }
+
+    #[test]
+    fn test_parse_export_type_after_emoji_comment() {
+        let input = r#"
+            -- 🚀 Fast vectors, façade for 数学 🚀
+            --[[ ✨ block comment ✨ ]]
+            local label = "→ Vector ←"
+            export type Vector<T = number> = { x: T, y: T } -- ✅
+            local ñame = `{label} 🎉`
+            export type Point = Vector
+        "#;
+        let result = parse_types(input);
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "export type Vector<T = number> = Module.Vector<T>\nexport type Point = Module.Point"
+        );
+    }
+
+    #[test]
+    fn test_multibyte_characters_around_keywords() {
+        let result = parse_types("local é = 1 export type A = number;é type B = string");
+        assert_eq!(result.format_forwarding_statements("Module"), "export type A = Module.A");
+
+        assert_eq!(
+            reexported_submodule("local 🦆 = 1\nreturn require(script.Types) -- 🦆"),
+            Some(vec!["Types".to_owned()])
+        );
+    }
+
     #[test]
     fn test_parse_simple_export_type() {
         let input = "export type Foo = string";
@@ -605,6 +1269,34 @@ mod tests {
         assert_eq!(result.statements[0].type_params[0].default, Some("string".to_string()));
     }
 
+    #[test]
+    fn test_parse_skips_unknown_type_param_syntax() {
+        let input = "export type Sorted<T: Comparable<T>, U = (T) -> ()> = Set<T, U>\n\
+                     export type Keyed<K where K: {[string]: K}> = Map<K>\n\
+                     export type Plain = string\n\
+                     export type Generic<T> = Array<T>";
+        let result = parse_types(input);
+        assert_eq!(result.statements.len(), 2);
+        assert_eq!(result.statements[0].name, "Plain");
+        assert_eq!(result.statements[1].name, "Generic");
+        assert_eq!(result.statements[1].type_params.len(), 1);
+        assert_eq!(result.skipped_types, ["Sorted", "Keyed"]);
+        assert_eq!(
+            result.warnings(&"biff/sorted@0.1.0")[0],
+            "biff/sorted@0.1.0: exported type Sorted has type parameters that Wally doesn't \
+             understand, so links to the package don't forward it"
+        );
+    }
+
+    #[test]
+    fn test_parse_continues_after_unclosed_type_param_list() {
+        let input = "export type Broken<T: ) = number\nexport type Plain = string";
+        let result = parse_types(input);
+        assert_eq!(result.statements.len(), 1);
+        assert_eq!(result.statements[0].name, "Plain");
+        assert!(result.skipped_types.is_empty());
+    }
+
     #[test]
     fn test_parse_non_exported_type() {
         let input = "type Foo = string";
@@ -647,6 +1339,24 @@ export type Real = number"#;
         assert_eq!(forwarding, "export type Foo = Module.Foo");
     }
 
+    #[test]
+    fn test_forwarding_statement_with_dotted_access() {
+        let mut stmt = ExportStatement::new();
+        stmt.name = "Foo".to_string();
+        stmt.is_exported = true;
+        let forwarding = stmt.to_forwarding_statement("MODULE.Types");
+        assert_eq!(forwarding, "export type Foo = MODULE.Types.Foo");
+    }
+
+    #[test]
+    fn test_format_forwarding_statements_with_dotted_access() {
+        let result = parse_types("export type Foo<T> = { T }\nexport type Bar = number");
+        assert_eq!(
+            result.format_forwarding_statements("MODULE.Types"),
+            "export type Foo<T> = MODULE.Types.Foo<T>\nexport type Bar = MODULE.Types.Bar"
+        );
+    }
+
     #[test]
     fn test_forwarding_statement_with_params() {
         let mut stmt = ExportStatement::new();
@@ -707,6 +1417,24 @@ export type Foo<T = LocalType> = Bar<T>
         assert_eq!(result.statements[0].type_params[0].default, None);
     }
 
+    #[test]
+    fn test_warn_about_non_exported_defaults() {
+        let input = r#"
+            type Internal = { secret: string }
+            export type Exported = number
+            export type Box<T = Internal, U = Exported> = { value: T, other: U }
+        "#;
+        let result = parse_types(input);
+        assert_eq!(
+            result.warnings(&"biff/types@0.1.0"),
+            vec![
+                "biff/types@0.1.0: exported type Box has the default T = Internal, which is not \
+                 exported, so links to the package leave the default out"
+                    .to_owned()
+            ]
+        );
+    }
+
     #[test]
     fn test_keep_exported_defaults() {
         let input = r#"
@@ -718,6 +1446,103 @@ export type Foo<T = ExportedType> = Bar<T>
         assert_eq!(result.statements[1].type_params[0].default, Some("ExportedType".to_string()));
     }
 
+    #[test]
+    fn test_parse_type_pack_default() {
+        let input = "export type Foo<T... = (string, number)> = (T...) -> ()";
+        let result = parse_types(input);
+        assert_eq!(result.statements.len(), 1);
+        assert_eq!(result.statements[0].type_params.len(), 1);
+        assert!(result.statements[0].type_params[0].is_pack);
+        assert_eq!(
+            result.statements[0].type_params[0].default,
+            Some("(string, number)".to_string())
+        );
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "export type Foo<T... = (string, number)> = Module.Foo<T...>"
+        );
+    }
+
+    #[test]
+    fn test_parse_table_type_defaults() {
+        let input = "export type Map<K = { [string]: number }, V = { read: (K) -> (), size: number }> = { [K]: V }";
+        let result = parse_types(input);
+        assert_eq!(result.statements.len(), 1);
+        assert_eq!(result.statements[0].type_params.len(), 2);
+        assert_eq!(
+            result.statements[0].type_params[0].default,
+            Some("{ [string]: number }".to_string())
+        );
+        assert_eq!(
+            result.statements[0].type_params[1].default,
+            Some("{ read: (K) -> (), size: number }".to_string())
+        );
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "export type Map<K = { [string]: number }, V = { read: (K) -> (), size: number }> = Module.Map<K, V>"
+        );
+    }
+
+    #[test]
+    fn test_type_keywords_inside_groups_are_ignored() {
+        let input = r#"
+            local check = { export = true, type = Default }
+            print(type < (limit), type)
+            export type Foo<T = { [string]: number }> = T
+        "#;
+        let result = parse_types(input);
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "export type Foo<T = { [string]: number }> = Module.Foo<T>"
+        );
+    }
+
+    #[test]
+    fn test_type_variable_compared_at_top_level() {
+        let input = "local ok = type < (limit)\nexport type Foo = number";
+        let result = parse_types(input);
+        assert_eq!(result.format_forwarding_statements("Module"), "export type Foo = Module.Foo");
+    }
+
+    #[test]
+    fn test_parse_function_type_default() {
+        let input = "export type Foo<T = (number) -> string, U = {T}> = Bar<T, U>";
+        let result = parse_types(input);
+        assert_eq!(result.statements.len(), 1);
+        assert_eq!(result.statements[0].type_params.len(), 2);
+        assert_eq!(
+            result.statements[0].type_params[0].default,
+            Some("(number) -> string".to_string())
+        );
+        assert_eq!(result.statements[0].type_params[1].default, Some("{T}".to_string()));
+    }
+
+    #[test]
+    fn test_parse_export_type_function() {
+        let input = r#"
+export type function Keys(t)
+    return t:keys()
+end
+export type Foo = string
+"#;
+        let result = parse_types(input);
+        assert_eq!(result.statements.len(), 2);
+        assert_eq!(result.statements[0].name, "Keys");
+        assert!(result.statements[0].is_function);
+        assert_eq!(result.statements[1].name, "Foo");
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "-- type function Keys cannot be forwarded\nexport type Foo = Module.Foo"
+        );
+    }
+
+    #[test]
+    fn test_parse_non_exported_type_function() {
+        let input = "type function Keys(t)\n    return t:keys()\nend";
+        let result = parse_types(input);
+        assert_eq!(result.statements.len(), 0);
+    }
+
     #[test]
     fn test_complex_type_params() {
         let input = "export type Foo<T, U..., V = string, W... = number> = Bar";
@@ -741,4 +1566,344 @@ export type Foo<T = ExportedType> = Bar<T>
         assert!(result.statements[0].type_params[3].is_pack);
         assert_eq!(result.statements[0].type_params[3].default, Some("number".to_string()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_resolve_tree_path() {
+        let package_path = Path::new("package");
+        let expected = package_path.join("src").join("Module.luau");
+
+        assert_eq!(resolve_tree_path(package_path, "src/Module.luau"), Some(expected.clone()));
+        assert_eq!(resolve_tree_path(package_path, "./src/Module.luau"), Some(expected.clone()));
+        assert_eq!(resolve_tree_path(package_path, ".\\src\\Module.luau"), Some(expected.clone()));
+        assert_eq!(resolve_tree_path(package_path, "lib/../src/Module.luau"), Some(expected));
+        assert_eq!(resolve_tree_path(package_path, "../outside/Module.luau"), None);
+        assert_eq!(resolve_tree_path(package_path, "src/../../Module.luau"), None);
+    }
+
+    #[test]
+    fn test_tree_path_outside_package_is_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let package_path = root.path().join("package");
+        fs::create_dir(&package_path).unwrap();
+        fs::write(
+            package_path.join("default.project.json"),
+            r#"{ "name": "escaping", "tree": { "$path": "../outside" } }"#,
+        )
+        .unwrap();
+
+        // The module exists, but it isn't part of the package.
+        fs::create_dir(root.path().join("outside")).unwrap();
+        fs::write(
+            root.path().join("outside").join("init.lua"),
+            "export type Secret = string\nreturn {}",
+        )
+        .unwrap();
+
+        assert_eq!(entry_module_path(&FilesystemSink, &package_path), None);
+        assert!(extract_types(&FilesystemSink, &package_path, None).is_empty());
+    }
+
+    #[test]
+    fn test_extract_types_from_single_file_tree() {
+        let package = tempfile::tempdir().unwrap();
+        fs::create_dir(package.path().join("src")).unwrap();
+        fs::write(
+            package.path().join("default.project.json"),
+            r#"{ "name": "single", "tree": { "$path": "./src/Module.luau" } }"#,
+        )
+        .unwrap();
+        fs::write(
+            package.path().join("src").join("Module.luau"),
+            "export type A = number\nreturn {}",
+        )
+        .unwrap();
+
+        let result = extract_types(&FilesystemSink, package.path(), None);
+        assert_eq!(
+            result.format_forwarding_statements("MODULE"),
+            "export type A = MODULE.A"
+        );
+    }
+
+    #[test]
+    fn test_parse_after_directive_bom_and_shebang() {
+        let expected = "export type X = Module.X";
+
+        let strict = parse_types("--!strict\nexport type X = number");
+        assert_eq!(strict.format_forwarding_statements("Module"), expected);
+
+        let bom = parse_types("\u{feff}export type X = number");
+        assert_eq!(bom.format_forwarding_statements("Module"), expected);
+
+        let shebang = parse_types("#!/usr/bin/env luau\nexport type X = number");
+        assert_eq!(shebang.format_forwarding_statements("Module"), expected);
+
+        let all =
+            parse_types("\u{feff}#!/usr/bin/env luau\n--!nonstrict\nexport type X = number");
+        assert_eq!(all.format_forwarding_statements("Module"), expected);
+
+        assert_eq!(
+            reexported_submodule("\u{feff}--!strict\nreturn require(script.Types)"),
+            Some(vec!["Types".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_extract_types_from_bom_and_directive_files() {
+        let package = tempfile::tempdir().unwrap();
+        let src = package.path().join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(
+            package.path().join("default.project.json"),
+            "\u{feff}{ \"name\": \"bom\", \"tree\": { \"$path\": \"src\" } }",
+        )
+        .unwrap();
+        fs::write(
+            src.join("init.lua"),
+            "\u{feff}--!strict\nreturn require(script.Types)",
+        )
+        .unwrap();
+        fs::write(
+            src.join("Types.lua"),
+            "--!strict\nexport type X = number\nreturn nil",
+        )
+        .unwrap();
+
+        let result = extract_types(&FilesystemSink, package.path(), None);
+        assert_eq!(result.submodule(), Some(&["Types".to_owned()][..]));
+        assert_eq!(
+            result.format_forwarding_statements("TYPES"),
+            "export type X = TYPES.X"
+        );
+    }
+
+    #[test]
+    fn test_reexported_submodule_patterns() {
+        assert_eq!(
+            reexported_submodule("return require(script.Types)"),
+            Some(vec!["Types".to_owned()])
+        );
+        assert_eq!(
+            reexported_submodule("local Lib = require(script.Src.Lib)\n\nreturn Lib\n"),
+            Some(vec!["Src".to_owned(), "Lib".to_owned()])
+        );
+        assert_eq!(reexported_submodule("local Lib = require(script.Lib)\nreturn {}"), None);
+        assert_eq!(reexported_submodule("return require(script.Parent.Lib).new()"), None);
+        assert_eq!(reexported_submodule("return require(script)"), None);
+    }
+
+    #[test]
+    fn test_extract_types_follows_reexported_submodule() {
+        let package = tempfile::tempdir().unwrap();
+        let src = package.path().join("src");
+        fs::create_dir_all(src.join("Impl")).unwrap();
+        fs::write(
+            package.path().join("default.project.json"),
+            r#"{ "name": "reexport", "tree": { "$path": "src" } }"#,
+        )
+        .unwrap();
+        fs::write(
+            src.join("init.lua"),
+            "-- Everything lives in Impl\nlocal Impl = require(script.Impl)\nreturn Impl",
+        )
+        .unwrap();
+        fs::write(
+            src.join("Impl").join("init.luau"),
+            "export type Point = { x: number }\nreturn require(script.Inner)",
+        )
+        .unwrap();
+
+        let result = extract_types(&FilesystemSink, package.path(), None);
+        assert_eq!(result.submodule(), Some(&["Impl".to_owned()][..]));
+        assert_eq!(
+            result.format_forwarding_statements("TYPES"),
+            "export type Point = TYPES.Point"
+        );
+    }
+
+    #[test]
+    fn test_extract_types_follows_one_level_only() {
+        let package = tempfile::tempdir().unwrap();
+        let src = package.path().join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(
+            package.path().join("default.project.json"),
+            r#"{ "name": "reexport", "tree": { "$path": "src" } }"#,
+        )
+        .unwrap();
+        fs::write(src.join("init.lua"), "return require(script.init)").unwrap();
+
+        // The init module re-exports itself; following it must not loop.
+        let result = extract_types(&FilesystemSink, package.path(), None);
+        assert!(result.is_empty());
+        assert_eq!(result.submodule(), None);
+    }
+
+    #[test]
+    fn test_extract_types_reuses_cached_types() {
+        let package = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = TypeCache::at(cache_dir.path());
+
+        let src = package.path().join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(
+            package.path().join("default.project.json"),
+            r#"{ "name": "cached", "tree": { "$path": "src" } }"#,
+        )
+        .unwrap();
+        fs::write(src.join("init.lua"), "export type Point = { x: number }").unwrap();
+
+        let result = extract_types(&FilesystemSink, package.path(), Some(&cache));
+        assert_eq!(
+            result.format_forwarding_statements("TYPES"),
+            "export type Point = TYPES.Point"
+        );
+
+        // Rename the type in the cache entry. If the module were parsed again
+        // the original name would come back.
+        let entries: Vec<_> = fs::read_dir(cache_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        let entry = fs::read_to_string(&entries[0]).unwrap();
+        fs::write(&entries[0], entry.replace("Point", "Cached")).unwrap();
+
+        let result = extract_types(&FilesystemSink, package.path(), Some(&cache));
+        assert_eq!(
+            result.format_forwarding_statements("TYPES"),
+            "export type Cached = TYPES.Cached"
+        );
+
+        // Different contents don't share the entry.
+        fs::write(src.join("init.lua"), "export type Point = { y: number }").unwrap();
+        let result = extract_types(&FilesystemSink, package.path(), Some(&cache));
+        assert_eq!(
+            result.format_forwarding_statements("TYPES"),
+            "export type Point = TYPES.Point"
+        );
+    }
+
+    #[test]
+    fn test_parse_semicolon_separated_exports() {
+        let result = parse_types("export type A = number; export type B = string");
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "export type A = Module.A\nexport type B = Module.B"
+        );
+    }
+
+    #[test]
+    fn test_parse_tightly_packed_exports() {
+        let result = parse_types("export type A=number;export type B<T>=T;export type C<T...>=(T...)->()");
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "export type A = Module.A\nexport type B<T> = Module.B<T>\nexport type C<T...> = Module.C<T...>"
+        );
+    }
+
+    #[test]
+    fn test_parse_exports_without_separators() {
+        let result = parse_types("export type A = number export type B = string");
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "export type A = Module.A\nexport type B = Module.B"
+        );
+    }
+
+    #[test]
+    fn test_typeof_and_typename_are_not_keywords() {
+        let input = r#"
+            local kind = typeof(x)
+            local typename = "number"
+            local exported = true
+            export type Foo = number
+        "#;
+        let result = parse_types(input);
+        assert_eq!(result.format_forwarding_statements("Module"), "export type Foo = Module.Foo");
+    }
+
+    #[test]
+    fn test_type_names_must_start_with_identifier_char() {
+        let input = r#"
+            local type = 5
+            export type 1Bad = number
+            type 2Hidden = string
+            export type _Good2 = number
+        "#;
+        let result = parse_types(input);
+        assert_eq!(result.statements.len(), 1);
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "export type _Good2 = Module._Good2"
+        );
+    }
+
+    #[test]
+    fn test_keywords_inside_identifiers_are_ignored() {
+        let result = parse_types("local exported = 1;export type A = Mytype;local typed = 2;export type B = string");
+        assert_eq!(
+            result.format_forwarding_statements("Module"),
+            "export type A = Module.A\nexport type B = Module.B"
+        );
+    }
+
+    #[test]
+    fn test_entry_module_precedence() {
+        let package = tempfile::tempdir().unwrap();
+        let path = package.path();
+        fs::create_dir_all(path.join("src")).unwrap();
+        fs::create_dir_all(path.join("lib")).unwrap();
+        fs::write(path.join("src").join("init.luau"), "return {}").unwrap();
+        fs::write(path.join("lib").join("Main.luau"), "return {}").unwrap();
+
+        // Without a project file or a lib, the conventional module is used.
+        fs::write(path.join("wally.toml"), "[package]\nname = \"biff/lib\"\n").unwrap();
+        assert_eq!(
+            entry_module_path(&FilesystemSink, path),
+            Some(path.join("src").join("init.luau"))
+        );
+
+        fs::write(
+            path.join("wally.toml"),
+            "[package]\nname = \"biff/lib\"\nlib = \"lib/Main.luau\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            entry_module_path(&FilesystemSink, path),
+            Some(path.join("lib").join("Main.luau"))
+        );
+
+        // The project file wins over the manifest.
+        fs::write(
+            path.join("default.project.json"),
+            r#"{ "name": "lib", "tree": { "$path": "src" } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            entry_module_path(&FilesystemSink, path),
+            Some(path.join("src").join("init.luau"))
+        );
+    }
+
+    #[test]
+    fn test_lib_outside_package_is_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let package_path = root.path().join("package");
+        fs::create_dir_all(root.path().join("outside")).unwrap();
+        fs::create_dir_all(&package_path).unwrap();
+        fs::write(
+            root.path().join("outside").join("init.lua"),
+            "export type Secret = string\nreturn {}",
+        )
+        .unwrap();
+        fs::write(
+            package_path.join("wally.toml"),
+            "[package]\nlib = \"../outside\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(entry_module_path(&FilesystemSink, &package_path), None);
+    }
+}
@@ -219,3 +219,30 @@ pub fn update_index(access_token: Option<String>, repository: &Repository) -> an
 
     Ok(())
 }
+
+/// Fetch every branch and tag of the repository at `url` into `repository`,
+/// along with the remote's `HEAD`, without checking anything out.
+pub fn fetch_all(repository: &Repository, url: &str) -> anyhow::Result<()> {
+    let git_config = git2::Config::open_default()?;
+
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(make_credentials_callback(None, &git_config));
+
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks);
+
+    repository
+        .remote_anonymous(url)?
+        .fetch(
+            &[
+                "+refs/heads/*:refs/remotes/origin/*",
+                "+refs/tags/*:refs/tags/*",
+                "+HEAD:refs/remotes/origin/HEAD",
+            ],
+            Some(&mut fetch_options),
+            None,
+        )
+        .with_context(|| format!("could not fetch Git repository {}", url))?;
+
+    Ok(())
+}
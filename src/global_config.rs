@@ -0,0 +1,79 @@
+//! Defines the configuration file shared by every project on this machine.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// The environment variable that moves Wally's cache directory somewhere
+/// else, unless `--cache-dir` is passed.
+pub const CACHE_DIR_ENV_VAR: &str = "WALLY_CACHE_DIR";
+
+/// The contents of `~/.wally/config.toml`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GlobalConfig {
+    /// Registries to use instead of the project's registry for packages in a
+    /// given scope, keyed by scope.
+    ///
+    /// Example: `{ "acme" = "https://github.com/acme/wally-mirror-index" }`
+    #[serde(default)]
+    pub mirrors: BTreeMap<String, String>,
+
+    /// Extra HTTP headers to send with every request to a registry's API,
+    /// keyed by the registry's index URL and then by header name.
+    ///
+    /// Example: `{ "https://github.com/acme/wally-index" = { "X-Api-Key" = "secret" } }`
+    #[serde(default)]
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl GlobalConfig {
+    /// Load the config from the user's home directory. A missing config file
+    /// is the same as an empty one.
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_from(&file_path()?)
+    }
+
+    /// Load the config from a specific file.
+    pub fn load_from(path: &Path) -> anyhow::Result<Self> {
+        let contents = match fs_err::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+
+        toml::from_str(&contents)
+            .with_context(|| format!("failed to parse Wally config at {}", path.display()))
+    }
+}
+
+/// The directory that registry indexes, Git repositories and cached packages
+/// are kept in: `WALLY_CACHE_DIR` if it's set, or `wally` in the user's cache
+/// directory otherwise.
+pub fn default_cache_dir() -> anyhow::Result<PathBuf> {
+    cache_dir_from_env(env::var_os(CACHE_DIR_ENV_VAR))
+}
+
+/// The cache directory to use when `WALLY_CACHE_DIR` is set to `env_dir`, so
+/// that it can be picked without reading the process's environment.
+pub(crate) fn cache_dir_from_env(env_dir: Option<OsString>) -> anyhow::Result<PathBuf> {
+    if let Some(cache_dir) = env_dir.filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(cache_dir));
+    }
+
+    let mut path = dirs::cache_dir().context("Failed to find cache directory")?;
+    path.push("wally");
+    Ok(path)
+}
+
+fn file_path() -> anyhow::Result<PathBuf> {
+    let mut path = dirs::home_dir().context("Failed to find home directory")?;
+    path.push(".wally");
+    path.push("config.toml");
+    Ok(path)
+}
@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crossterm::style::{Color, SetForegroundColor};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::package_id::PackageId;
+
+/// Something that's told about the progress of an install as it happens.
+///
+/// Installation doesn't draw anything itself, so tools that embed Wally can
+/// show progress in their own UI. Every method does nothing by default except
+/// [`InstallObserver::on_download_retry`], [`InstallObserver::on_dev_require`]
+/// and [`InstallObserver::on_orphaned_file`], which log warnings.
+///
+/// Downloads run on worker threads, so the download methods can be called
+/// from several threads at once.
+pub trait InstallObserver: Send + Sync {
+    /// An install is starting that will put `package_count` packages in
+    /// place.
+    fn on_install_start(&self, _package_count: usize) {}
+
+    /// A package has started downloading.
+    fn on_download_start(&self, _package_id: &PackageId) {}
+
+    /// `bytes` of a package have been downloaded so far. This starts again
+    /// from zero if the download is retried.
+    fn on_download_progress(&self, _package_id: &PackageId, _bytes: u64) {}
+
+    /// A download failed in a way that might succeed when tried again, and
+    /// will be retried after a delay.
+    fn on_download_retry(&self, retry: &DownloadRetry) {
+        log::warn!("{}", retry);
+    }
+
+    /// A package has stopped downloading, whether it succeeded or not.
+    fn on_download_complete(&self, _package_id: &PackageId) {}
+
+    /// A package is in place, either because it was `downloaded` or because
+    /// an installed or cached copy was reused. Packages are reported in the
+    /// same order on every install.
+    fn on_package_installed(&self, _package_id: &PackageId, _downloaded: bool) {}
+
+    /// A link or types module was written, because it didn't exist yet or
+    /// its contents changed.
+    fn on_link_written(&self, _path: &Path) {}
+
+    /// A file outside of the dev packages folder requires something inside
+    /// of it, which would break a build without dev dependencies.
+    fn on_dev_require(&self, dev_require: &DevRequire) {
+        log::warn!("{}", dev_require);
+    }
+
+    /// A Lua file in the package folders wasn't produced by the install, such
+    /// as a link that was added by hand.
+    fn on_orphaned_file(&self, path: &Path) {
+        log::warn!(
+            "{} was not produced by this install and may be stale",
+            path.display()
+        );
+    }
+}
+
+/// An observer that ignores everything, used when an install isn't given one.
+pub struct SilentObserver;
+
+impl InstallObserver for SilentObserver {}
+
+/// A download that's about to be retried.
+pub struct DownloadRetry<'a> {
+    pub package_id: &'a PackageId,
+
+    /// Which retry this is, starting from 1.
+    pub attempt: u32,
+
+    /// How many times the download will be retried in total.
+    pub retries: u32,
+
+    /// How long the install waits before trying again.
+    pub delay: Duration,
+
+    pub error: &'a anyhow::Error,
+}
+
+impl fmt::Display for DownloadRetry<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "Failed to download {}, retrying in {:?} (attempt {} of {}): {:#}",
+            self.package_id, self.delay, self.attempt, self.retries, self.error
+        )
+    }
+}
+
+/// A line in a file outside of the dev packages folder that requires
+/// something inside of it.
+pub struct DevRequire<'a> {
+    pub path: &'a Path,
+
+    /// The line the require is on, starting from 1.
+    pub line: usize,
+
+    /// The name of the dev packages folder.
+    pub dev_folder: &'a str,
+}
+
+impl fmt::Display for DevRequire<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{}:{} requires something in {}, which is only installed with dev dependencies, so \
+             it would break a build without them",
+            self.path.display(),
+            self.line,
+            self.dev_folder
+        )
+    }
+}
+
+/// Shows an install's progress in the terminal: an overall progress bar
+/// carrying the package that was just installed, with a status line above it
+/// for each download in flight. Once the install is over, it says how many
+/// packages were downloaded.
+pub struct ProgressBarObserver {
+    multi_progress: MultiProgress,
+    bar: ProgressBar,
+    downloads: Mutex<HashMap<PackageId, ProgressBar>>,
+    downloaded: AtomicUsize,
+    verbose: bool,
+}
+
+impl ProgressBarObserver {
+    pub fn new() -> Self {
+        Self::with_multi_progress(MultiProgress::new())
+    }
+
+    /// An observer that draws nothing, for when progress shouldn't be shown
+    /// at all. Retries are still logged.
+    pub fn hidden() -> Self {
+        Self::with_multi_progress(MultiProgress::with_draw_target(ProgressDrawTarget::hidden()))
+    }
+
+    fn with_multi_progress(multi_progress: MultiProgress) -> Self {
+        let bar = multi_progress.add(
+            ProgressBar::new(0).with_style(
+                ProgressStyle::with_template(
+                    "{spinner:.cyan.bold} {pos}/{len} [{wide_bar:.cyan/blue}] {msg}",
+                )
+                .unwrap()
+                .tick_chars("⠁⠈⠐⠠⠄⠂ ")
+                .progress_chars("#>-"),
+            ),
+        );
+
+        Self {
+            multi_progress,
+            bar,
+            downloads: Mutex::new(HashMap::new()),
+            downloaded: AtomicUsize::new(0),
+            verbose: false,
+        }
+    }
+
+    /// Print a line for every package as it's downloaded, rather than only
+    /// saying how many were downloaded at the end.
+    pub fn with_verbose(self, verbose: bool) -> Self {
+        Self { verbose, ..self }
+    }
+
+    /// Remove the progress bars from the terminal and say how many packages
+    /// were downloaded, once the install has finished or failed.
+    pub fn finish(&self) {
+        for (_, download_bar) in self.downloads.lock().unwrap().drain() {
+            download_bar.finish_and_clear();
+        }
+
+        self.bar.finish_and_clear();
+
+        let downloaded = self.downloaded.load(Ordering::SeqCst);
+        if downloaded > 0 {
+            let _ = self.multi_progress.println(format!(
+                "{} Downloaded {}{} {}!",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset),
+                downloaded,
+                if downloaded == 1 {
+                    "package"
+                } else {
+                    "packages"
+                }
+            ));
+        }
+    }
+}
+
+impl Default for ProgressBarObserver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstallObserver for ProgressBarObserver {
+    fn on_install_start(&self, package_count: usize) {
+        self.bar.set_length(package_count as u64);
+
+        if !self.bar.is_hidden() {
+            self.bar.enable_steady_tick(Duration::from_millis(100));
+        }
+    }
+
+    fn on_download_start(&self, package_id: &PackageId) {
+        let download_bar = self.multi_progress.insert_before(
+            &self.bar,
+            ProgressBar::new_spinner()
+                .with_style(
+                    ProgressStyle::with_template(
+                        "{spinner:.cyan} {msg} {binary_bytes} ({binary_bytes_per_sec})",
+                    )
+                    .unwrap()
+                    .tick_chars("⠁⠈⠐⠠⠄⠂ "),
+                )
+                .with_message(package_id.to_string()),
+        );
+        if !download_bar.is_hidden() {
+            download_bar.enable_steady_tick(Duration::from_millis(100));
+        }
+
+        self.downloads
+            .lock()
+            .unwrap()
+            .insert(package_id.clone(), download_bar);
+    }
+
+    fn on_download_progress(&self, package_id: &PackageId, bytes: u64) {
+        if let Some(download_bar) = self.downloads.lock().unwrap().get(package_id) {
+            download_bar.set_position(bytes);
+        }
+    }
+
+    fn on_download_retry(&self, retry: &DownloadRetry) {
+        self.multi_progress.suspend(|| log::warn!("{}", retry));
+    }
+
+    fn on_dev_require(&self, dev_require: &DevRequire) {
+        self.multi_progress
+            .suspend(|| log::warn!("{}", dev_require));
+    }
+
+    fn on_orphaned_file(&self, path: &Path) {
+        self.multi_progress.suspend(|| {
+            log::warn!(
+                "{} was not produced by this install and may be stale",
+                path.display()
+            )
+        });
+    }
+
+    fn on_download_complete(&self, package_id: &PackageId) {
+        if let Some(download_bar) = self.downloads.lock().unwrap().remove(package_id) {
+            download_bar.finish_and_clear();
+        }
+    }
+
+    fn on_package_installed(&self, package_id: &PackageId, downloaded: bool) {
+        if downloaded {
+            self.downloaded.fetch_add(1, Ordering::SeqCst);
+
+            // Hundreds of these would scroll the progress bar away, so they're
+            // only shown when asked for.
+            if self.verbose {
+                self.bar.println(format!(
+                    "{} Downloaded {}{}",
+                    SetForegroundColor(Color::DarkGreen),
+                    SetForegroundColor(Color::Reset),
+                    package_id,
+                ));
+            }
+        }
+
+        self.bar.set_message(package_id.to_string());
+        self.bar.inc(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io;
+    use std::sync::Arc;
+
+    use indicatif::TermLike;
+
+    /// A terminal that remembers everything written to it, except for lines
+    /// that are cleared to be drawn again.
+    #[derive(Debug, Clone, Default)]
+    struct CapturedTerm {
+        output: Arc<Mutex<String>>,
+    }
+
+    impl TermLike for CapturedTerm {
+        fn width(&self) -> u16 {
+            80
+        }
+
+        fn move_cursor_up(&self, _n: usize) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn move_cursor_down(&self, _n: usize) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn move_cursor_right(&self, _n: usize) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn move_cursor_left(&self, _n: usize) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn write_line(&self, line: &str) -> io::Result<()> {
+            let mut output = self.output.lock().unwrap();
+            output.push_str(line);
+            output.push('\n');
+            Ok(())
+        }
+
+        fn write_str(&self, s: &str) -> io::Result<()> {
+            self.output.lock().unwrap().push_str(s);
+            Ok(())
+        }
+
+        fn clear_line(&self) -> io::Result<()> {
+            let mut output = self.output.lock().unwrap();
+            let line_start = output.rfind('\n').map_or(0, |index| index + 1);
+            output.truncate(line_start);
+            Ok(())
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The lines printed while installing two downloaded packages and one
+    /// that was already installed.
+    fn install_output(verbose: bool) -> Vec<String> {
+        let term = CapturedTerm::default();
+        let observer = ProgressBarObserver::with_multi_progress(MultiProgress::with_draw_target(
+            ProgressDrawTarget::term_like(Box::new(term.clone())),
+        ))
+        .with_verbose(verbose);
+
+        observer.on_install_start(3);
+        for (package_id, downloaded) in [
+            ("biff/a@0.1.0", true),
+            ("biff/b@0.1.0", true),
+            ("biff/c@0.1.0", false),
+        ] {
+            observer.on_package_installed(&package_id.parse().unwrap(), downloaded);
+        }
+        observer.finish();
+
+        let output = term.output.lock().unwrap().clone();
+        output
+            .lines()
+            .filter(|line| line.contains("Downloaded"))
+            .map(|line| line.trim_end().to_owned())
+            .collect()
+    }
+
+    fn downloaded_line(message: &str) -> String {
+        format!(
+            "{} Downloaded {}{}",
+            SetForegroundColor(Color::DarkGreen),
+            SetForegroundColor(Color::Reset),
+            message
+        )
+    }
+
+    #[test]
+    fn downloads_are_summarized() {
+        assert_eq!(install_output(false), [downloaded_line("2 packages!")]);
+    }
+
+    #[test]
+    fn downloads_are_listed_when_verbose() {
+        assert_eq!(
+            install_output(true),
+            [
+                downloaded_line("biff/a@0.1.0"),
+                downloaded_line("biff/b@0.1.0"),
+                downloaded_line("2 packages!"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Defines the machine-readable report that `wally install --report` writes,
+//! describing what an install produced.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::Realm;
+use crate::package_id::PackageId;
+use crate::package_source::PackageSourceId;
+use crate::resolution::Resolve;
+
+/// The version of the report's schema. Bump this whenever a field is removed
+/// or changes meaning, so that tools reading reports can tell them apart.
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallReport {
+    pub schema_version: u32,
+
+    /// Every package that was installed, not including the project itself.
+    pub packages: Vec<ReportPackage>,
+
+    /// The link files and types modules written or confirmed up to date by
+    /// the install, relative to the project.
+    pub links: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportPackage {
+    pub id: PackageId,
+
+    /// The realm whose folder the package was installed into.
+    pub realm: Realm,
+
+    /// The registry the package came from: `default` for the project's own
+    /// registry, the location of a fallback registry, or the Git repository
+    /// the project depends on it from.
+    pub source_registry: String,
+
+    pub resolved_from: ResolvedFrom,
+}
+
+/// Whether a package was required by the project itself or only by other
+/// packages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolvedFrom {
+    Direct,
+    Transitive,
+}
+
+impl InstallReport {
+    /// Describe an install of `resolved`, which wrote the files in `links`.
+    pub fn new(
+        project_path: &Path,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+        links: &BTreeSet<PathBuf>,
+    ) -> Self {
+        let packages = ReportPackage::all(root_package_id, resolved);
+
+        let links = links
+            .iter()
+            .map(|path| path.strip_prefix(project_path).unwrap_or(path).to_owned())
+            .collect();
+
+        Self {
+            schema_version: REPORT_SCHEMA_VERSION,
+            packages,
+            links,
+        }
+    }
+}
+
+impl ReportPackage {
+    /// Describe every package in `resolved` other than the project itself.
+    pub fn all(root_package_id: &PackageId, resolved: &Resolve) -> Vec<Self> {
+        let direct: BTreeSet<&PackageId> = [
+            &resolved.shared_dependencies,
+            &resolved.server_dependencies,
+            &resolved.dev_dependencies,
+        ]
+        .iter()
+        .filter_map(|graph| graph.get(root_package_id))
+        .flat_map(|dependencies| dependencies.values())
+        .collect();
+
+        resolved
+            .activated
+            .iter()
+            .filter(|package_id| *package_id != root_package_id)
+            .map(|package_id| {
+                let metadata = &resolved.metadata[package_id];
+
+                ReportPackage {
+                    id: package_id.clone(),
+                    realm: metadata.origin_realm,
+                    source_registry: source_name(&metadata.source_registry),
+                    resolved_from: if direct.contains(package_id) {
+                        ResolvedFrom::Direct
+                    } else {
+                        ResolvedFrom::Transitive
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+fn source_name(source: &PackageSourceId) -> String {
+    match source {
+        PackageSourceId::DefaultRegistry => "default".to_owned(),
+        PackageSourceId::Git(url) => url.clone(),
+        PackageSourceId::Path(path) => path.display().to_string(),
+        PackageSourceId::GitRepository(spec) => spec.to_string(),
+        PackageSourceId::LocalPath(path) => path.clone(),
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use fs_err as fs;
+
+/// Somewhere that packages can be installed to.
+///
+/// Installation only touches the project through this trait, so tools that
+/// embed Wally can install into something other than the real filesystem,
+/// like a [`MemorySink`] in tests.
+pub trait InstallSink: Send + Sync {
+    /// Create a directory along with any missing parent directories. It is not
+    /// an error for the directory to exist already.
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Write a file, replacing it if it already exists.
+    fn write_file(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Read back the contents of a file.
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Remove a directory and everything inside of it.
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove a single file.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Move a file or directory to a new path.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// List the paths of the entries directly inside a directory.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Whether `path` is a directory.
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// Whether `path` is a file.
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Make `link` a symbolic link to the directory at `target`, which is
+    /// outside of the sink. Returns `false` without doing anything if the
+    /// sink or the platform doesn't support it, so that the caller can copy
+    /// the directory instead.
+    fn symlink_dir(&self, _target: &Path, _link: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Whether `path` is a symbolic link, whether or not what it points at
+    /// exists.
+    fn is_symlink(&self, _path: &Path) -> bool {
+        false
+    }
+}
+
+/// Installs packages onto the real filesystem. This is what Wally uses unless
+/// told otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilesystemSink;
+
+impl InstallSink for FilesystemSink {
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    #[cfg(unix)]
+    fn symlink_dir(&self, target: &Path, link: &Path) -> io::Result<bool> {
+        std::os::unix::fs::symlink(target, link)?;
+        Ok(true)
+    }
+
+    // Creating symbolic links on Windows requires either administrator rights
+    // or developer mode, so failing to is expected.
+    #[cfg(windows)]
+    fn symlink_dir(&self, target: &Path, link: &Path) -> io::Result<bool> {
+        match std::os::windows::fs::symlink_dir(target, link) {
+            Ok(()) => Ok(true),
+            Err(err) => {
+                log::debug!("Could not link {}: {}", link.display(), err);
+                Ok(false)
+            }
+        }
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        path.symlink_metadata()
+            .is_ok_and(|metadata| metadata.file_type().is_symlink())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MemoryEntry {
+    Dir,
+    File(Vec<u8>),
+}
+
+/// Installs packages into memory instead of onto disk, which is useful for
+/// inspecting or snapshotting an install without touching the filesystem.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    entries: Mutex<BTreeMap<PathBuf, MemoryEntry>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The contents of every file that has been written, keyed by path.
+    pub fn files(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(path, entry)| match entry {
+                MemoryEntry::File(contents) => Some((path.clone(), contents.clone())),
+                MemoryEntry::Dir => None,
+            })
+            .collect()
+    }
+}
+
+impl InstallSink for MemorySink {
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+
+        for ancestor in path.ancestors() {
+            match entries.get(ancestor) {
+                Some(MemoryEntry::File(_)) => return Err(already_exists(ancestor)),
+                Some(MemoryEntry::Dir) => break,
+                None => {
+                    entries.insert(ancestor.to_owned(), MemoryEntry::Dir);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(parent) = path.parent() {
+            if entries.get(parent) != Some(&MemoryEntry::Dir) {
+                return Err(not_found(parent));
+            }
+        }
+
+        if entries.get(path) == Some(&MemoryEntry::Dir) {
+            return Err(already_exists(path));
+        }
+
+        entries.insert(path.to_owned(), MemoryEntry::File(contents.to_vec()));
+        Ok(())
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.entries.lock().unwrap().get(path) {
+            Some(MemoryEntry::File(contents)) => Ok(contents.clone()),
+            _ => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.get(path) != Some(&MemoryEntry::Dir) {
+            return Err(not_found(path));
+        }
+
+        entries.retain(|entry_path, _| !entry_path.starts_with(path));
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(path) {
+            Some(MemoryEntry::File(_)) => {
+                entries.remove(path);
+                Ok(())
+            }
+            _ => Err(not_found(path)),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+
+        if !entries.contains_key(from) {
+            return Err(not_found(from));
+        }
+
+        let moved: Vec<PathBuf> = entries
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+
+        for path in moved {
+            let entry = entries.remove(&path).unwrap();
+            // Joining an empty path would add a trailing separator, so the
+            // entry being renamed is moved to `to` exactly.
+            let relative_path = path.strip_prefix(from).unwrap();
+            let new_path = if relative_path.as_os_str().is_empty() {
+                to.to_owned()
+            } else {
+                to.join(relative_path)
+            };
+
+            entries.remove(&new_path);
+            entries.insert(new_path, entry);
+        }
+
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries = self.entries.lock().unwrap();
+
+        if entries.get(path) != Some(&MemoryEntry::Dir) {
+            return Err(not_found(path));
+        }
+
+        Ok(entries
+            .keys()
+            .filter(|entry_path| entry_path.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.entries.lock().unwrap().get(path) == Some(&MemoryEntry::Dir)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        matches!(
+            self.entries.lock().unwrap().get(path),
+            Some(MemoryEntry::File(_))
+        )
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+fn already_exists(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} already exists", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_sink_renames_directories() {
+        let sink = MemorySink::new();
+
+        sink.create_dir(Path::new("/project/staging/src")).unwrap();
+        sink.write_file(Path::new("/project/staging/src/init.lua"), b"return {}")
+            .unwrap();
+        sink.rename(Path::new("/project/staging"), Path::new("/project/package"))
+            .unwrap();
+
+        assert!(!sink.is_dir(Path::new("/project/staging")));
+        assert_eq!(
+            sink.read_file(Path::new("/project/package/src/init.lua"))
+                .unwrap(),
+            b"return {}"
+        );
+        assert_eq!(
+            sink.read_dir(Path::new("/project")).unwrap(),
+            vec![PathBuf::from("/project/package")]
+        );
+    }
+
+    #[test]
+    fn memory_sink_requires_parent_directories() {
+        let sink = MemorySink::new();
+
+        let err = sink
+            .write_file(Path::new("/project/init.lua"), b"return {}")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -1,24 +1,45 @@
 use std::{
-    collections::BTreeMap, fmt::Display, io, path::{Path, PathBuf}, time::Duration
+    cell::Cell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    io::{self, Read},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
 };
 
 use anyhow::{bail, format_err};
 use crossterm::style::{Color, SetForegroundColor};
-use fs_err as fs;
-use indicatif::{ProgressBar, ProgressStyle};
-use indoc::{formatdoc, indoc};
+use globset::GlobSet;
+use indoc::formatdoc;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task::JoinError};
+use zip::result::ZipError;
 
 use crate::{
-    extract_types::{extract_types, ExtractTypesResult},
-    manifest::Realm,
-    package_contents::PackageContents,
+    extract_types::{code_ranges, entry_module_path, extract_types, ExtractTypesResult},
+    install_observer::{DevRequire, DownloadRetry, InstallObserver, SilentObserver},
+    install_report::InstallReport,
+    install_sink::{FilesystemSink, InstallSink, MemorySink},
+    manifest::{Layout, LineEndings, PlaceInfo, Realm, MANIFEST_FILE_NAME},
+    package_cache::{PackageCache, PendingEntry},
+    package_contents::{build_glob_set, PackageContents},
     package_id::PackageId,
-    package_source::{PackageSourceMap, PackageSourceProvider},
+    package_name::PackageName,
+    package_source::{
+        is_transient_cause, PackageSource, PackageSourceId, PackageSourceMap,
+        PackageSourceProvider,
+    },
     resolution::Resolve,
+    type_cache::TypeCache,
 };
 
 #[derive(Clone)]
 pub struct InstallationContext {
+    project_path: PathBuf,
     shared_dir: PathBuf,
     shared_index_dir: PathBuf,
     shared_path: Option<String>,
@@ -27,406 +48,4226 @@ pub struct InstallationContext {
     server_path: Option<String>,
     dev_dir: PathBuf,
     dev_index_dir: PathBuf,
+    dry_run: bool,
+    frozen: bool,
+    no_dev: bool,
+    check_orphans: bool,
+    check_dev_requires: bool,
+    require_types: bool,
+    verify: bool,
+    no_links: bool,
+    gitignore: bool,
+    force_gitignore: bool,
+    fail_fast: bool,
+    report_path: Option<PathBuf>,
+    concurrency: Option<usize>,
+    download_retries: u32,
+    locked_checksums: BTreeMap<PackageId, String>,
+    package_cache: Option<PackageCache>,
+    link_cache: bool,
+    type_cache: Option<TypeCache>,
+    combined_types: bool,
+    package_exclude: Vec<String>,
+    line_endings: LineEndings,
+    trailing_newline: bool,
+    disambiguate_file_names: bool,
+    no_type_forwarding: BTreeSet<PackageName>,
+    strict_type_forwarding: bool,
+    layout: Layout,
+    target: Option<PackageName>,
+    sink: Arc<dyn InstallSink>,
+    observer: Arc<dyn InstallObserver>,
+
+    /// The directory name of every package in this install, which only
+    /// differs from `package_id_file_name` for packages whose names collide.
+    file_names: Arc<HashMap<PackageId, String>>,
+
+    /// Where requires of each package's dependencies point once they're
+    /// rewritten for the flat layout, keyed by the dependency's alias.
+    flat_requires: Arc<HashMap<PackageId, BTreeMap<String, FlatRequire>>>,
+
+    /// The checksums that the registry's index recorded for packages when
+    /// they were resolved.
+    resolved_checksums: Arc<BTreeMap<PackageId, String>>,
+
+    /// Every link and types module written or confirmed up to date by this
+    /// install, shared between the tasks writing them.
+    produced_paths: Arc<Mutex<BTreeSet<PathBuf>>>,
+
+    /// Packages downloaded by this install, in the order they were reported.
+    downloaded: Arc<Mutex<Vec<PackageId>>>,
+
+    /// Packages that are completely in place, which are written into the
+    /// install's sentinel file so that an interrupted install can resume.
+    completed: Arc<Mutex<BTreeSet<PackageId>>>,
 }
 
 type PackageTypeExports = BTreeMap<PackageId, ExtractTypesResult>;
 
+/// What an install would produce, returned by [`InstallationContext::plan`].
+#[derive(Debug, Default)]
+pub struct InstallPlan {
+    /// The path and contents of every link and types module the install
+    /// would write, ordered by path.
+    pub links: Vec<(PathBuf, String)>,
+
+    /// The directory that each package would be unpacked into.
+    pub packages: BTreeMap<PackageId, PathBuf>,
+}
+
+/// Link file contents that have already been generated, keyed by the package
+/// being linked to and the realms of the package linking to it and the package
+/// being linked to.
+type LinkCache = HashMap<(PackageId, Realm, Realm), String>;
+
+/// What a require of a dependency is rewritten to in the flat layout.
+#[derive(Debug, Clone)]
+enum FlatRequire {
+    /// A package in the same packages folder as the dependent, which is
+    /// reached through the dependent's parent, with this directory name.
+    Sibling(String),
+
+    /// A package in another realm's packages folder, at this place path.
+    Place(String),
+}
+
+/// The packages in a packages folder with the flat layout that were installed
+/// completely, saved alongside them so that changed packages, whose
+/// directories keep the same name, are installed again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct FlatState {
+    /// The place paths that requires into other realms were rewritten with.
+    shared_packages: Option<String>,
+    server_packages: Option<String>,
+
+    packages: BTreeSet<PackageId>,
+}
+
+/// Errors from installing packages that are worth telling apart from other
+/// failures.
+#[derive(Debug)]
+pub enum InstallError {
+    /// A package depends on a package in another realm, but the project
+    /// doesn't declare where packages in that realm are placed.
+    MissingPlacePath {
+        realm: Realm,
+        offending_package: PackageId,
+        dependent_package: PackageId,
+    },
+
+    /// Two packages would be installed to the same directory, at least on
+    /// filesystems that ignore case.
+    FileNameCollision { first: PackageId, second: PackageId },
+
+    /// Links that require a module that doesn't exist, along with the
+    /// `require` target of each.
+    DanglingLinks { links: Vec<(PathBuf, String)> },
+
+    /// Two packages would be installed to the same directory in the flat
+    /// layout, which names directories after packages alone.
+    FlatLayoutConflict { first: PackageId, second: PackageId },
+
+    /// Packages that export no types, when every package was required to.
+    MissingTypes { packages: Vec<PackageId> },
+
+    /// More than one package failed to install.
+    PackagesFailed {
+        failures: Vec<(PackageId, anyhow::Error)>,
+    },
+}
+
+impl Display for InstallError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstallError::MissingPlacePath {
+                realm,
+                offending_package,
+                dependent_package,
+            } => {
+                let (realm_name, place_key, example_path) = match realm {
+                    Realm::Shared => ("shared", "shared-packages", "game.ReplicatedStorage.Packages"),
+                    Realm::Server => ("server", "server-packages", "game.ServerScriptService.Packages"),
+                    Realm::Dev => unreachable!("dev packages are never linked to from another realm"),
+                };
+
+                formatter.write_str(&formatdoc! {r#"
+                        {dependent} depends on {offending}, which is a {realm} dependency.
+                        To link these packages correctly you must declare where {realm}
+                        packages are placed in the roblox datamodel in your wally.toml.
+
+                        This typically looks like:
+
+                        [place]
+                        {key} = "{path}"
+                    "#,
+                    dependent = dependent_package,
+                    offending = offending_package,
+                    realm = realm_name,
+                    key = place_key,
+                    path = example_path,
+                })
+            }
+            InstallError::FileNameCollision { first, second } => write!(
+                formatter,
+                "{} and {} would both be installed to {} on case-insensitive filesystems, \
+                 so they can't be installed together",
+                first,
+                second,
+                package_id_file_name(first)
+            ),
+            InstallError::FlatLayoutConflict { first, second } => write!(
+                formatter,
+                "{} and {} would both be installed to {}, but the flat layout can only install \
+                 one package with each name. Use the index layout to install both.",
+                first,
+                second,
+                first.name().name()
+            ),
+            InstallError::DanglingLinks { links } => {
+                formatter.write_str("These generated links require modules that don't exist:")?;
+
+                for (link, target) in links {
+                    write!(
+                        formatter,
+                        "\n    {}\n      requires {}",
+                        link.display(),
+                        target
+                    )?;
+                }
+
+                Ok(())
+            }
+            InstallError::MissingTypes { packages } => {
+                formatter.write_str(
+                    "These packages export no types, but --require-types requires every package \
+                     to:",
+                )?;
+
+                for package_id in packages {
+                    write!(formatter, "\n    {}", package_id)?;
+                }
+
+                Ok(())
+            }
+            InstallError::PackagesFailed { failures } => {
+                write!(formatter, "{} packages failed to install:", failures.len())?;
+
+                for (package_id, err) in failures {
+                    write!(formatter, "\n\n{}: {:#}", package_id, err)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for InstallError {}
+
+/// The file that marks a project whose install is in progress, listing the
+/// packages that are completely in place, one per line. An install that finds
+/// it left behind by one that was cancelled or crashed keeps those packages and
+/// removes every other installed package first, since they may be half-written.
+pub const INSTALL_SENTINEL_FILE_NAME: &str = ".wally-install-in-progress";
+
+/// The `.gitignore` written into each packages folder, which ignores everything
+/// in the folder except itself.
+pub const PACKAGES_GITIGNORE: &str = "# Generated by Wally.\n*\n!.gitignore\n";
+
+/// The file in each packages folder with the flat layout that records which
+/// packages are installed there.
+pub const FLAT_STATE_FILE_NAME: &str = ".wally-installed";
+
+/// How long to wait before the first retry of a failed download. Each
+/// following retry waits twice as long as the one before it, up to
+/// `RETRY_MAX_DELAY`.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// The longest that any retry of a failed download waits.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
 impl InstallationContext {
-    /// Create a new `InstallationContext` for the given path.
-    pub fn new(
-        project_path: &Path,
-        shared_path: Option<String>,
-        server_path: Option<String>,
-    ) -> Self {
-        let shared_dir = project_path.join("Packages");
-        let server_dir = project_path.join("ServerPackages");
-        let dev_dir = project_path.join("DevPackages");
+    /// Create a new `InstallationContext` for the given path, placing packages
+    /// as described by the project's `[place]` section.
+    pub fn new(project_path: &Path, place: &PlaceInfo) -> Self {
+        let shared_dir = project_path.join(place.shared_packages_dir());
+        let server_dir = project_path.join(place.server_packages_dir());
+        let dev_dir = project_path.join(place.dev_packages_dir());
 
         let shared_index_dir = shared_dir.join("_Index");
         let server_index_dir = server_dir.join("_Index");
         let dev_index_dir = dev_dir.join("_Index");
 
         Self {
+            project_path: project_path.to_owned(),
             shared_dir,
             shared_index_dir,
-            shared_path,
+            shared_path: place.shared_packages.clone(),
             server_dir,
             server_index_dir,
-            server_path,
+            server_path: place.server_packages.clone(),
             dev_dir,
             dev_index_dir,
+            dry_run: false,
+            frozen: false,
+            no_dev: false,
+            check_orphans: false,
+            check_dev_requires: false,
+            require_types: false,
+            verify: false,
+            no_links: false,
+            gitignore: place.gitignore,
+            force_gitignore: false,
+            fail_fast: false,
+            report_path: None,
+            concurrency: None,
+            download_retries: 0,
+            locked_checksums: BTreeMap::new(),
+            package_cache: None,
+            link_cache: false,
+            type_cache: None,
+            combined_types: place.combined_types,
+            package_exclude: place.package_exclude.clone(),
+            line_endings: place.link_line_endings,
+            trailing_newline: place.link_trailing_newline,
+            disambiguate_file_names: place.disambiguate_file_names,
+            no_type_forwarding: place.no_type_forwarding.iter().cloned().collect(),
+            strict_type_forwarding: place.strict_type_forwarding,
+            layout: place.layout,
+            target: None,
+            sink: Arc::new(FilesystemSink),
+            observer: Arc::new(SilentObserver),
+            file_names: Arc::new(HashMap::new()),
+            flat_requires: Arc::new(HashMap::new()),
+            resolved_checksums: Arc::new(BTreeMap::new()),
+            produced_paths: Arc::new(Mutex::new(BTreeSet::new())),
+            downloaded: Arc::new(Mutex::new(Vec::new())),
+            completed: Arc::new(Mutex::new(BTreeSet::new())),
         }
     }
 
+    /// Report what would be installed instead of writing anything to disk.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Install packages through the given sink instead of onto the
+    /// filesystem.
+    pub fn with_sink(mut self, sink: Arc<dyn InstallSink>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// Report the install's progress to the given observer as it happens.
+    pub fn with_observer(mut self, observer: Arc<dyn InstallObserver>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Install packages through a package cache shared with other projects.
+    pub fn with_package_cache(mut self, package_cache: Option<PackageCache>) -> Self {
+        self.package_cache = package_cache;
+        self
+    }
+
+    /// Link the contents of packages to their entries in the package cache
+    /// instead of copying them, where the platform allows it. Packages whose
+    /// contents the install changes, because of `package-exclude` or the flat
+    /// layout, are still copied so that the cache isn't changed with them.
+    pub fn with_link_cache(mut self, link_cache: bool) -> Self {
+        self.link_cache = link_cache;
+        self
+    }
+
+    /// Reuse the types parsed from identical modules by earlier installs
+    /// instead of parsing them again.
+    pub fn with_type_cache(mut self, type_cache: Option<TypeCache>) -> Self {
+        self.type_cache = type_cache;
+        self
+    }
+
+    /// Only install packages that are already available locally, failing
+    /// instead of downloading anything.
+    pub fn with_frozen(mut self, frozen: bool) -> Self {
+        self.frozen = frozen;
+        self
+    }
+
+    /// Leave out dev dependencies, removing any that were installed before.
+    /// Packages that are also needed outside of dev dependencies are still
+    /// installed.
+    pub fn with_no_dev(mut self, no_dev: bool) -> Self {
+        self.no_dev = no_dev;
+        self
+    }
+
+    /// Warn about `.lua` and `.luau` files in the package directories that
+    /// weren't produced by the install. Those files are left in place rather
+    /// than removed as stale.
+    pub fn with_check_orphans(mut self, check_orphans: bool) -> Self {
+        self.check_orphans = check_orphans;
+        self
+    }
+
+    /// Warn about files outside of the dev packages folder that require
+    /// something inside of it, both in the generated links and in the
+    /// project's own source. Test files, like `*.spec.lua`, are allowed to.
+    pub fn with_check_dev_requires(mut self, check_dev_requires: bool) -> Self {
+        self.check_dev_requires = check_dev_requires;
+        self
+    }
+
+    /// Fail if any installed package exports no types for links to forward,
+    /// listing every package that doesn't.
+    pub fn with_require_types(mut self, require_types: bool) -> Self {
+        self.require_types = require_types;
+        self
+    }
+
+    /// Check that every link and types module requires a module that exists
+    /// once the install has finished, failing if any of them don't.
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Only unpack packages into the index, without reading their types or
+    /// writing any links to them, for tools that link packages themselves.
+    /// Links left by earlier installs are removed.
+    pub fn with_no_links(mut self, no_links: bool) -> Self {
+        self.no_links = no_links;
+        self
+    }
+
+    /// Write a `.gitignore` into each packages folder, so that installed
+    /// packages aren't committed.
+    pub fn with_gitignore(mut self, gitignore: bool) -> Self {
+        self.gitignore = gitignore;
+        self
+    }
+
+    /// Replace a `.gitignore` in a packages folder even if it was changed by
+    /// hand, instead of leaving it alone.
+    pub fn with_force_gitignore(mut self, force_gitignore: bool) -> Self {
+        self.force_gitignore = force_gitignore;
+        self
+    }
+
+    /// Lay out packages as given, instead of as the project's `[place]`
+    /// section says.
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Stop at the first package that fails to install, instead of trying
+    /// every package and reporting all of the failures together.
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Write an `InstallReport` describing the install to the given path once
+    /// it has finished.
+    pub fn with_report_path(mut self, report_path: Option<PathBuf>) -> Self {
+        self.report_path = report_path;
+        self
+    }
+
+    /// Set the number of packages to download in parallel. When unset, this
+    /// is based on the number of available CPUs.
+    pub fn with_concurrency(mut self, concurrency: Option<usize>) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Set how many times a download that failed due to a network or IO error
+    /// will be retried before giving up.
+    pub fn with_download_retries(mut self, retries: u32) -> Self {
+        self.download_retries = retries;
+        self
+    }
+
+    /// Set the checksums that downloaded packages must match, usually the
+    /// ones recorded in the project's lockfile.
+    pub fn with_locked_checksums(mut self, checksums: BTreeMap<PackageId, String>) -> Self {
+        self.locked_checksums = checksums;
+        self
+    }
+
+    /// Only reinstall the packages with this name and the dependencies that
+    /// nothing else in the graph needs, leaving every other installed package
+    /// as it is.
+    pub fn with_target(mut self, target: Option<PackageName>) -> Self {
+        self.target = target;
+        self
+    }
+
     /// Delete the existing index, if it exists.
     pub fn clean(&self) -> anyhow::Result<()> {
-        fn remove_ignore_not_found(path: &Path) -> io::Result<()> {
-            if let Err(err) = fs::remove_dir_all(path) {
-                if err.kind() != io::ErrorKind::NotFound {
-                    return Err(err);
-                }
+        for &realm in [Realm::Shared, Realm::Server, Realm::Dev].iter() {
+            self.clean_realm(realm)?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete the folder that packages in `realm` are installed into, if it
+    /// exists.
+    pub fn clean_realm(&self, realm: Realm) -> anyhow::Result<()> {
+        remove_ignore_not_found(&*self.sink, self.root_links_dir(realm))?;
+
+        Ok(())
+    }
+
+    /// Pick up an install that was interrupted, given the sentinel file it
+    /// left behind. Packages it didn't finish are removed, and the ones it
+    /// did are returned so that they aren't downloaded again.
+    fn resume_install(
+        &self,
+        sentinel_path: &Path,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+    ) -> anyhow::Result<BTreeSet<PackageId>> {
+        let journal = self.sink.read_file(sentinel_path)?;
+        let completed: BTreeSet<PackageId> = String::from_utf8_lossy(&journal)
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .filter(|package_id| resolved.activated.contains(package_id))
+            .collect();
+
+        log::warn!(
+            "The last install of this project didn't finish, so it will be resumed: {} \
+             finished packages are kept and the rest will be installed again",
+            completed.len()
+        );
+
+        for package_id in &resolved.activated {
+            if package_id == root_package_id || completed.contains(package_id) {
+                continue;
             }
 
-            Ok(())
+            let realm = resolved.metadata[package_id].origin_realm;
+            let contents_path = self.package_contents_path(package_id, realm);
+            remove_ignore_not_found(&*self.sink, &contents_path)?;
         }
 
-        remove_ignore_not_found(&self.shared_dir)?;
-        remove_ignore_not_found(&self.server_dir)?;
-        remove_ignore_not_found(&self.dev_dir)?;
+        Ok(completed)
+    }
+
+    /// Record that a package is completely in place, so that it's kept if
+    /// this install is interrupted. Failing to record it only means it's
+    /// installed again by the next install, so errors are logged rather than
+    /// returned.
+    fn record_completed(&self, package_id: &PackageId) {
+        self.completed.lock().unwrap().insert(package_id.clone());
 
-        Ok(())
+        if let Err(err) = self.write_install_journal() {
+            log::debug!(
+                "Failed to record that {} was installed: {}",
+                package_id,
+                err
+            );
+        }
+    }
+
+    /// Write the packages that are completely in place into the sentinel
+    /// file, one per line.
+    fn write_install_journal(&self) -> io::Result<()> {
+        // Held while writing so that two workers can't write an older list
+        // over a newer one.
+        let completed = self.completed.lock().unwrap();
+        let journal: String = completed
+            .iter()
+            .map(|package_id| format!("{}\n", package_id))
+            .collect();
+
+        self.sink.write_file(
+            &self.project_path.join(INSTALL_SENTINEL_FILE_NAME),
+            journal.as_bytes(),
+        )
     }
 
     /// Install all packages from the given `Resolve` into the package that this
     /// `InstallationContext` was built for.
+    ///
+    /// Returns the checksum of every installed package, suitable for saving
+    /// into a lockfile.
     pub fn install(
-        self,
+        mut self,
         sources: PackageSourceMap,
         root_package_id: PackageId,
         resolved: Resolve,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<BTreeMap<PackageId, String>> {
+        self.file_names = Arc::new(file_names(
+            &root_package_id,
+            &resolved,
+            self.disambiguate_file_names,
+        )?);
+        self.resolved_checksums = Arc::new(
+            resolved
+                .metadata
+                .iter()
+                .filter_map(|(package_id, metadata)| {
+                    let checksum = metadata.checksum.clone()?;
+                    Some((package_id.clone(), checksum))
+                })
+                .collect(),
+        );
+
+        let mut checksums = BTreeMap::new();
+
+        let resolved = if self.no_dev {
+            let without_dev = resolved.without_dev_dependencies();
+
+            // Packages that are left out still belong in the lockfile, so the
+            // checksums it already has for them are kept.
+            for package_id in resolved.activated.difference(&without_dev.activated) {
+                if let Some(checksum) = self.locked_checksums.get(package_id) {
+                    checksums.insert(package_id.clone(), checksum.clone());
+                }
+            }
+
+            without_dev
+        } else {
+            resolved
+        };
+
+        if self.layout == Layout::Flat {
+            check_flat_names(&root_package_id, &resolved)?;
+            self.flat_requires = Arc::new(self.flat_requires(&root_package_id, &resolved)?);
+        }
+
+        if self.dry_run {
+            self.report_dry_run(&root_package_id, &resolved);
+            return Ok(BTreeMap::new());
+        }
+
+        let targeted = match &self.target {
+            Some(name) => Some(target_subtree(&root_package_id, &resolved, name)?),
+            None => None,
+        };
+
+        let sentinel_path = self.project_path.join(INSTALL_SENTINEL_FILE_NAME);
+        let completed = if self.sink.is_file(&sentinel_path) {
+            self.resume_install(&sentinel_path, &root_package_id, &resolved)?
+        } else {
+            // Whatever an install that finished left behind is complete.
+            resolved
+                .activated
+                .iter()
+                .filter(|package_id| **package_id != root_package_id)
+                .filter(|package_id| {
+                    let realm = resolved.metadata[*package_id].origin_realm;
+                    self.sink
+                        .is_dir(&self.package_contents_path(package_id, realm))
+                })
+                .cloned()
+                .collect()
+        };
+        *self.completed.lock().unwrap() = completed;
+        self.sink.create_dir(&self.project_path)?;
+        self.write_install_journal()?;
+
+        if self.no_dev {
+            remove_ignore_not_found(&*self.sink, &self.dev_dir)?;
+        }
+
+        // Reinstalling one package leaves everything else in the index alone,
+        // even entries that are stale.
+        if targeted.is_none() {
+            self.remove_stale_entries(&root_package_id, &resolved)?;
+        }
+
         let mut handles = Vec::new();
+        let mut installed_paths = Vec::new();
+        let mut failures = Vec::new();
         let resolved_copy = resolved.clone();
-        let bar = ProgressBar::new((resolved_copy.activated.len() - 1) as u64).with_style(
-            ProgressStyle::with_template(
-                "{spinner:.cyan.bold} {pos}/{len} [{wide_bar:.cyan/blue}]",
-            )
-            .unwrap()
-            .tick_chars("⠁⠈⠐⠠⠄⠂ ")
-            .progress_chars("#>-"),
-        );
-        bar.enable_steady_tick(Duration::from_millis(100));
 
+        let package_count = match &targeted {
+            Some(targeted) => targeted.len(),
+            None => resolved_copy.activated.len().saturating_sub(1),
+        };
+        self.observer.on_install_start(package_count);
+
+        // There's never more work at once than there are packages, so a
+        // project with no dependencies only needs one worker for its links.
+        let worker_threads = self
+            .concurrency
+            .unwrap_or_else(default_concurrency)
+            .clamp(1, package_count.max(1));
+        log::debug!("Downloading packages using {} worker threads", worker_threads);
+
+        // Downloads run as blocking tasks, so the blocking pool is what
+        // actually bounds how many happen at once.
         let runtime = tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(50)
+            .worker_threads(worker_threads)
+            .max_blocking_threads(worker_threads)
             .enable_all()
             .build()
             .unwrap();
 
+        // Each download holds a slot from spawning until its package is
+        // unpacked, so a graph with thousands of packages never queues more
+        // tasks than there are workers, and never holds more downloads in
+        // memory or open for unpacking at once.
+        let download_slots = Arc::new(Semaphore::new(worker_threads));
+
+        // `activated` is ordered by `PackageId`, so downloads are always
+        // started in the same order.
         for package_id in &resolved_copy.activated {
             // Shadow because the thread will need to take ownership of this value.
             let package_id = package_id.clone();
             if package_id != root_package_id {
-                log::debug!("Downloading package {}...", package_id);
-
                 let metadata = resolved.metadata.get(&package_id).unwrap();
                 let package_realm = metadata.origin_realm;
+
+                // Packages in a local directory can change without their
+                // version changing, so they're copied again on every install.
+                let local = matches!(metadata.source_registry, PackageSourceId::LocalPath(_));
+
+                // The cache is keyed by package ID, which a package from a Git
+                // repository or a local directory may share with a different
+                // package in a registry.
+                let cacheable = metadata.git_commit.is_none() && !local;
+
+                let contents_path = self.package_contents_path(&package_id, package_realm);
+                let is_target = targeted
+                    .as_ref()
+                    .map(|targeted| targeted.contains(&package_id));
+
+                if is_target == Some(false) {
+                    // Packages outside of the target are only read for the
+                    // types that links to them forward.
+                    if let Some(checksum) = self.locked_checksums.get(&package_id) {
+                        checksums.insert(package_id.clone(), checksum.clone());
+                    }
+
+                    if self.sink.is_dir(&contents_path) {
+                        installed_paths.push((package_id, contents_path));
+                    }
+                    continue;
+                }
+
+                if (local || is_target == Some(true)) && !self.frozen {
+                    // Targets are installed from scratch. Packages in a local
+                    // directory are synced with it instead, so that only the
+                    // files that changed are written.
+                    if !local {
+                        remove_ignore_not_found(&*self.sink, &contents_path)?;
+                    }
+                } else if self.sink.is_dir(&contents_path) {
+                    log::debug!("Package {} is already installed, skipping", package_id);
+                    self.observer.on_package_installed(&package_id, false);
+
+                    if let Some(checksum) = self.locked_checksums.get(&package_id) {
+                        checksums.insert(package_id.clone(), checksum.clone());
+                    }
+
+                    installed_paths.push((package_id, contents_path));
+                    continue;
+                }
+
+                // A targeted package is downloaded again, in case the copy in
+                // the cache is what's misbehaving.
+                let cached = if cacheable && is_target.is_none() {
+                    self.install_from_cache(&package_id, package_realm)?
+                } else {
+                    None
+                };
+
+                if let Some(checksum) = cached {
+                    log::debug!("Installed package {} from the package cache", package_id);
+                    self.observer.on_package_installed(&package_id, false);
+                    self.record_completed(&package_id);
+
+                    checksums.insert(package_id.clone(), checksum);
+                    installed_paths.push((package_id, contents_path));
+                    continue;
+                }
+
+                if self.frozen {
+                    let err = format_err!(
+                        "Package {} is not installed locally or in the package cache and cannot \
+                         be downloaded because --frozen was passed",
+                        package_id
+                    );
+
+                    if self.fail_fast {
+                        return Err(err);
+                    }
+
+                    failures.push((package_id, err));
+                    continue;
+                }
+
+                log::debug!("Downloading package {}...", package_id);
+
                 let source_registry = resolved_copy.metadata[&package_id].source_registry.clone();
                 let source_copy = sources.clone();
                 let context = self.clone();
-                let b = bar.clone();
+                let package_id_copy = package_id.clone();
+                let slot = runtime
+                    .block_on(Arc::clone(&download_slots).acquire_owned())
+                    .expect("Download slots are never closed");
 
                 let handle = runtime.spawn_blocking(move || {
+                    let _slot = slot;
+
+                    context.observer.on_download_start(&package_id);
+
                     let package_source = source_copy.get(&source_registry).unwrap();
-                    let contents = package_source.download_package(&package_id)?;
-                    b.println(format!(
-                        "{} Downloaded {}{}",
-                        SetForegroundColor(Color::DarkGreen),
-                        SetForegroundColor(Color::Reset),
-                        package_id,
-                    ));
-                    b.inc(1);
-
-                    let write_result =
-                        context.write_contents(&package_id, &contents, package_realm);
-                    write_result.map(|path| {
-                        (package_id, extract_types(&path))
-                    })
+                    let result = context.download_with_retries(
+                        package_source,
+                        &package_id,
+                        package_realm,
+                        cacheable,
+                    );
+                    context.observer.on_download_complete(&package_id);
+
+                    if result.is_ok() {
+                        context.record_completed(&package_id);
+                    }
+
+                    result
                 });
 
-                handles.push(handle);
+                handles.push((package_id_copy, handle));
             }
         }
 
-        let num_packages = handles.len();
-        let mut types_for_package = PackageTypeExports::new();
-        for handle in handles {
-            let (package_id, exported_types) = runtime
+        // Downloads finish in whatever order the network allows, so they're
+        // reported here in the order they were started instead, keeping the
+        // output the same from one run to the next.
+        for (package_id, handle) in handles {
+            let result = runtime
                 .block_on(handle)
-                .expect("Package failed to be installed.")?;
+                .unwrap_or_else(|err| Err(task_error(err, "Installing", &package_id)));
 
-            types_for_package.insert(package_id, exported_types);
-        }
+            match result {
+                Ok((path, checksum)) => {
+                    self.observer.on_package_installed(&package_id, true);
+                    self.downloaded.lock().unwrap().push(package_id.clone());
 
-        for package_id in &resolved_copy.activated {
-            log::debug!("Installing package {}...", package_id);
-
-            let shared_deps = resolved.shared_dependencies.get(&package_id);
-            let server_deps = resolved.server_dependencies.get(&package_id);
-            let dev_deps = resolved.dev_dependencies.get(&package_id);
-
-            // Then 3), run these loops, passing in the registry object.
-            // We do not need to install the root package, but we should create
-            // package links for its dependencies.
-            if *package_id == root_package_id {
-                if let Some(deps) = shared_deps {
-                    self.write_root_package_links(Realm::Shared, deps, &resolved, &types_for_package)?;
+                    checksums.insert(package_id.clone(), checksum);
+                    installed_paths.push((package_id, path));
                 }
+                Err(err) if self.fail_fast => return Err(err),
+                Err(err) => failures.push((package_id, err)),
+            }
+        }
 
-                if let Some(deps) = server_deps {
-                    self.write_root_package_links(Realm::Server, deps, &resolved, &types_for_package)?;
-                }
+        // Types are only read to be forwarded through links, which the flat
+        // layout doesn't have, unless they're required.
+        if !self.writes_links() && !self.require_types {
+            installed_paths.clear();
+        }
 
-                if let Some(deps) = dev_deps {
-                    self.write_root_package_links(Realm::Dev, deps, &resolved, &types_for_package)?;
-                }
-            } else {
-                let metadata = resolved.metadata.get(&package_id).unwrap();
-                let package_realm = metadata.origin_realm;
+        // Types are extracted once every package is on disk, so that parsing a
+        // large module never holds up a worker that could be downloading.
+        let mut type_handles = Vec::new();
+        let mut types_for_package = PackageTypeExports::new();
 
-                if let Some(deps) = shared_deps {
-                    self.write_package_links(&package_id, package_realm, deps, &resolved, &types_for_package)?;
-                }
+        for (package_id, path) in installed_paths {
+            // Links to these are plain requires, as if they exported nothing.
+            if self.no_type_forwarding.contains(package_id.name()) {
+                types_for_package.insert(package_id, ExtractTypesResult::new());
+                continue;
+            }
+
+            let context = self.clone();
+            let handle = runtime.spawn_blocking(move || {
+                extract_types(&*context.sink, &path, context.type_cache.as_ref())
+            });
+
+            type_handles.push((package_id, handle));
+        }
 
-                if let Some(deps) = server_deps {
-                    self.write_package_links(&package_id, package_realm, deps, &resolved, &types_for_package)?;
+        for (package_id, handle) in type_handles {
+            match runtime.block_on(handle) {
+                Ok(exported_types) => {
+                    // These are only actionable by the package's author, so
+                    // they're only shown with `--verbose`.
+                    for warning in exported_types.warnings(&package_id) {
+                        log::debug!("{}", warning);
+                    }
+
+                    types_for_package.insert(package_id, exported_types);
                 }
+                Err(err) => {
+                    let err = task_error(err, "Extracting types from", &package_id);
+                    if self.fail_fast {
+                        return Err(err);
+                    }
 
-                if let Some(deps) = dev_deps {
-                    self.write_package_links(&package_id, package_realm, deps, &resolved, &types_for_package)?;
+                    failures.push((package_id, err));
                 }
             }
         }
 
-        bar.finish_and_clear();
-        log::info!("Downloaded {} packages!", num_packages);
-
-        Ok(())
-    }
+        if self.require_types {
+            // Packages that failed to install are reported as failures instead,
+            // and packages whose types aren't forwarded don't need any.
+            let packages: Vec<PackageId> = types_for_package
+                .iter()
+                .filter(|(package_id, exported_types)| {
+                    exported_types.is_empty()
+                        && !self.no_type_forwarding.contains(package_id.name())
+                })
+                .map(|(package_id, _)| package_id.clone())
+                .collect();
 
-    /// Contents of a package-to-package link within the same index.
-    fn link_sibling_same_index(&self, id: &PackageId, exports: &ExtractTypesResult) -> String {
-        // TODO: Here, pass and write set of types
-        if exports.is_empty() {
-            formatdoc! {r#"
-                return require(script.Parent.Parent["{full_name}"]["{short_name}"])
-                "#,
-                full_name = package_id_file_name(id),
-                short_name = id.name().name()
-            }
-        } else {
-            formatdoc! {r#"
-                local MODULE = require(script.Parent.Parent["{full_name}"]["{short_name}"])
-                {exports_string}
-                return MODULE
-                "#,
-                full_name = package_id_file_name(id),
-                short_name = id.name().name(),
-                exports_string = exports.format_forwarding_statements("MODULE")
+            if !packages.is_empty() {
+                bail!(InstallError::MissingTypes { packages });
             }
         }
 
-    }
+        // Links are written across the same thread pool as downloads. Errors
+        // are collected once every package has finished so that one bad link
+        // doesn't leave other packages half-linked.
+        let resolved = Arc::new(resolved);
+        let types_for_package = Arc::new(types_for_package);
+        let link_cache = Arc::new(Mutex::new(LinkCache::new()));
+        let mut link_handles = Vec::new();
 
-    /// Contents of a root-to-package link within the same index.
-    fn link_root_same_index(&self, id: &PackageId, exports: &ExtractTypesResult) -> String {
-        if exports.is_empty() {
-            formatdoc! {r#"
-                return require(script.Parent._Index["{full_name}"]["{short_name}"])
-                "#,
-                full_name = package_id_file_name(id),
-                short_name = id.name().name()
+        for package_id in resolved_copy
+            .activated
+            .iter()
+            .filter(|_| self.writes_links())
+        {
+            // Packages that failed to install don't get links of their own.
+            if *package_id != root_package_id && !types_for_package.contains_key(package_id) {
+                continue;
             }
-        } else {
-            formatdoc! {r#"
-                local MODULE = require(script.Parent._Index["{full_name}"]["{short_name}"])
-                {exports_string}
-                return MODULE
-                "#,
-                full_name = package_id_file_name(id),
-                short_name = id.name().name(),
-                exports_string = exports.format_forwarding_statements("MODULE")
+
+            // Only the target's links are written again, along with the root
+            // package's if it depends on the target directly.
+            if let Some(targeted) = &targeted {
+                let relinked = if *package_id == root_package_id {
+                    direct_dependencies(&resolved, package_id)
+                        .any(|dependency| targeted.contains(dependency))
+                } else {
+                    targeted.contains(package_id)
+                };
+
+                if !relinked {
+                    continue;
+                }
             }
+
+            let package_id = package_id.clone();
+            let root_package_id = root_package_id.clone();
+            let context = self.clone();
+            let resolved = Arc::clone(&resolved);
+            let types_for_package = Arc::clone(&types_for_package);
+            let link_cache = Arc::clone(&link_cache);
+
+            let package_id_copy = package_id.clone();
+            let handle = runtime.spawn_blocking(move || {
+                context.write_links(
+                    &package_id,
+                    &root_package_id,
+                    &resolved,
+                    &types_for_package,
+                    &link_cache,
+                )
+            });
+
+            link_handles.push((package_id_copy, handle));
         }
-    }
 
-    /// Contents of a link into the shared index from outside the shared index.
-    fn link_shared_index(&self, id: &PackageId, exports: &ExtractTypesResult) -> anyhow::Result<String> {
-        let shared_path = self.shared_path.as_ref().ok_or_else(|| {
-            format_err!(indoc! {r#"
-                A server or dev dependency is depending on a shared dependency.
-                To link these packages correctly you must declare where shared
-                packages are placed in the roblox datamodel in your wally.toml.
-                
-                This typically looks like:
-
-                [place]
-                shared-packages = "game.ReplicatedStorage.Packages"
-            "#})
-        })?;
+        let mut links_written = 0;
+        let mut link_errors = Vec::new();
 
-        let contents = if exports.is_empty() {
-            formatdoc! {r#"
-                return require({packages}._Index["{full_name}"]["{short_name}"])
-                "#,
-                packages = shared_path,
-                full_name = package_id_file_name(id),
-                short_name = id.name().name()
+        for (package_id, handle) in link_handles {
+            match runtime
+                .block_on(handle)
+                .unwrap_or_else(|err| Err(task_error(err, "Writing links for", &package_id)))
+            {
+                Ok(written) => links_written += written,
+                Err(err) => link_errors.push(err),
             }
-        } else {
-            formatdoc! {r#"
-                local MODULE = require({packages}._Index["{full_name}"]["{short_name}"])
-                {exports_string}
-                return MODULE
-                "#,
-                packages = shared_path,
-                full_name = package_id_file_name(id),
-                short_name = id.name().name(),
-                exports_string = exports.format_forwarding_statements("MODULE")
+        }
+
+        let mut link_errors = link_errors.into_iter();
+        if let Some(err) = link_errors.next() {
+            for other in link_errors {
+                log::error!("{:#}", other);
             }
-        };
 
-        Ok(contents)
-    }
+            return Err(err);
+        }
 
-    /// Contents of a link into the server index from outside the server index.
-    fn link_server_index(&self, id: &PackageId, exports: &ExtractTypesResult) -> anyhow::Result<String> {
-        let server_path = self.server_path.as_ref().ok_or_else(|| {
-            format_err!(indoc! {r#"
-                A dev dependency is depending on a server dependency.
-                To link these packages correctly you must declare where server
-                packages are placed in the roblox datamodel in your wally.toml.
-                
-                This typically looks like:
-
-                [place]
-                server-packages = "game.ServerScriptService.Packages"
-            "#})
-        })?;
+        log::debug!("Wrote {} link files", links_written);
 
-        let contents = if exports.is_empty() {
-            formatdoc! {r#"
-                return require({packages}._Index["{full_name}"]["{short_name}"])
-                "#,
-                packages = server_path,
-                full_name = package_id_file_name(id),
-                short_name = id.name().name()
+        if !failures.is_empty() {
+            // A single failure is returned as-is so that it reads the same as
+            // it would with --fail-fast.
+            if failures.len() == 1 {
+                return Err(failures.pop().unwrap().1);
             }
+
+            bail!(InstallError::PackagesFailed { failures });
+        }
+
+        if self.layout == Layout::Flat {
+            self.write_flat_states(&root_package_id, &resolved, targeted.as_ref())?;
+        }
+
+        if self.gitignore {
+            self.write_gitignores()?;
+        }
+
+        if self.verify {
+            self.verify_links()?;
+        }
+
+        if self.check_orphans {
+            self.warn_about_orphans(&root_package_id, &resolved)?;
+        }
+
+        if self.check_dev_requires {
+            self.warn_about_dev_requires()?;
+        }
+
+        if let Some(report_path) = &self.report_path {
+            let report = InstallReport::new(
+                &self.project_path,
+                &root_package_id,
+                &resolved,
+                &self.produced_paths.lock().unwrap(),
+            );
+
+            self.sink
+                .write_file(report_path, &serde_json::to_vec_pretty(&report)?)?;
+        }
+
+        self.sink.remove_file(&sentinel_path)?;
+
+        log::info!(
+            "Downloaded {} packages!",
+            self.downloaded.lock().unwrap().len()
+        );
+
+        Ok(checksums)
+    }
+
+    /// Work out what installing the given `Resolve` would produce, without
+    /// writing anything to disk: the contents of every link and types module,
+    /// and the directory each package would be unpacked into. The plan is for
+    /// a complete install, as if nothing was installed yet.
+    ///
+    /// Links depend on the types that packages export, so every package is
+    /// still downloaded, into memory. The package and type caches aren't
+    /// used, since using them fills them in, and nothing is reported or
+    /// checked, since planning shouldn't warn or fail where installing
+    /// wouldn't.
+    pub fn plan(
+        mut self,
+        sources: PackageSourceMap,
+        root_package_id: PackageId,
+        resolved: Resolve,
+    ) -> anyhow::Result<InstallPlan> {
+        self.file_names = Arc::new(file_names(
+            &root_package_id,
+            &resolved,
+            self.disambiguate_file_names,
+        )?);
+
+        let installed = if self.no_dev {
+            resolved.without_dev_dependencies()
         } else {
-            formatdoc! {r#"
-                local MODULE = require({packages}._Index["{full_name}"]["{short_name}"])
-                {exports_string}
-                return MODULE
-                "#,
-                packages = server_path,
-                full_name = package_id_file_name(id),
-                short_name = id.name().name(),
-                exports_string = exports.format_forwarding_statements("MODULE")
-            }
+            resolved.clone()
         };
+        let packages = installed
+            .activated
+            .iter()
+            .filter(|package_id| **package_id != root_package_id)
+            .map(|package_id| {
+                let realm = installed.metadata[package_id].origin_realm;
+                (
+                    package_id.clone(),
+                    self.package_contents_path(package_id, realm),
+                )
+            })
+            .collect();
 
-        Ok(contents)
+        let sink = Arc::new(MemorySink::new());
+        let context = self
+            .with_sink(sink.clone())
+            .with_dry_run(false)
+            .with_frozen(false)
+            .with_target(None)
+            .with_report_path(None)
+            .with_package_cache(None)
+            .with_type_cache(None)
+            .with_observer(Arc::new(SilentObserver))
+            .with_check_orphans(false)
+            .with_check_dev_requires(false)
+            .with_require_types(false)
+            .with_verify(false);
+        let produced_paths = Arc::clone(&context.produced_paths);
+
+        context.install(sources, root_package_id, resolved)?;
+
+        let links = produced_paths
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|path| {
+                let contents = sink.read_file(path)?;
+                Ok((
+                    path.clone(),
+                    String::from_utf8_lossy(&contents).into_owned(),
+                ))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(InstallPlan { links, packages })
     }
 
-    fn write_root_package_links<'a, K: Display>(
+    /// Write the links from a package to each of its dependencies, returning
+    /// how many link files were written.
+    fn write_links(
         &self,
-        root_realm: Realm,
-        dependencies: impl IntoIterator<Item = (K, &'a PackageId)>,
+        package_id: &PackageId,
+        root_package_id: &PackageId,
         resolved: &Resolve,
-        types: &PackageTypeExports
-    ) -> anyhow::Result<()> {
-        log::debug!("Writing root package links");
+        types: &PackageTypeExports,
+        link_cache: &Mutex<LinkCache>,
+    ) -> anyhow::Result<usize> {
+        log::debug!("Installing package {}...", package_id);
 
-        let base_path = match root_realm {
-            Realm::Shared => &self.shared_dir,
-            Realm::Server => &self.server_dir,
-            Realm::Dev => &self.dev_dir,
+        let shared_deps = resolved.shared_dependencies.get(package_id);
+        let server_deps = resolved.server_dependencies.get(package_id);
+        let dev_deps = resolved.dev_dependencies.get(package_id);
+        let mut written = 0;
+
+        // We do not need to install the root package, but we should create
+        // package links for its dependencies.
+        if package_id == root_package_id {
+            // Links from the root package differ from those between packages,
+            // so they get a cache of their own.
+            let root_link_cache = Mutex::new(LinkCache::new());
+
+            if let Some(deps) = shared_deps {
+                written += self.write_root_package_links(root_package_id, Realm::Shared, deps, resolved, types, &root_link_cache)?;
+            }
+
+            if let Some(deps) = server_deps {
+                written += self.write_root_package_links(root_package_id, Realm::Server, deps, resolved, types, &root_link_cache)?;
+            }
+
+            if let Some(deps) = dev_deps {
+                written += self.write_root_package_links(root_package_id, Realm::Dev, deps, resolved, types, &root_link_cache)?;
+            }
+        } else {
+            let metadata = resolved.metadata.get(package_id).unwrap();
+            let package_realm = metadata.origin_realm;
+
+            let exports = &types[package_id];
+            if self.combined_types && !exports.is_empty() {
+                written += self.write_types_module(package_id, package_realm, exports)?;
+            }
+
+            if let Some(deps) = shared_deps {
+                written += self.write_package_links(package_id, package_realm, deps, resolved, types, link_cache)?;
+            }
+
+            if let Some(deps) = server_deps {
+                written += self.write_package_links(package_id, package_realm, deps, resolved, types, link_cache)?;
+            }
+
+            if let Some(deps) = dev_deps {
+                written += self.write_package_links(package_id, package_realm, deps, resolved, types, link_cache)?;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Download and unpack a package, retrying with exponential backoff if the
+    /// download fails in a way that might succeed when tried again. Progress is
+    /// reported to the observer.
+    ///
+    /// Returns the path the package was unpacked to and its checksum.
+    fn download_with_retries(
+        &self,
+        package_source: &PackageSource,
+        package_id: &PackageId,
+        realm: Realm,
+        cacheable: bool,
+    ) -> anyhow::Result<(PathBuf, String)> {
+        let mut attempt = 0;
+
+        loop {
+            self.observer.on_download_progress(package_id, 0);
+
+            match self.download_and_unpack(package_source, package_id, realm, cacheable) {
+                Ok(installed) => return Ok(installed),
+                Err(err) if attempt < self.download_retries && is_retryable(&err) => {
+                    let delay = retry_delay(attempt);
+                    attempt += 1;
+
+                    self.observer.on_download_retry(&DownloadRetry {
+                        package_id,
+                        attempt,
+                        retries: self.download_retries,
+                        delay,
+                        error: &err,
+                    });
+                    thread::sleep(delay);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Download a package and unpack it as it arrives, so that only one file
+    /// of it is held in memory at a time. The package is added to the package
+    /// cache too if it's `cacheable`.
+    fn download_and_unpack(
+        &self,
+        package_source: &PackageSource,
+        package_id: &PackageId,
+        realm: Realm,
+        cacheable: bool,
+    ) -> anyhow::Result<(PathBuf, String)> {
+        let cache_entry = match self.package_cache.as_ref().filter(|_| cacheable) {
+            Some(cache) => cache.start_insert(package_id).unwrap_or_else(|err| {
+                log::warn!(
+                    "Failed to add {} to the package cache: {:#}",
+                    package_id,
+                    err
+                );
+                None
+            }),
+            None => None,
         };
+        let cache_contents_path = cache_entry.as_ref().map(PendingEntry::contents_path);
 
-        log::trace!("Creating directory {}", base_path.display());
-        fs::create_dir_all(base_path)?;
+        let mut checksum = String::new();
+        let path = self.stage_contents(package_id, realm, |staging_path, sink| {
+            let mut targets: Vec<(&Path, &dyn InstallSink)> = vec![(staging_path, sink)];
+            if let Some(cache_contents_path) = &cache_contents_path {
+                targets.push((cache_contents_path, &FilesystemSink));
+            }
 
-        for (dep_name, dep_package_id) in dependencies {
-            let dependencies_realm = resolved.metadata.get(dep_package_id).unwrap().origin_realm;
-            let path = base_path.join(format!("{}.lua", dep_name));
-            let types_for_dep = types.get(dep_package_id).unwrap();
-
-            let contents = match (root_realm, dependencies_realm) {
-                (source, dest) if source == dest => self.link_root_same_index(dep_package_id, types_for_dep),
-                (_, Realm::Server) => self.link_server_index(dep_package_id, types_for_dep)?,
-                (_, Realm::Shared) => self.link_shared_index(dep_package_id, types_for_dep)?,
-                (_, Realm::Dev) => {
-                    bail!("A dev dependency cannot be depended upon by a non-dev dependency")
+            let reader = ProgressReader {
+                inner: package_source.open_package(package_id)?,
+                observer: &*self.observer,
+                package_id,
+                read: 0,
+            };
+
+            checksum = match PackageContents::unpack_stream(reader, &targets) {
+                Ok(checksum) => checksum,
+                Err(err) if is_unstreamable(&err) => {
+                    log::debug!(
+                        "Downloading all of {} before unpacking it: {:#}",
+                        package_id,
+                        err
+                    );
+
+                    self.observer.on_download_progress(package_id, 0);
+
+                    let downloaded = Cell::new(0);
+                    let contents =
+                        package_source.download_package_with_progress(package_id, &|bytes| {
+                            downloaded.set(downloaded.get() + bytes);
+                            self.observer
+                                .on_download_progress(package_id, downloaded.get());
+                        })?;
+
+                    for &(output, sink) in &targets {
+                        remove_ignore_not_found(sink, output)?;
+                        contents.unpack_into(output, sink)?;
+                    }
+
+                    contents.checksum()
                 }
+                Err(err) => return Err(err),
             };
 
-            log::trace!("Writing {}", path.display());
-            fs::write(path, contents)?;
+            self.verify_checksum(package_id, &checksum)?;
+            verify_package_identity(package_id, staging_path, sink)
+        })?;
+
+        if let Some(cache_entry) = cache_entry {
+            match cache_entry.finish(&checksum) {
+                // Now that the package is in the cache, the copy that was
+                // unpacked alongside it can be swapped for a link to it.
+                Ok(()) => {
+                    let cache = self.package_cache.as_ref().unwrap();
+                    self.link_from_cache(cache, package_id, realm)?;
+                }
+                Err(err) => log::warn!(
+                    "Failed to add {} to the package cache: {:#}",
+                    package_id,
+                    err
+                ),
+            }
+        }
+
+        Ok((path, checksum))
+    }
+
+    /// Check the checksum of a downloaded package against the ones recorded
+    /// for it in the lockfile and in the registry's index, if there are any.
+    fn verify_checksum(&self, package_id: &PackageId, checksum: &str) -> anyhow::Result<()> {
+        if let Some(expected) = self.locked_checksums.get(package_id) {
+            if expected != checksum {
+                bail!(
+                    "Checksum mismatch for package {}\n\
+                     The lockfile expects {} but the downloaded package has {}.\n\
+                     The package may have been tampered with in the registry.",
+                    package_id,
+                    expected,
+                    checksum
+                );
+            }
+        }
+
+        // This holds even without a lockfile, since the registry's index and
+        // its storage should never disagree.
+        if let Some(expected) = self.resolved_checksums.get(package_id) {
+            if expected != checksum {
+                bail!(
+                    "Registry inconsistency for package {}\n\
+                     The registry's index recorded checksum {} when the package was resolved, \
+                     but the registry served a package with checksum {}.\n\
+                     The registry is misconfigured or has been tampered with.",
+                    package_id,
+                    expected,
+                    checksum
+                );
+            }
         }
 
         Ok(())
     }
 
-    fn write_package_links<'a, K: std::fmt::Display>(
+    /// Remove everything from the package directories that this install will
+    /// not produce, keeping the unpacked contents of packages that are still
+    /// part of the resolve so that they don't need to be downloaded again.
+    ///
+    /// Link files that this install will write again are kept too, so that
+    /// unchanged links don't need to be rewritten.
+    fn remove_stale_entries(
         &self,
-        package_id: &PackageId,
-        package_realm: Realm,
-        dependencies: impl IntoIterator<Item = (K, &'a PackageId)>,
+        root_package_id: &PackageId,
         resolved: &Resolve,
-        types: &PackageTypeExports
     ) -> anyhow::Result<()> {
-        log::debug!("Writing package links for {}", package_id);
+        if self.layout == Layout::Flat {
+            return self.remove_stale_flat_entries(root_package_id, resolved);
+        }
 
-        let mut base_path = match package_realm {
-            Realm::Shared => self.shared_index_dir.clone(),
-            Realm::Server => self.server_index_dir.clone(),
-            Realm::Dev => self.dev_index_dir.clone(),
-        };
+        let expected_links = self.expected_link_paths(root_package_id, resolved);
+        let combined_types = self.combined_types && !self.no_links;
 
-        base_path.push(package_id_file_name(package_id));
+        for (realm, base_path, index_path) in [
+            (Realm::Shared, &self.shared_dir, &self.shared_index_dir),
+            (Realm::Server, &self.server_dir, &self.server_index_dir),
+            (Realm::Dev, &self.dev_dir, &self.dev_index_dir),
+        ] {
+            let expected: BTreeMap<String, &PackageId> = resolved
+                .activated
+                .iter()
+                .filter(|package_id| *package_id != root_package_id)
+                .filter(|package_id| resolved.metadata[*package_id].origin_realm == realm)
+                .map(|package_id| (self.file_name(package_id), package_id))
+                .collect();
 
-        log::trace!("Creating directory {}", base_path.display());
-        fs::create_dir_all(&base_path)?;
+            let types_path = base_path.join("_Types");
 
-        for (dep_name, dep_package_id) in dependencies {
-            let dependencies_realm = resolved.metadata.get(dep_package_id).unwrap().origin_realm;
-            let path = base_path.join(format!("{}.lua", dep_name));
-            let types_for_dep = types.get(dep_package_id).unwrap();
-
-            let contents = match (package_realm, dependencies_realm) {
-                (source, dest) if source == dest => self.link_sibling_same_index(dep_package_id, types_for_dep),
-                (_, Realm::Server) => self.link_server_index(dep_package_id, types_for_dep)?,
-                (_, Realm::Shared) => self.link_shared_index(dep_package_id, types_for_dep)?,
-                (_, Realm::Dev) => {
-                    bail!("A dev dependency cannot be depended upon by a non-dev dependency")
+            for path in read_dir_ignore_not_found(&*self.sink, &types_path)? {
+                let file_name = path.file_name().unwrap().to_string_lossy();
+                let is_expected = file_name
+                    .strip_suffix(".luau")
+                    .is_some_and(|name| expected.contains_key(name));
+
+                if !combined_types || !is_expected {
+                    remove_path(&*self.sink, &path)?;
+                }
+            }
+
+            for path in read_dir_ignore_not_found(&*self.sink, base_path)? {
+                let is_types_dir = path == types_path && combined_types;
+                let is_gitignore = path.ends_with(".gitignore");
+
+                if &path != index_path
+                    && !is_types_dir
+                    && !is_gitignore
+                    && !is_expected_link(&*self.sink, &path, &expected_links)
+                    && !self.is_kept_orphan(&path)
+                {
+                    remove_path(&*self.sink, &path)?;
+                }
+            }
+
+            for entry_path in read_dir_ignore_not_found(&*self.sink, index_path)? {
+                let file_name = entry_path.file_name().unwrap().to_string_lossy();
+
+                let package_id = match expected.get(file_name.as_ref()) {
+                    Some(package_id) if self.sink.is_dir(&entry_path) => package_id,
+                    _ => {
+                        log::debug!("Removing stale index entry {}", entry_path.display());
+                        remove_path(&*self.sink, &entry_path)?;
+                        continue;
+                    }
+                };
+
+                for path in read_dir_ignore_not_found(&*self.sink, &entry_path)? {
+                    if !path.ends_with(package_id.name().name())
+                        && !is_expected_link(&*self.sink, &path, &expected_links)
+                        && !self.is_kept_orphan(&path)
+                    {
+                        remove_path(&*self.sink, &path)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove everything in the packages folders that isn't a package to be
+    /// installed there, along with packages that aren't recorded as being
+    /// installed completely with the current place paths.
+    fn remove_stale_flat_entries(
+        &self,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+    ) -> anyhow::Result<()> {
+        for (realm, base_path) in [
+            (Realm::Shared, &self.shared_dir),
+            (Realm::Server, &self.server_dir),
+            (Realm::Dev, &self.dev_dir),
+        ] {
+            let state = self.read_flat_state(base_path);
+            let expected: BTreeMap<&str, &PackageId> = resolved
+                .activated
+                .iter()
+                .filter(|package_id| *package_id != root_package_id)
+                .filter(|package_id| resolved.metadata[*package_id].origin_realm == realm)
+                .map(|package_id| (package_id.name().name(), package_id))
+                .collect();
+
+            for path in read_dir_ignore_not_found(&*self.sink, base_path)? {
+                let file_name = path.file_name().unwrap().to_string_lossy();
+                let is_current = match expected.get(file_name.as_ref()) {
+                    Some(package_id) => {
+                        self.sink.is_dir(&path)
+                            && state
+                                .as_ref()
+                                .is_some_and(|state| state.packages.contains(*package_id))
+                    }
+                    None => file_name == ".gitignore" || file_name == FLAT_STATE_FILE_NAME,
+                };
+
+                if !is_current && !self.is_kept_orphan(&path) {
+                    log::debug!("Removing stale entry {}", path.display());
+                    remove_path(&*self.sink, &path)?;
                 }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a stale file is left in place so that
+    /// [`InstallationContext::warn_about_orphans`] can report it, which is
+    /// the case for Lua files when checking for orphans.
+    fn is_kept_orphan(&self, path: &Path) -> bool {
+        self.check_orphans && is_lua_file(path) && self.sink.is_file(path)
+    }
+
+    /// The packages recorded as installed in the packages folder at `dir`,
+    /// unless they were installed with place paths other than the current
+    /// ones.
+    fn read_flat_state(&self, dir: &Path) -> Option<FlatState> {
+        let contents = self.sink.read_file(&dir.join(FLAT_STATE_FILE_NAME)).ok()?;
+        let state: FlatState = toml::from_slice(&contents).ok()?;
+
+        let same_places =
+            state.shared_packages == self.shared_path && state.server_packages == self.server_path;
+        same_places.then_some(state)
+    }
+
+    /// Record the packages installed into each packages folder. Packages left
+    /// alone by a targeted install stay recorded only if they already were.
+    fn write_flat_states(
+        &self,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+        targeted: Option<&BTreeSet<PackageId>>,
+    ) -> anyhow::Result<()> {
+        for (realm, dir) in [
+            (Realm::Shared, &self.shared_dir),
+            (Realm::Server, &self.server_dir),
+            (Realm::Dev, &self.dev_dir),
+        ] {
+            if !self.sink.is_dir(dir) {
+                continue;
+            }
+
+            let previous = self.read_flat_state(dir).unwrap_or_default();
+            let packages = resolved
+                .activated
+                .iter()
+                .filter(|package_id| *package_id != root_package_id)
+                .filter(|package_id| resolved.metadata[*package_id].origin_realm == realm)
+                .filter(|package_id| match targeted {
+                    Some(targeted) if !targeted.contains(*package_id) => {
+                        previous.packages.contains(*package_id)
+                    }
+                    _ => true,
+                })
+                .cloned()
+                .collect();
+
+            let state = FlatState {
+                shared_packages: self.shared_path.clone(),
+                server_packages: self.server_path.clone(),
+                packages,
             };
 
-            log::trace!("Writing {}", path.display());
-            fs::write(path, contents)?;
+            write_if_changed(
+                &*self.sink,
+                &dir.join(FLAT_STATE_FILE_NAME),
+                toml::to_string(&state)?.as_bytes(),
+            )?;
         }
 
         Ok(())
     }
 
-    fn write_contents(
+    /// Write a `.gitignore` into each packages folder that exists. One that's
+    /// already there with other contents is assumed to have been changed by
+    /// hand, and is only replaced if `force_gitignore` is set.
+    fn write_gitignores(&self) -> anyhow::Result<()> {
+        for dir in [&self.shared_dir, &self.server_dir, &self.dev_dir] {
+            if !self.sink.is_dir(dir) {
+                continue;
+            }
+
+            let path = dir.join(".gitignore");
+            let customized = match self.sink.read_file(&path) {
+                Ok(existing) => existing != PACKAGES_GITIGNORE.as_bytes(),
+                Err(_) => false,
+            };
+
+            if customized && !self.force_gitignore {
+                log::warn!(
+                    "Not replacing {}, which was changed by hand. Pass --force-gitignore to \
+                     replace it.",
+                    path.display()
+                );
+                continue;
+            }
+
+            write_if_changed(&*self.sink, &path, PACKAGES_GITIGNORE.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Every link file that installing `resolved` will write.
+    fn expected_link_paths(
+        &self,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+    ) -> BTreeSet<PathBuf> {
+        let mut paths = BTreeSet::new();
+        if self.no_links {
+            return paths;
+        }
+
+        for package_id in &resolved.activated {
+            let is_root = package_id == root_package_id;
+
+            for (root_realm, dependencies) in [
+                (Realm::Shared, &resolved.shared_dependencies),
+                (Realm::Server, &resolved.server_dependencies),
+                (Realm::Dev, &resolved.dev_dependencies),
+            ] {
+                let base_path = if is_root {
+                    self.root_links_dir(root_realm).to_owned()
+                } else {
+                    let package_realm = resolved.metadata[package_id].origin_realm;
+                    self.package_links_dir(package_id, package_realm)
+                };
+
+                for dep_name in dependencies.get(package_id).into_iter().flat_map(|deps| deps.keys()) {
+                    paths.insert(base_path.join(format!("{}.lua", dep_name)));
+                }
+            }
+        }
+
+        paths
+    }
+
+    /// Print every package that would be installed, grouped by the realm it
+    /// would be installed into.
+    fn report_dry_run(&self, root_package_id: &PackageId, resolved: &Resolve) {
+        println!(
+            "{}    Dry run {}no files will be written",
+            SetForegroundColor(Color::DarkYellow),
+            SetForegroundColor(Color::Reset)
+        );
+
+        for (realm, base_path) in [
+            (Realm::Shared, &self.shared_dir),
+            (Realm::Server, &self.server_dir),
+            (Realm::Dev, &self.dev_dir),
+        ] {
+            let packages: Vec<_> = resolved
+                .activated
+                .iter()
+                .filter(|package_id| *package_id != root_package_id)
+                .filter(|package_id| resolved.metadata[*package_id].origin_realm == realm)
+                .collect();
+
+            if packages.is_empty() {
+                continue;
+            }
+
+            println!("{}", base_path.display());
+
+            for package_id in packages {
+                println!(
+                    "  {} -> {}",
+                    package_id,
+                    self.package_contents_path(package_id, realm).display()
+                );
+            }
+        }
+    }
+
+    /// Whether this install writes links to packages, which only the index
+    /// layout has.
+    fn writes_links(&self) -> bool {
+        !self.no_links && self.layout == Layout::Index
+    }
+
+    /// Where the requires of each package's dependencies point in the flat
+    /// layout, keyed by the package and then by the dependency's alias.
+    fn flat_requires(
+        &self,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+    ) -> anyhow::Result<HashMap<PackageId, BTreeMap<String, FlatRequire>>> {
+        let mut flat_requires = HashMap::new();
+
+        for package_id in &resolved.activated {
+            if package_id == root_package_id {
+                continue;
+            }
+
+            let package_realm = resolved.metadata[package_id].origin_realm;
+            let mut requires = BTreeMap::new();
+
+            for dependencies in [
+                &resolved.shared_dependencies,
+                &resolved.server_dependencies,
+                &resolved.dev_dependencies,
+            ] {
+                for (dep_name, dep_package_id) in dependencies.get(package_id).into_iter().flatten()
+                {
+                    let dependencies_realm = resolved.metadata[dep_package_id].origin_realm;
+                    let name = dep_package_id.name().name();
+
+                    let place_path = match (package_realm, dependencies_realm) {
+                        (source, dest) if source == dest => {
+                            requires.insert(
+                                dep_name.to_string(),
+                                FlatRequire::Sibling(name.to_owned()),
+                            );
+                            continue;
+                        }
+                        (_, Realm::Server) => &self.server_path,
+                        (_, Realm::Shared) => &self.shared_path,
+                        (_, Realm::Dev) => {
+                            bail!(
+                                "A dev dependency cannot be depended upon by a non-dev dependency"
+                            )
+                        }
+                    };
+
+                    let place_path =
+                        place_path
+                            .as_ref()
+                            .ok_or_else(|| InstallError::MissingPlacePath {
+                                realm: dependencies_realm,
+                                offending_package: dep_package_id.clone(),
+                                dependent_package: package_id.clone(),
+                            })?;
+
+                    requires.insert(
+                        dep_name.to_string(),
+                        FlatRequire::Place(format!(r#"{}["{}"]"#, place_path, name)),
+                    );
+                }
+            }
+
+            flat_requires.insert(package_id.clone(), requires);
+        }
+
+        Ok(flat_requires)
+    }
+
+    /// Point the requires of dependencies in a package unpacked at
+    /// `package_path` in `sink` at where the flat layout installs them,
+    /// instead of at the links that the index layout puts next to the
+    /// package.
+    fn rewrite_flat_requires(
         &self,
         package_id: &PackageId,
-        contents: &PackageContents,
-        realm: Realm,
-    ) -> anyhow::Result<PathBuf> {
-        let mut path = match realm {
-            Realm::Shared => self.shared_index_dir.clone(),
-            Realm::Server => self.server_index_dir.clone(),
-            Realm::Dev => self.dev_index_dir.clone(),
+        package_path: &Path,
+        sink: &dyn InstallSink,
+    ) -> anyhow::Result<()> {
+        let requires = match self.flat_requires.get(package_id) {
+            Some(requires) if !requires.is_empty() => requires,
+            _ => return Ok(()),
         };
 
-        path.push(package_id_file_name(package_id));
-        path.push(package_id.name().name());
+        // Rojo turns a package's directory into the tree of its
+        // `default.project.json`, or into a module itself without one.
+        let module_path = if sink.is_file(&package_path.join("default.project.json")) {
+            match entry_module_path(sink, package_path) {
+                Some(path) => path,
+                None => return Ok(()),
+            }
+        } else {
+            package_path.to_owned()
+        };
+
+        let mut files = Vec::new();
+        if sink.is_dir(&module_path) {
+            module_files(sink, &module_path, 1, &mut files)?;
+        } else if is_init_file(&module_path) {
+            module_files(sink, module_path.parent().unwrap(), 1, &mut files)?;
+        } else {
+            files.push((module_path, 1));
+        }
 
-        fs::create_dir_all(&path)?;
-        contents.unpack_into_path(&path)?;
+        for (path, depth) in files {
+            let source = sink.read_file(&path)?;
+            let source = String::from_utf8_lossy(&source);
+            let rewritten = rewrite_requires(&source, depth, requires);
 
-        Ok(path)
+            if rewritten != source {
+                sink.write_file(&path, rewritten.as_bytes())?;
+            }
+        }
+
+        Ok(())
     }
-}
 
-/// Creates a suitable name for use in file paths that refer to this package.
-fn package_id_file_name(id: &PackageId) -> String {
-    format!(
-        "{}_{}@{}",
-        id.name().scope(),
-        id.name().name(),
-        id.version()
-    )
+    /// Contents of a package-to-package link within the same index.
+    fn link_sibling_same_index(&self, id: &PackageId, exports: &ExtractTypesResult) -> String {
+        if exports.is_empty() {
+            formatdoc! {r#"
+                return require(script.Parent.Parent["{full_name}"]["{short_name}"])
+                "#,
+                full_name = self.file_name(id),
+                short_name = id.name().name()
+            }
+        } else if self.combined_types {
+            let module = format!(
+                r#"script.Parent.Parent.Parent._Types["{}"]"#,
+                self.file_name(id)
+            );
+            self.forwarding_link(&module, exports)
+        } else {
+            let module = format!(
+                r#"script.Parent.Parent["{}"]["{}"]"#,
+                self.file_name(id),
+                id.name().name()
+            );
+            self.forwarding_link(&module, exports)
+        }
+    }
+
+    /// Contents of a root-to-package link within the same index.
+    fn link_root_same_index(&self, id: &PackageId, exports: &ExtractTypesResult) -> String {
+        if exports.is_empty() {
+            formatdoc! {r#"
+                return require(script.Parent._Index["{full_name}"]["{short_name}"])
+                "#,
+                full_name = self.file_name(id),
+                short_name = id.name().name()
+            }
+        } else if self.combined_types {
+            let module = format!(r#"script.Parent._Types["{}"]"#, self.file_name(id));
+            self.forwarding_link(&module, exports)
+        } else {
+            let module = format!(
+                r#"script.Parent._Index["{}"]["{}"]"#,
+                self.file_name(id),
+                id.name().name()
+            );
+            self.forwarding_link(&module, exports)
+        }
+    }
+
+    /// Contents of a link into the shared index from outside the shared index.
+    fn link_shared_index(
+        &self,
+        id: &PackageId,
+        exports: &ExtractTypesResult,
+        dependent: &PackageId,
+    ) -> anyhow::Result<String> {
+        let shared_path = self.shared_path.as_ref().ok_or_else(|| InstallError::MissingPlacePath {
+            realm: Realm::Shared,
+            offending_package: id.clone(),
+            dependent_package: dependent.clone(),
+        })?;
+
+        let contents = if exports.is_empty() {
+            formatdoc! {r#"
+                return require({packages}._Index["{full_name}"]["{short_name}"])
+                "#,
+                packages = shared_path,
+                full_name = self.file_name(id),
+                short_name = id.name().name()
+            }
+        } else if self.combined_types {
+            let module = format!(r#"{}._Types["{}"]"#, shared_path, self.file_name(id));
+            self.forwarding_link(&module, exports)
+        } else {
+            let module = format!(
+                r#"{}._Index["{}"]["{}"]"#,
+                shared_path,
+                self.file_name(id),
+                id.name().name()
+            );
+            self.forwarding_link(&module, exports)
+        };
+
+        Ok(contents)
+    }
+
+    /// Contents of a link into the server index from outside the server index.
+    fn link_server_index(
+        &self,
+        id: &PackageId,
+        exports: &ExtractTypesResult,
+        dependent: &PackageId,
+    ) -> anyhow::Result<String> {
+        let server_path = self.server_path.as_ref().ok_or_else(|| InstallError::MissingPlacePath {
+            realm: Realm::Server,
+            offending_package: id.clone(),
+            dependent_package: dependent.clone(),
+        })?;
+
+        let contents = if exports.is_empty() {
+            formatdoc! {r#"
+                return require({packages}._Index["{full_name}"]["{short_name}"])
+                "#,
+                packages = server_path,
+                full_name = self.file_name(id),
+                short_name = id.name().name()
+            }
+        } else if self.combined_types {
+            let module = format!(r#"{}._Types["{}"]"#, server_path, self.file_name(id));
+            self.forwarding_link(&module, exports)
+        } else {
+            let module = format!(
+                r#"{}._Index["{}"]["{}"]"#,
+                server_path,
+                self.file_name(id),
+                id.name().name()
+            );
+            self.forwarding_link(&module, exports)
+        };
+
+        Ok(contents)
+    }
+
+    fn write_root_package_links<'a, K: Display>(
+        &self,
+        root_package_id: &PackageId,
+        root_realm: Realm,
+        dependencies: impl IntoIterator<Item = (K, &'a PackageId)>,
+        resolved: &Resolve,
+        types: &PackageTypeExports,
+        link_cache: &Mutex<LinkCache>,
+    ) -> anyhow::Result<usize> {
+        log::debug!("Writing root package links");
+
+        let base_path = self.root_links_dir(root_realm);
+        let mut written = 0;
+
+        log::trace!("Creating directory {}", base_path.display());
+        self.sink.create_dir(base_path)?;
+
+        for (dep_name, dep_package_id) in dependencies {
+            let dependencies_realm = resolved.metadata.get(dep_package_id).unwrap().origin_realm;
+            let path = base_path.join(format!("{}.lua", dep_name));
+
+            // Packages that failed to install aren't linked to.
+            let types_for_dep = match types.get(dep_package_id) {
+                Some(types) => types,
+                None => continue,
+            };
+
+            let key = (dep_package_id.clone(), root_realm, dependencies_realm);
+            let cached = link_cache.lock().unwrap().get(&key).cloned();
+            let contents = match cached {
+                Some(contents) => contents,
+                None => {
+                    let contents = match (root_realm, dependencies_realm) {
+                        (source, dest) if source == dest => self.link_root_same_index(dep_package_id, types_for_dep),
+                        (_, Realm::Server) => self.link_server_index(dep_package_id, types_for_dep, root_package_id)?,
+                        (_, Realm::Shared) => self.link_shared_index(dep_package_id, types_for_dep, root_package_id)?,
+                        (_, Realm::Dev) => {
+                            bail!("A dev dependency cannot be depended upon by a non-dev dependency")
+                        }
+                    };
+
+                    link_cache.lock().unwrap().insert(key, contents.clone());
+                    contents
+                }
+            };
+
+            if self.write_link(&path, &contents)? {
+                log::trace!("Wrote {}", path.display());
+                written += 1;
+            }
+        }
+
+        Ok(written)
+    }
+
+    fn write_package_links<'a, K: std::fmt::Display>(
+        &self,
+        package_id: &PackageId,
+        package_realm: Realm,
+        dependencies: impl IntoIterator<Item = (K, &'a PackageId)>,
+        resolved: &Resolve,
+        types: &PackageTypeExports,
+        link_cache: &Mutex<LinkCache>,
+    ) -> anyhow::Result<usize> {
+        log::debug!("Writing package links for {}", package_id);
+
+        let base_path = self.package_links_dir(package_id, package_realm);
+        let mut written = 0;
+
+        // Links for other packages are written at the same time, possibly
+        // creating the same parent directories. `create_dir_all` treats a
+        // directory that another thread created first as success.
+        log::trace!("Creating directory {}", base_path.display());
+        self.sink.create_dir(&base_path)?;
+
+        for (dep_name, dep_package_id) in dependencies {
+            let dependencies_realm = resolved.metadata.get(dep_package_id).unwrap().origin_realm;
+            let path = base_path.join(format!("{}.lua", dep_name));
+
+            // Packages that failed to install aren't linked to.
+            let types_for_dep = match types.get(dep_package_id) {
+                Some(types) => types,
+                None => continue,
+            };
+
+            let key = (dep_package_id.clone(), package_realm, dependencies_realm);
+            let cached = link_cache.lock().unwrap().get(&key).cloned();
+            let contents = match cached {
+                Some(contents) => contents,
+                None => {
+                    let contents = match (package_realm, dependencies_realm) {
+                        (source, dest) if source == dest => self.link_sibling_same_index(dep_package_id, types_for_dep),
+                        (_, Realm::Server) => self.link_server_index(dep_package_id, types_for_dep, package_id)?,
+                        (_, Realm::Shared) => self.link_shared_index(dep_package_id, types_for_dep, package_id)?,
+                        (_, Realm::Dev) => {
+                            bail!("A dev dependency cannot be depended upon by a non-dev dependency")
+                        }
+                    };
+
+                    link_cache.lock().unwrap().insert(key, contents.clone());
+                    contents
+                }
+            };
+
+            if self.write_link(&path, &contents)? {
+                log::trace!("Wrote {}", path.display());
+                written += 1;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// The directory that the root package's links into the given realm are
+    /// written to.
+    fn root_links_dir(&self, realm: Realm) -> &Path {
+        match realm {
+            Realm::Shared => &self.shared_dir,
+            Realm::Server => &self.server_dir,
+            Realm::Dev => &self.dev_dir,
+        }
+    }
+
+    /// Write the module that declares a package's exported types once for
+    /// every link to it, returning how many files were written.
+    fn write_types_module(
+        &self,
+        package_id: &PackageId,
+        realm: Realm,
+        exports: &ExtractTypesResult,
+    ) -> anyhow::Result<usize> {
+        let types_dir = self.root_links_dir(realm).join("_Types");
+        self.sink.create_dir(&types_dir)?;
+
+        let path = types_dir.join(format!("{}.luau", self.file_name(package_id)));
+        let module = format!(
+            r#"script.Parent.Parent._Index["{}"]["{}"]"#,
+            self.file_name(package_id),
+            package_id.name().name()
+        );
+        let contents = self.forwarding_link(&module, exports);
+
+        Ok(self.write_link(&path, &contents)? as usize)
+    }
+
+    /// Contents of a link or types module that requires the module at
+    /// `module` and forwards the types it exports.
+    fn forwarding_link(&self, module: &str, exports: &ExtractTypesResult) -> String {
+        if self.strict_type_forwarding {
+            // Annotating MODULE with the type of the required module keeps
+            // its exported types reachable under strict mode, even where the
+            // require alone would leave MODULE typed as `any`.
+            formatdoc! {r#"
+                --!strict
+                local MODULE: typeof(require({module})) = require({module})
+                {exports_string}
+                return MODULE
+                "#,
+                exports_string = forwarding_statements(exports, module, true),
+                module = module
+            }
+        } else {
+            formatdoc! {r#"
+                local MODULE = require({module})
+                {exports_string}
+                return MODULE
+                "#,
+                exports_string = forwarding_statements(exports, module, false),
+                module = module
+            }
+        }
+    }
+
+    /// Write a link or types module if its contents changed, remembering that
+    /// this install produced it.
+    fn write_link(&self, path: &Path, contents: &str) -> io::Result<bool> {
+        self.produced_paths.lock().unwrap().insert(path.to_owned());
+
+        let contents = normalize_line_endings(contents, self.line_endings, self.trailing_newline);
+        let written = write_if_changed(&*self.sink, path, contents.as_bytes())?;
+
+        if written {
+            self.observer.on_link_written(path);
+        }
+
+        Ok(written)
+    }
+
+    /// Check that the module required by every link and types module this
+    /// install produced exists.
+    fn verify_links(&self) -> anyhow::Result<()> {
+        let produced_paths = self.produced_paths.lock().unwrap();
+        let mut dangling = Vec::new();
+
+        for path in produced_paths.iter() {
+            let contents = self.sink.read_file(path)?;
+            let contents = String::from_utf8_lossy(&contents);
+
+            for target in require_targets(&contents) {
+                if !self.require_target_exists(path, target) {
+                    dangling.push((path.clone(), target.to_owned()));
+                }
+            }
+        }
+
+        if !dangling.is_empty() {
+            bail!(InstallError::DanglingLinks { links: dangling });
+        }
+
+        log::debug!("Verified {} link files", produced_paths.len());
+        Ok(())
+    }
+
+    /// Whether the `require` target in the link at `link` names a module on
+    /// disk. Once the target reaches a module, like an installed package,
+    /// anything after it is inside that module and isn't checked.
+    fn require_target_exists(&self, link: &Path, target: &str) -> bool {
+        let realm_roots = [
+            (self.shared_path.as_deref(), &self.shared_dir),
+            (self.server_path.as_deref(), &self.server_dir),
+        ];
+
+        let (mut current, mut rest) = match target.strip_prefix("script") {
+            Some(rest) => (link.with_extension(""), rest),
+            None => {
+                let root = realm_roots.iter().find_map(|(path, dir)| {
+                    let rest = target.strip_prefix((*path)?)?;
+                    let is_whole_path = rest.is_empty() || rest.starts_with(['.', '['].as_ref());
+                    is_whole_path.then(|| (dir.to_path_buf(), rest))
+                });
+
+                match root {
+                    Some(root) => root,
+                    None => return false,
+                }
+            }
+        };
+
+        while !rest.is_empty() {
+            let (segment, remaining) = match next_path_segment(rest) {
+                Some(next) => next,
+                None => return false,
+            };
+            rest = remaining;
+
+            if segment == "Parent" {
+                if !current.pop() {
+                    return false;
+                }
+            } else if self.is_module(&current) {
+                break;
+            } else {
+                current.push(segment);
+            }
+        }
+
+        self.is_module(&current)
+    }
+
+    /// Whether `path`, without an extension, is a module: a Lua file, or a
+    /// directory that Rojo turns into one.
+    fn is_module(&self, path: &Path) -> bool {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return false,
+        };
+
+        // Package names contain dots, so extensions are appended rather than
+        // set.
+        let is_file = ["lua", "luau"].iter().any(|extension| {
+            self.sink
+                .is_file(&path.with_file_name(format!("{}.{}", name, extension)))
+        });
+
+        let is_dir_module = self.sink.is_dir(path)
+            && ["default.project.json", "init.lua", "init.luau"]
+                .iter()
+                .any(|file| self.sink.is_file(&path.join(file)));
+
+        is_file || is_dir_module
+    }
+
+    /// Warn about every Lua file in the package directories that this install
+    /// didn't produce, skipping the contents of installed packages.
+    ///
+    /// Stale Lua files aren't removed before installing when checking for
+    /// orphans, so anything that isn't a current link is reported here.
+    fn warn_about_orphans(
+        &self,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+    ) -> anyhow::Result<()> {
+        let package_paths: BTreeSet<PathBuf> = resolved
+            .activated
+            .iter()
+            .filter(|package_id| *package_id != root_package_id)
+            .map(|package_id| {
+                let realm = resolved.metadata[package_id].origin_realm;
+                self.package_contents_path(package_id, realm)
+            })
+            .collect();
+
+        let produced_paths = self.produced_paths.lock().unwrap();
+        let mut orphans = Vec::new();
+
+        for dir in [&self.shared_dir, &self.server_dir, &self.dev_dir] {
+            find_orphaned_files(
+                &*self.sink,
+                dir,
+                &produced_paths,
+                &package_paths,
+                &mut orphans,
+            )?;
+        }
+
+        for orphan in &orphans {
+            self.observer.on_orphaned_file(orphan);
+        }
+
+        Ok(())
+    }
+
+    /// Report every require of something in the dev packages folder from a
+    /// Lua file outside of it: the links in the other packages folders, and
+    /// the project's own source.
+    fn warn_about_dev_requires(&self) -> anyhow::Result<()> {
+        let dev_folder = match self.dev_dir.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+
+        // Installed packages are checked through their links, and only the
+        // links are checked in the packages folders, since packages' own code
+        // can't see the project's dev packages.
+        let package_dirs = [&self.shared_dir, &self.server_dir, &self.dev_dir];
+        let mut lua_files = Vec::new();
+        find_project_lua_files(
+            &*self.sink,
+            &self.project_path,
+            &package_dirs,
+            &mut lua_files,
+        )?;
+
+        let produced_paths = self.produced_paths.lock().unwrap();
+        lua_files.extend(
+            produced_paths
+                .iter()
+                .filter(|path| {
+                    (path.starts_with(&self.shared_dir) || path.starts_with(&self.server_dir))
+                        && is_lua_file(path)
+                })
+                .cloned(),
+        );
+
+        for path in lua_files {
+            let contents = match self.sink.read_file(&path) {
+                Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+                Err(err) => {
+                    log::debug!("Failed to read {}: {}", path.display(), err);
+                    continue;
+                }
+            };
+
+            for (index, line) in contents.lines().enumerate() {
+                if requires_folder(line, dev_folder) {
+                    self.observer.on_dev_require(&DevRequire {
+                        path: &path,
+                        line: index + 1,
+                        dev_folder,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The name of the directory that a package is installed into.
+    fn file_name(&self, package_id: &PackageId) -> String {
+        self.file_names
+            .get(package_id)
+            .cloned()
+            .unwrap_or_else(|| package_id_file_name(package_id))
+    }
+
+    /// The directory that a package's links to its dependencies are written to.
+    fn package_links_dir(&self, package_id: &PackageId, realm: Realm) -> PathBuf {
+        let mut path = match realm {
+            Realm::Shared => self.shared_index_dir.clone(),
+            Realm::Server => self.server_index_dir.clone(),
+            Realm::Dev => self.dev_index_dir.clone(),
+        };
+
+        path.push(self.file_name(package_id));
+        path
+    }
+
+    /// Copy a package from the package cache if it's there, returning its
+    /// checksum. Cached copies that don't match the lockfile are thrown away so
+    /// that the package is downloaded again.
+    fn install_from_cache(
+        &self,
+        package_id: &PackageId,
+        realm: Realm,
+    ) -> anyhow::Result<Option<String>> {
+        let cache = match &self.package_cache {
+            Some(cache) => cache,
+            None => return Ok(None),
+        };
+
+        let checksum = match cache.checksum(package_id) {
+            Some(checksum) => checksum,
+            None => return Ok(None),
+        };
+
+        if let Some(expected) = self.locked_checksums.get(package_id) {
+            if *expected != checksum {
+                log::warn!(
+                    "Cached copy of {} does not match the lockfile, ignoring it",
+                    package_id
+                );
+                cache.remove(package_id)?;
+                return Ok(None);
+            }
+        }
+
+        if let Some(expected) = self.resolved_checksums.get(package_id) {
+            if *expected != checksum {
+                log::warn!(
+                    "Cached copy of {} does not match the registry's index, ignoring it",
+                    package_id
+                );
+                cache.remove(package_id)?;
+                return Ok(None);
+            }
+        }
+
+        if self.link_from_cache(cache, package_id, realm)? {
+            return Ok(Some(checksum));
+        }
+
+        self.stage_contents(package_id, realm, |staging_path, sink| {
+            cache.copy_into(package_id, staging_path, sink)
+        })?;
+
+        Ok(Some(checksum))
+    }
+
+    /// Replace the contents of a package with a link to its entry in the
+    /// package cache, if `--link-cache` is set and the package can be linked.
+    /// Returns whether the package was linked.
+    fn link_from_cache(
+        &self,
+        cache: &PackageCache,
+        package_id: &PackageId,
+        realm: Realm,
+    ) -> anyhow::Result<bool> {
+        if !self.link_cache || !self.package_exclude.is_empty() || self.layout == Layout::Flat {
+            return Ok(false);
+        }
+
+        // A relative link would be followed from the package's directory
+        // rather than from where Wally was run.
+        let target = std::env::current_dir()?.join(cache.contents_path(package_id));
+        let path = self.package_contents_path(package_id, realm);
+
+        // The link is made next to the package first, so that a copy that's
+        // already there is only replaced once linking has worked.
+        let link_path = path.with_file_name(format!("{}.link", package_id.name().name()));
+        remove_ignore_not_found(&*self.sink, &link_path)?;
+        self.sink.create_dir(path.parent().unwrap())?;
+
+        if !self.sink.symlink_dir(&target, &link_path)? {
+            return Ok(false);
+        }
+
+        // Rojo and Studio only see the package if the link can be followed.
+        if !self.sink.is_dir(&link_path) {
+            log::warn!(
+                "The link from {} to the package cache can't be followed, copying {} instead",
+                path.display(),
+                package_id
+            );
+            remove_ignore_not_found(&*self.sink, &link_path)?;
+            return Ok(false);
+        }
+
+        remove_ignore_not_found(&*self.sink, &path)?;
+        self.sink.rename(&link_path, &path)?;
+
+        log::debug!("Linked {} to {}", path.display(), target.display());
+        Ok(true)
+    }
+
+    /// Fill in the contents of a package using `write`, which writes them to
+    /// a path through a sink, returning the path that they were written to.
+    fn stage_contents(
+        &self,
+        package_id: &PackageId,
+        realm: Realm,
+        write: impl FnOnce(&Path, &dyn InstallSink) -> anyhow::Result<()>,
+    ) -> anyhow::Result<PathBuf> {
+        let path = self.package_contents_path(package_id, realm);
+
+        // A package linked to the package cache by an earlier install is
+        // replaced, rather than written through the link into the cache.
+        if self.sink.is_symlink(&path) {
+            remove_ignore_not_found(&*self.sink, &path)?;
+        }
+
+        // A package that's already installed is unpacked into memory and then
+        // synced with the copy on disk, so files that didn't change aren't
+        // written again and editors watching them aren't woken up.
+        if self.sink.is_dir(&path) {
+            let staged = MemorySink::new();
+            let result = write(&path, &staged)
+                .and_then(|_| self.remove_excluded_contents(package_id, &path, &staged))
+                .and_then(|_| self.rewrite_flat_requires(package_id, &path, &staged))
+                .and_then(|_| Ok(sync_dir(&staged, &*self.sink, &path)?));
+
+            if let Err(err) = result {
+                let _ = remove_ignore_not_found(&*self.sink, &path);
+                return Err(err);
+            }
+
+            return Ok(path);
+        }
+
+        // Write into a staging directory first so that the package directory
+        // only exists once all of its contents have been written. Incremental
+        // installs rely on this to tell complete packages from partial ones.
+        let staging_path = path.with_file_name(format!("{}.partial", package_id.name().name()));
+        remove_ignore_not_found(&*self.sink, &staging_path)?;
+
+        // A package that failed to unpack, or that didn't match its checksum,
+        // shouldn't be left where it could be mistaken for anything else.
+        if let Err(err) = write(&staging_path, &*self.sink) {
+            let _ = remove_ignore_not_found(&*self.sink, &staging_path);
+            return Err(err);
+        }
+
+        self.remove_excluded_contents(package_id, &staging_path, &*self.sink)?;
+        self.rewrite_flat_requires(package_id, &staging_path, &*self.sink)?;
+        self.sink.rename(&staging_path, &path)?;
+
+        Ok(path)
+    }
+
+    /// Remove the files and folders matching the project's `package-exclude`
+    /// patterns from a package unpacked at `package_path` in `sink`. The files
+    /// that type extraction reads are kept even if a pattern matches them.
+    fn remove_excluded_contents(
+        &self,
+        package_id: &PackageId,
+        package_path: &Path,
+        sink: &dyn InstallSink,
+    ) -> anyhow::Result<()> {
+        if self.package_exclude.is_empty() {
+            return Ok(());
+        }
+
+        let exclude = build_glob_set(&self.package_exclude)?;
+
+        let mut protected = vec![package_path.join("default.project.json")];
+        protected.extend(entry_module_path(sink, package_path));
+
+        remove_excluded_entries(
+            sink,
+            package_id,
+            package_path,
+            package_path,
+            &exclude,
+            &protected,
+            false,
+        )?;
+
+        Ok(())
+    }
+
+    /// The directory that the contents of a package are unpacked into.
+    fn package_contents_path(&self, package_id: &PackageId, realm: Realm) -> PathBuf {
+        if self.layout == Layout::Flat {
+            return self.root_links_dir(realm).join(package_id.name().name());
+        }
+
+        let mut path = match realm {
+            Realm::Shared => self.shared_index_dir.clone(),
+            Realm::Server => self.server_index_dir.clone(),
+            Realm::Dev => self.dev_index_dir.clone(),
+        };
+
+        path.push(self.file_name(package_id));
+        path.push(package_id.name().name());
+
+        path
+    }
+}
+
+fn remove_ignore_not_found(sink: &dyn InstallSink, path: &Path) -> io::Result<()> {
+    if let Err(err) = sink.remove_dir_all(path) {
+        if err.kind() != io::ErrorKind::NotFound {
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Turn a task that didn't finish into an error naming the package it was
+/// working on, so that a panic while installing one package is reported like
+/// any other failure instead of taking down the whole install.
+fn task_error(err: JoinError, task: &str, package_id: &PackageId) -> anyhow::Error {
+    if !err.is_panic() {
+        return format_err!("{} {} was cancelled", task, package_id);
+    }
+
+    let payload = err.into_panic();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".to_owned());
+
+    format_err!("{} {} panicked: {}", task, package_id, message)
+}
+
+/// Give generated file contents the requested line endings, with exactly one
+/// trailing newline or none at all.
+fn normalize_line_endings(
+    contents: &str,
+    line_endings: LineEndings,
+    trailing_newline: bool,
+) -> String {
+    let mut normalized = contents.replace("\r\n", "\n");
+
+    normalized.truncate(normalized.trim_end_matches('\n').len());
+    if trailing_newline {
+        normalized.push('\n');
+    }
+
+    match line_endings {
+        LineEndings::Lf => normalized,
+        LineEndings::Crlf => normalized.replace('\n', "\r\n"),
+    }
+}
+
+/// The argument of every `require(...)` call in `source`.
+fn require_targets(source: &str) -> Vec<&str> {
+    require_target_ranges(source)
+        .into_iter()
+        .map(|range| &source[range])
+        .collect()
+}
+
+/// Where the argument of every `require(...)` call in `source` is. Calls are
+/// only found in code, not in comments or strings, and their arguments can
+/// span several lines.
+fn require_target_ranges(source: &str) -> Vec<Range<usize>> {
+    let code = code_ranges(source);
+    let is_code = |index: usize| {
+        let next = code.partition_point(|range| range.end <= index);
+        matches!(code.get(next), Some(range) if range.start <= index)
+    };
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+
+    while let Some(found) = source[start..].find("require") {
+        let name_start = start + found;
+        start = name_start + "require".len();
+
+        let rest = &source[start..];
+        let open = rest.len() - rest.trim_start().len();
+        let is_call = is_code(name_start)
+            && !source[..name_start].ends_with(|char: char| char.is_alphanumeric() || char == '_')
+            && rest[open..].starts_with('(');
+        if !is_call {
+            continue;
+        }
+
+        start += open + 1;
+        let rest = &source[start..];
+
+        // Place paths like `game:GetService("ReplicatedStorage")` contain
+        // parentheses of their own, and strings and comments can contain
+        // unmatched ones.
+        let mut depth = 0;
+        let end = rest.char_indices().find_map(|(index, char)| match char {
+            _ if !is_code(start + index) => None,
+            '(' => {
+                depth += 1;
+                None
+            }
+            ')' if depth == 0 => Some(index),
+            ')' => {
+                depth -= 1;
+                None
+            }
+            _ => None,
+        });
+
+        match end {
+            Some(end) => {
+                let argument = &rest[..end];
+                let leading = argument.len() - argument.trim_start().len();
+                ranges.push(start + leading..start + argument.trim_end().len());
+                start += end;
+            }
+            None => break,
+        }
+    }
+
+    ranges
+}
+
+/// Rewrite the requires in `source`, a module `depth` parents below the
+/// packages folder, that reach one of its package's dependencies through the
+/// folder the package is in.
+fn rewrite_requires(
+    source: &str,
+    depth: usize,
+    requires: &BTreeMap<String, FlatRequire>,
+) -> String {
+    let mut rewritten = String::with_capacity(source.len());
+    let mut copied = 0;
+
+    for range in require_target_ranges(source) {
+        if let Some(target) = rewrite_require(&source[range.clone()], depth, requires) {
+            rewritten.push_str(&source[copied..range.start]);
+            rewritten.push_str(&target);
+            copied = range.end;
+        }
+    }
+
+    rewritten.push_str(&source[copied..]);
+    rewritten
+}
+
+/// The rewritten `require` target, if `target` goes up exactly `depth`
+/// parents from `script` and then into a dependency.
+fn rewrite_require(
+    target: &str,
+    depth: usize,
+    requires: &BTreeMap<String, FlatRequire>,
+) -> Option<String> {
+    let mut rest = target.strip_prefix("script")?;
+    for _ in 0..depth {
+        let (segment, remaining) = next_path_segment(rest)?;
+        if segment != "Parent" {
+            return None;
+        }
+        rest = remaining;
+    }
+
+    let (dep_name, rest) = next_path_segment(rest)?;
+    let rewritten = match requires.get(dep_name)? {
+        FlatRequire::Sibling(name) => {
+            format!(r#"script{}["{}"]"#, ".Parent".repeat(depth), name)
+        }
+        FlatRequire::Place(path) => path.clone(),
+    };
+
+    Some(format!("{}{}", rewritten, rest))
+}
+
+/// Collect the Lua files in the module directory at `dir`, along with how many
+/// parents up from each the directory's parent is.
+fn module_files(
+    sink: &dyn InstallSink,
+    dir: &Path,
+    depth: usize,
+    files: &mut Vec<(PathBuf, usize)>,
+) -> io::Result<()> {
+    for path in read_dir_ignore_not_found(sink, dir)? {
+        if sink.is_dir(&path) {
+            module_files(sink, &path, depth + 1, files)?;
+            continue;
+        }
+
+        let is_lua = matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("lua") | Some("luau")
+        );
+
+        // An `init` script is the directory it's in, not a child of it.
+        if is_lua {
+            let depth = if is_init_file(&path) {
+                depth
+            } else {
+                depth + 1
+            };
+            files.push((path, depth));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the file at `path` is the `init` script of its directory, like
+/// `init.lua` or `init.server.luau`.
+fn is_init_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('.').next())
+        == Some("init")
+}
+
+/// Split the first `.Name` or `["Name"]` segment off of an instance path.
+fn next_path_segment(path: &str) -> Option<(&str, &str)> {
+    // Lua allows whitespace between the parts of a path, like in a require
+    // that's split across lines.
+    let path = path.trim_start();
+    if let Some(rest) = path.strip_prefix("[\"") {
+        let end = rest.find("\"]")?;
+        Some((&rest[..end], &rest[end + 2..]))
+    } else {
+        let rest = path.strip_prefix('.')?.trim_start();
+        let end = rest
+            .find(|char: char| !(char.is_alphanumeric() || char == '_'))
+            .unwrap_or(rest.len());
+
+        if end == 0 {
+            return None;
+        }
+
+        Some((&rest[..end], &rest[end..]))
+    }
+}
+
+/// Collect the Lua files under `dir` that aren't in `produced_paths`, without
+/// looking inside any of the directories in `skipped_dirs`.
+fn find_orphaned_files(
+    sink: &dyn InstallSink,
+    dir: &Path,
+    produced_paths: &BTreeSet<PathBuf>,
+    skipped_dirs: &BTreeSet<PathBuf>,
+    orphans: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for path in read_dir_ignore_not_found(sink, dir)? {
+        if sink.is_dir(&path) {
+            if !skipped_dirs.contains(&path) {
+                find_orphaned_files(sink, &path, produced_paths, skipped_dirs, orphans)?;
+            }
+
+            continue;
+        }
+
+        if is_lua_file(&path) && !produced_paths.contains(&path) {
+            orphans.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Add the Lua files under `dir` that belong to the project itself to
+/// `lua_files`, skipping the packages folders, hidden folders like `.git`,
+/// and test files, which are expected to require dev packages.
+fn find_project_lua_files(
+    sink: &dyn InstallSink,
+    dir: &Path,
+    package_dirs: &[&PathBuf],
+    lua_files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for path in read_dir_ignore_not_found(sink, dir)? {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if file_name.starts_with('.') {
+            continue;
+        }
+
+        if sink.is_dir(&path) {
+            if !package_dirs.contains(&&path) {
+                find_project_lua_files(sink, &path, package_dirs, lua_files)?;
+            }
+
+            continue;
+        }
+
+        let stem = file_name
+            .rsplit_once('.')
+            .map_or(file_name, |(stem, _)| stem);
+        let is_test = stem.ends_with(".spec") || stem.ends_with(".test");
+
+        if is_lua_file(&path) && !is_test {
+            lua_files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn is_lua_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("lua") | Some("luau")
+    )
+}
+
+/// Whether a line of Lua requires something in the folder called `folder`,
+/// such as `require(ReplicatedStorage.DevPackages.TestEZ)`. Comments are
+/// ignored, but the folder can be named by a string, like
+/// `require(script.Parent:FindFirstChild("DevPackages").TestEZ)`.
+fn requires_folder(line: &str, folder: &str) -> bool {
+    let code = line.split("--").next().unwrap_or("");
+
+    let require = match code.find("require") {
+        Some(require) => require,
+        None => return false,
+    };
+
+    code[require..].match_indices(folder).any(|(index, _)| {
+        let index = require + index;
+        let before = code[..index].chars().next_back();
+        let after = code[index + folder.len()..].chars().next();
+        let boundary = |c: Option<char>| !c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+
+        boundary(before) && boundary(after)
+    })
+}
+
+/// Remove the entries under `dir` whose path relative to `package_path`
+/// matches `exclude`, along with everything inside excluded folders. Entries in
+/// `protected`, and the folders containing them, are never removed.
+fn remove_excluded_entries(
+    sink: &dyn InstallSink,
+    package_id: &PackageId,
+    package_path: &Path,
+    dir: &Path,
+    exclude: &GlobSet,
+    protected: &[PathBuf],
+    parent_excluded: bool,
+) -> io::Result<()> {
+    for path in read_dir_ignore_not_found(sink, dir)? {
+        let relative_path = path.strip_prefix(package_path).unwrap();
+        let excluded = parent_excluded || exclude.is_match(relative_path);
+
+        if excluded {
+            if protected.contains(&path) {
+                log::warn!(
+                    "Not excluding {} from {} because it's needed to find the package's types",
+                    relative_path.display(),
+                    package_id
+                );
+                continue;
+            }
+
+            if !protected
+                .iter()
+                .any(|protected| protected.starts_with(&path))
+            {
+                remove_path(sink, &path)?;
+                continue;
+            }
+        }
+
+        if sink.is_dir(&path) {
+            remove_excluded_entries(
+                sink,
+                package_id,
+                package_path,
+                &path,
+                exclude,
+                protected,
+                excluded,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the `wally.toml` of a package unpacked at `package_path` in
+/// `sink` declares the package it was downloaded as, so that a registry can't
+/// serve one package in place of another.
+///
+/// Only the name and version are read, so that manifests with fields this
+/// version of Wally doesn't understand are still accepted. Packages published
+/// before archives included their manifest have nothing to check.
+fn verify_package_identity(
+    package_id: &PackageId,
+    package_path: &Path,
+    sink: &dyn InstallSink,
+) -> anyhow::Result<()> {
+    let contents = match sink.read_file(&package_path.join(MANIFEST_FILE_NAME)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let declared = toml::from_slice::<toml::Value>(&contents)
+        .ok()
+        .and_then(|manifest| {
+            let package = manifest.get("package")?;
+            let name: PackageName = package.get("name")?.as_str()?.parse().ok()?;
+            let version: Version = package.get("version")?.as_str()?.parse().ok()?;
+
+            Some(PackageId::new(name, version))
+        })
+        .ok_or_else(|| {
+            format_err!(
+                "The {} of {} does not declare a valid package name and version",
+                MANIFEST_FILE_NAME,
+                package_id
+            )
+        })?;
+
+    if declared != *package_id {
+        bail!(
+            "Registry inconsistency for package {}\n\
+             The package's {} declares that it is {}.\n\
+             The registry is misconfigured or has been tampered with.",
+            package_id,
+            MANIFEST_FILE_NAME,
+            declared
+        );
+    }
+
+    Ok(())
+}
+
+/// Remove a file or directory, whichever `path` points to.
+fn remove_path(sink: &dyn InstallSink, path: &Path) -> io::Result<()> {
+    if sink.is_dir(path) {
+        sink.remove_dir_all(path)
+    } else {
+        sink.remove_file(path)
+    }
+}
+
+/// Statements that forward a package's exported types from a link which
+/// required the package's main module, at `module`, into `MODULE`. Types read
+/// from a submodule the main module re-exports are forwarded from that
+/// submodule instead, since Luau doesn't carry them through the re-export.
+/// With `strict`, the submodule is annotated with its own type like `MODULE`
+/// is.
+fn forwarding_statements(exports: &ExtractTypesResult, module: &str, strict: bool) -> String {
+    match exports.submodule() {
+        Some(submodule) if strict => {
+            let submodule = format!("{}.{}", module, submodule.join("."));
+            format!(
+                "local TYPES: typeof(require({submodule})) = require({submodule})\n{}",
+                exports.format_forwarding_statements("TYPES"),
+                submodule = submodule
+            )
+        }
+        Some(submodule) => format!(
+            "local TYPES = require({}.{})\n{}",
+            module,
+            submodule.join("."),
+            exports.format_forwarding_statements("TYPES")
+        ),
+        None => exports.format_forwarding_statements("MODULE"),
+    }
+}
+
+/// Write `contents` to `path` unless the file already holds exactly those
+/// bytes, returning whether the file was written.
+///
+/// The contents are written to a temporary file next to `path` and then
+/// renamed over it, so an interrupted install never leaves a partially written
+/// file at `path`.
+fn write_if_changed(sink: &dyn InstallSink, path: &Path, contents: &[u8]) -> io::Result<bool> {
+    if let Ok(existing) = sink.read_file(path) {
+        if existing == contents {
+            return Ok(false);
+        }
+    }
+
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    if let Err(err) = sink.write_file(&temp_path, contents) {
+        let _ = sink.remove_file(&temp_path);
+        return Err(err);
+    }
+
+    sink.rename(&temp_path, path)?;
+    Ok(true)
+}
+
+/// Make the directory at `path` in `to` match the one in `from`. Only files
+/// whose contents differ are written, and anything that `from` doesn't have
+/// is removed.
+fn sync_dir(from: &dyn InstallSink, to: &dyn InstallSink, path: &Path) -> io::Result<()> {
+    to.create_dir(path)?;
+
+    let wanted: BTreeSet<PathBuf> = from.read_dir(path)?.into_iter().collect();
+    for existing in to.read_dir(path)? {
+        if wanted.contains(&existing) && from.is_dir(&existing) == to.is_dir(&existing) {
+            continue;
+        }
+
+        if to.is_dir(&existing) {
+            to.remove_dir_all(&existing)?;
+        } else {
+            to.remove_file(&existing)?;
+        }
+    }
+
+    for entry in &wanted {
+        if from.is_dir(entry) {
+            sync_dir(from, to, entry)?;
+        } else {
+            write_if_changed(to, entry, &from.read_file(entry)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_expected_link(
+    sink: &dyn InstallSink,
+    path: &Path,
+    expected_links: &BTreeSet<PathBuf>,
+) -> bool {
+    sink.is_file(path) && expected_links.contains(path)
+}
+
+/// List the paths of the entries in a directory, or nothing if the directory
+/// doesn't exist.
+fn read_dir_ignore_not_found(sink: &dyn InstallSink, path: &Path) -> io::Result<Vec<PathBuf>> {
+    match sink.read_dir(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        result => result,
+    }
+}
+
+/// Reports how much of a package has been read through it to an observer.
+struct ProgressReader<'a, R> {
+    inner: R,
+    observer: &'a dyn InstallObserver,
+    package_id: &'a PackageId,
+    read: u64,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        self.observer
+            .on_download_progress(self.package_id, self.read);
+        Ok(read)
+    }
+}
+
+/// Whether a package failed to unpack as it downloaded because its archive
+/// can't be read that way, rather than because it's broken.
+fn is_unstreamable(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<ZipError>(),
+        Some(ZipError::UnsupportedArchive(_))
+    )
+}
+
+/// Whether a failed download was caused by a problem talking to the registry
+/// that might not happen again, as opposed to something like the package not
+/// existing or the disk being full.
+fn is_retryable(err: &anyhow::Error) -> bool {
+    err.chain().any(is_transient_cause)
+}
+
+/// How long to wait before retrying a download that has already been retried
+/// `attempt` times.
+fn retry_delay(attempt: u32) -> Duration {
+    let factor = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+
+    RETRY_BASE_DELAY
+        .saturating_mul(factor)
+        .min(RETRY_MAX_DELAY)
+}
+
+/// The number of download workers to use when none was requested explicitly.
+fn default_concurrency() -> usize {
+    thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+        .max(4)
+}
+
+/// Creates a suitable name for use in file paths that refer to this package.
+pub(crate) fn package_id_file_name(id: &PackageId) -> String {
+    format!(
+        "{}_{}@{}",
+        id.name().scope(),
+        id.name().name(),
+        id.version()
+    )
+}
+
+/// The packages named `target`, along with every package they depend on that
+/// isn't also needed by the rest of the graph.
+fn target_subtree(
+    root_package_id: &PackageId,
+    resolved: &Resolve,
+    target: &PackageName,
+) -> anyhow::Result<BTreeSet<PackageId>> {
+    let targets: BTreeSet<PackageId> = resolved
+        .activated
+        .iter()
+        .filter(|package_id| package_id.name() == target && *package_id != root_package_id)
+        .cloned()
+        .collect();
+
+    if targets.is_empty() {
+        bail!("{} is not a dependency of {}", target, root_package_id);
+    }
+
+    let outside = reachable(resolved, root_package_id, &targets);
+    let subtree = targets
+        .iter()
+        .flat_map(|target| reachable(resolved, target, &BTreeSet::new()))
+        .filter(|package_id| targets.contains(package_id) || !outside.contains(package_id))
+        .collect();
+
+    Ok(subtree)
+}
+
+/// Every package reachable from `start` without passing through `excluded`.
+fn reachable(
+    resolved: &Resolve,
+    start: &PackageId,
+    excluded: &BTreeSet<PackageId>,
+) -> BTreeSet<PackageId> {
+    let mut seen = BTreeSet::new();
+    let mut to_visit = vec![start];
+
+    while let Some(package_id) = to_visit.pop() {
+        if excluded.contains(package_id) || !seen.insert(package_id.clone()) {
+            continue;
+        }
+
+        to_visit.extend(direct_dependencies(resolved, package_id));
+    }
+
+    seen
+}
+
+/// The packages that `package_id` depends on directly, from any realm.
+fn direct_dependencies<'a>(
+    resolved: &'a Resolve,
+    package_id: &'a PackageId,
+) -> impl Iterator<Item = &'a PackageId> {
+    vec![
+        &resolved.shared_dependencies,
+        &resolved.server_dependencies,
+        &resolved.dev_dependencies,
+    ]
+    .into_iter()
+    .filter_map(move |dependencies| dependencies.get(package_id))
+    .flat_map(|dependencies| dependencies.values())
+}
+
+/// Choose the directory name of every package in the resolve so that no two
+/// packages are installed to the same directory, comparing names
+/// case-insensitively since that's how many filesystems will treat them.
+///
+/// Packages whose names collide are an error unless `disambiguate` is set, in
+/// which case each of them has a short hash of its exact name appended.
+fn file_names(
+    root_package_id: &PackageId,
+    resolved: &Resolve,
+    disambiguate: bool,
+) -> anyhow::Result<HashMap<PackageId, String>> {
+    let mut by_folded_name: BTreeMap<String, Vec<&PackageId>> = BTreeMap::new();
+
+    for package_id in &resolved.activated {
+        if package_id == root_package_id {
+            continue;
+        }
+
+        let folded_name = package_id_file_name(package_id).to_lowercase();
+        by_folded_name
+            .entry(folded_name)
+            .or_default()
+            .push(package_id);
+    }
+
+    let mut file_names = HashMap::new();
+
+    for colliding in by_folded_name.values() {
+        if let [package_id] = colliding.as_slice() {
+            file_names.insert((*package_id).clone(), package_id_file_name(package_id));
+            continue;
+        }
+
+        if !disambiguate {
+            bail!(InstallError::FileNameCollision {
+                first: colliding[0].clone(),
+                second: colliding[1].clone(),
+            });
+        }
+
+        // Every package in the collision is renamed, rather than all but one,
+        // so that a package's directory doesn't depend on which of them
+        // happens to sort first.
+        for package_id in colliding {
+            let file_name = package_id_file_name(package_id);
+            let hash = blake3::hash(file_name.as_bytes()).to_hex();
+            file_names.insert(
+                (*package_id).clone(),
+                format!("{}-{}", file_name, &hash[..8]),
+            );
+        }
+    }
+
+    Ok(file_names)
+}
+
+/// Check that no two packages in `resolved` would be installed to the same
+/// directory in the flat layout, even on filesystems that ignore case.
+fn check_flat_names(root_package_id: &PackageId, resolved: &Resolve) -> anyhow::Result<()> {
+    let mut installed: HashMap<(Realm, String), &PackageId> = HashMap::new();
+
+    for package_id in &resolved.activated {
+        if package_id == root_package_id {
+            continue;
+        }
+
+        let realm = resolved.metadata[package_id].origin_realm;
+        let folded_name = package_id.name().name().to_lowercase();
+
+        if let Some(first) = installed.insert((realm, folded_name), package_id) {
+            bail!(InstallError::FlatLayoutConflict {
+                first: first.clone(),
+                second: package_id.clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::PathDependency;
+    use crate::package_source::{InMemoryRegistry, PathSource};
+    use crate::resolution::{resolve, ResolvePackageMetadata};
+    use crate::test_package::PackageBuilder;
+    use fs_err as fs;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn local_io_errors_are_not_retryable() {
+        let err = anyhow::Error::new(io::Error::new(io::ErrorKind::PermissionDenied, "denied"))
+            .context("failed to unpack package");
+        assert!(!is_retryable(&err));
+    }
+
+    #[test]
+    fn retry_delays_are_capped() {
+        assert_eq!(retry_delay(0), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(1), RETRY_BASE_DELAY * 2);
+        assert_eq!(retry_delay(10), RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(32), RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(u32::MAX), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn missing_packages_are_not_retryable() {
+        let err = format_err!("Failed to download package biff/minimal@0.1.0 from registry");
+        assert!(!is_retryable(&err));
+    }
+
+    #[test]
+    fn identical_contents_are_not_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Link.lua");
+
+        assert!(write_if_changed(&FilesystemSink, &path, b"return 1").unwrap());
+        assert!(!write_if_changed(&FilesystemSink, &path, b"return 1").unwrap());
+        assert!(write_if_changed(&FilesystemSink, &path, b"return 2").unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"return 2");
+    }
+
+    #[test]
+    fn link_line_endings_are_normalized() {
+        let contents = "local MODULE = require(script)\r\nreturn MODULE\n\n";
+
+        assert_eq!(
+            normalize_line_endings(contents, LineEndings::Lf, true),
+            "local MODULE = require(script)\nreturn MODULE\n"
+        );
+        assert_eq!(
+            normalize_line_endings(contents, LineEndings::Crlf, true),
+            "local MODULE = require(script)\r\nreturn MODULE\r\n"
+        );
+        assert_eq!(
+            normalize_line_endings(contents, LineEndings::Crlf, false),
+            "local MODULE = require(script)\r\nreturn MODULE"
+        );
+    }
+
+    /// Replacements for some of the methods of a [`HookedSink`]. Anything
+    /// that isn't replaced goes straight to the sink's `MemorySink`.
+    trait SinkHooks: Send + Sync {
+        /// The path that's used in place of `path` for every method.
+        fn map_path(&self, path: &Path) -> PathBuf {
+            path.to_owned()
+        }
+
+        fn write_file(&self, sink: &MemorySink, path: &Path, contents: &[u8]) -> io::Result<()> {
+            sink.write_file(path, contents)
+        }
+
+        fn is_dir(&self, sink: &MemorySink, path: &Path) -> bool {
+            sink.is_dir(path)
+        }
+    }
+
+    /// A `MemorySink` with some of its behavior replaced by `hooks`, for
+    /// tests that need a sink that misbehaves or is watched.
+    struct HookedSink<H> {
+        inner: MemorySink,
+        hooks: H,
+    }
+
+    impl<H: SinkHooks> HookedSink<H> {
+        fn new(hooks: H) -> Self {
+            Self {
+                inner: MemorySink::new(),
+                hooks,
+            }
+        }
+    }
+
+    impl<H: SinkHooks> InstallSink for HookedSink<H> {
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            self.inner.create_dir(&self.hooks.map_path(path))
+        }
+
+        fn write_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.hooks
+                .write_file(&self.inner, &self.hooks.map_path(path), contents)
+        }
+
+        fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.inner.read_file(&self.hooks.map_path(path))
+        }
+
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.inner.remove_dir_all(&self.hooks.map_path(path))
+        }
+
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.inner.remove_file(&self.hooks.map_path(path))
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            self.inner
+                .rename(&self.hooks.map_path(from), &self.hooks.map_path(to))
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            self.inner.read_dir(&self.hooks.map_path(path))
+        }
+
+        fn is_dir(&self, path: &Path) -> bool {
+            self.hooks.is_dir(&self.inner, &self.hooks.map_path(path))
+        }
+
+        fn is_file(&self, path: &Path) -> bool {
+            self.inner.is_file(&self.hooks.map_path(path))
+        }
+    }
+
+    /// Stops halfway through writing every file, like an install that was
+    /// killed mid-write.
+    struct Interrupted;
+
+    impl SinkHooks for Interrupted {
+        fn write_file(&self, sink: &MemorySink, path: &Path, contents: &[u8]) -> io::Result<()> {
+            sink.write_file(path, &contents[..contents.len() / 2])?;
+            Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"))
+        }
+    }
+
+    #[test]
+    fn interrupted_writes_leave_no_partial_links() {
+        let sink = HookedSink::new(Interrupted);
+        let packages = Path::new("/project/Packages");
+        sink.create_dir(packages).unwrap();
+
+        let existing = packages.join("Existing.lua");
+        sink.inner.write_file(&existing, b"return 1").unwrap();
+        let new = packages.join("New.lua");
+
+        assert!(
+            write_if_changed(&sink, &existing, b"return require(script.Parent.Other)").is_err()
+        );
+        assert!(write_if_changed(&sink, &new, b"return require(script.Parent.Other)").is_err());
+
+        // The old link is untouched and the new one was never created, rather
+        // than either holding half of the new contents.
+        let files = sink.inner.files();
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec![&existing]);
+        assert_eq!(files[&existing], b"return 1");
+    }
+
+    /// Records every file that's written.
+    #[derive(Default)]
+    struct RecordWrites {
+        written: Mutex<Vec<PathBuf>>,
+    }
+
+    impl SinkHooks for RecordWrites {
+        fn write_file(&self, sink: &MemorySink, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.written.lock().unwrap().push(path.to_owned());
+            sink.write_file(path, contents)
+        }
+    }
+
+    #[test]
+    fn unchanged_local_packages_are_not_written_again() {
+        let library = tempfile::tempdir().unwrap();
+        let library_path = library.path().join("library");
+        fs::create_dir_all(library_path.join("src")).unwrap();
+        fs::write(
+            library_path.join("wally.toml"),
+            "[package]\nname = \"biff/library\"\nversion = \"0.1.0\"\nregistry = \"\"\nrealm = \"shared\"\n",
+        )
+        .unwrap();
+        fs::write(library_path.join("src/init.lua"), "return {}").unwrap();
+        fs::write(library_path.join("src/old.lua"), "return 1").unwrap();
+
+        let mut root = PackageBuilder::new("biff/root@0.1.0").into_manifest();
+        root.path_dependencies.insert(
+            "Library".to_owned(),
+            PathDependency {
+                table_realm: Realm::Shared,
+                path: "library".to_owned(),
+            },
+        );
+
+        let sink = Arc::new(HookedSink::new(RecordWrites::default()));
+        let install = || {
+            let mut sources = PackageSourceMap::new(Box::new(InMemoryRegistry::new().source()));
+            sources.add_path_source(PathSource::new(library.path(), "library").unwrap());
+            let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+            sink.hooks.written.lock().unwrap().clear();
+            InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+                .with_sink(sink.clone())
+                .with_package_cache(None)
+                .install(sources, root.package_id(), resolved)
+                .unwrap();
+
+            let index = Path::new("/project/Packages/_Index");
+            let written = sink.hooks.written.lock().unwrap();
+            written
+                .iter()
+                .filter(|path| path.starts_with(index))
+                .map(|path| path.strip_prefix(index).unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(!install().is_empty());
+        assert_eq!(install(), Vec::<PathBuf>::new());
+
+        // Only the file that changed is written, and the one that's gone is
+        // removed.
+        fs::write(library_path.join("src/init.lua"), "return 2").unwrap();
+        fs::remove_file(library_path.join("src/old.lua")).unwrap();
+
+        assert_eq!(
+            install(),
+            vec![PathBuf::from("biff_library@0.1.0/library/src/init.lua.tmp")]
+        );
+
+        let package = Path::new("/project/Packages/_Index/biff_library@0.1.0/library");
+        assert_eq!(
+            sink.read_file(&package.join("src/init.lua")).unwrap(),
+            b"return 2"
+        );
+        assert!(!sink.is_file(&package.join("src/old.lua")));
+    }
+
+    /// Tracks the most downloads that were ever spawned without having
+    /// finished. The installing thread checks whether each package is in
+    /// place once up front, and again right before spawning its download, so
+    /// the second check is counted as the spawn.
+    struct WatchSpawns {
+        installing_thread: thread::ThreadId,
+        checks: Mutex<BTreeMap<PathBuf, usize>>,
+        spawned: AtomicUsize,
+        finished: Arc<AtomicUsize>,
+        max_pending: AtomicUsize,
+    }
+
+    impl SinkHooks for WatchSpawns {
+        fn write_file(&self, sink: &MemorySink, path: &Path, contents: &[u8]) -> io::Result<()> {
+            // Slow unpacking down enough for downloads to pile up.
+            thread::sleep(Duration::from_millis(1));
+            sink.write_file(path, contents)
+        }
+
+        fn is_dir(&self, sink: &MemorySink, path: &Path) -> bool {
+            let is_package = path
+                .parent()
+                .and_then(Path::parent)
+                .is_some_and(|index| index.ends_with("_Index"));
+
+            if is_package && thread::current().id() == self.installing_thread {
+                let mut checks = self.checks.lock().unwrap();
+                let count = checks.entry(path.to_owned()).or_default();
+                *count += 1;
+
+                if *count == 2 {
+                    let spawned = self.spawned.fetch_add(1, Ordering::SeqCst);
+                    let pending = spawned.saturating_sub(self.finished.load(Ordering::SeqCst));
+                    self.max_pending.fetch_max(pending, Ordering::SeqCst);
+                }
+            }
+
+            sink.is_dir(path)
+        }
+    }
+
+    /// Counts the downloads that have finished.
+    struct CountFinished(Arc<AtomicUsize>);
+
+    impl InstallObserver for CountFinished {
+        fn on_download_complete(&self, _package_id: &PackageId) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn large_installs_are_bounded_by_concurrency() {
+        let registry = InMemoryRegistry::new();
+        let mut root = PackageBuilder::new("biff/root@0.1.0");
+
+        for index in 0..200 {
+            let id = format!("biff/package-{}@0.1.0", index);
+            registry.publish(PackageBuilder::new(&id).with_file("init.lua", "return {}"));
+            root = root.with_dep(format!("Package{}", index), &id);
+        }
+
+        let root = root.into_manifest();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        let finished = Arc::new(AtomicUsize::new(0));
+        let sink = Arc::new(HookedSink::new(WatchSpawns {
+            installing_thread: thread::current().id(),
+            checks: Mutex::default(),
+            spawned: AtomicUsize::new(0),
+            finished: finished.clone(),
+            max_pending: AtomicUsize::new(0),
+        }));
+        let checksums = InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+            .with_sink(sink.clone())
+            .with_observer(Arc::new(CountFinished(finished)))
+            .with_package_cache(None)
+            .with_concurrency(Some(4))
+            .install(sources, root.package_id(), resolved)
+            .unwrap();
+
+        // Blocking threads would already keep only four downloads running,
+        // so what's checked is that the rest weren't all queued up behind
+        // them.
+        assert_eq!(checksums.len(), 200);
+        assert_eq!(sink.hooks.spawned.load(Ordering::SeqCst), 200);
+        assert!(sink.hooks.max_pending.load(Ordering::SeqCst) <= 4);
+    }
+
+    #[test]
+    fn colliding_file_names_are_rejected() {
+        let root = PackageId::from_str("biff/root@0.1.0").unwrap();
+        let upper = PackageId::from_str("biff/minimal@1.0.0-Beta").unwrap();
+        let lower = PackageId::from_str("biff/minimal@1.0.0-beta").unwrap();
+
+        let mut resolved = Resolve::default();
+        resolved.activated.insert(root.clone());
+        resolved.activated.insert(upper.clone());
+        assert!(file_names(&root, &resolved, false).is_ok());
+
+        resolved.activated.insert(lower.clone());
+        let err = file_names(&root, &resolved, false).unwrap_err();
+        match err.downcast_ref::<InstallError>() {
+            Some(InstallError::FileNameCollision { first, second }) => {
+                assert_eq!(first, &upper);
+                assert_eq!(second, &lower);
+            }
+            _ => panic!("expected a file name collision, got {:?}", err),
+        }
+    }
+
+    /// Ignores case in paths, like the default filesystems on Windows and
+    /// macOS.
+    struct CaseInsensitive;
+
+    impl SinkHooks for CaseInsensitive {
+        fn map_path(&self, path: &Path) -> PathBuf {
+            PathBuf::from(path.to_string_lossy().to_lowercase())
+        }
+    }
+
+    #[test]
+    fn colliding_file_names_are_disambiguated() {
+        let registry = InMemoryRegistry::new();
+        for version in ["1.0.0-Beta", "1.0.0-beta"] {
+            registry.publish(
+                PackageBuilder::new(format!("biff/minimal@{}", version))
+                    .with_file("init.lua", format!("return \"{}\"", version)),
+            );
+        }
+
+        // The resolver never picks both versions for one realm, so the graph
+        // is put together by hand.
+        let root = PackageId::from_str("biff/root@0.1.0").unwrap();
+        let upper = PackageId::from_str("biff/minimal@1.0.0-Beta").unwrap();
+        let lower = PackageId::from_str("biff/minimal@1.0.0-beta").unwrap();
+
+        let mut resolved = Resolve::default();
+        for package_id in [&root, &upper, &lower] {
+            resolved.activated.insert(package_id.clone());
+            resolved.metadata.insert(
+                package_id.clone(),
+                ResolvePackageMetadata {
+                    realm: Realm::Shared,
+                    origin_realm: Realm::Shared,
+                    source_registry: PackageSourceId::DefaultRegistry,
+                    git_commit: None,
+                    checksum: None,
+                },
+            );
+        }
+        resolved.shared_dependencies.insert(
+            root.clone(),
+            vec![
+                ("Upper".to_owned(), upper.clone()),
+                ("Lower".to_owned(), lower.clone()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let place = PlaceInfo {
+            disambiguate_file_names: true,
+            ..Default::default()
+        };
+        let sink = Arc::new(HookedSink::new(CaseInsensitive));
+        InstallationContext::new(Path::new("/project"), &place)
+            .with_sink(sink.clone())
+            .with_package_cache(None)
+            .with_verify(true)
+            .install(
+                PackageSourceMap::new(Box::new(registry.source())),
+                root,
+                resolved,
+            )
+            .unwrap();
+
+        let files = sink.inner.files();
+        for (package_id, alias) in [(&upper, "upper"), (&lower, "lower")] {
+            let file_name = package_id_file_name(package_id);
+            let hash = blake3::hash(file_name.as_bytes()).to_hex();
+            let disambiguated = format!("{}-{}", file_name, &hash[..8]);
+
+            // Both packages keep their own contents, and each link requires
+            // the package it was generated for.
+            let contents_path = format!(
+                "/project/packages/_index/{}/minimal/init.lua",
+                disambiguated.to_lowercase()
+            );
+            assert_eq!(
+                files[Path::new(&contents_path)],
+                format!("return \"{}\"", package_id.version()).into_bytes()
+            );
+
+            let link = String::from_utf8(
+                files[Path::new(&format!("/project/packages/{}.lua", alias))].clone(),
+            )
+            .unwrap();
+            assert!(
+                link.contains(&format!("[\"{}\"]", disambiguated)),
+                "{} should require {}",
+                link,
+                disambiguated
+            );
+        }
+    }
+
+    #[test]
+    fn downloads_are_reported_in_the_same_order_every_time() {
+        let registry = InMemoryRegistry::new();
+        let mut root = PackageBuilder::new("biff/root@0.1.0");
+
+        for index in 0..20 {
+            let id = format!("biff/package-{}@0.1.0", index);
+            registry.publish(PackageBuilder::new(&id).with_file("init.lua", "return {}"));
+            root = root.with_dep(format!("Package{}", index), &id);
+        }
+
+        let root = root.into_manifest();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        let install = || {
+            let context = InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+                .with_sink(Arc::new(MemorySink::new()))
+                .with_package_cache(None)
+                .with_concurrency(Some(8));
+
+            context
+                .clone()
+                .install(sources.clone(), root.package_id(), resolved.clone())
+                .unwrap();
+
+            let downloaded = context.downloaded.lock().unwrap().clone();
+            downloaded
+        };
+
+        let first = install();
+        assert_eq!(first.len(), 20);
+        assert!(first.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(first, install());
+    }
+
+    /// Panics when writing the contents of a package, like an unpacker that
+    /// hit an archive it didn't expect.
+    struct PanicOnUnpack;
+
+    impl SinkHooks for PanicOnUnpack {
+        fn write_file(&self, sink: &MemorySink, path: &Path, contents: &[u8]) -> io::Result<()> {
+            if path
+                .components()
+                .any(|component| component.as_os_str() == "_Index")
+            {
+                panic!("unexpected archive entry");
+            }
+
+            sink.write_file(path, contents)
+        }
+    }
+
+    #[test]
+    fn panicking_download_is_an_error() {
+        let registry = InMemoryRegistry::new();
+        registry
+            .publish(PackageBuilder::new("biff/broken@0.1.0").with_file("init.lua", "return {}"));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Broken", "biff/broken@0.1.0")
+            .into_manifest();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        let err = InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+            .with_sink(Arc::new(HookedSink::new(PanicOnUnpack)))
+            .with_package_cache(None)
+            .install(sources, root.package_id(), resolved)
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Installing biff/broken@0.1.0 panicked: unexpected archive entry"
+        );
+    }
+
+    #[test]
+    fn packages_must_match_the_checksum_they_were_resolved_with() {
+        let registry = InMemoryRegistry::new();
+        registry.publish_with_checksum(
+            PackageBuilder::new("biff/tampered@0.1.0").with_file("init.lua", "return {}"),
+        );
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Tampered", "biff/tampered@0.1.0")
+            .into_manifest();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        let package_id = PackageId::from_str("biff/tampered@0.1.0").unwrap();
+        let recorded = resolved.metadata[&package_id].checksum.clone().unwrap();
+
+        // The package installs while the registry still agrees with itself.
+        InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+            .with_sink(Arc::new(MemorySink::new()))
+            .with_package_cache(None)
+            .install(sources.clone(), root.package_id(), resolved.clone())
+            .unwrap();
+
+        // The registry serves something else by the time it's downloaded.
+        let served = PackageBuilder::new("biff/tampered@0.1.0")
+            .with_file("init.lua", "return 'evil'")
+            .contents();
+        registry.replace_contents(&package_id, served.clone());
+
+        let err = InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+            .with_sink(Arc::new(MemorySink::new()))
+            .with_package_cache(None)
+            .install(sources, root.package_id(), resolved)
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Registry inconsistency for package biff/tampered@0.1.0\n\
+                 The registry's index recorded checksum {} when the package was resolved, but \
+                 the registry served a package with checksum {}.\n\
+                 The registry is misconfigured or has been tampered with.",
+                recorded,
+                served.checksum()
+            )
+        );
+    }
+
+    #[test]
+    fn plan_matches_the_installed_files() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(
+            PackageBuilder::new("biff/minimal@0.1.0")
+                .with_file("init.lua", "export type Thing = number\nreturn {}"),
+        );
+        registry.publish(
+            PackageBuilder::new("biff/one-dependency@0.1.0")
+                .with_dep("Minimal", "biff/minimal@0.1.0")
+                .with_file("init.lua", "return require(script.Parent.Minimal)"),
+        );
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0")
+            .into_manifest();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        let plan = InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+            .plan(sources.clone(), root.package_id(), resolved.clone())
+            .unwrap();
+
+        let sink = Arc::new(MemorySink::new());
+        InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+            .with_sink(sink.clone())
+            .with_package_cache(None)
+            .install(sources, root.package_id(), resolved)
+            .unwrap();
+
+        let index = Path::new("/project/Packages/_Index");
+        assert_eq!(
+            plan.links
+                .iter()
+                .map(|(path, _)| path.as_path())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new("/project/Packages/OneDependency.lua"),
+                &index.join("biff_one-dependency@0.1.0/Minimal.lua"),
+            ]
+        );
+        for (path, contents) in &plan.links {
+            assert_eq!(
+                String::from_utf8(sink.read_file(path).unwrap()).unwrap(),
+                *contents
+            );
+        }
+
+        assert_eq!(plan.packages.len(), 2);
+        for path in plan.packages.values() {
+            assert!(sink.is_dir(path), "{} was not installed", path.display());
+        }
+    }
+
+    /// Fails the test if it's told about anything.
+    struct PanicOnReport;
+
+    impl InstallObserver for PanicOnReport {
+        fn on_download_start(&self, package_id: &PackageId) {
+            panic!("reported downloading {}", package_id);
+        }
+
+        fn on_dev_require(&self, dev_require: &DevRequire) {
+            panic!("reported a dev require in {}", dev_require.path.display());
+        }
+
+        fn on_orphaned_file(&self, path: &Path) {
+            panic!("reported orphaned file {}", path.display());
+        }
+    }
+
+    #[test]
+    fn plans_are_silent_and_skip_checks() {
+        let registry = InMemoryRegistry::new();
+        registry
+            .publish(PackageBuilder::new("biff/minimal@0.1.0").with_file("init.lua", "return {}"));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .into_manifest();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        // Minimal exports no types, so installing with these flags would fail.
+        let plan = InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+            .with_observer(Arc::new(PanicOnReport))
+            .with_check_orphans(true)
+            .with_check_dev_requires(true)
+            .with_require_types(true)
+            .with_verify(true)
+            .plan(sources, root.package_id(), resolved)
+            .unwrap();
+
+        assert_eq!(plan.packages.len(), 1);
+        assert_eq!(plan.links.len(), 1);
+    }
+
+    #[test]
+    fn packages_must_declare_the_identity_they_were_downloaded_as() {
+        let registry = InMemoryRegistry::new();
+        registry
+            .publish(PackageBuilder::new("biff/impostor@1.0.0").with_file("init.lua", "return {}"));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Impostor", "biff/impostor@1.0.0")
+            .into_manifest();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        // The registry serves a package whose manifest claims another version.
+        let package_id = PackageId::from_str("biff/impostor@1.0.0").unwrap();
+        let served = PackageBuilder::new("biff/impostor@2.0.0")
+            .with_file("init.lua", "return {}")
+            .contents();
+        registry.replace_contents(&package_id, served);
+
+        let sink = Arc::new(MemorySink::new());
+        let err = InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+            .with_sink(sink.clone())
+            .with_package_cache(None)
+            .install(sources, root.package_id(), resolved)
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Registry inconsistency for package biff/impostor@1.0.0\n\
+             The package's wally.toml declares that it is biff/impostor@2.0.0.\n\
+             The registry is misconfigured or has been tampered with."
+        );
+        assert!(!sink.is_dir(Path::new(
+            "/project/Packages/_Index/biff_impostor@1.0.0/impostor"
+        )));
+    }
+
+    /// Install a root package with three dependencies, two of which don't
+    /// match the checksums they're locked to.
+    fn install_with_two_failures(fail_fast: bool) -> (Arc<MemorySink>, anyhow::Result<()>) {
+        let registry = InMemoryRegistry::new();
+        let mut root = PackageBuilder::new("biff/root@0.1.0");
+
+        for name in ["good", "bad-one", "bad-two"].iter() {
+            let id = format!("biff/{}@0.1.0", name);
+            registry.publish(PackageBuilder::new(&id).with_file("init.lua", "return {}"));
+            root = root.with_dep(name.replace('-', "_"), &id);
+        }
+
+        let root = root.into_manifest();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        let locked_checksums = ["biff/bad-one@0.1.0", "biff/bad-two@0.1.0"]
+            .iter()
+            .map(|id| (PackageId::from_str(id).unwrap(), "0000".to_owned()))
+            .collect();
+
+        let sink = Arc::new(MemorySink::new());
+        let result = InstallationContext::new(Path::new("/project"), &PlaceInfo::default())
+            .with_sink(sink.clone())
+            .with_package_cache(None)
+            .with_locked_checksums(locked_checksums)
+            .with_fail_fast(fail_fast)
+            .install(sources, root.package_id(), resolved)
+            .map(|_| ());
+
+        (sink, result)
+    }
+
+    #[test]
+    fn every_failed_package_is_reported() {
+        let (sink, result) = install_with_two_failures(false);
+        let err = result.unwrap_err();
+
+        match err.downcast_ref::<InstallError>() {
+            Some(InstallError::PackagesFailed { failures }) => {
+                let failed: Vec<_> = failures.iter().map(|(id, _)| id.to_string()).collect();
+                assert_eq!(failed, ["biff/bad-one@0.1.0", "biff/bad-two@0.1.0"]);
+            }
+            _ => panic!("expected failed packages, got {:?}", err),
+        }
+
+        // Packages that did install are still linked, and nothing links to
+        // the ones that didn't.
+        let files = sink.files();
+        assert!(files.contains_key(Path::new("/project/Packages/good.lua")));
+        assert!(!files.contains_key(Path::new("/project/Packages/bad_one.lua")));
+        assert!(!files.contains_key(Path::new("/project/Packages/bad_two.lua")));
+    }
+
+    #[test]
+    fn fail_fast_stops_at_the_first_failure() {
+        let (_, result) = install_with_two_failures(true);
+        let err = result.unwrap_err();
+
+        assert!(err.downcast_ref::<InstallError>().is_none());
+        assert!(
+            format!("{}", err).starts_with("Checksum mismatch"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn dangling_links_are_reported() {
+        let sink = Arc::new(MemorySink::new());
+        let place = PlaceInfo {
+            shared_packages: Some("game.ReplicatedStorage.Packages".to_owned()),
+            ..Default::default()
+        };
+        let context =
+            InstallationContext::new(Path::new("/project"), &place).with_sink(sink.clone());
+
+        let package = Path::new("/project/Packages/_Index/biff_present@0.1.0/present");
+        sink.create_dir(package).unwrap();
+        sink.create_dir(Path::new("/project/ServerPackages"))
+            .unwrap();
+        sink.write_file(&package.join("default.project.json"), b"{}")
+            .unwrap();
+
+        let present = Path::new("/project/Packages/Present.lua");
+        let missing = Path::new("/project/Packages/Missing.lua");
+        let cross_realm = Path::new("/project/ServerPackages/Present.lua");
+
+        context
+            .write_link(
+                present,
+                r#"local MODULE = require(script.Parent._Index["biff_present@0.1.0"]["present"])
+local TYPES = require(script.Parent._Index["biff_present@0.1.0"]["present"].Types)
+return MODULE"#,
+            )
+            .unwrap();
+        context
+            .write_link(
+                missing,
+                r#"return require(script.Parent._Index["biff_missing@0.1.0"]["missing"])"#,
+            )
+            .unwrap();
+        context
+            .write_link(
+                cross_realm,
+                r#"return require(game.ReplicatedStorage.Packages._Index["biff_present@0.1.0"]["present"])"#,
+            )
+            .unwrap();
+
+        let err = context.verify_links().unwrap_err();
+        match err.downcast_ref::<InstallError>() {
+            Some(InstallError::DanglingLinks { links }) => {
+                assert_eq!(
+                    links,
+                    &[(
+                        missing.to_owned(),
+                        r#"script.Parent._Index["biff_missing@0.1.0"]["missing"]"#.to_owned()
+                    )]
+                );
+            }
+            _ => panic!("expected dangling links, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn require_targets_are_parsed() {
+        let source = r#"local MODULE = require(game:GetService("ReplicatedStorage").Packages.Foo)
+export type Bar = MODULE.Bar
+return require(script.Parent["Baz"])"#;
+
+        assert_eq!(
+            require_targets(source),
+            [
+                r#"game:GetService("ReplicatedStorage").Packages.Foo"#,
+                r#"script.Parent["Baz"]"#
+            ]
+        );
+    }
+
+    #[test]
+    fn require_targets_are_only_found_in_code() {
+        let source = r#"-- Use it with require(script.Parent.Commented)
+local message = "call require(script.Parent.Quoted) first"
+--[[ require(script.Parent.Blocked) ]]
+local Foo = require(script.Parent[")"] --[[ ) ]])
+local notrequire = myrequire(script.Parent.Other)
+return require (
+    script.Parent
+        .Bar
+)"#;
+
+        assert_eq!(
+            require_targets(source),
+            [
+                r#"script.Parent[")"] --[[ ) ]]"#,
+                "script.Parent\n        .Bar"
+            ]
+        );
+    }
+
+    #[test]
+    fn flat_layout_rewrites_requires() {
+        let registry = InMemoryRegistry::new();
+        for version in ["0.1.0", "0.2.0"] {
+            registry.publish(
+                PackageBuilder::new(format!("biff/library@{}", version))
+                    .with_dep("Util", "biff/util@0.1.0")
+                    .with_file(
+                        "init.lua",
+                        "local Util = require(script.Parent.Util)\nreturn require(script.Helper)",
+                    )
+                    .with_file(
+                        "Helper.lua",
+                        format!(
+                            "return require(script.Parent.Parent[\"Util\"]).value .. \"{}\"",
+                            version
+                        ),
+                    ),
+            );
+        }
+        registry.publish(PackageBuilder::new("biff/util@0.1.0").with_file("init.lua", "return {}"));
+
+        let place = PlaceInfo {
+            layout: Layout::Flat,
+            ..Default::default()
+        };
+        let sink = Arc::new(MemorySink::new());
+        let install = |version: &str| {
+            let root = PackageBuilder::new("biff/root@0.1.0")
+                .with_dep("Library", format!("biff/library@{}", version))
+                .into_manifest();
+            let sources = PackageSourceMap::new(Box::new(registry.source()));
+            let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+            InstallationContext::new(Path::new("/project"), &place)
+                .with_sink(sink.clone())
+                .with_package_cache(None)
+                .install(sources, root.package_id(), resolved)
+                .unwrap();
+        };
+
+        install("0.1.0");
+
+        let library = Path::new("/project/Packages/library");
+        assert_eq!(
+            sink.read_file(&library.join("init.lua")).unwrap(),
+            b"local Util = require(script.Parent[\"util\"])\nreturn require(script.Helper)"
+        );
+        assert_eq!(
+            sink.read_file(&library.join("Helper.lua")).unwrap(),
+            b"return require(script.Parent.Parent[\"util\"]).value .. \"0.1.0\""
+        );
+        assert!(sink.is_file(Path::new("/project/Packages/util/init.lua")));
+        assert!(!sink.is_dir(Path::new("/project/Packages/_Index")));
+        assert!(!sink.is_file(Path::new("/project/Packages/Library.lua")));
+
+        // The directory keeps its name when the version changes, but the
+        // package is still replaced.
+        install("0.2.0");
+        assert_eq!(
+            sink.read_file(&library.join("Helper.lua")).unwrap(),
+            b"return require(script.Parent.Parent[\"util\"]).value .. \"0.2.0\""
+        );
+    }
+
+    #[test]
+    fn flat_layout_rewrites_only_requires_in_code() {
+        let requires = vec![("Util".to_owned(), FlatRequire::Sibling("util".to_owned()))]
+            .into_iter()
+            .collect();
+        let source = r#"-- Call require(script.Parent.Util) for the utilities
+local help = "require(script.Parent.Util)"
+local Util = require(
+    script.Parent
+        .Util
+)
+return Util"#;
+
+        assert_eq!(
+            rewrite_requires(source, 1, &requires),
+            r#"-- Call require(script.Parent.Util) for the utilities
+local help = "require(script.Parent.Util)"
+local Util = require(
+    script.Parent["util"]
+)
+return Util"#
+        );
+    }
+
+    #[test]
+    fn flat_layout_rejects_two_versions() {
+        let root = PackageId::from_str("biff/root@0.1.0").unwrap();
+        let old = PackageId::from_str("biff/minimal@1.0.0").unwrap();
+        let new = PackageId::from_str("biff/minimal@2.0.0").unwrap();
+
+        let mut resolved = Resolve::default();
+        for package_id in [&root, &old, &new] {
+            resolved.activated.insert(package_id.clone());
+            resolved.metadata.insert(
+                package_id.clone(),
+                ResolvePackageMetadata {
+                    realm: Realm::Shared,
+                    origin_realm: Realm::Shared,
+                    source_registry: PackageSourceId::DefaultRegistry,
+                    git_commit: None,
+                    checksum: None,
+                },
+            );
+        }
+
+        let err = check_flat_names(&root, &resolved).unwrap_err();
+        match err.downcast_ref::<InstallError>() {
+            Some(InstallError::FlatLayoutConflict { first, second }) => {
+                assert_eq!(first, &old);
+                assert_eq!(second, &new);
+            }
+            _ => panic!("expected a flat layout conflict, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn requires_are_rewritten_for_the_flat_layout() {
+        let requires = BTreeMap::from([
+            ("Util".to_owned(), FlatRequire::Sibling("util".to_owned())),
+            (
+                "Shared".to_owned(),
+                FlatRequire::Place(r#"game.ReplicatedStorage.Packages["shared"]"#.to_owned()),
+            ),
+        ]);
+
+        let source = r#"local Util = require(script.Parent.Parent.Util.Module)
+local Shared = require( script.Parent.Parent["Shared"] )
+local Sibling = require(script.Parent.Util)
+local Other = require(script.Parent.Parent.Other)"#;
+
+        assert_eq!(
+            rewrite_requires(source, 2, &requires),
+            r#"local Util = require(script.Parent.Parent["util"].Module)
+local Shared = require( game.ReplicatedStorage.Packages["shared"] )
+local Sibling = require(script.Parent.Util)
+local Other = require(script.Parent.Parent.Other)"#
+        );
+    }
+
+    #[test]
+    fn orphaned_files_are_found() {
+        let sink = MemorySink::new();
+        let packages = Path::new("/project/Packages");
+        let package = packages.join("_Index/biff_minimal@0.1.0/minimal");
+
+        sink.create_dir(&package).unwrap();
+        for path in [
+            packages.join("Minimal.lua"),
+            packages.join("Stray.lua"),
+            packages.join("notes.txt"),
+            package.join("init.lua"),
+        ] {
+            sink.write_file(&path, b"return {}").unwrap();
+        }
+
+        let produced_paths = BTreeSet::from([packages.join("Minimal.lua")]);
+        let skipped_dirs = BTreeSet::from([package]);
+        let mut orphans = Vec::new();
+
+        find_orphaned_files(
+            &sink,
+            packages,
+            &produced_paths,
+            &skipped_dirs,
+            &mut orphans,
+        )
+        .unwrap();
+
+        assert_eq!(orphans, vec![packages.join("Stray.lua")]);
+    }
 }
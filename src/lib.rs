@@ -1,9 +1,16 @@
 pub mod auth;
+pub mod cache_pruning;
 pub mod commands;
+pub mod extract_types;
 pub mod git_util;
+pub mod global_config;
+pub mod install_observer;
+pub mod install_report;
+pub mod install_sink;
 pub mod installation;
 pub mod lockfile;
 pub mod manifest;
+pub mod package_cache;
 pub mod package_contents;
 pub mod package_id;
 pub mod package_index;
@@ -12,6 +19,6 @@ pub mod package_req;
 pub mod package_source;
 pub mod resolution;
 pub mod test_package;
-mod extract_types;
+pub mod type_cache;
 
 pub use commands::*;
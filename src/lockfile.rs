@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 use std::{
     fs::read_to_string,
@@ -11,7 +11,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::package_id;
 use crate::{
-    manifest::Manifest, package_id::PackageId, package_name::PackageName, resolution::Resolve,
+    manifest::{GitSpec, Manifest},
+    package_id::PackageId,
+    package_name::PackageName,
+    package_source::PackageSourceId,
+    resolution::Resolve,
 };
 
 pub const LOCKFILE_NAME: &str = "wally.lock";
@@ -61,12 +65,49 @@ impl Lockfile {
             ]
             .concat();
 
-            packages.push(LockPackage::Registry(RegistryLockPackage {
-                name: package_id.name().clone(),
-                version: package_id.version().clone(),
-                checksum: None,
-                dependencies,
-            }));
+            let metadata = resolve.metadata.get(package_id);
+            let git_source = metadata.and_then(|metadata| {
+                match (&metadata.source_registry, &metadata.git_commit) {
+                    (PackageSourceId::GitRepository(spec), Some(commit)) => Some((spec, commit)),
+                    _ => None,
+                }
+            });
+
+            let local_path = metadata.and_then(|metadata| match &metadata.source_registry {
+                PackageSourceId::LocalPath(path) => Some(path),
+                _ => None,
+            });
+
+            match (git_source, local_path) {
+                (Some((spec, commit)), _) => packages.push(LockPackage::Git(GitLockPackage {
+                    name: package_id.name().clone(),
+                    version: package_id.version().clone(),
+                    git: spec.git.clone(),
+                    rev: spec.rev.clone(),
+                    path: spec.path.clone(),
+                    commit: commit.clone(),
+                    dependencies,
+                })),
+                (None, Some(path)) => packages.push(LockPackage::Path(PathLockPackage {
+                    name: package_id.name().clone(),
+                    version: package_id.version().clone(),
+                    path: path.clone(),
+                    dependencies,
+                })),
+                (None, None) => packages.push(LockPackage::Registry(RegistryLockPackage {
+                    name: package_id.name().clone(),
+                    version: package_id.version().clone(),
+                    registry: metadata.and_then(|metadata| match &metadata.source_registry {
+                        // Test registries are identified by where they are on
+                        // this machine, which doesn't belong in a lockfile
+                        // that's committed.
+                        PackageSourceId::Git(url) => Some(url.clone()),
+                        _ => None,
+                    }),
+                    checksum: None,
+                    dependencies,
+                })),
+            }
         }
 
         Self {
@@ -90,16 +131,23 @@ impl Lockfile {
         Ok(Some(toml::from_str(&contents)?))
     }
 
+    /// Write the lockfile into the project. Packages are always written in
+    /// the same order, sorted by ID, so that loading a lockfile and saving it
+    /// again produces exactly the same file, and changing one package only
+    /// changes the lines that describe it.
     pub fn save(&self, project_path: &Path) -> anyhow::Result<()> {
         let lockfile_path = project_path.join(LOCKFILE_NAME);
 
+        let mut packages: Vec<_> = self.packages.iter().collect();
+        packages.sort_by_key(|lock_package| lock_package.package_id());
+
         let mut file = BufWriter::new(File::create(lockfile_path)?);
         writeln!(file, "# This file is automatically @generated by Wally.")?;
         writeln!(file, "# It is not intended for manual editing.")?;
-        writeln!(file, "registry = \"{}\"", self.registry)?;
+        writeln!(file, "registry = {}", toml_string(&self.registry))?;
         writeln!(file, "")?;
 
-        for lock_package in self.packages.iter() {
+        for lock_package in packages {
             writeln!(file, "[[package]]")?;
 
             match lock_package {
@@ -107,8 +155,12 @@ impl Lockfile {
                     writeln!(file, "name = \"{}\"", registry_lock_package.name)?;
                     writeln!(file, "version = \"{}\"", registry_lock_package.version)?;
 
+                    if let Some(registry) = &registry_lock_package.registry {
+                        writeln!(file, "registry = {}", toml_string(registry))?;
+                    }
+
                     if let Some(checksum) = &registry_lock_package.checksum {
-                        writeln!(file, "checksum = \"{}\"", checksum)?;
+                        writeln!(file, "checksum = {}", toml_string(checksum))?;
                     }
 
                     if registry_lock_package.dependencies.len() == 0 {
@@ -116,22 +168,59 @@ impl Lockfile {
                     } else {
                         writeln!(file, "dependencies = [")?;
                         for dependency in registry_lock_package.dependencies.iter() {
-                            writeln!(file, "\t[\"{}\", \"{}\"],", dependency.0, dependency.1)?;
+                            writeln!(
+                                file,
+                                "\t[{}, \"{}\"],",
+                                toml_string(&dependency.0),
+                                dependency.1
+                            )?;
                         }
                         writeln!(file, "]")?;
                     }
                 }
                 LockPackage::Git(git_lock_package) => {
                     writeln!(file, "name = \"{}\"", git_lock_package.name)?;
-                    writeln!(file, "rev = \"{}\"", git_lock_package.rev)?;
-                    writeln!(file, "commit = \"{}\"", git_lock_package.commit)?;
+                    writeln!(file, "version = \"{}\"", git_lock_package.version)?;
+                    writeln!(file, "git = {}", toml_string(&git_lock_package.git))?;
+                    writeln!(file, "rev = {}", toml_string(&git_lock_package.rev))?;
+
+                    if let Some(path) = &git_lock_package.path {
+                        writeln!(file, "path = {}", toml_string(path))?;
+                    }
+
+                    writeln!(file, "commit = {}", toml_string(&git_lock_package.commit))?;
 
                     if git_lock_package.dependencies.len() == 0 {
                         writeln!(file, "dependencies = []")?;
                     } else {
                         writeln!(file, "dependencies = [")?;
                         for dependency in git_lock_package.dependencies.iter() {
-                            writeln!(file, "\t\"{}\",", dependency)?;
+                            writeln!(
+                                file,
+                                "\t[{}, \"{}\"],",
+                                toml_string(&dependency.0),
+                                dependency.1
+                            )?;
+                        }
+                        writeln!(file, "]")?;
+                    }
+                }
+                LockPackage::Path(path_lock_package) => {
+                    writeln!(file, "name = \"{}\"", path_lock_package.name)?;
+                    writeln!(file, "version = \"{}\"", path_lock_package.version)?;
+                    writeln!(file, "path = {}", toml_string(&path_lock_package.path))?;
+
+                    if path_lock_package.dependencies.is_empty() {
+                        writeln!(file, "dependencies = []")?;
+                    } else {
+                        writeln!(file, "dependencies = [")?;
+                        for dependency in path_lock_package.dependencies.iter() {
+                            writeln!(
+                                file,
+                                "\t[{}, \"{}\"],",
+                                toml_string(&dependency.0),
+                                dependency.1
+                            )?;
                         }
                         writeln!(file, "]")?;
                     }
@@ -146,27 +235,96 @@ impl Lockfile {
         Ok(())
     }
 
-    pub fn as_ids(&self) -> impl Iterator<Item = PackageId> + '_ {
-        self.packages.iter().map(|lock_package| match lock_package {
-            LockPackage::Registry(lock_package) => {
-                PackageId::new(lock_package.name.clone(), lock_package.version.clone())
+    /// The checksums recorded for each package in this lockfile.
+    pub fn checksums(&self) -> BTreeMap<PackageId, String> {
+        self.packages
+            .iter()
+            .filter_map(|lock_package| match lock_package {
+                LockPackage::Registry(lock_package) => {
+                    let package_id =
+                        PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+                    let checksum = lock_package.checksum.clone()?;
+
+                    Some((package_id, checksum))
+                }
+                LockPackage::Git(_) | LockPackage::Path(_) => None,
+            })
+            .collect()
+    }
+
+    /// Record the given checksums against the packages in this lockfile.
+    pub fn with_checksums(mut self, checksums: &BTreeMap<PackageId, String>) -> Self {
+        for lock_package in &mut self.packages {
+            if let LockPackage::Registry(lock_package) = lock_package {
+                let package_id =
+                    PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+                if let Some(checksum) = checksums.get(&package_id) {
+                    lock_package.checksum = Some(checksum.clone());
+                }
             }
-            LockPackage::Git(_) => todo!(),
-        })
+        }
+
+        self
+    }
+
+    /// The commit that each package from a Git repository was checked out at,
+    /// keyed by where in the repository it was found.
+    pub fn git_commits(&self) -> HashMap<GitSpec, String> {
+        self.packages
+            .iter()
+            .filter_map(|lock_package| match lock_package {
+                LockPackage::Git(lock_package) => {
+                    Some((lock_package.spec(), lock_package.commit.clone()))
+                }
+                LockPackage::Path(_) | LockPackage::Registry(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn as_ids(&self) -> impl Iterator<Item = PackageId> + '_ {
+        self.packages.iter().map(LockPackage::package_id)
     }
 }
 
+/// `value` as a TOML string, quoted and escaped.
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_owned()).to_string()
+}
+
+// Git packages are tried first, since every field a registry package needs is
+// also present on them. Path packages come next for the same reason, after Git
+// packages because those can have a `path` too.
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum LockPackage {
-    Registry(RegistryLockPackage),
     Git(GitLockPackage),
+    Path(PathLockPackage),
+    Registry(RegistryLockPackage),
+}
+
+impl LockPackage {
+    pub fn package_id(&self) -> PackageId {
+        match self {
+            LockPackage::Registry(lock_package) => {
+                PackageId::new(lock_package.name.clone(), lock_package.version.clone())
+            }
+            LockPackage::Git(lock_package) => lock_package.package_id(),
+            LockPackage::Path(lock_package) => lock_package.package_id(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RegistryLockPackage {
     pub name: PackageName,
     pub version: Version,
+
+    /// The registry the package was found in, if it wasn't the project's own
+    /// registry.
+    #[serde(default)]
+    pub registry: Option<String>,
+
     pub checksum: Option<String>,
 
     #[serde(default)]
@@ -175,10 +333,145 @@ pub struct RegistryLockPackage {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GitLockPackage {
-    pub name: String,
+    pub name: PackageName,
+    pub version: Version,
+    pub git: String,
     pub rev: String,
+
+    #[serde(default)]
+    pub path: Option<String>,
+
+    /// The full hash of the commit that `rev` pointed to when the package was
+    /// locked.
     pub commit: String,
 
     #[serde(default)]
-    pub dependencies: Vec<PackageId>,
+    pub dependencies: Vec<(String, PackageId)>,
+}
+
+impl GitLockPackage {
+    pub fn package_id(&self) -> PackageId {
+        PackageId::new(self.name.clone(), self.version.clone())
+    }
+
+    /// Where in its repository the package was found.
+    pub fn spec(&self) -> GitSpec {
+        GitSpec {
+            git: self.git.clone(),
+            rev: self.rev.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+/// A package from a directory on this machine. Its contents can change at any
+/// time, so unlike other packages it has no checksum.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PathLockPackage {
+    pub name: PackageName,
+    pub version: Version,
+
+    /// The directory containing the package, as written in the manifest of
+    /// the project that depends on it.
+    pub path: String,
+
+    #[serde(default)]
+    pub dependencies: Vec<(String, PackageId)>,
+}
+
+impl PathLockPackage {
+    pub fn package_id(&self) -> PackageId {
+        PackageId::new(self.name.clone(), self.version.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use fs_err as fs;
+
+    const LOCKFILE: &str = r#"# This file is automatically @generated by Wally.
+# It is not intended for manual editing.
+registry = "test"
+
+[[package]]
+name = "biff/minimal"
+version = "0.1.0"
+checksum = "abc"
+dependencies = []
+
+[[package]]
+name = "biff/one-dependency"
+version = "0.1.0"
+registry = "https://github.com/biff/wally-index"
+dependencies = [
+	["Minimal", "biff/minimal@0.1.0"],
+]
+
+[[package]]
+name = "biff/project"
+version = "0.1.0"
+path = "../project"
+dependencies = [
+	["Has \"Quotes\"", "biff/one-dependency@0.1.0"],
+]
+
+"#;
+
+    fn resave(contents: &str) -> String {
+        let project = tempfile::tempdir().unwrap();
+        fs::write(project.path().join(LOCKFILE_NAME), contents).unwrap();
+
+        Lockfile::load(project.path())
+            .unwrap()
+            .unwrap()
+            .save(project.path())
+            .unwrap();
+
+        fs::read_to_string(project.path().join(LOCKFILE_NAME)).unwrap()
+    }
+
+    #[test]
+    fn saving_a_loaded_lockfile_changes_nothing() {
+        assert_eq!(resave(LOCKFILE), LOCKFILE);
+    }
+
+    #[test]
+    fn packages_are_sorted() {
+        let (header, packages) = LOCKFILE.split_once("[[package]]").unwrap();
+        let mut packages: Vec<_> = packages.split("[[package]]").collect();
+        packages.reverse();
+        let shuffled = format!("{}[[package]]{}", header, packages.join("[[package]]"));
+
+        assert_eq!(resave(&shuffled), LOCKFILE);
+    }
+
+    #[test]
+    fn version_bump_only_changes_its_lines() {
+        let bumped = resave(
+            &LOCKFILE
+                .replace(
+                    "version = \"0.1.0\"\nchecksum",
+                    "version = \"0.1.1\"\nchecksum",
+                )
+                .replace("\"biff/minimal@0.1.0\"", "\"biff/minimal@0.1.1\""),
+        );
+
+        let changed: Vec<_> = LOCKFILE
+            .lines()
+            .zip(bumped.lines())
+            .filter(|(before, after)| before != after)
+            .map(|(_, after)| after)
+            .collect();
+
+        assert_eq!(LOCKFILE.lines().count(), bumped.lines().count());
+        assert_eq!(
+            changed,
+            [
+                "version = \"0.1.1\"",
+                "\t[\"Minimal\", \"biff/minimal@0.1.1\"],"
+            ]
+        );
+    }
 }
@@ -8,6 +8,7 @@ fn main() {
     let args = Args::from_args();
 
     let log_filter = match args.global.verbosity {
+        _ if args.global.quiet => "libwally=error",
         0 => "libwally=info",
         1 => "libwally=debug",
         2 => "libwally=trace",
@@ -1,8 +1,10 @@
-use std::collections::BTreeMap;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Component, Path};
+use std::str::FromStr;
 
-use anyhow::Context;
-use semver::Version;
+use anyhow::{bail, format_err, Context};
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 
 use crate::package_id::PackageId;
@@ -13,7 +15,7 @@ pub const MANIFEST_FILE_NAME: &str = "wally.toml";
 
 /// The contents of a `wally.toml` file, which defines a package.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[serde(rename_all = "kebab-case", from = "ManifestFile")]
 pub struct Manifest {
     pub package: Package,
 
@@ -28,16 +30,95 @@ pub struct Manifest {
 
     #[serde(default)]
     pub dev_dependencies: BTreeMap<String, PackageReq>,
+
+    /// Realms that this package's own dependencies are installed into instead
+    /// of the realm of the table that declares them, keyed by that table's
+    /// realm and the alias. These only apply when this package is the project
+    /// being installed.
+    #[serde(skip)]
+    pub realm_overrides: BTreeMap<(Realm, String), Realm>,
+
+    /// Dependencies that are fetched from a Git repository instead of a
+    /// registry, keyed by alias. Like realm overrides, these only apply when
+    /// this package is the project being installed.
+    #[serde(skip)]
+    pub git_dependencies: BTreeMap<String, GitDependency>,
+
+    /// Dependencies on packages in a directory on this machine, keyed by
+    /// alias. Like realm overrides, these only apply when this package is the
+    /// project being installed.
+    #[serde(skip)]
+    pub path_dependencies: BTreeMap<String, PathDependency>,
+
+    /// Features of this package, each naming the optional dependencies it
+    /// enables. Like realm overrides, these only apply when this package is
+    /// the project being installed.
+    #[serde(skip)]
+    pub features: BTreeMap<String, Vec<String>>,
+
+    /// Dependencies that are only installed when a feature enables them,
+    /// keyed by alias.
+    #[serde(skip)]
+    pub optional_dependencies: BTreeMap<String, OptionalDependency>,
+
+    /// Packages that are taken from a Git repository or a local directory
+    /// instead of a registry everywhere in the dependency graph, keyed by the
+    /// name of the package they replace. Like realm overrides, these only
+    /// apply when this package is the project being installed.
+    #[serde(skip)]
+    pub patches: BTreeMap<PackageName, PatchSource>,
+
+    /// Registries that packages are searched for in, in order, before the
+    /// package's own `registry` unless it's listed among them. A package in
+    /// more than one of them comes from the first that has a version
+    /// satisfying the requirement on it. Like realm overrides, these only
+    /// apply when this package is the project being installed.
+    #[serde(skip)]
+    pub registries: Vec<String>,
+
+    /// Commands that are run around installing this package. Like realm
+    /// overrides, these only apply when this package is the project being
+    /// installed.
+    #[serde(skip)]
+    pub scripts: Scripts,
+
+    /// The checksum of the package's archive, recorded by the registry in its
+    /// index when the package was published. Never written in a
+    /// `wally.toml`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
 }
 
 impl Manifest {
     /// Load a manifest from a project directory containing a `wally.toml` file.
     pub fn load(dir: &Path) -> anyhow::Result<Self> {
-        let file_path = dir.join(MANIFEST_FILE_NAME);
+        Self::load_file(&dir.join(MANIFEST_FILE_NAME))
+    }
+
+    /// Load a manifest from a file that might not be named `wally.toml`, or
+    /// might not be in the project it describes.
+    pub fn load_file(file_path: &Path) -> anyhow::Result<Self> {
+        let content = fs_err::read_to_string(file_path)?;
+        let manifest: Manifest = toml::from_str(&content).map_err(|err| {
+            // Dependencies can be written several ways, so serde can only say
+            // that none of them matched. Report what's actually wrong instead.
+            let problems = find_problems(&content);
+            let err = if problems.is_empty() {
+                anyhow::Error::from(err)
+            } else {
+                format_err!("{}", problems.join("\n"))
+            };
 
-        let content = fs_err::read_to_string(&file_path)?;
-        let manifest: Manifest = toml::from_str(&content)
-            .with_context(|| format!("failed to parse manifest at path {}", file_path.display()))?;
+            err.context(format!(
+                "failed to parse manifest at path {}",
+                file_path.display()
+            ))
+        })?;
+
+        manifest
+            .place
+            .check_paths()
+            .with_context(|| format!("invalid manifest at path {}", file_path.display()))?;
 
         Ok(manifest)
     }
@@ -52,6 +133,629 @@ impl Manifest {
     pub fn package_id(&self) -> PackageId {
         PackageId::new(self.package.name.clone(), self.package.version.clone())
     }
+
+    /// The realm that the dependency called `alias`, declared in the table for
+    /// `table_realm`, should be installed into.
+    pub fn dependency_realm(&self, table_realm: Realm, alias: &str) -> Realm {
+        self.realm_overrides
+            .get(&(table_realm, alias.to_owned()))
+            .copied()
+            .unwrap_or(table_realm)
+    }
+
+    /// Fail if two dependencies would be linked under the same name, because
+    /// they install into the same realm with the same alias, or with aliases
+    /// that only differ by case, which would collide on case-insensitive
+    /// filesystems.
+    pub fn check_aliases(&self) -> anyhow::Result<()> {
+        let aliases = self
+            .dependencies
+            .keys()
+            .map(|alias| (Realm::Shared, alias))
+            .chain(
+                self.server_dependencies
+                    .keys()
+                    .map(|alias| (Realm::Server, alias)),
+            )
+            .chain(
+                self.dev_dependencies
+                    .keys()
+                    .map(|alias| (Realm::Dev, alias)),
+            )
+            .chain(
+                self.git_dependencies
+                    .iter()
+                    .map(|(alias, dependency)| (dependency.table_realm, alias)),
+            )
+            .chain(
+                self.path_dependencies
+                    .iter()
+                    .map(|(alias, dependency)| (dependency.table_realm, alias)),
+            );
+
+        let mut linked: HashMap<(Realm, String), &str> = HashMap::new();
+
+        for (table_realm, alias) in aliases {
+            let realm = self.dependency_realm(table_realm, alias);
+
+            match linked.insert((realm, alias.to_lowercase()), alias) {
+                Some(other) if other == alias => bail!(
+                    "{} is declared more than once for the {:?} realm, so its links would \
+                     collide. Give one of them a different alias.",
+                    alias,
+                    realm
+                ),
+                Some(other) => bail!(
+                    "The aliases {} and {} only differ by case, so their links in the {:?} \
+                     realm would collide. Give one of them a different alias.",
+                    other,
+                    alias,
+                    realm
+                ),
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move the optional dependencies enabled by `features`, or by every
+    /// feature if `all_features` is set, into the tables they were declared
+    /// in so that they're resolved like any other dependency.
+    pub fn enable_features(
+        &mut self,
+        features: &[String],
+        all_features: bool,
+    ) -> anyhow::Result<()> {
+        for (feature, aliases) in &self.features {
+            for alias in aliases {
+                if !self.optional_dependencies.contains_key(alias) {
+                    bail!(
+                        "Feature {} enables {}, which is not an optional dependency",
+                        feature,
+                        alias
+                    );
+                }
+            }
+        }
+
+        for feature in features {
+            if !self.features.contains_key(feature) {
+                bail!("{} has no feature named {}", self.package.name, feature);
+            }
+        }
+
+        let enabled: BTreeSet<String> = self
+            .features
+            .iter()
+            .filter(|(feature, _)| all_features || features.contains(feature))
+            .flat_map(|(_, aliases)| aliases.iter().cloned())
+            .collect();
+
+        for alias in enabled {
+            let dependency = self.optional_dependencies.remove(&alias).unwrap();
+            let table_realm = dependency.table_realm;
+
+            match dependency.source {
+                OptionalSource::Registry(req) => {
+                    let table = match table_realm {
+                        Realm::Shared => &mut self.dependencies,
+                        Realm::Server => &mut self.server_dependencies,
+                        Realm::Dev => &mut self.dev_dependencies,
+                    };
+
+                    table.insert(alias, req);
+                }
+                OptionalSource::Git(source) => {
+                    self.git_dependencies.insert(
+                        alias,
+                        GitDependency {
+                            table_realm,
+                            source,
+                        },
+                    );
+                }
+                OptionalSource::Path(path) => {
+                    self.path_dependencies
+                        .insert(alias, PathDependency { table_realm, path });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Fail if the manifest at `file_path` requires a different version of Wally
+/// than this one.
+///
+/// Only `[package] wally-version` is read, so that a manifest using fields
+/// this version doesn't understand still gets this error instead of a parse
+/// error. A manifest that's missing or unreadable is left for the command
+/// that loads it to report.
+pub fn check_wally_version(file_path: &Path) -> anyhow::Result<()> {
+    let file: toml::Value = match fs_err::read_to_string(file_path)
+        .ok()
+        .and_then(|content| toml::from_str(&content).ok())
+    {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+
+    let requirement = match file
+        .get("package")
+        .and_then(|package| package.get("wally-version"))
+        .and_then(toml::Value::as_str)
+    {
+        Some(requirement) => requirement,
+        None => return Ok(()),
+    };
+
+    let requirement: VersionReq = requirement
+        .parse()
+        .with_context(|| format!("invalid wally-version requirement {}", requirement))?;
+    let current = Version::parse(env!("CARGO_PKG_VERSION"))?;
+
+    if !requirement.matches(&current) {
+        bail!(
+            "This project requires Wally {}, but this is Wally {}. Upgrade Wally to a version \
+             that matches to work on it.",
+            requirement,
+            current
+        );
+    }
+
+    Ok(())
+}
+
+/// Every problem with the contents of a `wally.toml` file that can be found
+/// without looking up any of its dependencies, such as a malformed version
+/// requirement or an alias declared in more than one dependency table.
+///
+/// Unlike loading the manifest, this keeps going after the first problem so
+/// that they can all be reported at once.
+pub fn find_problems(content: &str) -> Vec<String> {
+    let file: toml::Value = match toml::from_str(content) {
+        Ok(file) => file,
+        Err(err) => return vec![format!("{} is not valid TOML: {}", MANIFEST_FILE_NAME, err)],
+    };
+
+    let mut problems = Vec::new();
+
+    match file
+        .get("package")
+        .cloned()
+        .map(toml::Value::try_into::<Package>)
+    {
+        Some(Ok(package)) => {
+            if package.realm == Realm::Dev {
+                problems.push("[package] realm must be shared or server, not dev".to_owned());
+            }
+        }
+        Some(Err(err)) => problems.push(format!("[package] is invalid: {}", err)),
+        None => problems.push("[package] is missing".to_owned()),
+    }
+
+    match file
+        .get("place")
+        .cloned()
+        .map(toml::Value::try_into::<PlaceInfo>)
+    {
+        Some(Ok(place)) => problems.extend(place.path_problems()),
+        Some(Err(err)) => problems.push(format!("[place] is invalid: {}", err)),
+        None => {}
+    }
+
+    // The table that declared each alias, and whether it's optional.
+    let mut declared: BTreeMap<&str, (&str, bool)> = BTreeMap::new();
+
+    for &table_name in ["dependencies", "server-dependencies", "dev-dependencies"].iter() {
+        let table = match file.get(table_name) {
+            Some(toml::Value::Table(table)) => table,
+            Some(_) => {
+                problems.push(format!("[{}] must be a table", table_name));
+                continue;
+            }
+            None => continue,
+        };
+
+        for (alias, value) in table {
+            let optional = match value.clone().try_into::<DependencySpec>() {
+                Ok(DependencySpec::Req(_)) => false,
+                Ok(DependencySpec::Detailed { optional, .. })
+                | Ok(DependencySpec::Git { optional, .. })
+                | Ok(DependencySpec::Path { optional, .. }) => optional,
+                Err(_) => {
+                    problems.push(format!(
+                        "[{}] {} is invalid: {}",
+                        table_name,
+                        alias,
+                        dependency_spec_problem(value)
+                    ));
+                    false
+                }
+            };
+
+            // Aliases are what links are named after, so one that's declared
+            // twice would install two packages to the same place.
+            match declared.get(alias.as_str()) {
+                Some((first_table, _)) => problems.push(format!(
+                    "{} is declared in both [{}] and [{}]",
+                    alias, first_table, table_name
+                )),
+                None => {
+                    declared.insert(alias, (table_name, optional));
+                }
+            }
+        }
+    }
+
+    match file
+        .get("features")
+        .cloned()
+        .map(toml::Value::try_into::<BTreeMap<String, Vec<String>>>)
+    {
+        Some(Ok(features)) => {
+            for (feature, aliases) in &features {
+                for alias in aliases {
+                    if !matches!(declared.get(alias.as_str()), Some((_, true))) {
+                        problems.push(format!(
+                            "Feature {} enables {}, which is not an optional dependency",
+                            feature, alias
+                        ));
+                    }
+                }
+            }
+        }
+        Some(Err(err)) => problems.push(format!("[features] is invalid: {}", err)),
+        None => {}
+    }
+
+    match file.get("patch") {
+        Some(toml::Value::Table(table)) => {
+            for (name, value) in table {
+                if let Err(err) = name.parse::<PackageName>() {
+                    problems.push(format!("[patch] {} is invalid: {:#}", name, err));
+                } else if value.clone().try_into::<PatchSource>().is_err() {
+                    problems.push(format!(
+                        "[patch] {} is invalid: expected a table with a `git` or `path` key",
+                        name
+                    ));
+                }
+            }
+        }
+        Some(_) => problems.push("[patch] must be a table".to_owned()),
+        None => {}
+    }
+
+    if let Some(Err(err)) = file
+        .get("registries")
+        .cloned()
+        .map(toml::Value::try_into::<Vec<RegistryEntry>>)
+    {
+        problems.push(format!("[[registries]] is invalid: {}", err));
+    }
+
+    if let Some(Err(err)) = file
+        .get("scripts")
+        .cloned()
+        .map(toml::Value::try_into::<Scripts>)
+    {
+        problems.push(format!("[scripts] is invalid: {}", err));
+    }
+
+    problems
+}
+
+/// Explain why a dependency didn't match any form of `DependencySpec`, since
+/// serde can only say that none of them matched.
+fn dependency_spec_problem(value: &toml::Value) -> String {
+    let package_req = match value {
+        toml::Value::String(package_req) => Some(package_req.as_str()),
+        _ => value.get("package").and_then(toml::Value::as_str),
+    };
+
+    if let Some(Err(err)) = package_req.map(str::parse::<PackageReq>) {
+        return format!("{:#}", err);
+    }
+
+    if let Some(Err(err)) = value
+        .get("realm")
+        .cloned()
+        .map(toml::Value::try_into::<Realm>)
+    {
+        return format!("invalid realm: {}", err);
+    }
+
+    "expected a package requirement like \"scope/name@1.0.0\", or a table with a \
+     `package`, `git` or `path` key"
+        .to_owned()
+}
+
+/// A `wally.toml` file as it's written, before dependencies that override their
+/// realm are split apart from the rest of their requirement.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ManifestFile {
+    package: Package,
+
+    #[serde(default)]
+    place: PlaceInfo,
+
+    #[serde(default)]
+    dependencies: BTreeMap<String, DependencySpec>,
+
+    #[serde(default)]
+    server_dependencies: BTreeMap<String, DependencySpec>,
+
+    #[serde(default)]
+    dev_dependencies: BTreeMap<String, DependencySpec>,
+
+    #[serde(default)]
+    features: BTreeMap<String, Vec<String>>,
+
+    #[serde(default)]
+    patch: BTreeMap<PackageName, PatchSource>,
+
+    #[serde(default)]
+    registries: Vec<RegistryEntry>,
+
+    #[serde(default)]
+    scripts: Scripts,
+
+    #[serde(default)]
+    checksum: Option<String>,
+}
+
+/// A single entry in one of the dependency tables of a manifest.
+///
+/// Examples:
+/// * `Roact = "roblox/roact@1.4.2"`
+/// * `Roact = { package = "roblox/roact@1.4.2", realm = "server" }`
+/// * `Roact = { git = "https://github.com/Roblox/roact.git", rev = "v1.4.2" }`
+/// * `Roact = { path = "../roact" }`
+/// * `Roact = { package = "roblox/roact@1.4.2", optional = true }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DependencySpec {
+    Req(PackageReq),
+    Detailed {
+        package: PackageReq,
+
+        #[serde(default)]
+        realm: Option<Realm>,
+
+        #[serde(default)]
+        optional: bool,
+    },
+    Git {
+        #[serde(flatten)]
+        source: GitSpec,
+
+        #[serde(default)]
+        realm: Option<Realm>,
+
+        #[serde(default)]
+        optional: bool,
+    },
+    // Git dependencies may also have a `path`, so they're tried first.
+    Path {
+        path: String,
+
+        #[serde(default)]
+        realm: Option<Realm>,
+
+        #[serde(default)]
+        optional: bool,
+    },
+}
+
+/// A dependency on the package in a Git repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitDependency {
+    /// The realm of the dependency table the dependency was declared in.
+    pub table_realm: Realm,
+
+    pub source: GitSpec,
+}
+
+/// A dependency on the package in a directory on this machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathDependency {
+    /// The realm of the dependency table the dependency was declared in.
+    pub table_realm: Realm,
+
+    /// The directory containing the package's `wally.toml`, relative to the
+    /// project that depends on it.
+    ///
+    /// Example: `../roact`
+    pub path: String,
+}
+
+/// A dependency that's only installed when a feature enables it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionalDependency {
+    /// The realm of the dependency table the dependency was declared in.
+    pub table_realm: Realm,
+
+    pub source: OptionalSource,
+}
+
+/// Where an optional dependency comes from once it's enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionalSource {
+    Registry(PackageReq),
+    Git(GitSpec),
+    Path(String),
+}
+
+/// Where a patched package comes from instead of a registry.
+///
+/// Examples:
+/// * `"roblox/roact" = { git = "https://github.com/Roblox/roact.git", rev = "fix" }`
+/// * `"roblox/roact" = { path = "../roact" }`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum PatchSource {
+    // Like dependencies, Git patches may also have a `path`, so they're tried
+    // first.
+    Git(GitSpec),
+    Path { path: String },
+}
+
+/// Where to find a package in a Git repository.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GitSpec {
+    /// The URL of the repository.
+    ///
+    /// Example: `https://github.com/Roblox/roact.git`
+    pub git: String,
+
+    /// The branch, tag or commit to use.
+    ///
+    /// Example: `main`, `v1.4.2`
+    #[serde(default = "default_rev")]
+    pub rev: String,
+
+    /// The directory within the repository that contains the package's
+    /// `wally.toml`, if it isn't the root of the repository.
+    ///
+    /// Example: `packages/roact`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+fn default_rev() -> String {
+    "HEAD".to_owned()
+}
+
+impl fmt::Display for GitSpec {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}#{}", self.git, self.rev)?;
+
+        if let Some(path) = &self.path {
+            write!(formatter, ":{}", path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<ManifestFile> for Manifest {
+    fn from(file: ManifestFile) -> Self {
+        let mut realm_overrides = BTreeMap::new();
+        let mut git_dependencies = BTreeMap::new();
+        let mut path_dependencies = BTreeMap::new();
+        let mut optional_dependencies = BTreeMap::new();
+
+        let mut split = |table_realm: Realm, table: BTreeMap<String, DependencySpec>| {
+            table
+                .into_iter()
+                .filter_map(|(alias, spec)| match spec {
+                    DependencySpec::Req(req) => Some((alias, req)),
+                    DependencySpec::Detailed {
+                        package,
+                        realm,
+                        optional,
+                    } => {
+                        if let Some(realm) = realm {
+                            realm_overrides.insert((table_realm, alias.clone()), realm);
+                        }
+
+                        if optional {
+                            optional_dependencies.insert(
+                                alias,
+                                OptionalDependency {
+                                    table_realm,
+                                    source: OptionalSource::Registry(package),
+                                },
+                            );
+                            return None;
+                        }
+
+                        Some((alias, package))
+                    }
+                    DependencySpec::Git {
+                        source,
+                        realm,
+                        optional,
+                    } => {
+                        if let Some(realm) = realm {
+                            realm_overrides.insert((table_realm, alias.clone()), realm);
+                        }
+
+                        if optional {
+                            optional_dependencies.insert(
+                                alias,
+                                OptionalDependency {
+                                    table_realm,
+                                    source: OptionalSource::Git(source),
+                                },
+                            );
+                        } else {
+                            git_dependencies.insert(
+                                alias,
+                                GitDependency {
+                                    table_realm,
+                                    source,
+                                },
+                            );
+                        }
+
+                        None
+                    }
+                    DependencySpec::Path {
+                        path,
+                        realm,
+                        optional,
+                    } => {
+                        if let Some(realm) = realm {
+                            realm_overrides.insert((table_realm, alias.clone()), realm);
+                        }
+
+                        if optional {
+                            optional_dependencies.insert(
+                                alias,
+                                OptionalDependency {
+                                    table_realm,
+                                    source: OptionalSource::Path(path),
+                                },
+                            );
+                        } else {
+                            path_dependencies.insert(alias, PathDependency { table_realm, path });
+                        }
+
+                        None
+                    }
+                })
+                .collect()
+        };
+
+        let dependencies = split(Realm::Shared, file.dependencies);
+        let server_dependencies = split(Realm::Server, file.server_dependencies);
+        let dev_dependencies = split(Realm::Dev, file.dev_dependencies);
+
+        Self {
+            package: file.package,
+            place: file.place,
+            dependencies,
+            server_dependencies,
+            dev_dependencies,
+            realm_overrides,
+            git_dependencies,
+            path_dependencies,
+            features: file.features,
+            optional_dependencies,
+            patches: file.patch,
+            registries: file
+                .registries
+                .into_iter()
+                .map(|entry| entry.index)
+                .collect(),
+            scripts: file.scripts,
+            checksum: file.checksum,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -138,6 +842,45 @@ pub struct Package {
     /// Example: "https://github.com/Sleitnick/Knit.git"
     #[serde(default)]
     pub repository: Option<String>,
+
+    /// The module that's returned when the package is required, relative to
+    /// the package. Exported types are read from it when the package has no
+    /// `default.project.json`. Defaults to `src/init.lua` or `src/init.luau`.
+    ///
+    /// Example: "lib/init.luau"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lib: Option<String>,
+
+    /// The versions of Wally that can work on this package. Older versions of
+    /// Wally refuse to, instead of ignoring fields they don't know about.
+    ///
+    /// Example: ">=0.4"
+    #[serde(
+        default,
+        rename = "wally-version",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub wally_version: Option<VersionReq>,
+}
+
+/// An entry in the `[[registries]]` list of a manifest.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryEntry {
+    /// The registry's index, written the same way as a package's `registry`.
+    index: String,
+}
+
+/// The `[scripts]` table of a manifest.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Scripts {
+    /// A command that `wally install --allow-scripts` runs in the project
+    /// directory once every package and link has been written.
+    ///
+    /// Example: `rojo sourcemap default.project.json -o sourcemap.json`
+    #[serde(default)]
+    pub postinstall: Option<String>,
 }
 
 // Metadata we require when this manifest will be used to generate package folders
@@ -156,6 +899,296 @@ pub struct PlaceInfo {
     /// Example: `game.ServerScriptStorage.Packages`
     #[serde(default)]
     pub server_packages: Option<String>,
+
+    /// The directory, relative to the project, that shared packages are
+    /// installed into. Defaults to `Packages`.
+    ///
+    /// Example: `Deps`
+    #[serde(default)]
+    pub shared_packages_dir: Option<String>,
+
+    /// The directory, relative to the project, that server packages are
+    /// installed into. Defaults to `ServerPackages`.
+    ///
+    /// Example: `ServerDeps`
+    #[serde(default)]
+    pub server_packages_dir: Option<String>,
+
+    /// The directory, relative to the project, that dev packages are
+    /// installed into. Defaults to `DevPackages`.
+    ///
+    /// Example: `DevDeps`
+    #[serde(default)]
+    pub dev_packages_dir: Option<String>,
+
+    /// Whether to declare each package's exported types once, in a module
+    /// under `_Types`, which links then require instead of forwarding every
+    /// type themselves.
+    #[serde(default)]
+    pub combined_types: bool,
+
+    /// Glob patterns, relative to the root of each installed package, for
+    /// files and folders to leave out when unpacking packages. A package's
+    /// `default.project.json` and main module are always kept.
+    ///
+    /// Example: `["tests", "**/*.spec.lua"]`
+    #[serde(default)]
+    pub package_exclude: Vec<String>,
+
+    /// The line endings of generated link files and types modules. Defaults
+    /// to `lf`, whatever platform Wally runs on.
+    ///
+    /// Example: `crlf`
+    #[serde(default)]
+    pub link_line_endings: LineEndings,
+
+    /// Whether generated link files and types modules end with a newline.
+    /// Defaults to `true`.
+    #[serde(default = "default_true")]
+    pub link_trailing_newline: bool,
+
+    /// Whether packages whose names only differ by case are installed to
+    /// distinct directories, by appending a short hash to their names,
+    /// instead of refusing to install them together. Those names would
+    /// otherwise collide on case-insensitive filesystems.
+    #[serde(default)]
+    pub disambiguate_file_names: bool,
+
+    /// Packages whose exported types aren't forwarded through links to them,
+    /// which are plain `return require(...)` modules instead. This works
+    /// around types that Wally can't copy into links correctly.
+    ///
+    /// Example: `["biff/exotic-types"]`
+    #[serde(default)]
+    pub no_type_forwarding: Vec<PackageName>,
+
+    /// Whether links and types modules that forward types are written for
+    /// `--!strict` mode, with the required module annotated with its own
+    /// type.
+    #[serde(default)]
+    pub strict_type_forwarding: bool,
+
+    /// Whether to write a `.gitignore` into each packages folder that ignores
+    /// everything installed there.
+    #[serde(default)]
+    pub gitignore: bool,
+
+    /// How packages are laid out in each packages folder. Defaults to
+    /// `index`.
+    ///
+    /// Example: `flat`
+    #[serde(default)]
+    pub layout: Layout,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Line endings to write generated files with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+/// How installed packages are laid out in a packages folder.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Every package is unpacked under `_Index`, in a directory named after
+    /// its scope, name and version, and reached through generated links.
+    #[default]
+    Index,
+
+    /// Every package is unpacked directly into the packages folder, in a
+    /// directory named after it, and requires between packages are rewritten
+    /// to point at each other. Only one version of each package can be
+    /// installed.
+    Flat,
+}
+
+/// Check that a packages directory is the name of one folder directly inside
+/// the project, other than the `_Index` folder that packages are unpacked
+/// into.
+fn check_packages_dir(dir: &str) -> Result<(), &'static str> {
+    if dir.contains('\\') {
+        return Err("it has more than one component");
+    }
+
+    let mut components = Path::new(dir).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) => {
+            if name.eq_ignore_ascii_case("_Index") {
+                Err("that name is used for the index of installed packages")
+            } else {
+                Ok(())
+            }
+        }
+        (None, _) => Err("it's empty"),
+        (Some(Component::Normal(_)), Some(_)) => Err("it has more than one component"),
+        (Some(_), _) => Err("it must be relative to the project and can't be `.` or `..`"),
+    }
+}
+
+/// Check that `path` is an expression that indexes a Roblox instance, like
+/// `game.ReplicatedStorage.Packages`, `game:GetService("ServerStorage").Packages`
+/// or `script.Parent["My Packages"]`, describing the first problem if it isn't.
+fn check_instance_path(path: &str) -> Result<(), String> {
+    let unexpected = |rest: &str| match rest.chars().next() {
+        Some(c) => format!(
+            "unexpected `{}` at character {}",
+            c,
+            path[..path.len() - rest.len()].chars().count() + 1
+        ),
+        None => "it ends unexpectedly".to_owned(),
+    };
+
+    let mut rest = skip_name(path).ok_or_else(|| unexpected(path))?;
+
+    while !rest.is_empty() {
+        rest = if let Some(after) = rest.strip_prefix('.') {
+            skip_name(after).ok_or_else(|| unexpected(after))?
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let after = skip_string(after).ok_or_else(|| unexpected(after))?;
+            after.strip_prefix(']').ok_or_else(|| unexpected(after))?
+        } else if let Some(after) = rest.strip_prefix(':') {
+            let after = skip_name(after).ok_or_else(|| unexpected(after))?;
+            let after = after.strip_prefix('(').ok_or_else(|| unexpected(after))?;
+            let after = skip_string(after).ok_or_else(|| unexpected(after))?;
+            after.strip_prefix(')').ok_or_else(|| unexpected(after))?
+        } else {
+            return Err(unexpected(rest));
+        };
+    }
+
+    Ok(())
+}
+
+/// The code after the Lua name at the start of `code`, if it starts with one.
+fn skip_name(code: &str) -> Option<&str> {
+    let end = code
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(code.len());
+
+    if end == 0 || code.starts_with(|c: char| c.is_ascii_digit()) {
+        None
+    } else {
+        Some(&code[end..])
+    }
+}
+
+/// The code after the quoted string at the start of `code`, if it starts
+/// with one. Escapes aren't needed in instance names, so they aren't allowed.
+fn skip_string(code: &str) -> Option<&str> {
+    let quote = code.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let end = code[1..].find([quote, '\\', '\n'])? + 1;
+
+    code[end..].strip_prefix(quote)
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "index" => Ok(Layout::Index),
+            "flat" => Ok(Layout::Flat),
+            _ => bail!("Unknown layout '{}', expected 'index' or 'flat'", value),
+        }
+    }
+}
+
+impl PlaceInfo {
+    /// Check the paths in `[place]`, failing with every problem that
+    /// [`PlaceInfo::path_problems`] finds.
+    pub fn check_paths(&self) -> anyhow::Result<()> {
+        let problems = self.path_problems();
+        if !problems.is_empty() {
+            bail!("{}", problems.join("\n"));
+        }
+
+        Ok(())
+    }
+
+    /// Every problem with the paths in `[place]`.
+    ///
+    /// `shared-packages` and `server-packages` must look like paths to Roblox
+    /// instances. They're written into the requires of links as they are, so
+    /// anything else would only be noticed once Studio fails to run the
+    /// links.
+    ///
+    /// The packages directories must each be a single, distinct folder in the
+    /// project, because installing and cleaning delete whatever is in them.
+    pub fn path_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for (key, path) in [
+            ("shared-packages", &self.shared_packages),
+            ("server-packages", &self.server_packages),
+        ] {
+            if let Some(path) = path {
+                if let Err(problem) = check_instance_path(path) {
+                    problems.push(format!(
+                        "[place] {} = {:?} is not a path to a Roblox instance: {}. Expected \
+                         something like \"game.ReplicatedStorage.Packages\"",
+                        key, path, problem
+                    ));
+                }
+            }
+        }
+
+        let dirs = [
+            ("shared-packages-dir", self.shared_packages_dir()),
+            ("server-packages-dir", self.server_packages_dir()),
+            ("dev-packages-dir", self.dev_packages_dir()),
+        ];
+
+        for (key, dir) in dirs {
+            if let Err(problem) = check_packages_dir(dir) {
+                problems.push(format!(
+                    "[place] {} = {:?} is not a folder in the project: {}. Expected the name \
+                     of a single folder, like \"Packages\"",
+                    key, dir, problem
+                ));
+            }
+        }
+
+        // Folders that only differ by case are the same folder on Windows
+        // and macOS.
+        for (index, (key, dir)) in dirs.iter().enumerate() {
+            for (other_key, other_dir) in &dirs[..index] {
+                if dir.eq_ignore_ascii_case(other_dir) {
+                    problems.push(format!(
+                        "[place] {} and {} are both {:?}, so installing packages into one \
+                         would remove the packages in the other",
+                        other_key, key, dir
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// The directory that shared packages are installed into.
+    pub fn shared_packages_dir(&self) -> &str {
+        self.shared_packages_dir.as_deref().unwrap_or("Packages")
+    }
+
+    /// The directory that server packages are installed into.
+    pub fn server_packages_dir(&self) -> &str {
+        self.server_packages_dir
+            .as_deref()
+            .unwrap_or("ServerPackages")
+    }
+
+    /// The directory that dev packages are installed into.
+    pub fn dev_packages_dir(&self) -> &str {
+        self.dev_packages_dir.as_deref().unwrap_or("DevPackages")
+    }
 }
 
 impl Default for PlaceInfo {
@@ -163,11 +1196,23 @@ impl Default for PlaceInfo {
         Self {
             shared_packages: None,
             server_packages: None,
+            shared_packages_dir: None,
+            server_packages_dir: None,
+            dev_packages_dir: None,
+            combined_types: false,
+            package_exclude: Vec::new(),
+            link_line_endings: LineEndings::Lf,
+            link_trailing_newline: true,
+            disambiguate_file_names: false,
+            no_type_forwarding: Vec::new(),
+            strict_type_forwarding: false,
+            gitignore: false,
+            layout: Layout::Index,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Realm {
     Server,
@@ -175,6 +1220,18 @@ pub enum Realm {
     Dev,
 }
 
+impl fmt::Display for Realm {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Realm::Server => "server",
+            Realm::Shared => "shared",
+            Realm::Dev => "dev",
+        };
+
+        formatter.write_str(name)
+    }
+}
+
 impl Realm {
     pub fn is_dependency_valid(dep_type: Self, dep_realm: Self) -> bool {
         use Realm::*;
@@ -185,3 +1242,35 @@ impl Realm {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn realm_overrides_only_apply_to_their_own_table() {
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/overrides"
+            version = "0.1.0"
+            license = "MIT"
+            realm = "shared"
+            registry = "test-registries/primary-registry"
+
+            [dependencies]
+            Dep = { package = "biff/minimal@0.1.0", realm = "server" }
+
+            [dev-dependencies]
+            Dep = "biff/minimal@0.1.0"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.dependency_realm(Realm::Shared, "Dep"),
+            Realm::Server
+        );
+        assert_eq!(manifest.dependency_realm(Realm::Dev, "Dep"), Realm::Dev);
+    }
+}
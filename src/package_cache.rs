@@ -0,0 +1,326 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use fs_err as fs;
+use walkdir::WalkDir;
+
+use crate::cache_pruning::{record_use, CachedEntry};
+use crate::install_sink::{FilesystemSink, InstallSink};
+use crate::installation::package_id_file_name;
+use crate::package_contents::PackageContents;
+use crate::package_id::PackageId;
+
+const CHECKSUM_FILE_NAME: &str = "checksum";
+const CONTENTS_DIR_NAME: &str = "contents";
+
+/// A cache of unpacked packages shared by every project on this machine, so
+/// that popular packages only need to be downloaded once.
+///
+/// Each entry holds the unpacked contents of a package along with the checksum
+/// of the archive it was unpacked from. Entries are written to a staging
+/// directory and renamed into place, so an entry that exists is complete.
+/// Looking an entry up records that it was used, so that the least recently
+/// used entries can be pruned first.
+#[derive(Debug, Clone)]
+pub struct PackageCache {
+    root: PathBuf,
+}
+
+impl PackageCache {
+    /// Open the package cache in Wally's cache directory, `cache_dir`.
+    pub fn new(cache_dir: &Path) -> Self {
+        Self::at(cache_dir.join("packages"))
+    }
+
+    /// Open a package cache stored in the given directory.
+    pub fn at<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The checksum of the cached copy of a package, if there is one.
+    ///
+    /// Entries that are missing any of their pieces are removed so that they
+    /// can be written again.
+    pub fn checksum(&self, package_id: &PackageId) -> Option<String> {
+        let entry_path = self.entry_path(package_id);
+        if !entry_path.exists() {
+            return None;
+        }
+
+        let checksum = fs::read_to_string(entry_path.join(CHECKSUM_FILE_NAME)).ok();
+
+        match checksum {
+            Some(checksum) if entry_path.join(CONTENTS_DIR_NAME).is_dir() => {
+                record_use(&entry_path.join(CHECKSUM_FILE_NAME));
+                Some(checksum.trim().to_owned())
+            }
+            _ => {
+                log::debug!("Removing incomplete cache entry {}", entry_path.display());
+
+                if let Err(err) = fs::remove_dir_all(&entry_path) {
+                    log::warn!("Failed to remove incomplete cache entry: {}", err);
+                }
+
+                None
+            }
+        }
+    }
+
+    /// The directory that the cached contents of a package are unpacked into.
+    /// It's only complete if [`PackageCache::checksum`] finds the entry.
+    pub fn contents_path(&self, package_id: &PackageId) -> PathBuf {
+        self.entry_path(package_id).join(CONTENTS_DIR_NAME)
+    }
+
+    /// Copy the cached contents of a package into `destination` through an
+    /// [`InstallSink`].
+    pub fn copy_into(
+        &self,
+        package_id: &PackageId,
+        destination: &Path,
+        sink: &dyn InstallSink,
+    ) -> anyhow::Result<()> {
+        let contents_path = self.contents_path(package_id);
+
+        copy_dir(&contents_path, destination, sink)
+            .with_context(|| format!("could not copy {} from the package cache", package_id))
+    }
+
+    /// Unpack a package into the cache. If another install cached the same
+    /// package first, its entry is kept.
+    pub fn insert(
+        &self,
+        package_id: &PackageId,
+        contents: &PackageContents,
+        checksum: &str,
+    ) -> anyhow::Result<()> {
+        let entry = match self.start_insert(package_id)? {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+
+        contents.unpack_into(&entry.contents_path(), &FilesystemSink)?;
+        entry.finish(checksum)
+    }
+
+    /// Start adding a package to the cache, for when its contents are written
+    /// bit by bit, like while it's downloading. Nothing is added until the
+    /// returned entry is finished. Returns `None` if the package is already
+    /// cached.
+    pub fn start_insert(&self, package_id: &PackageId) -> anyhow::Result<Option<PendingEntry>> {
+        let entry_path = self.entry_path(package_id);
+        if entry_path.exists() {
+            return Ok(None);
+        }
+
+        // Other processes may be caching the same package at the same time, so
+        // each one stages its copy somewhere different.
+        let staging_path = self.root.join(format!(
+            ".{}.partial-{}",
+            package_id_file_name(package_id),
+            std::process::id()
+        ));
+        if staging_path.exists() {
+            fs::remove_dir_all(&staging_path)?;
+        }
+
+        Ok(Some(PendingEntry {
+            staging_path,
+            entry_path,
+        }))
+    }
+
+    /// Remove the cached copy of a package.
+    pub fn remove(&self, package_id: &PackageId) -> io::Result<()> {
+        match fs::remove_dir_all(self.entry_path(package_id)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every complete entry in the cache, for pruning.
+    pub fn entries(&self) -> anyhow::Result<Vec<CachedEntry>> {
+        let mut entries = Vec::new();
+        if !self.root.is_dir() {
+            return Ok(entries);
+        }
+
+        for dir_entry in fs::read_dir(&self.root)? {
+            let entry_path = dir_entry?.path();
+
+            // Skip entries that are still being staged, and anything else
+            // that's kept next to the cache, like the type cache in tests.
+            let hidden = entry_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_none_or(|name| name.starts_with('.'));
+            let checksum_path = entry_path.join(CHECKSUM_FILE_NAME);
+            if hidden || !checksum_path.is_file() {
+                continue;
+            }
+
+            entries.push(CachedEntry::read(entry_path, &checksum_path)?);
+        }
+
+        Ok(entries)
+    }
+
+    fn entry_path(&self, package_id: &PackageId) -> PathBuf {
+        self.root.join(package_id_file_name(package_id))
+    }
+}
+
+/// A package that's being added to a [`PackageCache`]. Its contents are
+/// written to a staging directory, which is only moved into the cache once the
+/// entry is finished, and is removed if the entry is dropped before then.
+pub struct PendingEntry {
+    staging_path: PathBuf,
+    entry_path: PathBuf,
+}
+
+impl PendingEntry {
+    /// Where the unpacked contents of the package should be written.
+    pub fn contents_path(&self) -> PathBuf {
+        self.staging_path.join(CONTENTS_DIR_NAME)
+    }
+
+    /// Add the package to the cache now that all of its contents have been
+    /// written. If another install cached the same package first, its entry
+    /// is kept.
+    pub fn finish(self, checksum: &str) -> anyhow::Result<()> {
+        fs::write(self.staging_path.join(CHECKSUM_FILE_NAME), checksum)?;
+
+        match fs::rename(&self.staging_path, &self.entry_path) {
+            Err(_) if self.entry_path.exists() => Ok(()),
+            result => Ok(result?),
+        }
+    }
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        if self.staging_path.exists() {
+            let _ = fs::remove_dir_all(&self.staging_path);
+        }
+    }
+}
+
+fn copy_dir(source: &Path, destination: &Path, sink: &dyn InstallSink) -> anyhow::Result<()> {
+    for entry in WalkDir::new(source) {
+        let entry = entry?;
+        let relative_path = entry.path().strip_prefix(source).unwrap();
+        let target_path = destination.join(relative_path);
+
+        if entry.file_type().is_dir() {
+            sink.create_dir(&target_path)?;
+        } else {
+            sink.write_file(&target_path, &fs::read(entry.path())?)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime};
+
+    use crate::cache_pruning::{prune, PruneSummary};
+
+    #[test]
+    fn insert_then_copy() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let package_dir = tempfile::tempdir().unwrap();
+        let destination_dir = tempfile::tempdir().unwrap();
+
+        fs::write(
+            package_dir.path().join("wally.toml"),
+            "[package]\n\
+             name = \"biff/minimal\"\n\
+             version = \"0.1.0\"\n\
+             registry = \"test-registries/primary-registry\"\n\
+             realm = \"shared\"\n",
+        )
+        .unwrap();
+        fs::create_dir(package_dir.path().join("src")).unwrap();
+        fs::write(package_dir.path().join("src").join("init.lua"), "return {}").unwrap();
+        let contents = PackageContents::pack_from_path(package_dir.path()).unwrap();
+
+        let cache = PackageCache::at(cache_dir.path());
+        let package_id = PackageId::from_str("biff/minimal@0.1.0").unwrap();
+
+        assert_eq!(cache.checksum(&package_id), None);
+        cache.insert(&package_id, &contents, "abc").unwrap();
+        assert_eq!(cache.checksum(&package_id), Some("abc".to_owned()));
+
+        let destination = destination_dir.path().join("minimal");
+        cache
+            .copy_into(&package_id, &destination, &FilesystemSink)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(destination.join("src").join("init.lua")).unwrap(),
+            "return {}"
+        );
+    }
+
+    #[test]
+    fn incomplete_entries_are_removed() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::at(cache_dir.path());
+        let package_id = PackageId::from_str("biff/minimal@0.1.0").unwrap();
+
+        let entry_path = cache.entry_path(&package_id);
+        fs::create_dir_all(entry_path.join(CONTENTS_DIR_NAME)).unwrap();
+
+        assert_eq!(cache.checksum(&package_id), None);
+        assert!(!entry_path.exists());
+    }
+
+    #[test]
+    fn pruning_removes_least_recently_used_entries() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::at(cache_dir.path());
+
+        // Insert entries whose last uses are an hour apart, the oldest first.
+        let now = SystemTime::now();
+        let package_ids: Vec<PackageId> = ["biff/a@0.1.0", "biff/b@0.1.0", "biff/c@0.1.0"]
+            .iter()
+            .map(|package_id| PackageId::from_str(package_id).unwrap())
+            .collect();
+        for (hours_ago, package_id) in (1..=3).rev().zip(&package_ids) {
+            let entry = cache.start_insert(package_id).unwrap().unwrap();
+            fs::create_dir_all(entry.contents_path()).unwrap();
+            fs::write(entry.contents_path().join("init.lua"), "return {}").unwrap();
+            entry.finish("abc").unwrap();
+
+            let last_used = now - Duration::from_secs(hours_ago * 60 * 60);
+            std::fs::File::options()
+                .append(true)
+                .open(cache.entry_path(package_id).join(CHECKSUM_FILE_NAME))
+                .unwrap()
+                .set_modified(last_used)
+                .unwrap();
+        }
+
+        // Using `b` makes `a` and `c` the least recently used.
+        assert!(cache.checksum(&package_ids[1]).is_some());
+
+        let entry_size = cache.entries().unwrap()[0].size;
+        let summary = prune(cache.entries().unwrap(), None, Some(entry_size)).unwrap();
+
+        assert_eq!(
+            summary,
+            PruneSummary {
+                removed: 2,
+                freed: entry_size * 2
+            }
+        );
+        assert_eq!(cache.checksum(&package_ids[0]), None);
+        assert!(cache.checksum(&package_ids[1]).is_some());
+        assert_eq!(cache.checksum(&package_ids[2]), None);
+    }
+}
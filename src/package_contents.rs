@@ -1,13 +1,18 @@
-use std::io::{self, BufRead, BufReader, Cursor};
-use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::{Component, Path, PathBuf};
 
-use anyhow::format_err;
+use anyhow::{bail, format_err};
+use flate2::read::GzDecoder;
 use fs_err::File;
 use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde_json::json;
+use tar::EntryType;
 use walkdir::WalkDir;
+use zip::read::read_zipfile_from_stream;
+use zip::result::ZipError;
 use zip::{write::FileOptions, ZipArchive, ZipWriter};
 
+use crate::install_sink::{FilesystemSink, InstallSink};
 use crate::manifest::Manifest;
 
 static EXCLUDED_GLOBS: &[&str] = &[
@@ -21,10 +26,37 @@ static EXCLUDED_GLOBS: &[&str] = &[
 /// Container for the contents of a package that have been downloaded.
 #[derive(Clone)]
 pub struct PackageContents {
-    /// Contains a zip with the contents of the package.
+    /// Contains an archive with the contents of the package, in one of the
+    /// formats of [`ArchiveFormat`].
     data: Vec<u8>,
 }
 
+/// The kinds of archive a package can be downloaded as. Packages are always
+/// packed as zips, but registries may serve them in other formats too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// The longest prefix of an archive that [`ArchiveFormat::detect`] needs.
+    const MAGIC_LEN: usize = 4;
+
+    /// Work out the format of an archive from the magic bytes it starts with,
+    /// since the content type a registry serves it with can't be relied on.
+    pub fn detect(data: &[u8]) -> anyhow::Result<Self> {
+        // Empty zips only contain an end of central directory record.
+        if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+            Ok(ArchiveFormat::Zip)
+        } else if data.starts_with(&[0x1f, 0x8b]) {
+            Ok(ArchiveFormat::TarGz)
+        } else {
+            bail!("Package is not a zip or tar.gz archive")
+        }
+    }
+}
+
 impl PackageContents {
     pub fn pack_from_path(input: &Path) -> anyhow::Result<Self> {
         let manifest = Manifest::load(input)?;
@@ -88,11 +120,128 @@ impl PackageContents {
 
     /// Unpack the package into the given path on the filesystem.
     pub fn unpack_into_path(&self, output: &Path) -> anyhow::Result<()> {
+        match self.format()? {
+            ArchiveFormat::Zip => {
+                let mut archive = ZipArchive::new(Cursor::new(self.data.as_slice()))?;
+                archive.extract(output)?;
+            }
+            ArchiveFormat::TarGz => {
+                unpack_tar_gz(self.data.as_slice(), &[(output, &FilesystemSink)])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unpack the package into the given path through an [`InstallSink`].
+    pub fn unpack_into(&self, output: &Path, sink: &dyn InstallSink) -> anyhow::Result<()> {
+        if self.format()? == ArchiveFormat::TarGz {
+            sink.create_dir(output)?;
+            return unpack_tar_gz(self.data.as_slice(), &[(output, sink)]);
+        }
+
         let mut archive = ZipArchive::new(Cursor::new(self.data.as_slice()))?;
-        archive.extract(output)?;
+        sink.create_dir(output)?;
+
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+            let relative_path = file
+                .enclosed_name()
+                .ok_or_else(|| format_err!("Package contains an invalid path {}", file.name()))?
+                .to_owned();
+            let path = output.join(relative_path);
+
+            if file.is_dir() {
+                sink.create_dir(&path)?;
+            } else {
+                if let Some(parent) = path.parent() {
+                    sink.create_dir(parent)?;
+                }
+
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents)?;
+                sink.write_file(&path, &contents)?;
+            }
+        }
+
         Ok(())
     }
 
+    /// Unpack a package as it's read from `reader`, writing every file to each
+    /// of `targets` through its [`InstallSink`]. Only one file of the package
+    /// is held in memory at a time, so large packages can be unpacked while
+    /// they're still downloading.
+    ///
+    /// Returns the checksum of the whole archive, the same as
+    /// [`PackageContents::checksum`] would.
+    ///
+    /// Zips whose entries don't record their size up front can't be read this
+    /// way, and fail with [`ZipError::UnsupportedArchive`]. They need to be
+    /// downloaded in full and unpacked with [`PackageContents::unpack_into`]
+    /// instead.
+    pub fn unpack_stream(
+        reader: impl Read,
+        targets: &[(&Path, &dyn InstallSink)],
+    ) -> anyhow::Result<String> {
+        let mut hashing_reader = HashingReader {
+            inner: reader,
+            hasher: blake3::Hasher::new(),
+        };
+
+        // Read just enough to tell what kind of archive this is, then put it
+        // back in front of the rest.
+        let mut magic = Vec::with_capacity(ArchiveFormat::MAGIC_LEN);
+        (&mut hashing_reader)
+            .take(ArchiveFormat::MAGIC_LEN as u64)
+            .read_to_end(&mut magic)?;
+        let format = ArchiveFormat::detect(&magic)?;
+        let mut reader = Cursor::new(magic).chain(&mut hashing_reader);
+
+        for (output, sink) in targets {
+            sink.create_dir(output)?;
+        }
+
+        if format == ArchiveFormat::TarGz {
+            unpack_tar_gz(&mut reader, targets)?;
+            io::copy(&mut reader, &mut io::sink())?;
+
+            return Ok(hashing_reader.hasher.finalize().to_hex().to_string());
+        }
+
+        while let Some(mut file) = read_zipfile_from_stream(&mut reader).map_err(zip_error)? {
+            let relative_path = file
+                .enclosed_name()
+                .ok_or_else(|| format_err!("Package contains an invalid path {}", file.name()))?
+                .to_owned();
+
+            if file.is_dir() {
+                for (output, sink) in targets {
+                    sink.create_dir(&output.join(&relative_path))?;
+                }
+            } else {
+                // The size comes from the archive, so it can't be trusted to
+                // preallocate with.
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents)?;
+
+                for (output, sink) in targets {
+                    let path = output.join(&relative_path);
+                    if let Some(parent) = path.parent() {
+                        sink.create_dir(parent)?;
+                    }
+
+                    sink.write_file(&path, &contents)?;
+                }
+            }
+        }
+
+        // The central directory at the end of the archive isn't needed to
+        // unpack it, but it's still part of the checksum.
+        io::copy(&mut reader, &mut io::sink())?;
+
+        Ok(hashing_reader.hasher.finalize().to_hex().to_string())
+    }
+
     pub fn filtered_contents(input: &Path) -> anyhow::Result<Vec<PathBuf>> {
         let manifest = Manifest::load(input)?;
         let includes = manifest.package.include;
@@ -114,6 +263,16 @@ impl PackageContents {
             .map(|pattern| pattern.to_string())
             .for_each(|pattern| excludes.push(pattern));
 
+        // Packages may be installed somewhere other than the default folders.
+        let place = &manifest.place;
+        for dir in [
+            place.shared_packages_dir(),
+            place.server_packages_dir(),
+            place.dev_packages_dir(),
+        ] {
+            excludes.push(dir.to_owned());
+        }
+
         let include = build_glob_set(&includes)?;
         let exclude = build_glob_set(&excludes)?;
 
@@ -138,13 +297,114 @@ impl PackageContents {
         &self.data
     }
 
+    /// The kind of archive the package was downloaded as.
+    pub fn format(&self) -> anyhow::Result<ArchiveFormat> {
+        ArchiveFormat::detect(&self.data)
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// A hex-encoded BLAKE3 hash of the package archive, used to verify that
+    /// a package hasn't changed since it was recorded in a lockfile.
+    pub fn checksum(&self) -> String {
+        blake3::hash(&self.data).to_hex().to_string()
+    }
+
     /// Create a new PackageContents object from a buffer.
     pub fn from_buffer(data: Vec<u8>) -> PackageContents {
         PackageContents { data }
     }
 }
 
-fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+/// Hashes everything read through it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Unpack a gzipped tarball read from `reader`, writing every file to each of
+/// `targets`, which must already exist. Links aren't followed anywhere else
+/// packages are unpacked, so packages that contain them are rejected.
+fn unpack_tar_gz(reader: impl Read, targets: &[(&Path, &dyn InstallSink)]) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        let relative_path = enclosed_path(&entry_path).ok_or_else(|| {
+            format_err!("Package contains an invalid path {}", entry_path.display())
+        })?;
+
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                for (output, sink) in targets {
+                    sink.create_dir(&output.join(&relative_path))?;
+                }
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                let mut contents = Vec::new();
+                entry.read_to_end(&mut contents)?;
+
+                for (output, sink) in targets {
+                    let path = output.join(&relative_path);
+                    if let Some(parent) = path.parent() {
+                        sink.create_dir(parent)?;
+                    }
+
+                    sink.write_file(&path, &contents)?;
+                }
+            }
+            EntryType::Symlink | EntryType::Link => {
+                bail!("Package contains a link at {}", entry_path.display())
+            }
+            // Metadata like the global headers written by `git archive`.
+            _ => {}
+        }
+    }
+
+    // Finish reading the gzip stream, so its trailer is checked too.
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+
+    Ok(())
+}
+
+/// `path` if it stays inside the directory it's unpacked into, without any
+/// `.` components, like zip's `enclosed_name`.
+fn enclosed_path(path: &Path) -> Option<PathBuf> {
+    let mut enclosed = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => enclosed.push(part),
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) | Component::ParentDir => return None,
+        }
+    }
+
+    Some(enclosed)
+}
+
+/// The zip crate's errors hide the I/O errors inside them from `source`, so
+/// they're taken out to keep failed downloads distinguishable from invalid
+/// archives.
+fn zip_error(err: ZipError) -> anyhow::Error {
+    match err {
+        ZipError::Io(err) => err.into(),
+        err => err.into(),
+    }
+}
+
+pub(crate) fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
     let mut builder = GlobSetBuilder::new();
 
     for pattern in patterns {
@@ -153,3 +413,169 @@ fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
 
     Ok(builder.build()?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::install_sink::MemorySink;
+    use crate::test_package::PackageBuilder;
+
+    fn package() -> PackageContents {
+        PackageBuilder::new("biff/minimal@0.1.0")
+            .with_file("src/init.lua", "return {}")
+            .with_file("src/util/init.lua", "return 1")
+            .contents()
+    }
+
+    #[test]
+    fn streaming_matches_unpacking_all_at_once() {
+        let contents = package();
+
+        let unpacked = MemorySink::new();
+        contents
+            .unpack_into(Path::new("/unpacked"), &unpacked)
+            .unwrap();
+
+        let streamed = MemorySink::new();
+        let copy = MemorySink::new();
+        let checksum = PackageContents::unpack_stream(
+            contents.data(),
+            &[
+                (Path::new("/unpacked"), &streamed),
+                (Path::new("/copy"), &copy),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(checksum, contents.checksum());
+        assert_eq!(streamed.files(), unpacked.files());
+        assert_eq!(
+            copy.files().values().collect::<Vec<_>>(),
+            unpacked.files().values().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn zip_and_tar_gz_unpack_the_same() {
+        let builder = PackageBuilder::new("biff/minimal@0.1.0")
+            .with_file("src/init.lua", "return {}")
+            .with_file("src/util/init.lua", "return 1");
+        let zip = builder.contents();
+        let tar_gz = builder.tar_gz_contents();
+
+        assert_eq!(zip.format().unwrap(), ArchiveFormat::Zip);
+        assert_eq!(tar_gz.format().unwrap(), ArchiveFormat::TarGz);
+
+        let unpack = |contents: &PackageContents| {
+            let dir = tempfile::tempdir().unwrap();
+            contents.unpack_into_path(dir.path()).unwrap();
+
+            WalkDir::new(dir.path())
+                .min_depth(1)
+                .sort_by(|a, b| a.path().cmp(b.path()))
+                .into_iter()
+                .map(|entry| entry.unwrap())
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| {
+                    let relative = entry.path().strip_prefix(dir.path()).unwrap().to_owned();
+                    (relative, fs_err::read_to_string(entry.path()).unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let unpacked = unpack(&zip);
+        assert_eq!(unpacked.len(), 3);
+        assert_eq!(unpacked, unpack(&tar_gz));
+    }
+
+    #[test]
+    fn tar_gz_streams_like_it_unpacks() {
+        let contents = PackageBuilder::new("biff/minimal@0.1.0")
+            .with_file("src/init.lua", "return {}")
+            .tar_gz_contents();
+
+        let unpacked = MemorySink::new();
+        contents
+            .unpack_into(Path::new("/unpacked"), &unpacked)
+            .unwrap();
+
+        let streamed = MemorySink::new();
+        let checksum =
+            PackageContents::unpack_stream(contents.data(), &[(Path::new("/unpacked"), &streamed)])
+                .unwrap();
+
+        assert_eq!(checksum, contents.checksum());
+        assert_eq!(streamed.files(), unpacked.files());
+        assert_eq!(
+            unpacked.files()[Path::new("/unpacked/src/init.lua")],
+            b"return {}"
+        );
+    }
+
+    #[test]
+    fn tar_gz_entries_with_oversized_headers_are_rejected() {
+        // Claim far more data than the archive holds, which would abort the
+        // process if it were allocated up front.
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1 << 50);
+        header.set_mode(0o644);
+        header.set_path("src/init.lua").unwrap();
+        header.set_cksum();
+
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut archive = tar::Builder::new(encoder);
+        archive.append(&header, &b"return {}"[..]).unwrap();
+        let contents =
+            PackageContents::from_buffer(archive.into_inner().unwrap().finish().unwrap());
+
+        contents
+            .unpack_into(Path::new("/unpacked"), &MemorySink::new())
+            .unwrap_err();
+        PackageContents::unpack_stream(
+            contents.data(),
+            &[(Path::new("/unpacked"), &MemorySink::new())],
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn unknown_archive_formats_are_rejected() {
+        let contents = PackageContents::from_buffer(b"<html>Not Found</html>".to_vec());
+
+        let err = contents
+            .unpack_into(Path::new("/unpacked"), &MemorySink::new())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Package is not a zip or tar.gz archive");
+
+        let err = PackageContents::unpack_stream(
+            contents.data(),
+            &[(Path::new("/unpacked"), &MemorySink::new())],
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Package is not a zip or tar.gz archive");
+    }
+
+    #[test]
+    fn entries_without_sizes_cannot_be_streamed() {
+        // Mark the first entry as having its size after its data, like
+        // archives written by tools that can't seek back to fill it in.
+        let mut data = package().into_data();
+        data[6] |= 1 << 3;
+
+        let err = PackageContents::unpack_stream(
+            data.as_slice(),
+            &[(Path::new("/unpacked"), &MemorySink::new())],
+        )
+        .unwrap_err();
+
+        assert!(
+            matches!(
+                err.downcast_ref::<ZipError>(),
+                Some(ZipError::UnsupportedArchive(_))
+            ),
+            "{:#}",
+            err
+        );
+    }
+}
@@ -146,6 +146,33 @@ mod test {
         not_enough_version.unwrap_err();
     }
 
+    #[test]
+    fn parse_prerelease_and_build() {
+        let id: PackageId = "hello/world@1.2.3-beta.1+build5".parse().unwrap();
+        assert_eq!(id.version().to_string(), "1.2.3-beta.1+build5");
+        assert_eq!(id.to_string(), "hello/world@1.2.3-beta.1+build5");
+    }
+
+    #[test]
+    fn prerelease_ordering() {
+        // Prereleases come before their release, and their numeric
+        // identifiers are compared as numbers.
+        let ids: Vec<PackageId> = [
+            "hello/world@1.1.0-beta",
+            "hello/world@1.1.0-beta.2",
+            "hello/world@1.1.0-beta.10",
+            "hello/world@1.1.0-rc.1",
+            "hello/world@1.1.0",
+        ]
+        .iter()
+        .map(|id| id.parse().unwrap())
+        .collect();
+
+        for pair in ids.windows(2) {
+            assert!(pair[0] < pair[1], "{} < {}", pair[0], pair[1]);
+        }
+    }
+
     #[test]
     fn serialization() {
         let name = PackageName::new("lpghatguy", "asink").unwrap();
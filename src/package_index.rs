@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 use std::io::{BufReader, ErrorKind, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context};
+use anyhow::Context;
 use fs_err::{create_dir_all, File, OpenOptions};
 use git2::Repository;
 use serde::{Deserialize, Serialize};
@@ -51,8 +51,14 @@ pub struct PackageIndex {
 }
 
 impl PackageIndex {
-    pub fn new(index_url: &Url, access_token: Option<String>) -> anyhow::Result<Self> {
-        let path = index_path(index_url)?;
+    /// Open the copy of an index kept in Wally's cache directory,
+    /// `cache_dir`, downloading it first if there isn't one yet, and update it.
+    pub fn new(
+        cache_dir: &Path,
+        index_url: &Url,
+        access_token: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let path = index_path(cache_dir, index_url);
         let repository = git_util::open_or_clone(access_token.clone(), index_url, &path)?;
 
         let index = Self {
@@ -68,6 +74,27 @@ impl PackageIndex {
         Ok(index)
     }
 
+    /// Open the copy of an index that's already on this machine without
+    /// contacting the remote, failing if it was never downloaded.
+    pub fn open_offline(cache_dir: &Path, index_url: &Url) -> anyhow::Result<Self> {
+        let path = index_path(cache_dir, index_url);
+        let repository = Repository::open(&path).with_context(|| {
+            format!(
+                "The package index {} has not been downloaded, so it cannot be used offline",
+                index_url
+            )
+        })?;
+
+        Ok(Self {
+            url: index_url.clone(),
+            path,
+            repository: Mutex::new(repository),
+            package_cache: Mutex::new(HashMap::new()),
+            access_token: None,
+            temp_dir: None,
+        })
+    }
+
     pub fn url(&self) -> &Url {
         &self.url
     }
@@ -157,10 +184,12 @@ impl PackageIndex {
 
     /// Read the list of versions for a package from the index.
     pub fn get_package_metadata(&self, name: &PackageName) -> anyhow::Result<Arc<PackageMetadata>> {
-        let mut package_cache = self.package_cache.lock().unwrap();
+        // The cache isn't locked while reading the index, so that several
+        // packages can be read at once.
+        let cached = self.package_cache.lock().unwrap().get(name).cloned();
 
-        if package_cache.contains_key(name) {
-            Ok(Arc::clone(&package_cache[name]))
+        if let Some(metadata) = cached {
+            Ok(metadata)
         } else {
             let package_path = self.package_path(name);
 
@@ -187,7 +216,10 @@ impl PackageIndex {
             versions.sort_by(|a, b| b.package.version.cmp(&a.package.version));
 
             let metadata = Arc::new(PackageMetadata { versions });
-            package_cache.insert(name.clone(), Arc::clone(&metadata));
+            self.package_cache
+                .lock()
+                .unwrap()
+                .insert(name.clone(), Arc::clone(&metadata));
 
             Ok(metadata)
         }
@@ -263,7 +295,7 @@ pub struct PackageMetadata {
     pub versions: Vec<Manifest>,
 }
 
-fn index_path(index_url: &Url) -> anyhow::Result<PathBuf> {
+fn index_path(cache_dir: &Path, index_url: &Url) -> PathBuf {
     let registry_name = match (index_url.domain(), index_url.scheme()) {
         (Some(domain), _) => domain,
         (None, "file") => "local-registry",
@@ -274,11 +306,5 @@ fn index_path(index_url: &Url) -> anyhow::Result<PathBuf> {
     let hash_hex = hex::encode(&hash.as_bytes()[..8]);
     let ident = format!("{}-{}", registry_name, hash_hex);
 
-    let path = dirs::cache_dir()
-        .ok_or_else(|| anyhow!("could not find cache directory"))?
-        .join("wally")
-        .join("index")
-        .join(ident);
-
-    Ok(path)
+    cache_dir.join("index").join(ident)
 }
@@ -16,6 +16,12 @@ use crate::package_name::PackageName;
 /// * `roblox/roact@1.4.2`
 /// * `lpghatguy/asink@0.2.0-alpha.3`
 /// * `foo/bar@1`
+///
+/// Version requirements use the same syntax as Cargo: a comma-separated list
+/// of comparators, each of which is a caret (`^1.2`, also written `1.2`),
+/// tilde (`~1.2`), wildcard (`*`, `1.*`), exact (`=1.2.3`) or inequality
+/// (`>=1.2`, `<2`) requirement. Anything else is rejected rather than guessed
+/// at.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct PackageReq {
     name: PackageName,
@@ -74,7 +80,9 @@ impl FromStr for PackageReq {
             bail!(BAD_FORMAT_MSG);
         }
 
-        let version_req = version_req_source
+        check_version_req_syntax(version_req_source)?;
+
+        let version_req = without_build_metadata(version_req_source)
             .parse()
             .context("could not parse version requirement")?;
 
@@ -83,6 +91,115 @@ impl FromStr for PackageReq {
     }
 }
 
+/// The operators a comparator can start with, longest first so that `>=` isn't
+/// read as `>`.
+const OPERATORS: &[&str] = &[">=", "<=", ">", "<", "=", "^", "~"];
+
+/// Reject version requirement syntax that the semver crate accepts but
+/// doesn't interpret the way Cargo does, or that has no single obvious
+/// meaning. Without this, `>1.*` would mean `>1.0.0` and `1.*.3` would mean
+/// `1.*`, silently installing versions the author didn't ask for.
+fn check_version_req_syntax(version_req: &str) -> anyhow::Result<()> {
+    if version_req.contains("||") {
+        bail!(
+            "invalid version requirement `{}`: alternatives separated by `||` are not supported",
+            version_req
+        );
+    }
+
+    for comparator in version_req.split(',') {
+        let comparator = comparator.trim();
+        if comparator.is_empty() {
+            bail!(
+                "invalid version requirement `{}`: it has an empty comparator",
+                version_req
+            );
+        }
+
+        if comparator.starts_with("~>") {
+            bail!(
+                "invalid version requirement `{}`: `~>` is not supported, use `~` instead",
+                version_req
+            );
+        }
+
+        let operator = OPERATORS
+            .iter()
+            .find(|operator| comparator.starts_with(*operator));
+        let version = comparator[operator.map_or(0, |operator| operator.len())..].trim_start();
+
+        if version.contains(char::is_whitespace) {
+            bail!(
+                "invalid version requirement `{}`: comparators must be separated by commas, \
+                 like `>=1.2.0, <2.0.0`",
+                version_req
+            );
+        }
+
+        let core = version.split(['-', '+']).next().unwrap_or("");
+        let is_wildcard = |part: &str| part == "*" || part == "x" || part == "X";
+        let parts: Vec<&str> = core.split('.').collect();
+
+        if let Some(first_wildcard) = parts.iter().position(|part| is_wildcard(part)) {
+            if let Some(operator) = operator {
+                bail!(
+                    "invalid version requirement `{}`: the wildcard in `{}` can't be combined \
+                     with `{}`, write it as `{}` instead",
+                    version_req,
+                    comparator,
+                    operator,
+                    version
+                );
+            }
+
+            if !parts[first_wildcard..].iter().all(|part| is_wildcard(part)) {
+                bail!(
+                    "invalid version requirement `{}`: a wildcard in `{}` can only be \
+                     followed by other wildcards",
+                    version_req,
+                    comparator
+                );
+            }
+
+            if core.len() != version.len() {
+                bail!(
+                    "invalid version requirement `{}`: `{}` can't have both a wildcard \
+                     and a prerelease",
+                    version_req,
+                    comparator
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the build metadata from each version in a version requirement.
+///
+/// Build metadata never affects which versions match, but the semver crate
+/// fails to parse it after a prerelease, and parses it as a prerelease
+/// otherwise, so `=1.0.0+build5` wouldn't match `1.0.0+build5`.
+fn without_build_metadata(version_req: &str) -> String {
+    let mut stripped = String::with_capacity(version_req.len());
+    let mut in_build_metadata = false;
+
+    for character in version_req.chars() {
+        if character == '+' {
+            in_build_metadata = true;
+        } else if in_build_metadata
+            && (character.is_ascii_alphanumeric() || character == '.' || character == '-')
+        {
+            continue;
+        } else {
+            in_build_metadata = false;
+            stripped.push(character);
+        }
+    }
+
+    stripped
+}
+
 impl Serialize for PackageReq {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         let combined_name = format!(
@@ -178,6 +295,74 @@ mod test {
         no_version_at.unwrap_err();
     }
 
+    #[test]
+    fn version_req_syntax() {
+        let version = |version: &str| Version::parse(version).unwrap();
+        let name = PackageName::new("hello", "world").unwrap();
+        let matches = |req: &str, version: &Version| {
+            let req: PackageReq = format!("hello/world@{}", req).parse().unwrap();
+            req.matches(&name, version)
+        };
+
+        assert!(matches("^1.2", &version("1.9.0")));
+        assert!(!matches("^0.2", &version("0.3.0")));
+        assert!(matches("~1.2", &version("1.2.9")));
+        assert!(!matches("~1.2", &version("1.3.0")));
+        assert!(matches("*", &version("5.0.0")));
+        assert!(matches("1.*", &version("1.9.0")));
+        assert!(!matches("1.2.x", &version("1.3.0")));
+        assert!(matches(">= 1.2, < 2", &version("1.9.0")));
+        assert!(!matches(">1.2.0, <=1.3.0", &version("1.2.0")));
+
+        let error = |req: &str| {
+            format!("hello/world@{}", req)
+                .parse::<PackageReq>()
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert!(error("^1 || ^2").contains("`||`"));
+        assert!(error(">=1.0.0,").contains("empty comparator"));
+        assert!(error("~>1.2").contains("use `~` instead"));
+        assert!(error(">=1.0.0 <2.0.0").contains("separated by commas"));
+        assert!(error("1.0.0 - 2.0.0").contains("separated by commas"));
+        assert!(error(">1.*").contains("write it as `1.*` instead"));
+        assert!(error("1.*.3").contains("only be followed by other wildcards"));
+        assert!(error("1.*-beta").contains("both a wildcard and a prerelease"));
+    }
+
+    #[test]
+    fn prerelease_matching() {
+        let version = |version: &str| Version::parse(version).unwrap();
+        let name = PackageName::new("hello", "world").unwrap();
+        let matches = |req: &str, version: &Version| {
+            let req: PackageReq = format!("hello/world@{}", req).parse().unwrap();
+            req.matches(&name, version)
+        };
+
+        // Prereleases are only matched by requirements that ask for a
+        // prerelease of the same version.
+        assert!(!matches("1.0.0", &version("1.1.0-beta")));
+        assert!(!matches(">=1.0.0", &version("1.1.0-beta")));
+        assert!(matches("=1.1.0-beta", &version("1.1.0-beta")));
+        assert!(!matches("=1.1.0-beta", &version("1.1.0")));
+
+        assert!(matches("1.1.0-beta", &version("1.1.0-beta.2")));
+        assert!(matches("1.1.0-beta", &version("1.1.0")));
+        assert!(matches("1.1.0-beta", &version("1.2.0")));
+        assert!(!matches("1.1.0-beta", &version("1.2.0-beta")));
+
+        // Build metadata is ignored, as it is when comparing versions.
+        assert!(matches("=1.0.0+build5", &version("1.0.0")));
+        assert!(matches("=1.0.0+build5", &version("1.0.0+build6")));
+        assert!(!matches("=1.0.0+build5", &version("1.0.0-build5")));
+        assert!(matches(
+            "=1.1.0-beta.1+build5",
+            &version("1.1.0-beta.1+build5")
+        ));
+        assert!(matches(">=1.0.0+a, <2.0.0+b", &version("1.5.0")));
+    }
+
     #[test]
     fn serialization() {
         let name = PackageName::new("lpghatguy", "asink").unwrap();
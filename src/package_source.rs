@@ -1,20 +1,30 @@
+mod git;
 mod in_memory;
+mod mirrored;
+mod path;
 mod registry;
 mod test_registry;
 
+pub use self::git::GitSource;
 pub use self::in_memory::InMemoryRegistry;
 use self::in_memory::InMemoryRegistrySource;
-pub use self::registry::Registry;
+pub use self::mirrored::MirroredSource;
+pub use self::path::PathSource;
+pub(crate) use self::registry::is_transient_cause;
+pub use self::registry::{Registry, DEFAULT_DOWNLOAD_TIMEOUT};
 pub use self::test_registry::TestRegistry;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Read};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use serde::Serialize;
 
-use crate::manifest::Manifest;
+use crate::manifest::{GitSpec, Manifest};
 use crate::package_contents::PackageContents;
 use crate::package_id::PackageId;
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
@@ -22,12 +32,24 @@ pub enum PackageSourceId {
     DefaultRegistry,
     Git(String),
     Path(PathBuf),
+
+    /// A package that the project depends on directly from a Git repository,
+    /// rather than a registry.
+    GitRepository(GitSpec),
+
+    /// A package that the project depends on directly from a directory on
+    /// this machine, keyed by the path written in the project's manifest.
+    LocalPath(String),
 }
 
 #[derive(Clone)]
 pub struct PackageSourceMap {
     sources: HashMap<PackageSourceId, Box<PackageSource>>,
     source_order: Vec<PackageSourceId>,
+    offline: bool,
+    download_timeout: Duration,
+    cache_dir: Option<PathBuf>,
+    registry_headers: BTreeMap<String, BTreeMap<String, String>>,
 }
 
 impl PackageSourceMap {
@@ -38,9 +60,44 @@ impl PackageSourceMap {
         Self {
             sources,
             source_order: vec![PackageSourceId::DefaultRegistry],
+            offline: false,
+            download_timeout: DEFAULT_DOWNLOAD_TIMEOUT,
+            cache_dir: None,
+            registry_headers: BTreeMap::new(),
         }
     }
 
+    /// Create fallback sources that refuse to access the network, matching
+    /// the sources this map was created with.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Create fallback registries that give up on downloads taking longer
+    /// than `timeout`, matching the sources this map was created with.
+    pub fn with_download_timeout(mut self, timeout: Duration) -> Self {
+        self.download_timeout = timeout;
+        self
+    }
+
+    /// Create fallback registries that keep their indexes in `cache_dir`,
+    /// matching the sources this map was created with.
+    pub fn with_cache_dir(mut self, cache_dir: PathBuf) -> Self {
+        self.cache_dir = Some(cache_dir);
+        self
+    }
+
+    /// Create fallback registries that send the headers configured for them,
+    /// keyed by registry index URL and then by header name.
+    pub fn with_registry_headers(
+        mut self,
+        registry_headers: BTreeMap<String, BTreeMap<String, String>>,
+    ) -> Self {
+        self.registry_headers = registry_headers;
+        self
+    }
+
     pub fn get(&self, id: &PackageSourceId) -> Option<&PackageSource> {
         self.sources.get(id).map(|source| source.as_ref())
     }
@@ -49,6 +106,88 @@ impl PackageSourceMap {
         &self.source_order
     }
 
+    /// Send requests to the default registry for packages in each of the
+    /// given scopes to that scope's mirror instead. Fallback registries are
+    /// not affected.
+    pub fn add_mirrors(&mut self, mirrors: HashMap<String, PackageSource>) {
+        if mirrors.is_empty() {
+            return;
+        }
+
+        let default_registry = self
+            .sources
+            .remove(&PackageSourceId::DefaultRegistry)
+            .unwrap();
+
+        self.sources.insert(
+            PackageSourceId::DefaultRegistry,
+            Box::new(PackageSource::Mirrored(MirroredSource::new(
+                default_registry,
+                mirrors,
+            ))),
+        );
+    }
+
+    /// Search the registries listed in a project's manifest for packages, in
+    /// the order they're listed, so that a package in more than one of them
+    /// comes from the first. Each is given with its source, except for the
+    /// default registry, which is searched after them unless it's listed too.
+    pub fn add_registries(&mut self, registries: Vec<(PackageSourceId, Option<PackageSource>)>) {
+        let mut source_order = Vec::new();
+
+        for (id, source) in registries {
+            if let Some(source) = source {
+                self.sources.insert(id.clone(), Box::new(source));
+            }
+
+            if !source_order.contains(&id) {
+                source_order.push(id);
+            }
+        }
+
+        for id in self.source_order.drain(..) {
+            if !source_order.contains(&id) {
+                source_order.push(id);
+            }
+        }
+
+        self.source_order = source_order;
+    }
+
+    /// Add the package in a Git repository as a source. It's only used for
+    /// dependencies on that repository, not searched for other packages.
+    pub fn add_git_source(&mut self, source: GitSource) {
+        self.sources.insert(
+            PackageSourceId::GitRepository(source.spec().clone()),
+            Box::new(PackageSource::Git(source)),
+        );
+    }
+
+    /// The source added for the Git repository described by `spec`.
+    pub fn git_source(&self, spec: &GitSpec) -> anyhow::Result<&GitSource> {
+        match self.get(&PackageSourceId::GitRepository(spec.clone())) {
+            Some(PackageSource::Git(source)) => Ok(source),
+            _ => anyhow::bail!("The Git repository {} has not been fetched", spec),
+        }
+    }
+
+    /// Add the package in a local directory as a source. Like Git sources,
+    /// it's only used for dependencies on that directory.
+    pub fn add_path_source(&mut self, source: PathSource) {
+        self.sources.insert(
+            PackageSourceId::LocalPath(source.path().to_owned()),
+            Box::new(PackageSource::Path(source)),
+        );
+    }
+
+    /// The source added for the local directory at `path`.
+    pub fn path_source(&self, path: &str) -> anyhow::Result<&PathSource> {
+        match self.get(&PackageSourceId::LocalPath(path.to_owned())) {
+            Some(PackageSource::Path(source)) => Ok(source),
+            _ => anyhow::bail!("The path dependency {} has not been loaded", path),
+        }
+    }
+
     /// Searches the current list of sources for fallbacks and adds any not yet in the list, producing
     /// a complete tree of reachable sources for packages.
     /// Sources are searched breadth-first to ensure correct fallback priority.
@@ -63,14 +202,30 @@ impl PackageSourceMap {
                 if !self.source_order.contains(&fallback) {
                     let source: Box<PackageSource> = match &fallback {
                         PackageSourceId::Git(url) => {
-                            Box::new(PackageSource::Registry(Registry::from_registry_spec(url)?))
-                        }
-                        PackageSourceId::Path(path) => {
-                            Box::new(PackageSource::TestRegistry(TestRegistry::new(path.clone())))
+                            let mut registry = Registry::from_registry_spec(url)?
+                                .with_offline(self.offline)
+                                .with_download_timeout(self.download_timeout);
+                            if let Some(cache_dir) = &self.cache_dir {
+                                registry = registry.with_cache_dir(cache_dir.clone());
+                            }
+                            if let Some(headers) = self.registry_headers.get(url) {
+                                registry = registry.with_headers(headers)?;
+                            }
+
+                            Box::new(PackageSource::Registry(registry))
                         }
+                        PackageSourceId::Path(path) => Box::new(PackageSource::TestRegistry(
+                            TestRegistry::new(path.clone()).with_offline(self.offline),
+                        )),
                         PackageSourceId::DefaultRegistry => {
                             panic!("Default registry should never be added as a fallback source!")
                         }
+                        PackageSourceId::GitRepository(_) => {
+                            panic!("Git repositories should never be added as fallback sources!")
+                        }
+                        PackageSourceId::LocalPath(_) => {
+                            panic!("Local paths should never be added as fallback sources!")
+                        }
                     };
 
                     self.sources.insert(fallback.clone(), source);
@@ -89,21 +244,61 @@ pub trait PackageSourceProvider: Sync + Send + Clone {
     /// Update this package source, if it has state that needs to be updated.
     fn update(&self) -> anyhow::Result<()>;
 
+    /// List every version of the package called `name` that this package
+    /// source has.
+    fn versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>>;
+
     /// Query this package source for all of the packages that match this
     /// `PackageReq`.
-    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>>;
+    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+        let versions = self
+            .versions(package_req.name())?
+            .into_iter()
+            .filter(|manifest| {
+                package_req.matches(&manifest.package.name, &manifest.package.version)
+            })
+            .collect();
+
+        Ok(versions)
+    }
 
     /// Downloads the contents of a package given its fully-qualified
     /// `PackageId`.
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents>;
 
+    /// Downloads the contents of a package like `download_package`, calling
+    /// `on_progress` with the number of bytes received each time more of the
+    /// package arrives.
+    fn download_package_with_progress(
+        &self,
+        package_id: &PackageId,
+        on_progress: &dyn Fn(u64),
+    ) -> anyhow::Result<PackageContents> {
+        let contents = self.download_package(package_id)?;
+        on_progress(contents.data().len() as u64);
+
+        Ok(contents)
+    }
+
+    /// Starts downloading the contents of a package, returning a reader that
+    /// yields the package archive as it arrives so that it can be unpacked
+    /// without holding all of it in memory. Sources that can't stream a
+    /// package download all of it before returning.
+    fn open_package(&self, package_id: &PackageId) -> anyhow::Result<Box<dyn Read + Send>> {
+        let contents = self.download_package(package_id)?;
+        Ok(Box::new(Cursor::new(contents.into_data())))
+    }
+
     /// Provide a list of fallback sources to search if this source can't provide a package
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>>;
 }
 
 #[derive(Clone)]
 pub enum PackageSource {
+    Git(GitSource),
     InMemory(InMemoryRegistrySource),
+    Mirrored(MirroredSource),
+    Path(PathSource),
     Registry(Registry),
     TestRegistry(TestRegistry),
 }
@@ -111,31 +306,81 @@ pub enum PackageSource {
 impl PackageSourceProvider for PackageSource {
     fn update(&self) -> anyhow::Result<()> {
         match self {
+            PackageSource::Git(source) => source.update(),
             PackageSource::InMemory(source) => source.update(),
+            PackageSource::Mirrored(source) => source.update(),
+            PackageSource::Path(source) => source.update(),
             PackageSource::Registry(source) => source.update(),
             PackageSource::TestRegistry(source) => source.update(),
         }
     }
 
-    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+    fn versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
         match self {
-            PackageSource::InMemory(source) => source.query(package_req),
-            PackageSource::Registry(source) => source.query(package_req),
-            PackageSource::TestRegistry(source) => source.query(package_req),
+            PackageSource::Git(source) => source.versions(name),
+            PackageSource::InMemory(source) => source.versions(name),
+            PackageSource::Mirrored(source) => source.versions(name),
+            PackageSource::Path(source) => source.versions(name),
+            PackageSource::Registry(source) => source.versions(name),
+            PackageSource::TestRegistry(source) => source.versions(name),
         }
     }
 
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
         match self {
+            PackageSource::Git(source) => source.download_package(package_id),
             PackageSource::InMemory(source) => source.download_package(package_id),
+            PackageSource::Mirrored(source) => source.download_package(package_id),
+            PackageSource::Path(source) => source.download_package(package_id),
             PackageSource::Registry(source) => source.download_package(package_id),
             PackageSource::TestRegistry(source) => source.download_package(package_id),
         }
     }
 
+    fn download_package_with_progress(
+        &self,
+        package_id: &PackageId,
+        on_progress: &dyn Fn(u64),
+    ) -> anyhow::Result<PackageContents> {
+        match self {
+            PackageSource::Git(source) => {
+                source.download_package_with_progress(package_id, on_progress)
+            }
+            PackageSource::InMemory(source) => {
+                source.download_package_with_progress(package_id, on_progress)
+            }
+            PackageSource::Mirrored(source) => {
+                source.download_package_with_progress(package_id, on_progress)
+            }
+            PackageSource::Path(source) => {
+                source.download_package_with_progress(package_id, on_progress)
+            }
+            PackageSource::Registry(source) => {
+                source.download_package_with_progress(package_id, on_progress)
+            }
+            PackageSource::TestRegistry(source) => {
+                source.download_package_with_progress(package_id, on_progress)
+            }
+        }
+    }
+
+    fn open_package(&self, package_id: &PackageId) -> anyhow::Result<Box<dyn Read + Send>> {
+        match self {
+            PackageSource::Git(source) => source.open_package(package_id),
+            PackageSource::InMemory(source) => source.open_package(package_id),
+            PackageSource::Mirrored(source) => source.open_package(package_id),
+            PackageSource::Path(source) => source.open_package(package_id),
+            PackageSource::Registry(source) => source.open_package(package_id),
+            PackageSource::TestRegistry(source) => source.open_package(package_id),
+        }
+    }
+
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
         match self {
+            PackageSource::Git(source) => source.fallback_sources(),
             PackageSource::InMemory(source) => source.fallback_sources(),
+            PackageSource::Mirrored(source) => source.fallback_sources(),
+            PackageSource::Path(source) => source.fallback_sources(),
             PackageSource::Registry(source) => source.fallback_sources(),
             PackageSource::TestRegistry(source) => source.fallback_sources(),
         }
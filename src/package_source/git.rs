@@ -0,0 +1,290 @@
+//! Defines a package source for a single package that lives in a Git
+//! repository instead of a registry.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, format_err, Context};
+use git2::{ObjectType, Oid, Repository, Tree};
+use semver::VersionReq;
+use tempfile::TempDir;
+
+use crate::git_util;
+use crate::manifest::{GitSpec, Manifest, MANIFEST_FILE_NAME};
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+
+use super::{PackageContents, PackageSourceId, PackageSourceProvider};
+
+/// The package in a Git repository, checked out at a single commit.
+#[derive(Clone)]
+pub struct GitSource {
+    spec: GitSpec,
+
+    /// The path to Wally's copy of the repository.
+    path: PathBuf,
+
+    /// The commit the package is read from.
+    commit: Oid,
+
+    /// The manifest of the package at `commit`.
+    manifest: Arc<Manifest>,
+
+    /// If the repository is in a temporary location, like when running tests,
+    /// hold onto it here so that it'll be dropped at the right time.
+    #[allow(unused)]
+    temp_dir: Option<Arc<TempDir>>,
+}
+
+impl GitSource {
+    /// Fetch the repository described by `spec` into Wally's cache and find
+    /// its package at `locked_commit`, or at the spec's `rev` if no commit was
+    /// locked.
+    pub fn new(
+        cache_dir: &Path,
+        spec: &GitSpec,
+        locked_commit: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let path = checkout_path(cache_dir, &spec.git);
+        let repository = open_or_init(&path)?;
+
+        Self::load(spec, repository, path, locked_commit, true, None)
+    }
+
+    /// Like `new`, but fetches the repository into a temporary directory.
+    pub fn new_temp(spec: &GitSpec, locked_commit: Option<&str>) -> anyhow::Result<Self> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().to_owned();
+        let repository = open_or_init(&path)?;
+
+        Self::load(
+            spec,
+            repository,
+            path,
+            locked_commit,
+            true,
+            Some(Arc::new(temp_dir)),
+        )
+    }
+
+    /// Open the copy of the repository that's already on this machine without
+    /// contacting the remote, failing if it was never fetched.
+    pub fn open_offline(
+        cache_dir: &Path,
+        spec: &GitSpec,
+        locked_commit: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let path = checkout_path(cache_dir, &spec.git);
+        let repository = Repository::open_bare(&path).with_context(|| {
+            format!(
+                "The Git repository {} has not been downloaded, so it cannot be used offline",
+                spec.git
+            )
+        })?;
+
+        Self::load(spec, repository, path, locked_commit, false, None)
+    }
+
+    fn load(
+        spec: &GitSpec,
+        repository: Repository,
+        path: PathBuf,
+        locked_commit: Option<&str>,
+        fetch: bool,
+        temp_dir: Option<Arc<TempDir>>,
+    ) -> anyhow::Result<Self> {
+        // A locked commit that was fetched before can't have changed, so
+        // there's no need to contact the remote for it.
+        let has_locked_commit = locked_commit
+            .and_then(|commit| Oid::from_str(commit).ok())
+            .is_some_and(|commit| repository.find_commit(commit).is_ok());
+
+        if fetch && !has_locked_commit {
+            log::info!("Fetching Git repository {}...", spec.git);
+            git_util::fetch_all(&repository, &spec.git)?;
+        }
+
+        let commit = match locked_commit {
+            Some(commit) => {
+                let commit = Oid::from_str(commit)
+                    .with_context(|| format!("invalid locked commit {}", commit))?;
+                repository.find_commit(commit).with_context(|| {
+                    format!(
+                        "The locked commit {} was not found in Git repository {}",
+                        commit, spec.git
+                    )
+                })?;
+                commit
+            }
+            None => resolve_rev(&repository, &spec.rev).with_context(|| {
+                format!("Could not find {} in Git repository {}", spec.rev, spec.git)
+            })?,
+        };
+
+        let tree = package_tree(&repository, commit, spec)?;
+        let manifest_entry = tree
+            .get_name(MANIFEST_FILE_NAME)
+            .ok_or_else(|| format_err!("{} does not contain a {}", spec, MANIFEST_FILE_NAME))?;
+        let manifest_blob = manifest_entry.to_object(&repository)?.peel_to_blob()?;
+        let manifest = Manifest::from_slice(manifest_blob.content())
+            .with_context(|| format!("invalid {} in {}", MANIFEST_FILE_NAME, spec))?;
+
+        Ok(Self {
+            spec: spec.clone(),
+            path,
+            commit,
+            manifest: Arc::new(manifest),
+            temp_dir,
+        })
+    }
+
+    pub fn spec(&self) -> &GitSpec {
+        &self.spec
+    }
+
+    /// The full hash of the commit the package is read from.
+    pub fn commit(&self) -> String {
+        self.commit.to_string()
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// A requirement that only the package in this repository satisfies.
+    pub fn package_req(&self) -> PackageReq {
+        PackageReq::new(
+            self.manifest.package.name.clone(),
+            VersionReq::exact(&self.manifest.package.version),
+        )
+    }
+}
+
+impl PackageSourceProvider for GitSource {
+    fn update(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        if *name == self.manifest.package.name {
+            Ok(vec![Manifest::clone(&self.manifest)])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        if *package_id != self.manifest.package_id() {
+            bail!("{} does not contain package {}", self.spec, package_id);
+        }
+
+        let repository = Repository::open_bare(&self.path)?;
+        let tree = package_tree(&repository, self.commit, &self.spec)?;
+
+        // Packing from a checkout means packages from Git get exactly the
+        // files that publishing them would include.
+        let checkout = tempfile::tempdir()?;
+        write_tree(&repository, &tree, checkout.path())?;
+
+        PackageContents::pack_from_path(checkout.path())
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        Ok(Vec::new())
+    }
+}
+
+fn open_or_init(path: &Path) -> anyhow::Result<Repository> {
+    match Repository::open_bare(path) {
+        Ok(repository) => Ok(repository),
+        Err(_) => {
+            fs_err::create_dir_all(path)?;
+            Ok(Repository::init_bare(path)?)
+        }
+    }
+}
+
+/// Find the commit that `rev` names, trying it as a branch, then as a tag and
+/// finally as a commit hash.
+fn resolve_rev(repository: &Repository, rev: &str) -> anyhow::Result<Oid> {
+    let candidates = [
+        format!("refs/remotes/origin/{}", rev),
+        format!("refs/tags/{}", rev),
+        rev.to_owned(),
+    ];
+
+    for candidate in candidates.iter() {
+        if let Ok(object) = repository.revparse_single(candidate) {
+            return Ok(object.peel_to_commit()?.id());
+        }
+    }
+
+    bail!("no branch, tag or commit named {}", rev)
+}
+
+/// The tree holding the package described by `spec` at `commit`.
+fn package_tree<'a>(
+    repository: &'a Repository,
+    commit: Oid,
+    spec: &GitSpec,
+) -> anyhow::Result<Tree<'a>> {
+    let tree = repository.find_commit(commit)?.tree()?;
+
+    match &spec.path {
+        Some(path) => {
+            let entry = tree
+                .get_path(Path::new(path))
+                .with_context(|| format!("{} has no directory {}", spec.git, path))?;
+
+            entry
+                .to_object(repository)?
+                .into_tree()
+                .map_err(|_| anyhow!("{} in {} is not a directory", path, spec.git))
+        }
+        None => Ok(tree),
+    }
+}
+
+/// Write the files in `tree` to `output`, skipping anything that isn't a
+/// plain file or directory, like submodules.
+fn write_tree(repository: &Repository, tree: &Tree, output: &Path) -> anyhow::Result<()> {
+    for entry in tree.iter() {
+        let name = entry
+            .name()
+            .ok_or_else(|| format_err!("path in Git repository is not valid Unicode"))?;
+        let path = output.join(name);
+
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                fs_err::create_dir(&path)?;
+                let subtree = entry.to_object(repository)?.peel_to_tree()?;
+                write_tree(repository, &subtree, &path)?;
+            }
+            Some(ObjectType::Blob) => {
+                let blob = entry.to_object(repository)?.peel_to_blob()?;
+                fs_err::write(&path, blob.content())?;
+            }
+            _ => log::debug!("Skipping {} in Git repository", name),
+        }
+    }
+
+    Ok(())
+}
+
+/// Where Wally keeps its copy of the repository at `url`.
+fn checkout_path(cache_dir: &Path, url: &str) -> PathBuf {
+    let repository_name = url
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .rsplit(&['/', '\\', ':'][..])
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or("repository");
+
+    let hash = blake3::hash(url.as_bytes());
+    let hash_hex = hex::encode(&hash.as_bytes()[..8]);
+    let ident = format!("{}-{}", repository_name, hash_hex);
+
+    cache_dir.join("git").join(ident)
+}
@@ -3,13 +3,12 @@
 //! resolution, installation, upgrading, etc.
 
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::format_err;
 
 use crate::{
-    manifest::Manifest, package_id::PackageId, package_req::PackageReq,
+    manifest::Manifest, package_id::PackageId, package_name::PackageName,
     package_source::PackageSource, test_package::PackageBuilder,
 };
 
@@ -32,8 +31,20 @@ impl InMemoryRegistry {
 
     /// Publish a new package to the registry.
     pub fn publish(&self, builder: PackageBuilder) {
-        let mut storage = self.storage.contents.write().unwrap();
         let (manifest, contents) = builder.package();
+        self.publish_entry(manifest, contents);
+    }
+
+    /// Publish a new package to the registry with its checksum recorded in
+    /// the index, like the registry server does.
+    pub fn publish_with_checksum(&self, builder: PackageBuilder) {
+        let (mut manifest, contents) = builder.package();
+        manifest.checksum = Some(contents.checksum());
+        self.publish_entry(manifest, contents);
+    }
+
+    fn publish_entry(&self, manifest: Manifest, contents: PackageContents) {
+        let mut storage = self.storage.contents.write().unwrap();
 
         let scope = storage
             .entry(manifest.package.name.scope().to_owned())
@@ -46,6 +57,30 @@ impl InMemoryRegistry {
         entries.push(PackageEntry { manifest, contents });
     }
 
+    /// Serve different contents for a package that's already published,
+    /// without changing its index entry, like a registry whose storage no
+    /// longer agrees with its index.
+    pub fn replace_contents(&self, package_id: &PackageId, contents: PackageContents) {
+        let mut storage = self.storage.contents.write().unwrap();
+        let entry = storage
+            .get_mut(package_id.name().scope())
+            .and_then(|scope| scope.get_mut(package_id.name().name()))
+            .and_then(|entries| {
+                entries
+                    .iter_mut()
+                    .find(|entry| &entry.manifest.package.version == package_id.version())
+            })
+            .expect("package is not published");
+
+        entry.contents = contents;
+    }
+
+    /// The name of each package whose versions have been listed through a
+    /// source for this registry, once per time it was listed.
+    pub fn listed(&self) -> Vec<PackageName> {
+        self.storage.listed.lock().unwrap().clone()
+    }
+
     /// Returns a handle to an object that can be used as a `PackageSource`.
     pub fn source(&self) -> PackageSource {
         PackageSource::InMemory(InMemoryRegistrySource {
@@ -66,28 +101,21 @@ impl PackageSourceProvider for InMemoryRegistrySource {
         Ok(())
     }
 
-    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+    fn versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        self.storage.listed.lock().unwrap().push(name.clone());
+
         let storage = self.storage.contents.read().unwrap();
-        let scope = match storage.get(package_req.name().scope()) {
+        let scope = match storage.get(name.scope()) {
             Some(scope) => scope,
             None => return Ok(Vec::new()),
         };
 
-        let entries = match scope.get(package_req.name().name()) {
+        let entries = match scope.get(name.name()) {
             Some(entries) => entries,
             None => return Ok(Vec::new()),
         };
 
-        let result = entries
-            .iter()
-            .filter(|entry| {
-                package_req
-                    .version_req()
-                    .matches(&entry.manifest.package.version)
-            })
-            .map(|entry| &entry.manifest)
-            .cloned()
-            .collect();
+        let result = entries.iter().map(|entry| entry.manifest.clone()).collect();
 
         Ok(result)
     }
@@ -123,4 +151,7 @@ struct PackageEntry {
 #[derive(Clone, Default)]
 struct Storage {
     contents: Arc<RwLock<HashMap<String, HashMap<String, Vec<PackageEntry>>>>>,
+
+    /// The name of every package whose versions have been listed, in order.
+    listed: Arc<Mutex<Vec<PackageName>>>,
 }
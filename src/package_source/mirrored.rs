@@ -0,0 +1,115 @@
+//! Defines a package source that sends requests for some scopes to a mirror
+//! instead of the registry it wraps.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::manifest::Manifest;
+use crate::package_contents::PackageContents;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+
+use super::{PackageSource, PackageSourceId, PackageSourceProvider};
+
+/// Wraps a package source, sending requests for packages in a mirrored scope
+/// to that scope's mirror instead.
+///
+/// A mirror takes precedence over the wrapped source for its scope: packages in
+/// that scope are only ever listed and downloaded from the mirror.
+#[derive(Clone)]
+pub struct MirroredSource {
+    source: Box<PackageSource>,
+    mirrors: HashMap<String, PackageSource>,
+}
+
+impl MirroredSource {
+    pub fn new(source: Box<PackageSource>, mirrors: HashMap<String, PackageSource>) -> Self {
+        Self { source, mirrors }
+    }
+
+    fn source_for_scope(&self, scope: &str) -> &PackageSource {
+        self.mirrors.get(scope).unwrap_or(&self.source)
+    }
+}
+
+impl PackageSourceProvider for MirroredSource {
+    fn update(&self) -> anyhow::Result<()> {
+        self.source.update()?;
+
+        for mirror in self.mirrors.values() {
+            mirror.update()?;
+        }
+
+        Ok(())
+    }
+
+    fn versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        self.source_for_scope(name.scope()).versions(name)
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        self.source_for_scope(package_id.name().scope())
+            .download_package(package_id)
+    }
+
+    fn download_package_with_progress(
+        &self,
+        package_id: &PackageId,
+        on_progress: &dyn Fn(u64),
+    ) -> anyhow::Result<PackageContents> {
+        self.source_for_scope(package_id.name().scope())
+            .download_package_with_progress(package_id, on_progress)
+    }
+
+    fn open_package(&self, package_id: &PackageId) -> anyhow::Result<Box<dyn Read + Send>> {
+        self.source_for_scope(package_id.name().scope())
+            .open_package(package_id)
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        self.source.fallback_sources()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use crate::package_req::PackageReq;
+    use crate::package_source::InMemoryRegistry;
+    use crate::test_package::PackageBuilder;
+
+    #[test]
+    fn mirrored_scopes_use_the_mirror() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("acme/rocket@1.0.0"));
+
+        let mirror = InMemoryRegistry::new();
+        mirror.publish(PackageBuilder::new("acme/rocket@2.0.0"));
+
+        let mut mirrors = HashMap::new();
+        mirrors.insert("acme".to_owned(), mirror.source());
+        let source = MirroredSource::new(Box::new(registry.source()), mirrors);
+
+        let versions = |spec: &str| -> Vec<String> {
+            source
+                .query(&PackageReq::from_str(spec).unwrap())
+                .unwrap()
+                .iter()
+                .map(|manifest| manifest.package.version.to_string())
+                .collect()
+        };
+
+        assert_eq!(versions("acme/rocket@*"), vec!["2.0.0"]);
+        assert_eq!(versions("biff/minimal@*"), vec!["1.0.0"]);
+
+        let rocket = PackageId::from_str("acme/rocket@2.0.0").unwrap();
+        assert!(source.download_package(&rocket).is_ok());
+
+        let old_rocket = PackageId::from_str("acme/rocket@1.0.0").unwrap();
+        assert!(source.download_package(&old_rocket).is_err());
+    }
+}
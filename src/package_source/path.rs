@@ -0,0 +1,96 @@
+//! Defines a package source for a single package that lives in a directory on
+//! this machine, like a sibling package in the same monorepo.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use semver::VersionReq;
+
+use crate::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+
+use super::{PackageContents, PackageSourceId, PackageSourceProvider};
+
+/// The package in a local directory, read from disk every time it's needed so
+/// that changes to it are picked up by the next install.
+#[derive(Clone)]
+pub struct PathSource {
+    /// The path to the package as it was written in the dependent manifest.
+    path: String,
+
+    /// Where the package's directory actually is.
+    root: PathBuf,
+
+    /// The manifest of the package.
+    manifest: Arc<Manifest>,
+}
+
+impl PathSource {
+    /// Read the package at `path`, which is relative to `project_path`.
+    pub fn new(project_path: &Path, path: &str) -> anyhow::Result<Self> {
+        let root = project_path.join(path);
+        if !root.join(MANIFEST_FILE_NAME).is_file() {
+            bail!(
+                "The path dependency {} does not contain a {}",
+                path,
+                MANIFEST_FILE_NAME
+            );
+        }
+
+        let manifest = Manifest::load(&root)
+            .with_context(|| format!("invalid {} in {}", MANIFEST_FILE_NAME, path))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            root,
+            manifest: Arc::new(manifest),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// A requirement that only the package in this directory satisfies.
+    pub fn package_req(&self) -> PackageReq {
+        PackageReq::new(
+            self.manifest.package.name.clone(),
+            VersionReq::exact(&self.manifest.package.version),
+        )
+    }
+}
+
+impl PackageSourceProvider for PathSource {
+    fn update(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        if *name == self.manifest.package.name {
+            Ok(vec![Manifest::clone(&self.manifest)])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        if *package_id != self.manifest.package_id() {
+            bail!("{} does not contain package {}", self.path, package_id);
+        }
+
+        // Packing the directory gives path dependencies exactly the files that
+        // publishing them would include.
+        PackageContents::pack_from_path(&self.root)
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        Ok(Vec::new())
+    }
+}
@@ -1,28 +1,45 @@
-use std::io::Read;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use once_cell::sync::OnceCell;
-use reqwest::{blocking::Client, header::AUTHORIZATION};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
 use url::Url;
 
 use crate::auth::AuthStore;
+use crate::global_config::default_cache_dir;
 use crate::manifest::Manifest;
 use crate::package_id::PackageId;
 use crate::package_index::PackageIndex;
-use crate::package_req::PackageReq;
+use crate::package_name::PackageName;
 use crate::package_source::PackageContents;
 
 use super::{PackageSourceId, PackageSourceProvider};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The `User-Agent` sent with every request to a registry's API.
+const USER_AGENT: &str = concat!("wally/", env!("CARGO_PKG_VERSION"));
+
+/// How long a single attempt at downloading a package may take before it's
+/// abandoned, unless another timeout is configured.
+pub const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Clone)]
 pub struct Registry {
     index_url: Url,
     auth_token: OnceCell<Option<Arc<str>>>,
     index: OnceCell<Arc<PackageIndex>>,
     client: Client,
+    offline: bool,
+    download_timeout: Duration,
+    cache_dir: Option<PathBuf>,
 }
 
 impl Registry {
@@ -35,13 +52,73 @@ impl Registry {
             index_url,
             auth_token: OnceCell::new(),
             index: OnceCell::new(),
-            client: Client::new(),
+            client: client(HeaderMap::new())?,
+            offline: false,
+            download_timeout: DEFAULT_DOWNLOAD_TIMEOUT,
+            cache_dir: None,
         })
     }
 
+    /// Only use the copy of the index already on this machine, refusing to
+    /// update it or download packages.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Give up on a package download that hasn't finished after `timeout`.
+    /// Each retry of a download gets the full timeout again.
+    pub fn with_download_timeout(mut self, timeout: Duration) -> Self {
+        self.download_timeout = timeout;
+        self
+    }
+
+    /// Keep the copy of the index in `cache_dir` instead of the default
+    /// cache directory.
+    pub fn with_cache_dir(mut self, cache_dir: PathBuf) -> Self {
+        self.cache_dir = Some(cache_dir);
+        self
+    }
+
+    /// Send `headers`, keyed by name, with every request to the registry's
+    /// API. Their values often carry secrets, so only their names are ever
+    /// logged.
+    pub fn with_headers(mut self, headers: &BTreeMap<String, String>) -> anyhow::Result<Self> {
+        let mut header_map = HeaderMap::new();
+
+        for (name, value) in headers {
+            let header_name = HeaderName::from_bytes(name.as_bytes()).with_context(|| {
+                format!(
+                    "Invalid header name {:?} for registry {}",
+                    name, self.index_url
+                )
+            })?;
+            let mut header_value = HeaderValue::from_str(value).with_context(|| {
+                format!(
+                    "Invalid value for header {} for registry {}",
+                    name, self.index_url
+                )
+            })?;
+            header_value.set_sensitive(true);
+
+            header_map.insert(header_name, header_value);
+        }
+
+        if !header_map.is_empty() {
+            log::debug!(
+                "Sending headers {} to registry {}",
+                headers.keys().cloned().collect::<Vec<_>>().join(", "),
+                self.index_url
+            );
+        }
+
+        self.client = client(header_map)?;
+        Ok(self)
+    }
+
     fn auth_token(&self) -> anyhow::Result<Option<Arc<str>>> {
         self.auth_token
-            .get_or_try_init(|| match AuthStore::get_token(self.api_url()?.as_str())? {
+            .get_or_try_init(|| match AuthStore::get_token_for(&self.api_url()?)? {
                 Some(token) => Ok(Some(Arc::from(token.as_str()))),
                 None => Ok(None),
             })
@@ -49,8 +126,20 @@ impl Registry {
     }
 
     fn index(&self) -> anyhow::Result<&Arc<PackageIndex>> {
-        self.index
-            .get_or_try_init(|| Ok(Arc::new(PackageIndex::new(&self.index_url, None)?)))
+        self.index.get_or_try_init(|| {
+            let cache_dir = match &self.cache_dir {
+                Some(cache_dir) => cache_dir.clone(),
+                None => default_cache_dir()?,
+            };
+
+            let index = if self.offline {
+                PackageIndex::open_offline(&cache_dir, &self.index_url)?
+            } else {
+                PackageIndex::new(&cache_dir, &self.index_url, None)?
+            };
+
+            Ok(Arc::new(index))
+        })
     }
 
     fn api_url(&self) -> anyhow::Result<Url> {
@@ -61,54 +150,62 @@ impl Registry {
 
 impl PackageSourceProvider for Registry {
     fn update(&self) -> anyhow::Result<()> {
+        if self.offline {
+            log::debug!(
+                "Not updating package index {} while offline",
+                self.index_url
+            );
+            return Ok(());
+        }
+
         self.index()?.update()
     }
 
-    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
-        let metadata = self.index()?.get_package_metadata(package_req.name())?;
-        let versions: Vec<_> = metadata
-            .versions
-            .iter()
-            .filter(|manifest| {
-                package_req.matches(&manifest.package.name, &manifest.package.version)
-            })
-            .cloned()
-            .collect();
+    fn versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        let metadata = self.index()?.get_package_metadata(name)?;
 
-        Ok(versions)
+        Ok(metadata.versions.clone())
     }
 
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
-        let path = format!(
-            "/v1/package-contents/{}/{}/{}",
-            package_id.name().scope(),
-            package_id.name().name(),
-            package_id.version()
-        );
+        self.download_package_with_progress(package_id, &|_| {})
+    }
 
-        let url = self.api_url()?.join(&path)?;
+    fn download_package_with_progress(
+        &self,
+        package_id: &PackageId,
+        on_progress: &dyn Fn(u64),
+    ) -> anyhow::Result<PackageContents> {
+        if self.offline {
+            bail!("Cannot download package {} while offline", package_id);
+        }
 
-        let mut request = self.client.get(url).header("Wally-Version", VERSION);
+        let token = self.auth_token()?;
+        download_from_api(
+            &self.client,
+            &self.api_url()?,
+            token.as_deref(),
+            self.download_timeout,
+            package_id,
+            on_progress,
+        )
+    }
 
-        if let Some(token) = self.auth_token()? {
-            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
-        }
-        let mut response = request.send()?;
-
-        if !response.status().is_success() {
-            bail!(
-                "Failed to download package {} from registry: {}\n{} {}",
-                package_id,
-                self.api_url()?,
-                response.status(),
-                response.text()?
-            );
+    fn open_package(&self, package_id: &PackageId) -> anyhow::Result<Box<dyn Read + Send>> {
+        if self.offline {
+            bail!("Cannot download package {} while offline", package_id);
         }
 
-        let mut data = Vec::new();
-        response.read_to_end(&mut data)?;
+        let token = self.auth_token()?;
+        let contents = open_from_api(
+            &self.client,
+            &self.api_url()?,
+            token.as_deref(),
+            self.download_timeout,
+            package_id,
+        )?;
 
-        Ok(PackageContents::from_buffer(data))
+        Ok(Box::new(contents))
     }
 
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
@@ -122,3 +219,546 @@ impl PackageSourceProvider for Registry {
         Ok(sources)
     }
 }
+
+/// An HTTP client for a registry's API that identifies itself as Wally and
+/// sends `headers` with every request.
+fn client(headers: HeaderMap) -> anyhow::Result<Client> {
+    Ok(Client::builder()
+        .user_agent(USER_AGENT)
+        .default_headers(headers)
+        .build()?)
+}
+
+/// Download a package from the registry API at `api`, authenticating with
+/// `token` if there is one, and failing if the download takes longer than
+/// `timeout`.
+fn download_from_api(
+    client: &Client,
+    api: &Url,
+    token: Option<&str>,
+    timeout: Duration,
+    package_id: &PackageId,
+    on_progress: &dyn Fn(u64),
+) -> anyhow::Result<PackageContents> {
+    let mut contents = open_from_api(client, api, token, timeout, package_id)?;
+
+    // The server says how long the response is, but that can't be trusted
+    // with an allocation, so only use it as a hint up to a point.
+    let capacity = contents.response.content_length().unwrap_or(0).min(1 << 20);
+    let mut data = Vec::with_capacity(capacity as usize);
+    let mut buffer = [0; 8192];
+
+    loop {
+        let read = contents.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        data.extend_from_slice(&buffer[..read]);
+        on_progress(read as u64);
+    }
+
+    Ok(PackageContents::from_buffer(data))
+}
+
+/// Start downloading a package, returning once the registry has responded
+/// without waiting for the package's contents to arrive.
+fn open_from_api(
+    client: &Client,
+    api: &Url,
+    token: Option<&str>,
+    timeout: Duration,
+    package_id: &PackageId,
+) -> anyhow::Result<ContentsReader> {
+    let response = request_contents(client, api, token, timeout, package_id).map_err(|err| {
+        if is_timeout(&err) {
+            err.context(timed_out_message(timeout, package_id, api))
+        } else {
+            err
+        }
+    })?;
+
+    Ok(ContentsReader {
+        response,
+        timeout,
+        package_id: package_id.clone(),
+        api: api.clone(),
+    })
+}
+
+fn request_contents(
+    client: &Client,
+    api: &Url,
+    token: Option<&str>,
+    timeout: Duration,
+    package_id: &PackageId,
+) -> anyhow::Result<Response> {
+    let path = format!(
+        "/v1/package-contents/{}/{}/{}",
+        package_id.name().scope(),
+        package_id.name().name(),
+        package_id.version()
+    );
+
+    let url = api.join(&path)?;
+
+    let mut request = client
+        .get(url)
+        .header("Wally-Version", VERSION)
+        .timeout(timeout);
+
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send()?;
+
+    let status = response.status();
+    if !status.is_success() {
+        bail!(RegistryStatusError {
+            package_id: package_id.clone(),
+            api: api.clone(),
+            status,
+            body: response.text()?,
+        });
+    }
+
+    Ok(response)
+}
+
+/// A registry responded to a package download with an error status.
+#[derive(Debug)]
+struct RegistryStatusError {
+    package_id: PackageId,
+    api: Url,
+    status: StatusCode,
+    body: String,
+}
+
+impl RegistryStatusError {
+    /// Whether the registry might succeed if asked again: it's overloaded,
+    /// failing on its end, or asking clients to slow down.
+    fn is_transient(&self) -> bool {
+        self.status.is_server_error() || self.status == StatusCode::TOO_MANY_REQUESTS
+    }
+}
+
+impl fmt::Display for RegistryStatusError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "Failed to download package {} from registry: {}\n{} {}",
+            self.package_id, self.api, self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for RegistryStatusError {}
+
+fn timed_out_message(timeout: Duration, package_id: &PackageId, api: &Url) -> String {
+    format!(
+        "Timed out after {:?} downloading package {} from registry {}",
+        timeout, package_id, api
+    )
+}
+
+/// The contents of a package as they arrive from a registry. Reading them
+/// fails with an error saying which package timed out if the download takes
+/// too long.
+struct ContentsReader {
+    response: Response,
+    timeout: Duration,
+    package_id: PackageId,
+    api: Url,
+}
+
+impl Read for ContentsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.response.read(buf).map_err(|err| {
+            if is_timeout_cause(&err) {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    DownloadTimedOut {
+                        message: timed_out_message(self.timeout, &self.package_id, &self.api),
+                        source: err,
+                    },
+                )
+            } else {
+                err
+            }
+        })
+    }
+}
+
+/// An error reading a package's contents because the download took too long.
+#[derive(Debug)]
+struct DownloadTimedOut {
+    message: String,
+    source: io::Error,
+}
+
+impl fmt::Display for DownloadTimedOut {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for DownloadTimedOut {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Whether a download failed because it took too long.
+fn is_timeout(err: &anyhow::Error) -> bool {
+    err.chain().any(is_timeout_cause)
+}
+
+/// Whether this error in a chain is a failure talking to a registry that might
+/// not happen again: an error status that the registry may recover from, a
+/// connection that couldn't be made or dropped partway through, or a timeout.
+pub(crate) fn is_transient_cause(cause: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(err) = cause.downcast_ref::<RegistryStatusError>() {
+        return err.is_transient();
+    }
+
+    if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+        return is_transient_request_error(err);
+    }
+
+    // Errors reading the response body come back as I/O errors wrapping the
+    // HTTP client's error. Other I/O errors, like a full disk, are local and
+    // would only happen again.
+    match cause.downcast_ref::<io::Error>() {
+        Some(err) => match err.get_ref() {
+            Some(inner) => {
+                inner.is::<DownloadTimedOut>()
+                    || inner
+                        .downcast_ref::<reqwest::Error>()
+                        .is_some_and(is_transient_request_error)
+            }
+            None => false,
+        },
+        None => false,
+    }
+}
+
+fn is_transient_request_error(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout() || err.is_body()
+}
+
+/// Whether this error in a chain means a download took too long. Timeouts
+/// while reading the response body come back as I/O errors wrapping the HTTP
+/// client's error.
+fn is_timeout_cause(cause: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+        return err.is_timeout();
+    }
+
+    match cause.downcast_ref::<io::Error>() {
+        Some(err) => {
+            err.kind() == io::ErrorKind::TimedOut
+                || err
+                    .get_ref()
+                    .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+                    .is_some_and(|inner| inner.is_timeout())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::str::FromStr;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Read the head of an HTTP request, which is all that these mock
+    /// registries look at.
+    fn read_request(stream: &mut TcpStream) -> Vec<u8> {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).unwrap();
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+        }
+
+        request
+    }
+
+    /// Start a registry API on a local port that only serves packages to
+    /// requests authorized with `token`.
+    fn mock_registry(token: &'static str) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&mut stream);
+
+                let expected = format!("authorization: bearer {}", token);
+                let authorized = String::from_utf8_lossy(&request)
+                    .lines()
+                    .any(|line| line.to_lowercase() == expected);
+
+                let response: &[u8] = if authorized {
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\nConnection: close\r\n\r\ncontents"
+                } else {
+                    b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                stream.write_all(response).unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn each_registry_gets_its_own_token() {
+        let public = mock_registry("public-token");
+        let internal = mock_registry("internal-token");
+
+        let auth = AuthStore {
+            tokens: HashMap::from([(
+                format!(
+                    "{}:{}",
+                    internal.host_str().unwrap(),
+                    internal.port().unwrap()
+                ),
+                "internal-token".to_owned(),
+            )]),
+            default_token: Some("public-token".to_owned()),
+        };
+
+        let client = Client::new();
+        let package_id = PackageId::from_str("biff/minimal@0.1.0").unwrap();
+
+        for api in [&public, &internal].iter() {
+            let contents = download_from_api(
+                &client,
+                api,
+                auth.token_for(api),
+                DEFAULT_DOWNLOAD_TIMEOUT,
+                &package_id,
+                &|_| {},
+            )
+            .unwrap();
+            assert_eq!(contents.data(), b"contents");
+        }
+
+        let err = download_from_api(
+            &client,
+            &internal,
+            auth.token_for(&public),
+            DEFAULT_DOWNLOAD_TIMEOUT,
+            &package_id,
+            &|_| {},
+        )
+        .map(|_| ())
+        .unwrap_err();
+        assert!(err.to_string().contains("401"), "{}", err);
+    }
+
+    /// Start a registry API on a local port that serves every request, sending
+    /// the head of each one to the returned receiver.
+    fn recording_registry() -> (Url, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&mut stream);
+                sender
+                    .send(String::from_utf8_lossy(&request).to_lowercase())
+                    .unwrap();
+
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\nConnection: close\r\n\r\ncontents",
+                    )
+                    .unwrap();
+            }
+        });
+
+        (url, receiver)
+    }
+
+    #[test]
+    fn requests_carry_user_agent_and_configured_headers() {
+        let (api, requests) = recording_registry();
+        let registry = Registry::from_registry_spec("https://example.com/index")
+            .unwrap()
+            .with_headers(&BTreeMap::from([(
+                "X-Api-Key".to_owned(),
+                "secret".to_owned(),
+            )]))
+            .unwrap();
+        let package_id = PackageId::from_str("biff/minimal@0.1.0").unwrap();
+
+        download_from_api(
+            &registry.client,
+            &api,
+            None,
+            DEFAULT_DOWNLOAD_TIMEOUT,
+            &package_id,
+            &|_| {},
+        )
+        .unwrap();
+
+        let request = requests.recv().unwrap();
+        let lines: Vec<&str> = request.lines().collect();
+        assert!(
+            lines.contains(&format!("user-agent: wally/{}", VERSION).as_str()),
+            "{}",
+            request
+        );
+        assert!(lines.contains(&"x-api-key: secret"), "{}", request);
+    }
+
+    #[test]
+    fn invalid_header_names_are_rejected() {
+        let err = Registry::from_registry_spec("https://example.com/index")
+            .unwrap()
+            .with_headers(&BTreeMap::from([(
+                "Bad Name".to_owned(),
+                "value".to_owned(),
+            )]))
+            .map(|_| ())
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Invalid header name \"Bad Name\" for registry https://example.com/index"
+        );
+    }
+
+    /// Start a registry API on a local port that starts sending a package but
+    /// never finishes.
+    fn stalled_registry() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        thread::spawn(move || {
+            let mut streams = Vec::new();
+
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                read_request(&mut stream);
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\ncontents")
+                    .unwrap();
+
+                // Keep the connection open without sending anything else.
+                streams.push(stream);
+            }
+        });
+
+        url
+    }
+
+    /// Start a registry API on a local port that responds to every request
+    /// with `status`.
+    fn failing_registry(status: &'static str) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                read_request(&mut stream);
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        url
+    }
+
+    fn download_error(api: &Url) -> anyhow::Error {
+        let package_id = PackageId::from_str("biff/minimal@0.1.0").unwrap();
+
+        download_from_api(
+            &Client::new(),
+            api,
+            None,
+            DEFAULT_DOWNLOAD_TIMEOUT,
+            &package_id,
+            &|_| {},
+        )
+        .map(|_| ())
+        .unwrap_err()
+    }
+
+    #[test]
+    fn server_errors_and_rate_limits_are_transient() {
+        for status in [
+            "500 Internal Server Error",
+            "503 Service Unavailable",
+            "429 Too Many Requests",
+        ] {
+            let err = download_error(&failing_registry(status));
+            assert!(err.chain().any(is_transient_cause), "{}: {:#}", status, err);
+        }
+
+        for status in ["404 Not Found", "401 Unauthorized"] {
+            let err = download_error(&failing_registry(status));
+            assert!(
+                !err.chain().any(is_transient_cause),
+                "{}: {:#}",
+                status,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn refused_connections_are_transient() {
+        // Nothing listens on a port once its listener is dropped.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        drop(listener);
+
+        let err = download_error(&api);
+        assert!(err.chain().any(is_transient_cause), "{:#}", err);
+    }
+
+    #[test]
+    fn stalled_downloads_time_out() {
+        let api = stalled_registry();
+        let package_id = PackageId::from_str("biff/minimal@0.1.0").unwrap();
+
+        let err = download_from_api(
+            &Client::new(),
+            &api,
+            None,
+            Duration::from_millis(200),
+            &package_id,
+            &|_| {},
+        )
+        .map(|_| ())
+        .unwrap_err();
+
+        assert!(is_timeout(&err), "{:#}", err);
+        assert!(err.chain().any(is_transient_cause), "{:#}", err);
+        assert!(
+            err.to_string()
+                .starts_with("Timed out after 200ms downloading package biff/minimal@0.1.0"),
+            "{}",
+            err
+        );
+    }
+}
@@ -1,13 +1,13 @@
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use std::path::PathBuf;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use fs_err::File;
 
 use crate::manifest::Manifest;
 use crate::package_id::PackageId;
 use crate::package_index::PackageIndexConfig;
-use crate::package_req::PackageReq;
+use crate::package_name::PackageName;
 use crate::package_source::PackageContents;
 
 use super::{PackageSourceId, PackageSourceProvider};
@@ -15,11 +15,22 @@ use super::{PackageSourceId, PackageSourceProvider};
 #[derive(Clone)]
 pub struct TestRegistry {
     path: PathBuf,
+    offline: bool,
 }
 
 impl TestRegistry {
     pub fn new<P: Into<PathBuf>>(path: P) -> Self {
-        Self { path: path.into() }
+        Self {
+            path: path.into(),
+            offline: false,
+        }
+    }
+
+    /// Refuse to download packages, as a real registry would while offline.
+    /// The index can still be queried, standing in for a local copy of it.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
     }
 }
 
@@ -28,20 +39,20 @@ impl PackageSourceProvider for TestRegistry {
         Ok(())
     }
 
-    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+    fn versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
         // Each package has all of its versions stored in a folder based on its
         // scope and name.
         let mut package_path = self.path.clone();
         package_path.push("index");
-        package_path.push(package_req.name().scope());
-        package_path.push(package_req.name().name());
+        package_path.push(name.scope());
+        package_path.push(name.name());
 
         // Construct a buffered file reader, with a nice error message in the
         // event of failure. We might want to return a structured error from
         // this method in the future to distinguish between general I/O errors
         // and a package not existing.
         let file = File::open(&package_path)
-            .with_context(|| format!("could not open package {} from index", package_req.name()))?;
+            .with_context(|| format!("could not open package {} from index", name))?;
         let file = BufReader::new(file);
 
         // Read all of the manifests from the package file.
@@ -51,34 +62,34 @@ impl PackageSourceProvider for TestRegistry {
         let manifest_stream: Result<Vec<Manifest>, serde_json::Error> =
             serde_json::Deserializer::from_reader(file)
                 .into_iter::<Manifest>()
-                .filter(|manifest| {
-                    if let Ok(manifest) = manifest {
-                        package_req.matches(&manifest.package.name, &manifest.package.version)
-                    } else {
-                        true
-                    }
-                })
                 .collect();
 
-        let versions = manifest_stream.with_context(|| {
-            format!(
-                "could not parse package index entry for {}",
-                package_req.name()
-            )
-        })?;
+        let versions = manifest_stream
+            .with_context(|| format!("could not parse package index entry for {}", name))?;
 
         Ok(versions)
     }
 
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        let mut data = Vec::new();
+        self.open_package(package_id)?.read_to_end(&mut data)?;
+
+        Ok(PackageContents::from_buffer(data))
+    }
+
+    fn open_package(&self, package_id: &PackageId) -> anyhow::Result<Box<dyn Read + Send>> {
+        if self.offline {
+            bail!("Cannot download package {} while offline", package_id);
+        }
+
         let mut package_path = self.path.clone();
         package_path.push("contents");
         package_path.push(package_id.name().scope());
         package_path.push(package_id.name().name());
         package_path.push(format!("{}.zip", package_id.version()));
 
-        let data = fs_err::read(&package_path)?;
-        Ok(PackageContents::from_buffer(data))
+        let file = File::open(&package_path)?;
+        Ok(Box::new(BufReader::new(file)))
     }
 
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
@@ -1,13 +1,18 @@
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 use anyhow::bail;
 use anyhow::format_err;
 use semver::Version;
 use serde::Serialize;
 
-use crate::manifest::{Manifest, Realm};
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::{Manifest, PatchSource, Realm};
 use crate::package_id::PackageId;
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 use crate::package_source::{PackageSourceId, PackageSourceMap, PackageSourceProvider};
 
@@ -36,6 +41,32 @@ pub struct Resolve {
 }
 
 impl Resolve {
+    /// A copy of this resolve without the root package's dev dependencies or
+    /// any packages that are only depended on through them. Packages that are
+    /// also needed outside of dev dependencies are kept.
+    pub fn without_dev_dependencies(&self) -> Resolve {
+        let mut resolve = self.clone();
+
+        let dev_only: Vec<PackageId> = resolve
+            .metadata
+            .iter()
+            .filter(|(_, metadata)| metadata.origin_realm == Realm::Dev)
+            .map(|(package_id, _)| package_id.clone())
+            .collect();
+
+        for package_id in &dev_only {
+            resolve.activated.remove(package_id);
+            resolve.metadata.remove(package_id);
+            resolve.shared_dependencies.remove(package_id);
+            resolve.server_dependencies.remove(package_id);
+        }
+
+        // Only the root package's dev dependencies are ever part of the graph.
+        resolve.dev_dependencies.clear();
+
+        resolve
+    }
+
     fn activate(&mut self, source: PackageId, dep_name: String, dep_realm: Realm, dep: PackageId) {
         self.activated.insert(dep.clone());
 
@@ -46,6 +77,64 @@ impl Resolve {
         };
         dependencies.insert(dep_name, dep);
     }
+
+    /// A chain of packages that depend on each other in a loop, starting and
+    /// ending with the same package, if the graph has one.
+    pub fn find_cycle(&self) -> Option<Vec<PackageId>> {
+        let mut path = Vec::new();
+        let mut finished = BTreeSet::new();
+
+        self.activated
+            .iter()
+            .find_map(|package_id| self.find_cycle_from(package_id, &mut path, &mut finished))
+    }
+
+    fn find_cycle_from(
+        &self,
+        package_id: &PackageId,
+        path: &mut Vec<PackageId>,
+        finished: &mut BTreeSet<PackageId>,
+    ) -> Option<Vec<PackageId>> {
+        if let Some(start) = path.iter().position(|visiting| visiting == package_id) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(package_id.clone());
+            return Some(cycle);
+        }
+
+        if finished.contains(package_id) {
+            return None;
+        }
+
+        path.push(package_id.clone());
+
+        for dependencies in [
+            &self.shared_dependencies,
+            &self.server_dependencies,
+            &self.dev_dependencies,
+        ] {
+            for dependency_id in dependencies
+                .get(package_id)
+                .into_iter()
+                .flat_map(|d| d.values())
+            {
+                if let Some(cycle) = self.find_cycle_from(dependency_id, path, finished) {
+                    return Some(cycle);
+                }
+            }
+        }
+
+        path.pop();
+        finished.insert(package_id.clone());
+        None
+    }
+
+    /// Fail if any packages in the graph depend on each other in a loop.
+    fn check_for_cycles(&self) -> anyhow::Result<()> {
+        match self.find_cycle() {
+            Some(packages) => Err(ResolveError::Cycle { packages }.into()),
+            None => Ok(()),
+        }
+    }
 }
 
 /// A single node in the package resolution graph.
@@ -57,6 +146,16 @@ pub struct ResolvePackageMetadata {
     pub realm: Realm,
     pub origin_realm: Realm,
     pub source_registry: PackageSourceId,
+
+    /// The commit that a package from a Git repository was checked out at.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+
+    /// The checksum that the registry's index recorded for the package when
+    /// it was resolved, which the package must still match when it's
+    /// downloaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
 }
 
 pub fn resolve(
@@ -64,7 +163,21 @@ pub fn resolve(
     try_to_use: &BTreeSet<PackageId>,
     package_sources: &PackageSourceMap,
 ) -> anyhow::Result<Resolve> {
+    resolve_with_explanation(root_manifest, try_to_use, package_sources)
+        .map(|(resolve, _)| resolve)
+}
+
+/// Resolve like `resolve`, also returning how each package in the graph was
+/// chosen.
+pub fn resolve_with_explanation(
+    root_manifest: &Manifest,
+    try_to_use: &BTreeSet<PackageId>,
+    package_sources: &PackageSourceMap,
+) -> anyhow::Result<(Resolve, ResolutionExplanation)> {
+    root_manifest.check_aliases()?;
+
     let mut resolve = Resolve::default();
+    let mut explanation = ResolutionExplanation::default();
 
     // Insert root project into graph and activated dependencies, as it'll
     // always be present.
@@ -75,39 +188,82 @@ pub fn resolve(
             realm: root_manifest.package.realm,
             origin_realm: root_manifest.package.realm,
             source_registry: PackageSourceId::DefaultRegistry,
+            git_commit: None,
+            checksum: None,
         },
     );
 
+    let patches = patched_packages(root_manifest, package_sources)?;
+    let listings = VersionListings::new(package_sources);
+
     // Queue of all dependency requests that need to be resolved.
     let mut packages_to_visit = VecDeque::new();
 
+    // Every requirement that each activated package was selected for, so that
+    // conflicts can say where the packages in them came from.
+    let mut requirements: BTreeMap<PackageId, Vec<Requirement>> = BTreeMap::new();
+
     for (alias, req) in &root_manifest.dependencies {
+        let realm = root_manifest.dependency_realm(Realm::Shared, alias);
         packages_to_visit.push_back(DependencyRequest {
             request_source: root_manifest.package_id(),
-            request_realm: Realm::Shared,
-            origin_realm: Realm::Shared,
+            request_realm: realm,
+            origin_realm: realm,
             package_alias: alias.clone(),
             package_req: req.clone(),
+            source: None,
         });
     }
 
     for (alias, req) in &root_manifest.server_dependencies {
+        let realm = root_manifest.dependency_realm(Realm::Server, alias);
         packages_to_visit.push_back(DependencyRequest {
             request_source: root_manifest.package_id(),
-            request_realm: Realm::Server,
-            origin_realm: Realm::Server,
+            request_realm: realm,
+            origin_realm: realm,
             package_alias: alias.clone(),
             package_req: req.clone(),
+            source: None,
         });
     }
 
     for (alias, req) in &root_manifest.dev_dependencies {
+        let realm = root_manifest.dependency_realm(Realm::Dev, alias);
         packages_to_visit.push_back(DependencyRequest {
             request_source: root_manifest.package_id(),
-            request_realm: Realm::Dev,
-            origin_realm: Realm::Dev,
+            request_realm: realm,
+            origin_realm: realm,
             package_alias: alias.clone(),
             package_req: req.clone(),
+            source: None,
+        });
+    }
+
+    for (alias, dependency) in &root_manifest.git_dependencies {
+        let realm = root_manifest.dependency_realm(dependency.table_realm, alias);
+        let git_source = package_sources.git_source(&dependency.source)?;
+
+        packages_to_visit.push_back(DependencyRequest {
+            request_source: root_manifest.package_id(),
+            request_realm: realm,
+            origin_realm: realm,
+            package_alias: alias.clone(),
+            package_req: git_source.package_req(),
+            source: Some(PackageSourceId::GitRepository(dependency.source.clone())),
+        });
+    }
+
+    for (alias, dependency) in &root_manifest.path_dependencies {
+        let realm = root_manifest.dependency_realm(dependency.table_realm, alias);
+        let path_source = package_sources.path_source(&dependency.path)?;
+
+        packages_to_visit.push_back(DependencyRequest {
+            request_source: root_manifest.package_id(),
+            request_realm: realm,
+            origin_realm: realm,
+            package_alias: alias.clone(),
+            package_req: path_source.package_req(),
+            source: Some(PackageSourceId::LocalPath(dependency.path.clone())),
         });
     }
 
@@ -143,16 +299,16 @@ pub fn resolve(
                 // if they usually belong to another realm. Likewise we want to keep shared
                 // dependencies in the server realm unless they are explicitly required as a
                 // shared dependency.
-                let realm_match = match (metadata.origin_realm, dependency_request.origin_realm) {
-                    (_, Realm::Shared) => Realm::Shared,
-                    (Realm::Shared, _) => Realm::Shared,
-                    (_, Realm::Server) => Realm::Server,
-                    (Realm::Server, _) => Realm::Server,
-                    (Realm::Dev, Realm::Dev) => Realm::Dev,
-                };
+                let realm_match =
+                    merge_origin_realms(metadata.origin_realm, dependency_request.origin_realm);
 
                 metadata.origin_realm = realm_match;
 
+                requirements
+                    .entry(package_id.clone())
+                    .or_default()
+                    .push(dependency_request.requirement());
+
                 resolve.activate(
                     dependency_request.request_source.clone(),
                     dependency_request.package_alias.clone(),
@@ -164,26 +320,58 @@ pub fn resolve(
             }
         }
 
-        // Look through all our packages sources in order of priority
-        let (source_registry, mut candidates) = package_sources
-            .source_order()
-            .iter()
-            .find_map(|source| {
-                let registry = package_sources.get(source).unwrap();
-
-                // Pull all of the possible candidate versions of the package we're
-                // looking for from the highest priority source which has them.
-                match registry.query(&dependency_request.package_req) {
-                    Ok(manifests) => Some((source, manifests)),
-                    Err(_) => None,
-                }
-            })
-            .ok_or_else(|| {
-                format_err!(
-                    "Failed to find a source for {}",
-                    dependency_request.package_req
-                )
-            })?;
+        let patch = match &dependency_request.source {
+            Some(_) => None,
+            None => patches.get(dependency_request.package_req.name()),
+        };
+
+        // Listing versions is the slow part of resolving, so when one package
+        // needs to be listed, every package waiting to be visited is listed
+        // along with it.
+        let needs_listing = |request: &DependencyRequest| {
+            request.source.is_none() && !patches.contains_key(request.package_req.name())
+        };
+
+        if needs_listing(&dependency_request)
+            && !listings.is_listed(dependency_request.package_req.name())
+        {
+            let names = packages_to_visit
+                .iter()
+                .chain(Some(&dependency_request))
+                .filter(|request| needs_listing(request))
+                .map(|request| request.package_req.name().clone())
+                .collect();
+
+            listings.prefetch(names);
+        }
+
+        let source = dependency_request
+            .source
+            .as_ref()
+            .or_else(|| patch.map(|(source, _)| source));
+
+        let (source_registry, mut candidates) = match source {
+            // Dependencies on a Git repository or a local directory, and
+            // patched packages, only ever come from it.
+            Some(source) => {
+                let registry = package_sources.get(source).ok_or_else(|| {
+                    format_err!("Failed to find a source for {}", dependency_request.package_req)
+                })?;
+
+                (source, registry.query(&dependency_request.package_req)?)
+            }
+
+            // Pull all of the possible candidate versions of the package we're
+            // looking for from the highest priority source which has them.
+            None => listings
+                .query(&dependency_request.package_req)
+                .ok_or_else(|| {
+                    format_err!(
+                        "Failed to find a source for {}",
+                        dependency_request.package_req
+                    )
+                })?,
+        };
 
         // Sort our candidate packages by descending version, so that we try the
         // highest versions first.
@@ -202,13 +390,19 @@ pub fn resolve(
             }
         });
 
-        let filtered_candidates = candidates.iter().filter(|candidate| {
-            Realm::is_dependency_valid(dependency_request.request_realm, candidate.package.realm)
-        });
+        let filtered_candidates: Vec<&Manifest> = candidates
+            .iter()
+            .filter(|candidate| {
+                Realm::is_dependency_valid(
+                    dependency_request.request_realm,
+                    candidate.package.realm,
+                )
+            })
+            .collect();
 
         let mut conflicting = Vec::new();
 
-        for candidate in filtered_candidates {
+        for &candidate in &filtered_candidates {
             // Conflicts occur if two packages are SemVer compatible. We choose
             // to only allow one compatible copy of a given package to prevent
             // common user errors.
@@ -232,6 +426,55 @@ pub fn resolve(
                 candidate.package.version.clone(),
             );
 
+            if !candidate.git_dependencies.is_empty() {
+                bail!(
+                    "{} depends on packages from Git repositories, which is only supported for \
+                     the project being installed",
+                    candidate_id
+                );
+            }
+
+            if !candidate.path_dependencies.is_empty() {
+                bail!(
+                    "{} depends on packages by path, which is only supported for the project \
+                     being installed",
+                    candidate_id
+                );
+            }
+
+            let git_commit = match source_registry {
+                PackageSourceId::GitRepository(spec) => {
+                    Some(package_sources.git_source(spec)?.commit())
+                }
+                _ => None,
+            };
+
+            let reason = match source_registry {
+                _ if patch.is_some() => SelectionReason::Patched,
+                PackageSourceId::GitRepository(_) => SelectionReason::GitRepository,
+                PackageSourceId::LocalPath(_) => SelectionReason::Path,
+                _ if try_to_use.contains(&candidate_id) => SelectionReason::Locked,
+                _ if conflicting.is_empty() => SelectionReason::Newest,
+                _ => SelectionReason::AvoidsConflict,
+            };
+
+            explanation.packages.insert(
+                candidate_id.clone(),
+                PackageExplanation {
+                    candidates: filtered_candidates
+                        .iter()
+                        .map(|candidate| candidate.package.version.clone())
+                        .collect(),
+                    requirements: Vec::new(),
+                    reason,
+                },
+            );
+
+            requirements
+                .entry(candidate_id.clone())
+                .or_default()
+                .push(dependency_request.requirement());
+
             resolve.activate(
                 dependency_request.request_source.clone(),
                 dependency_request.package_alias.to_owned(),
@@ -245,6 +488,8 @@ pub fn resolve(
                     realm: candidate.package.realm,
                     origin_realm: dependency_request.origin_realm,
                     source_registry: source_registry.clone(),
+                    git_commit,
+                    checksum: candidate.checksum.clone(),
                 },
             );
 
@@ -255,6 +500,7 @@ pub fn resolve(
                     origin_realm: dependency_request.origin_realm,
                     package_alias: alias.clone(),
                     package_req: req.clone(),
+                    source: None,
                 })
             }
 
@@ -265,40 +511,535 @@ pub fn resolve(
                     origin_realm: dependency_request.origin_realm,
                     package_alias: alias.clone(),
                     package_req: req.clone(),
+                    source: None,
                 })
             }
 
             continue 'outer;
         }
 
-        if conflicting.is_empty() {
-            bail!(
-                "No packages were found that matched ({req_realm:?}) {req}.\nAre you sure this is \
-                 a {req_realm:?} dependency?",
-                req_realm = dependency_request.request_realm,
-                req = dependency_request.package_req,
-            );
+        if let (Some((_, patched)), true) = (patch, conflicting.is_empty()) {
+            return Err(ResolveError::PatchMismatch {
+                patched: patched.clone(),
+                requirement: dependency_request.requirement(),
+            }
+            .into());
+        } else if conflicting.is_empty() {
+            return Err(ResolveError::NoMatchingVersions {
+                requirement: dependency_request.requirement(),
+            }
+            .into());
         } else {
-            let conflicting_debug: Vec<_> = conflicting
+            let selected: Vec<PackageId> = matching_activated
                 .into_iter()
-                .map(|id| format!("{:?}", id))
+                .filter(|activated| {
+                    conflicting
+                        .iter()
+                        .any(|candidate| compatible(candidate.version(), activated.version()))
+                })
+                .collect();
+
+            let mut conflict_requirements: Vec<Requirement> = selected
+                .iter()
+                .flat_map(|package_id| requirements.remove(package_id).unwrap_or_default())
+                .collect();
+            conflict_requirements.push(dependency_request.requirement());
+
+            return Err(ResolveError::Conflict {
+                package: dependency_request.package_req.name().clone(),
+                selected,
+                requirements: conflict_requirements,
+            }
+            .into());
+        }
+    }
+
+    resolve.check_for_cycles()?;
+
+    for (package_id, package) in &mut explanation.packages {
+        package.requirements = requirements.remove(package_id).unwrap_or_default();
+    }
+
+    Ok((resolve, explanation))
+}
+
+/// How `resolve_with_explanation` chose each package in the graph besides the
+/// root, for working out why a particular version was installed.
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct ResolutionExplanation {
+    pub packages: BTreeMap<PackageId, PackageExplanation>,
+}
+
+/// How one package in the graph was chosen.
+#[derive(Debug, Serialize, Clone)]
+pub struct PackageExplanation {
+    /// The versions that satisfied the requirement the package was first
+    /// selected for, in the order they were tried.
+    pub candidates: Vec<Version>,
+
+    /// Every requirement that this package was selected for.
+    pub requirements: Vec<Requirement>,
+
+    pub reason: SelectionReason,
+}
+
+/// Why a version was chosen over the other candidates.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionReason {
+    /// It was in the lockfile, and locked versions are tried first.
+    Locked,
+
+    /// It's the newest version that matches.
+    Newest,
+
+    /// Newer versions that match were SemVer compatible with a version that
+    /// was already selected.
+    AvoidsConflict,
+
+    /// The root manifest patches the package with it.
+    Patched,
+
+    /// It's the only version in the Git repository that was depended on.
+    GitRepository,
+
+    /// It's the only version in the directory that was depended on.
+    Path,
+}
+
+impl fmt::Display for SelectionReason {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            SelectionReason::Locked => "it is in the lockfile",
+            SelectionReason::Newest => "it is the newest version that matches",
+            SelectionReason::AvoidsConflict => {
+                "newer versions conflict with a version that was already selected"
+            }
+            SelectionReason::Patched => "the package is patched with it",
+            SelectionReason::GitRepository => "it is the version in the Git repository",
+            SelectionReason::Path => "it is the version in the dependency's directory",
+        };
+
+        formatter.write_str(reason)
+    }
+}
+
+impl fmt::Display for ResolutionExplanation {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        for (package_id, package) in &self.packages {
+            let candidates: Vec<_> = package.candidates.iter().map(ToString::to_string).collect();
+
+            writeln!(formatter, "{}", package_id)?;
+            writeln!(formatter, "  Candidates: {}", candidates.join(", "))?;
+            writeln!(formatter, "  Requirements:")?;
+
+            for requirement in &package.requirements {
+                writeln!(
+                    formatter,
+                    "    {} requires {} ({:?}, as {})",
+                    requirement.dependent,
+                    requirement.requirement,
+                    requirement.realm,
+                    requirement.alias
+                )?;
+            }
+
+            writeln!(formatter, "  Chose {} because {}", package_id.version(), package.reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The most packages that are listed at once while resolving.
+const MAX_CONCURRENT_LISTINGS: usize = 8;
+
+/// The versions of a package in one source, or `None` if the source couldn't
+/// list the package.
+type Listing = Option<Arc<Vec<Manifest>>>;
+
+/// Every version of each package that package sources have listed during a
+/// resolve, so that packages depended on by several packages are only listed
+/// once.
+pub(crate) struct VersionListings<'a> {
+    package_sources: &'a PackageSourceMap,
+    listings: Mutex<HashMap<(PackageSourceId, PackageName), Listing>>,
+}
+
+impl<'a> VersionListings<'a> {
+    pub(crate) fn new(package_sources: &'a PackageSourceMap) -> Self {
+        Self {
+            package_sources,
+            listings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The versions that match `package_req` from the highest priority
+    /// source that has any. If none of them do, the highest priority source
+    /// that can list the package is returned with no versions.
+    pub(crate) fn query(
+        &self,
+        package_req: &PackageReq,
+    ) -> Option<(&'a PackageSourceId, Vec<Manifest>)> {
+        let mut first_listed = None;
+
+        for source in self.package_sources.source_order() {
+            let versions = match self.list(source, package_req.name()) {
+                Some(versions) => versions,
+                None => continue,
+            };
+
+            let matching: Vec<Manifest> = versions
+                .iter()
+                .filter(|manifest| {
+                    package_req.matches(&manifest.package.name, &manifest.package.version)
+                })
+                .cloned()
                 .collect();
 
+            if !matching.is_empty() {
+                return Some((source, matching));
+            }
+
+            first_listed.get_or_insert(source);
+        }
+
+        first_listed.map(|source| (source, Vec::new()))
+    }
+
+    /// Every version of a package from the highest priority source that can
+    /// list it.
+    fn listing(&self, name: &PackageName) -> Option<(&'a PackageSourceId, Arc<Vec<Manifest>>)> {
+        self.package_sources
+            .source_order()
+            .iter()
+            .find_map(|source| Some((source, self.list(source, name)?)))
+    }
+
+    fn list(&self, source: &PackageSourceId, name: &PackageName) -> Listing {
+        let key = (source.clone(), name.clone());
+        if let Some(listing) = self.listings.lock().unwrap().get(&key) {
+            return listing.clone();
+        }
+
+        let registry = self.package_sources.get(source).unwrap();
+        let listing = registry.versions(name).ok().map(Arc::new);
+        self.listings.lock().unwrap().insert(key, listing.clone());
+
+        listing
+    }
+
+    /// Whether `listing` can find the package without listing it again.
+    fn is_listed(&self, name: &PackageName) -> bool {
+        let listings = self.listings.lock().unwrap();
+
+        for source in self.package_sources.source_order() {
+            match listings.get(&(source.clone(), name.clone())) {
+                Some(Some(_)) => return true,
+                Some(None) => continue,
+                None => return false,
+            }
+        }
+
+        true
+    }
+
+    /// List every package in `names` that hasn't been listed yet, several at
+    /// a time.
+    fn prefetch(&self, names: BTreeSet<PackageName>) {
+        let pending: Vec<_> = names
+            .into_iter()
+            .filter(|name| !self.is_listed(name))
+            .collect();
+        let workers = pending.len().min(MAX_CONCURRENT_LISTINGS);
+        let pending = Mutex::new(pending);
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let name = match pending.lock().unwrap().pop() {
+                        Some(name) => name,
+                        None => break,
+                    };
+
+                    self.listing(&name);
+                });
+            }
+        });
+    }
+}
+
+/// The source and ID of the package that replaces each package patched by the
+/// root manifest.
+fn patched_packages(
+    root_manifest: &Manifest,
+    package_sources: &PackageSourceMap,
+) -> anyhow::Result<BTreeMap<PackageName, (PackageSourceId, PackageId)>> {
+    let mut patches = BTreeMap::new();
+
+    for (name, patch) in &root_manifest.patches {
+        let (source, manifest) = match patch {
+            PatchSource::Git(spec) => (
+                PackageSourceId::GitRepository(spec.clone()),
+                package_sources.git_source(spec)?.manifest(),
+            ),
+            PatchSource::Path { path } => (
+                PackageSourceId::LocalPath(path.clone()),
+                package_sources.path_source(path)?.manifest(),
+            ),
+        };
+
+        if manifest.package.name != *name {
             bail!(
-                "All possible candidates for package {req} ({req_realm:?}) conflicted with other \
-                 packages that were already installed. These packages were previously selected: \
-                 {conflicting}",
-                req = dependency_request.package_req,
-                req_realm = dependency_request.request_realm,
-                conflicting = conflicting_debug.join(", "),
+                "The patch for {} contains {} instead",
+                name,
+                manifest.package_id()
             );
         }
+
+        patches.insert(name.clone(), (source, manifest.package_id()));
+    }
+
+    Ok(patches)
+}
+
+/// Build the package graph recorded in a lockfile without querying any
+/// package sources, failing if the lockfile doesn't satisfy the manifest.
+///
+/// Lockfiles don't record which realm each package declares, so every
+/// package's realm is taken to be its origin realm.
+pub fn resolve_from_lockfile(root_manifest: &Manifest, lockfile: &Lockfile) -> anyhow::Result<Resolve> {
+    root_manifest.check_aliases()?;
+
+    let mut locked_dependencies = BTreeMap::new();
+    let mut git_packages = BTreeMap::new();
+    let mut path_packages = BTreeMap::new();
+
+    for lock_package in &lockfile.packages {
+        match lock_package {
+            LockPackage::Registry(lock_package) => {
+                let package_id =
+                    PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+                locked_dependencies.insert(package_id, &lock_package.dependencies);
+            }
+            LockPackage::Git(lock_package) => {
+                let package_id = lock_package.package_id();
+                locked_dependencies.insert(package_id.clone(), &lock_package.dependencies);
+                git_packages.insert(package_id, lock_package);
+            }
+            LockPackage::Path(lock_package) => {
+                let package_id = lock_package.package_id();
+                locked_dependencies.insert(package_id.clone(), &lock_package.dependencies);
+                path_packages.insert(package_id, lock_package);
+            }
+        }
+    }
+
+    // Patched packages must come from their patch, and packages from anywhere
+    // other than a registry must still be depended on or patched in.
+    for package_id in locked_dependencies.keys() {
+        let patch = root_manifest.patches.get(package_id.name());
+
+        let git_package = git_packages.get(package_id);
+        let path_package = path_packages.get(package_id);
+
+        let locked_from_source = match (git_package, path_package) {
+            (Some(lock_package), _) => {
+                let spec = lock_package.spec();
+                patch == Some(&PatchSource::Git(spec.clone()))
+                    || root_manifest
+                        .git_dependencies
+                        .values()
+                        .any(|dependency| dependency.source == spec)
+            }
+            (_, Some(lock_package)) => {
+                let path = &lock_package.path;
+                patch == Some(&PatchSource::Path { path: path.clone() })
+                    || root_manifest
+                        .path_dependencies
+                        .values()
+                        .any(|dependency| dependency.path == *path)
+            }
+            (None, None) => patch.is_none(),
+        };
+
+        if !locked_from_source {
+            bail!(
+                "The lockfile is out of date: {} is not locked to where the manifest gets it from",
+                package_id
+            );
+        }
+    }
+
+    let root_id = root_manifest.package_id();
+    let root_dependencies = locked_dependencies.get(&root_id).ok_or_else(|| {
+        format_err!("The lockfile is out of date: it does not contain {}", root_id)
+    })?;
+
+    let mut resolve = Resolve::default();
+    resolve.activated.insert(root_id.clone());
+    resolve.metadata.insert(
+        root_id.clone(),
+        ResolvePackageMetadata {
+            realm: root_manifest.package.realm,
+            origin_realm: root_manifest.package.realm,
+            source_registry: PackageSourceId::DefaultRegistry,
+            git_commit: None,
+            checksum: None,
+        },
+    );
+
+    let mut packages_to_visit = VecDeque::new();
+    let mut root_dependency_count = 0;
+
+    for (table_realm, dependencies) in [
+        (Realm::Shared, &root_manifest.dependencies),
+        (Realm::Server, &root_manifest.server_dependencies),
+        (Realm::Dev, &root_manifest.dev_dependencies),
+    ] {
+        for (alias, req) in dependencies {
+            let realm = root_manifest.dependency_realm(table_realm, alias);
+            let (_, package_id) = root_dependencies
+                .iter()
+                .find(|(locked_alias, package_id)| locked_alias == alias && req.matches_id(package_id))
+                .ok_or_else(|| {
+                    format_err!("The lockfile is out of date: {} = \"{}\" is not locked", alias, req)
+                })?;
+
+            resolve.activate(root_id.clone(), alias.clone(), realm, package_id.clone());
+            packages_to_visit.push_back((package_id.clone(), realm));
+            root_dependency_count += 1;
+        }
+    }
+
+    for (alias, dependency) in &root_manifest.git_dependencies {
+        let realm = root_manifest.dependency_realm(dependency.table_realm, alias);
+        let (_, package_id) = root_dependencies
+            .iter()
+            .find(|(locked_alias, package_id)| {
+                locked_alias == alias
+                    && git_packages
+                        .get(package_id)
+                        .is_some_and(|lock_package| lock_package.spec() == dependency.source)
+            })
+            .ok_or_else(|| {
+                format_err!(
+                    "The lockfile is out of date: {} from {} is not locked",
+                    alias,
+                    dependency.source
+                )
+            })?;
+
+        resolve.activate(root_id.clone(), alias.clone(), realm, package_id.clone());
+        packages_to_visit.push_back((package_id.clone(), realm));
+        root_dependency_count += 1;
+    }
+
+    for (alias, dependency) in &root_manifest.path_dependencies {
+        let realm = root_manifest.dependency_realm(dependency.table_realm, alias);
+        let (_, package_id) = root_dependencies
+            .iter()
+            .find(|(locked_alias, package_id)| {
+                locked_alias == alias
+                    && path_packages
+                        .get(package_id)
+                        .is_some_and(|lock_package| lock_package.path == dependency.path)
+            })
+            .ok_or_else(|| {
+                format_err!(
+                    "The lockfile is out of date: {} from {} is not locked",
+                    alias,
+                    dependency.path
+                )
+            })?;
+
+        resolve.activate(root_id.clone(), alias.clone(), realm, package_id.clone());
+        packages_to_visit.push_back((package_id.clone(), realm));
+        root_dependency_count += 1;
+    }
+
+    if root_dependencies.len() != root_dependency_count {
+        bail!("The lockfile is out of date: it locks dependencies that are no longer in the manifest");
+    }
+
+    // Origin realms are propagated the same way `resolve` does, revisiting a
+    // package whenever its origin realm becomes less restrictive.
+    while let Some((package_id, origin_realm)) = packages_to_visit.pop_front() {
+        let origin_realm = match resolve.metadata.get(&package_id) {
+            Some(metadata) => {
+                let merged = merge_origin_realms(metadata.origin_realm, origin_realm);
+                if merged == metadata.origin_realm {
+                    continue;
+                }
+                merged
+            }
+            None => origin_realm,
+        };
+
+        let (source_registry, git_commit) = match git_packages.get(&package_id) {
+            Some(lock_package) => (
+                PackageSourceId::GitRepository(lock_package.spec()),
+                Some(lock_package.commit.clone()),
+            ),
+            None => match path_packages.get(&package_id) {
+                Some(lock_package) => (PackageSourceId::LocalPath(lock_package.path.clone()), None),
+                None => (PackageSourceId::DefaultRegistry, None),
+            },
+        };
+
+        resolve.metadata.insert(
+            package_id.clone(),
+            ResolvePackageMetadata {
+                realm: origin_realm,
+                origin_realm,
+                source_registry,
+                git_commit,
+                checksum: None,
+            },
+        );
+
+        let dependencies = locked_dependencies.get(&package_id).ok_or_else(|| {
+            format_err!("The lockfile is out of date: it does not contain {}", package_id)
+        })?;
+
+        for (_, dependency_id) in dependencies.iter() {
+            packages_to_visit.push_back((dependency_id.clone(), origin_realm));
+        }
     }
 
+    let origin_realms: Vec<_> = resolve
+        .metadata
+        .iter()
+        .filter(|(package_id, _)| **package_id != root_id)
+        .map(|(package_id, metadata)| (package_id.clone(), metadata.origin_realm))
+        .collect();
+
+    for (package_id, origin_realm) in origin_realms {
+        for (alias, dependency_id) in locked_dependencies[&package_id].iter() {
+            resolve.activate(package_id.clone(), alias.clone(), origin_realm, dependency_id.clone());
+        }
+    }
+
+    resolve.check_for_cycles()?;
+
     Ok(resolve)
 }
 
-fn compatible(a: &Version, b: &Version) -> bool {
+/// Combine the origin realms of two requests for the same package, keeping the
+/// least restrictive one. See [ origin_realm clarification ].
+fn merge_origin_realms(existing: Realm, requested: Realm) -> Realm {
+    match (existing, requested) {
+        (_, Realm::Shared) => Realm::Shared,
+        (Realm::Shared, _) => Realm::Shared,
+        (_, Realm::Server) => Realm::Server,
+        (Realm::Server, _) => Realm::Server,
+        (Realm::Dev, Realm::Dev) => Realm::Dev,
+    }
+}
+
+/// Whether two versions are SemVer compatible, so that only one of them can be
+/// activated at once.
+pub(crate) fn compatible(a: &Version, b: &Version) -> bool {
     if a == b {
         return true;
     }
@@ -310,12 +1051,144 @@ fn compatible(a: &Version, b: &Version) -> bool {
     }
 }
 
+/// A requirement that one package in the graph places on another.
+#[derive(Debug, Clone, Serialize)]
+pub struct Requirement {
+    /// The package that has the dependency.
+    pub dependent: PackageId,
+
+    /// The name the dependent gave the dependency.
+    pub alias: String,
+
+    pub requirement: PackageReq,
+
+    /// The realm the dependency was requested from.
+    pub realm: Realm,
+}
+
+/// Why the dependencies of a project couldn't be resolved, in a form that
+/// tools can read as well as people.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum ResolveError {
+    /// No version of a package satisfies a requirement on it.
+    NoMatchingVersions { requirement: Requirement },
+
+    /// Every version that satisfies a requirement is SemVer compatible with a
+    /// version of the package that was already selected for other
+    /// requirements, and only one compatible copy of a package is allowed.
+    Conflict {
+        package: PackageName,
+
+        /// The versions that were already selected.
+        selected: Vec<PackageId>,
+
+        /// The requirements the selected versions were chosen for, followed
+        /// by the requirement that couldn't be satisfied alongside them.
+        requirements: Vec<Requirement>,
+    },
+
+    /// The package that the root manifest patches a package with doesn't
+    /// satisfy a requirement on the package it replaces.
+    PatchMismatch {
+        patched: PackageId,
+        requirement: Requirement,
+    },
+
+    /// Packages depend on each other in a loop, so their links would require
+    /// each other forever. The first package is repeated at the end.
+    Cycle { packages: Vec<PackageId> },
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolveError::NoMatchingVersions { requirement } => write!(
+                formatter,
+                "No packages were found that matched ({realm:?}) {req}.\nAre you sure this is a \
+                 {realm:?} dependency?",
+                realm = requirement.realm,
+                req = requirement.requirement,
+            ),
+            ResolveError::Conflict {
+                package,
+                selected,
+                requirements,
+            } => {
+                let failed = requirements
+                    .last()
+                    .expect("a conflict always has the requirement that failed");
+                let selected: Vec<_> = selected.iter().map(ToString::to_string).collect();
+
+                write!(
+                    formatter,
+                    "All possible candidates for package {req} ({realm:?}) conflicted with other \
+                     packages that were already installed. These packages were previously \
+                     selected: {selected}\nRequirements on {package}:",
+                    req = failed.requirement,
+                    realm = failed.realm,
+                    selected = selected.join(", "),
+                    package = package,
+                )?;
+
+                for requirement in requirements {
+                    write!(
+                        formatter,
+                        "\n  {} requires {}",
+                        requirement.dependent, requirement.requirement
+                    )?;
+                }
+
+                Ok(())
+            }
+            ResolveError::PatchMismatch {
+                patched,
+                requirement,
+            } => write!(
+                formatter,
+                "{dependent} requires {req}, but {name} is patched with version {version}, which \
+                 does not satisfy it",
+                dependent = requirement.dependent,
+                req = requirement.requirement,
+                name = patched.name(),
+                version = patched.version(),
+            ),
+            ResolveError::Cycle { packages } => {
+                let packages: Vec<_> = packages.iter().map(ToString::to_string).collect();
+
+                write!(
+                    formatter,
+                    "Dependency cycle detected: {}\nPackages can't depend on each other in a loop.",
+                    packages.join(" -> ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
 pub struct DependencyRequest {
     request_source: PackageId,
     request_realm: Realm,
     origin_realm: Realm,
     package_alias: String,
     package_req: PackageReq,
+
+    /// The only source the package may come from, if it isn't found through
+    /// the usual search of registries.
+    source: Option<PackageSourceId>,
+}
+
+impl DependencyRequest {
+    fn requirement(&self) -> Requirement {
+        Requirement {
+            dependent: self.request_source.clone(),
+            alias: self.package_alias.clone(),
+            requirement: self.package_req.clone(),
+            realm: self.request_realm,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -446,6 +1319,125 @@ mod tests {
         insta::assert_snapshot!(err);
     }
 
+    /// B and C both depend on D, but on versions of it that are SemVer
+    /// compatible with each other, so one copy of D can't satisfy both.
+    #[test]
+    fn fail_unsatisfiable_diamond() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@=1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_dep("D", "biff/d@=1.1.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+        insta::assert_snapshot!(err);
+
+        let conflict = err.downcast_ref::<ResolveError>().unwrap();
+        insta::assert_snapshot!(
+            "fail_unsatisfiable_diamond_json",
+            serde_json::to_string_pretty(conflict).unwrap()
+        );
+    }
+
+    /// B and C both depend on D, with different requirements, but D's
+    /// versions are only listed once.
+    #[test]
+    fn shared_dependency_is_listed_once() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_dep("D", "biff/d@1.1.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources).unwrap();
+        let d: PackageId = "biff/d@1.1.0".parse().unwrap();
+        assert!(resolved.activated.contains(&d));
+
+        let mut listed: Vec<String> = registry.listed().iter().map(ToString::to_string).collect();
+        listed.sort();
+        assert_eq!(listed, vec!["biff/b", "biff/c", "biff/d"]);
+    }
+
+    /// Prereleases are only selected when a requirement asks for a
+    /// prerelease of that version, and are ordered before their release.
+    #[test]
+    fn prerelease_selection() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/a@1.1.0-beta"));
+        registry.publish(PackageBuilder::new("biff/b@2.0.0-beta.2"));
+        registry.publish(PackageBuilder::new("biff/b@2.0.0-beta.10"));
+        registry.publish(PackageBuilder::new("biff/b@2.0.0-rc.1+build5"));
+        registry.publish(PackageBuilder::new("biff/c@3.0.0-beta"));
+        registry.publish(PackageBuilder::new("biff/c@3.0.0"));
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let selected = |req: &str| -> String {
+            let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Dep", req);
+            let resolved = resolve(root.manifest(), &Default::default(), &package_sources).unwrap();
+            resolved.shared_dependencies[&root.manifest().package_id()]["Dep"].to_string()
+        };
+
+        assert_eq!(selected("biff/a@1.0.0"), "biff/a@1.0.0");
+        assert_eq!(selected("biff/a@=1.1.0-beta"), "biff/a@1.1.0-beta");
+        assert_eq!(selected("biff/b@2.0.0-beta.2"), "biff/b@2.0.0-rc.1+build5");
+        assert_eq!(
+            selected("biff/b@=2.0.0-rc.1+build5"),
+            "biff/b@2.0.0-rc.1+build5"
+        );
+        assert_eq!(
+            selected("biff/b@<2.0.0-rc.1, >=2.0.0-beta"),
+            "biff/b@2.0.0-beta.10"
+        );
+        assert_eq!(selected("biff/c@3.0.0-beta"), "biff/c@3.0.0");
+
+        let err = resolve(
+            PackageBuilder::new("biff/root@1.0.0")
+                .with_dep("B", "biff/b@2.0.0")
+                .manifest(),
+            &Default::default(),
+            &package_sources,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ResolveError>(),
+            Some(ResolveError::NoMatchingVersions { .. })
+        ));
+    }
+
+    /// A and B depend on each other, which can never be installed.
+    #[test]
+    fn fail_dependency_cycle() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0").with_dep("B", "biff/b@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("A", "biff/a@1.0.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("A", "biff/a@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Dependency cycle detected: biff/a@1.0.0 -> biff/b@1.0.0 -> biff/a@1.0.0\nPackages \
+             can't depend on each other in a loop."
+        );
+
+        match err.downcast_ref::<ResolveError>() {
+            Some(ResolveError::Cycle { packages }) => assert_eq!(packages.len(), 3),
+            _ => panic!("expected a cycle error, got {}", err),
+        }
+    }
+
     /// Tests the simple one dependency case, except that a new version of the
     /// dependency will be published after the initial resolve. By persisting
     /// the set of activated packages from the initial install, we signal that
@@ -498,4 +1490,41 @@ mod tests {
 
         Ok(())
     }
+
+    /// Resolving from a lockfile should place packages in the same realms as
+    /// the resolve that produced the lockfile.
+    #[test]
+    fn from_lockfile_matches_resolve() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/shared@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/server-only@1.0.0"));
+        registry.publish(
+            PackageBuilder::new("biff/server@1.0.0")
+                .with_realm(Realm::Server)
+                .with_dep("Shared", "biff/shared@1.0.0")
+                .with_dep("ServerOnly", "biff/server-only@1.0.0"),
+        );
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_server_dep("Server", "biff/server@1.0.0")
+            .with_dep("Shared", "biff/shared@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let manifest = root.into_manifest();
+        let resolved = resolve(&manifest, &Default::default(), &package_sources)?;
+
+        let lockfile = Lockfile::from_resolve(&resolved);
+        let from_lockfile = resolve_from_lockfile(&manifest, &lockfile)?;
+
+        assert_eq!(from_lockfile.activated, resolved.activated);
+        for (package_id, metadata) in &resolved.metadata {
+            assert_eq!(
+                from_lockfile.metadata[package_id].origin_realm, metadata.origin_realm,
+                "{} should have the same origin realm",
+                package_id
+            );
+        }
+
+        Ok(())
+    }
 }
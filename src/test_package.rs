@@ -5,6 +5,7 @@ use std::{
     io::{Cursor, Write},
 };
 
+use flate2::{write::GzEncoder, Compression};
 use zip::write::{FileOptions, ZipWriter};
 
 use crate::{
@@ -41,11 +42,22 @@ impl PackageBuilder {
                 private: false,
                 homepage: None,
                 repository: None,
+                lib: None,
+                wally_version: None,
             },
             place: Default::default(),
             dependencies: Default::default(),
             server_dependencies: Default::default(),
             dev_dependencies: Default::default(),
+            realm_overrides: Default::default(),
+            git_dependencies: Default::default(),
+            path_dependencies: Default::default(),
+            features: Default::default(),
+            optional_dependencies: Default::default(),
+            patches: Default::default(),
+            registries: Default::default(),
+            scripts: Default::default(),
+            checksum: None,
         };
 
         Self {
@@ -120,6 +132,32 @@ impl PackageBuilder {
         contents
     }
 
+    /// The same files as [`PackageBuilder::contents`], packed as a gzipped
+    /// tarball instead of a zip.
+    pub fn tar_gz_contents(&self) -> PackageContents {
+        let encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let mut archive = tar::Builder::new(encoder);
+
+        let encoded_manifest = toml::to_string_pretty(&self.manifest).unwrap();
+        let files = self
+            .files
+            .iter()
+            .map(|(path, contents)| (path.as_str(), contents.as_str()))
+            .chain(std::iter::once(("wally.toml", encoded_manifest.as_str())));
+
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            archive
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+
+        let buffer = archive.into_inner().unwrap().finish().unwrap();
+        PackageContents::from_buffer(buffer)
+    }
+
     pub fn package(self) -> (Manifest, PackageContents) {
         let contents = self.contents();
         (self.manifest, contents)
@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+
+use crate::cache_pruning::{record_use, CachedEntry};
+use crate::extract_types::ExtractTypesResult;
+
+/// A cache of the types parsed out of Lua modules, shared by every project on
+/// this machine, so that installing the same package again doesn't need to
+/// parse its main module again.
+///
+/// Entries are keyed by a hash of the module's contents along with Wally's
+/// version, since a newer parser may read the same module differently.
+#[derive(Debug, Clone)]
+pub struct TypeCache {
+    root: PathBuf,
+}
+
+impl TypeCache {
+    /// Open the type cache in Wally's cache directory, `cache_dir`.
+    pub fn new(cache_dir: &Path) -> Self {
+        Self::at(cache_dir.join("types"))
+    }
+
+    /// Open a type cache stored in the given directory.
+    pub fn at<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The types previously parsed from a module with these contents, if
+    /// there are any.
+    pub(crate) fn get(&self, contents: &str) -> Option<ExtractTypesResult> {
+        let entry_path = self.entry_path(contents);
+        let entry = fs::read(&entry_path).ok()?;
+
+        match serde_json::from_slice(&entry) {
+            Ok(types) => {
+                record_use(&entry_path);
+                Some(types)
+            }
+            Err(err) => {
+                log::debug!(
+                    "Ignoring invalid type cache entry {}: {}",
+                    entry_path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Remember the types parsed from a module with these contents. Failing
+    /// to write an entry only means the module is parsed again next time, so
+    /// errors are logged rather than returned.
+    pub(crate) fn insert(&self, contents: &str, types: &ExtractTypesResult) {
+        if let Err(err) = self.try_insert(contents, types) {
+            log::warn!("Failed to write to the type cache: {}", err);
+        }
+    }
+
+    fn try_insert(&self, contents: &str, types: &ExtractTypesResult) -> anyhow::Result<()> {
+        let entry_path = self.entry_path(contents);
+        if entry_path.exists() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.root)?;
+
+        // Entries are written somewhere else and renamed into place, so that
+        // another install never reads one that's only partly written.
+        let staging_path = entry_path.with_extension(format!("partial-{}", std::process::id()));
+        fs::write(&staging_path, serde_json::to_vec(types)?)?;
+
+        if let Err(err) = fs::rename(&staging_path, &entry_path) {
+            let _ = fs::remove_file(&staging_path);
+
+            if !entry_path.exists() {
+                return Err(err.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every entry in the cache, for pruning.
+    pub fn entries(&self) -> anyhow::Result<Vec<CachedEntry>> {
+        let mut entries = Vec::new();
+        if !self.root.is_dir() {
+            return Ok(entries);
+        }
+
+        for dir_entry in fs::read_dir(&self.root)? {
+            let entry_path = dir_entry?.path();
+            if entry_path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                entries.push(CachedEntry::read(entry_path.clone(), &entry_path)?);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn entry_path(&self, contents: &str) -> PathBuf {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update(&[0]);
+        hasher.update(contents.as_bytes());
+
+        let hash = hex::encode(hasher.finalize().as_bytes());
+        self.root.join(format!("{}.json", hash))
+    }
+}
@@ -0,0 +1 @@
+return "version one"
@@ -0,0 +1,5 @@
+local ServerDependsOnShared = require(script.Parent.ServerDependsOnShared)
+
+return function()
+	print(ServerDependsOnShared)
+end
\ No newline at end of file
@@ -0,0 +1,6 @@
+export type Point = {
+	x: number,
+	y: number,
+}
+
+return {}
\ No newline at end of file
@@ -0,0 +1,5 @@
+local MinimalWithTypes = require(script.Parent.MinimalWithTypes)
+
+return function()
+	print(MinimalWithTypes)
+end
\ No newline at end of file
@@ -0,0 +1,5 @@
+local sdos = require(script.Parent.sdos)
+
+return function()
+	sdos()
+end
\ No newline at end of file
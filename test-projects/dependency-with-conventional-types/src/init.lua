@@ -0,0 +1,5 @@
+local ConventionalTypes = require(script.Parent.ConventionalTypes)
+
+return function()
+	print(ConventionalTypes)
+end
\ No newline at end of file
@@ -0,0 +1,5 @@
+local SingleFileWithTypes = require(script.Parent.SingleFileWithTypes)
+
+return function()
+	print(SingleFileWithTypes)
+end
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- Tests run with DevPackages, but this module is shipped.
+local ReplicatedStorage = game:GetService("ReplicatedStorage")
+
+local Transitive = require(ReplicatedStorage.DevPackages.Transitive)
+
+return Transitive
@@ -0,0 +1,7 @@
+local ReplicatedStorage = game:GetService("ReplicatedStorage")
+
+local Transitive = require(ReplicatedStorage.DevPackages.Transitive)
+
+return function()
+	print(Transitive)
+end
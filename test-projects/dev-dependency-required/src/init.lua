@@ -0,0 +1,5 @@
+local Shipped = require(script.Shipped)
+
+return function()
+	print(Shipped)
+end
\ No newline at end of file
@@ -0,0 +1,5 @@
+local OneDependency = require(script.Parent.Parent.ServerPackages["one-dependency"])
+
+return function()
+	print(OneDependency)
+end
\ No newline at end of file
@@ -0,0 +1,5 @@
+local WithTests = require(script.Parent.WithTests)
+
+return function()
+	print(WithTests.greet())
+end
\ No newline at end of file
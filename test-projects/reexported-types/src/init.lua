@@ -0,0 +1,5 @@
+local ReexportedTypes = require(script.Parent.ReexportedTypes)
+
+return function()
+	print(ReexportedTypes.origin)
+end
\ No newline at end of file
@@ -0,0 +1,45 @@
+use crate::temp_project::TempProject;
+use crate::util::{install_args, run_install, test_global};
+use libwally::{Args, CleanSubcommand, GlobalOptions, Subcommand};
+use std::path::Path;
+
+#[test]
+fn clean_one_realm() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/dev-dependency-also-required-as-non-dev"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    let server_packages = project.path().join("ServerPackages");
+    let dev_packages = project.path().join("DevPackages");
+    assert!(server_packages.exists());
+    assert!(dev_packages.exists());
+
+    run_clean(project.path(), false, false, true).unwrap();
+    assert!(server_packages.exists());
+    assert!(!dev_packages.exists());
+
+    // Folders that are already gone are skipped.
+    run_clean(project.path(), false, false, false).unwrap();
+    assert!(!server_packages.exists());
+    assert!(!dev_packages.exists());
+}
+
+fn run_clean(path: &Path, shared: bool, server: bool, dev: bool) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Clean(CleanSubcommand {
+            project_path: path.to_owned(),
+            shared,
+            server,
+            dev,
+        }),
+    }
+    .run()
+}
@@ -0,0 +1,190 @@
+use crate::temp_project::TempProject;
+use crate::util::{install_args, run_install, test_global};
+use fs_err as fs;
+use git2::{build::RepoBuilder, Repository};
+use libwally::{
+    git_util,
+    lockfile::{LockPackage, Lockfile},
+    Args, GlobalOptions, Subcommand, UpdateSubcommand,
+};
+use std::path::Path;
+use tempfile::TempDir;
+
+#[test]
+fn package_in_subdirectory() {
+    let (work, remote) = git_repository_fixture("monorepo");
+    let project = git_dependency_project(remote.path(), "main", Some("packages/thing"));
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    let package_path = project
+        .path()
+        .join("Packages/_Index/biff_thing@0.1.0/thing");
+    assert_eq!(
+        fs::read_to_string(package_path.join("src/init.lua")).unwrap(),
+        "return \"version one\"\n"
+    );
+    assert!(project.path().join("Packages/Thing.lua").exists());
+
+    assert_eq!(locked_commit(project.path()), head_commit(work.path()));
+}
+
+#[test]
+fn missing_package_directory() {
+    let (_work, remote) = git_repository_fixture("monorepo");
+    let project = git_dependency_project(remote.path(), "main", Some("packages/other"));
+
+    let err = run_install(test_global(), install_args(project.path())).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("has no directory packages/other"),
+        "unexpected error: {:#}",
+        err
+    );
+}
+
+#[test]
+fn lockfile_pins_commit_until_update() {
+    let (work, remote) = git_repository_fixture("monorepo");
+    let project = git_dependency_project(remote.path(), "main", Some("packages/thing"));
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    let first_commit = locked_commit(project.path());
+
+    fs::write(
+        work.path().join("packages/thing/src/init.lua"),
+        "return \"version two\"\n",
+    )
+    .unwrap();
+    commit_and_push(work.path(), remote.path());
+
+    // The branch moved, but installing again keeps the locked commit.
+    run_install(test_global(), install_args(project.path())).unwrap();
+    assert_eq!(locked_commit(project.path()), first_commit);
+
+    run_update(project.path()).unwrap();
+    assert_eq!(locked_commit(project.path()), head_commit(work.path()));
+    assert_ne!(locked_commit(project.path()), first_commit);
+
+    let package_path = project
+        .path()
+        .join("Packages/_Index/biff_thing@0.1.0/thing");
+    assert_eq!(
+        fs::read_to_string(package_path.join("src/init.lua")).unwrap(),
+        "return \"version two\"\n"
+    );
+}
+
+/// Commit the Git repository fixture called `name` into a working copy and
+/// clone that into a bare repository to depend on.
+fn git_repository_fixture(name: &str) -> (TempProject, TempDir) {
+    let source = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-git-repositories"
+    ))
+    .join(name);
+    let work = TempProject::new(&source).unwrap();
+    git_util::init_test_repo(work.path()).unwrap();
+
+    let remote = tempfile::tempdir().unwrap();
+    RepoBuilder::new()
+        .bare(true)
+        .clone(work.path().to_str().unwrap(), remote.path())
+        .unwrap();
+
+    (work, remote)
+}
+
+/// A project that depends on the package in the repository at `remote`.
+fn git_dependency_project(remote: &Path, rev: &str, path: Option<&str>) -> TempProject {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/minimal"
+    ));
+    let project = TempProject::new(source_project).unwrap();
+
+    let path = path
+        .map(|path| format!(", path = \"{}\"", path))
+        .unwrap_or_default();
+    let manifest = format!(
+        r#"[package]
+name = "biff/git-dependency"
+version = "0.1.0"
+license = "MIT"
+realm = "shared"
+registry = "test-registries/primary-registry"
+
+[dependencies]
+Thing = {{ git = "{}", rev = "{}"{} }}
+"#,
+        remote.display().to_string().replace('\\', "/"),
+        rev,
+        path
+    );
+    fs::write(project.path().join("wally.toml"), manifest).unwrap();
+
+    project
+}
+
+fn commit_and_push(work: &Path, remote: &Path) {
+    let repository = Repository::open(work).unwrap();
+
+    let mut index = repository.index().unwrap();
+    index
+        .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    index.write().unwrap();
+
+    let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+    let parent = repository.head().unwrap().peel_to_commit().unwrap();
+    let signature = git2::Signature::now("PackageUser", "PackageUser@localhost").unwrap();
+    repository
+        .commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Change the package",
+            &tree,
+            &[&parent],
+        )
+        .unwrap();
+
+    repository
+        .remote_anonymous(remote.to_str().unwrap())
+        .unwrap()
+        .push(&["refs/heads/main:refs/heads/main"], None)
+        .unwrap();
+}
+
+fn head_commit(work: &Path) -> String {
+    let repository = Repository::open(work).unwrap();
+    let commit = repository.head().unwrap().peel_to_commit().unwrap();
+    commit.id().to_string()
+}
+
+fn locked_commit(project: &Path) -> String {
+    let lockfile = Lockfile::load(project).unwrap().unwrap();
+
+    lockfile
+        .packages
+        .iter()
+        .find_map(|package| match package {
+            LockPackage::Git(package) => Some(package.commit.clone()),
+            LockPackage::Registry(_) | LockPackage::Path(_) => None,
+        })
+        .expect("the lockfile has no Git package")
+}
+
+fn run_update(project_path: &Path) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Update(UpdateSubcommand {
+            project_path: project_path.to_owned(),
+            package_specs: Vec::new(),
+            features: Default::default(),
+        }),
+    }
+    .run()
+}
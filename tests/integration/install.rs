@@ -1,6 +1,24 @@
 use super::temp_project::TempProject;
-use libwally::{Args, GlobalOptions, InstallSubcommand, Subcommand};
-use std::path::Path;
+use super::util::{install_args, run_install, test_global};
+use fs_err as fs;
+use libwally::{
+    extract_types::package_types,
+    install_observer::{DevRequire, InstallObserver},
+    install_report::{InstallReport, ResolvedFrom, REPORT_SCHEMA_VERSION},
+    install_sink::{InstallSink, MemorySink},
+    installation::{InstallError, InstallationContext, INSTALL_SENTINEL_FILE_NAME},
+    lockfile::Lockfile,
+    manifest::{Manifest, Realm},
+    package_id::PackageId,
+    package_source::{PackageSource, PackageSourceMap, TestRegistry},
+    resolution::{resolve, resolve_from_lockfile},
+    GlobalOptions, InstallSubcommand,
+};
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 #[test]
 fn minimal() {
@@ -8,12 +26,196 @@ fn minimal() {
     assert_dir_snapshot!(project.path());
 }
 
+#[test]
+fn no_dependencies_is_a_no_op() {
+    let project = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/minimal"
+    )))
+    .unwrap();
+
+    let manifest_path = project.path().join("wally.toml");
+    let mut manifest = fs::read_to_string(&manifest_path).unwrap();
+    manifest.push_str("\n[dependencies]\n\n[server-dependencies]\n\n[dev-dependencies]\n");
+    fs::write(&manifest_path, manifest).unwrap();
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    let lockfile = fs::read_to_string(project.path().join("wally.lock")).unwrap();
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    assert_eq!(
+        fs::read_to_string(project.path().join("wally.lock")).unwrap(),
+        lockfile
+    );
+
+    for folder in ["Packages", "ServerPackages", "DevPackages"] {
+        assert!(!project.path().join(folder).exists(), "{} exists", folder);
+    }
+    assert!(!project.path().join(".wally-install-in-progress").exists());
+}
+
 #[test]
 fn dependency_with_types() {
     let project = run_install_test("dependency-with-types");
     assert_dir_snapshot!(project.path());
 }
 
+#[test]
+fn package_types_can_be_queried() {
+    let project = run_install_test("dependency-with-types");
+    let types = package_types(
+        &project
+            .path()
+            .join("ServerPackages/_Index/biff_minimal-with-types@0.1.0/minimal-with-types"),
+    );
+
+    let names: Vec<_> = types
+        .statements()
+        .iter()
+        .map(|statement| statement.name())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "A",
+            "B",
+            "C",
+            "D",
+            "E",
+            "F",
+            "BadDefaultTypeParam",
+            "WithTypeParams",
+            "WithParamPack"
+        ]
+    );
+
+    let params = |index: usize| -> Vec<_> {
+        types.statements()[index]
+            .type_params()
+            .iter()
+            .map(|param| (param.name(), param.is_pack(), param.default()))
+            .collect()
+    };
+
+    // The default names a type that isn't exported, so it's left out.
+    assert_eq!(params(6), [("T", false, None)]);
+    assert_eq!(params(7), [("T", false, None), ("U", false, None)]);
+    assert_eq!(params(8), [("T", true, None)]);
+    assert!(types.statements().iter().all(|statement| !statement.is_function()));
+}
+
+#[test]
+fn packages_without_types_fail_require_types() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/dependency-with-types"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let manifest_path = project.path().join("wally.toml");
+    let mut manifest = fs::read_to_string(&manifest_path).unwrap();
+    manifest.push_str("Minimal = \"biff/minimal@0.1.0\"\n");
+    fs::write(&manifest_path, manifest).unwrap();
+
+    let manifest = Manifest::load(project.path()).unwrap();
+    let package_sources = PackageSourceMap::new(Box::new(PackageSource::TestRegistry(
+        TestRegistry::new(&manifest.package.registry),
+    )));
+    let resolved = resolve(&manifest, &BTreeSet::new(), &package_sources).unwrap();
+
+    let err = InstallationContext::new(project.path(), &manifest.place)
+        .with_require_types(true)
+        .install(package_sources, manifest.package_id(), resolved)
+        .unwrap_err();
+
+    // `minimal-with-types` exports types, so only `minimal` is reported.
+    match err.downcast_ref::<InstallError>() {
+        Some(InstallError::MissingTypes { packages }) => {
+            let packages: Vec<String> = packages.iter().map(ToString::to_string).collect();
+            assert_eq!(packages, ["biff/minimal@0.1.0"]);
+        }
+        _ => panic!("expected missing types, got {:#}", err),
+    }
+}
+
+#[test]
+fn combined_types() {
+    let project = run_install_test("combined-types");
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn combined_types_cross_realm() {
+    // Links into another realm forward the types from its _Types module too.
+    let project = run_install_test("combined-types-cross-realm");
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn no_type_forwarding() {
+    // The dependency exports types, but its link is a plain require.
+    let project = run_install_test("no-type-forwarding");
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn flat_layout() {
+    // Packages are installed straight into ServerPackages, with no links.
+    let project = run_install_test("flat-layout");
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn reexported_types() {
+    let project = run_install_test("reexported-types");
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn crlf_links() {
+    let project = run_install_test("crlf-links");
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn strict_type_forwarding() {
+    let project = run_install_test("strict-type-forwarding");
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn forced_realm() {
+    let project = run_install_test("forced-realm");
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn package_exclude() {
+    let project = run_install_test("package-exclude");
+
+    let package_path = project
+        .path()
+        .join("Packages/_Index/biff_with-tests@0.1.0/with-tests");
+    assert!(!package_path.join("tests").exists());
+    assert!(package_path.join("default.project.json").exists());
+
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn dependency_with_single_file_types() {
+    let project = run_install_test("dependency-with-single-file-types");
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn dependency_with_conventional_types() {
+    // The dependency has no default.project.json, so its types are read from
+    // src/init.luau.
+    let project = run_install_test("dependency-with-conventional-types");
+    assert_dir_snapshot!(project.path());
+}
+
 #[test]
 fn one_dependency() {
     let project = run_install_test("one-dependency");
@@ -26,6 +228,1399 @@ fn transitive_dependency() {
     assert_dir_snapshot!(project.path());
 }
 
+#[test]
+fn transitive_dependency_single_job() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    run_install(
+        GlobalOptions {
+            concurrency: Some(1),
+            ..test_global()
+        },
+        install_args(project.path()),
+    )
+    .unwrap();
+
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn no_links_only_unpacks_packages() {
+    // Links from an earlier install are removed too.
+    let project = run_install_test("transitive-dependency");
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            no_links: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    let server_packages = project.path().join("ServerPackages");
+    let index = server_packages.join("_Index");
+    assert!(index.join("biff_minimal@0.1.0/minimal/init.lua").is_file());
+    assert!(index
+        .join("biff_one-dependency@0.1.0/one-dependency/init.lua")
+        .is_file());
+
+    assert!(!server_packages.join("OneDependency.lua").exists());
+    assert!(!index.join("biff_one-dependency@0.1.0/Minimal.lua").exists());
+}
+
+#[test]
+fn gitignore_ignores_installed_packages() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            gitignore: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    let gitignore = project.path().join("ServerPackages/.gitignore");
+    assert_eq!(
+        fs::read_to_string(&gitignore).unwrap(),
+        "# Generated by Wally.\n*\n!.gitignore\n"
+    );
+    assert!(!project.path().join("Packages").exists());
+
+    // A .gitignore that was changed by hand is only replaced with --force-gitignore.
+    fs::write(&gitignore, "*.lua\n").unwrap();
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            gitignore: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+    assert_eq!(fs::read_to_string(&gitignore).unwrap(), "*.lua\n");
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            gitignore: true,
+            force_gitignore: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(&gitignore).unwrap(),
+        "# Generated by Wally.\n*\n!.gitignore\n"
+    );
+}
+
+#[test]
+fn postinstall_script_runs_when_allowed() {
+    let project = postinstall_project("echo ran > postinstall.txt");
+    let output = project.path().join("postinstall.txt");
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    assert!(!output.exists());
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            allow_scripts: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+    assert_eq!(fs::read_to_string(&output).unwrap().trim(), "ran");
+    assert!(project.path().join("ServerPackages/Minimal.lua").exists());
+}
+
+#[test]
+fn failing_postinstall_script_fails_install() {
+    let project = postinstall_project("exit 3");
+
+    let error = run_install(
+        test_global(),
+        InstallSubcommand {
+            allow_scripts: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("The postinstall script `exit 3` failed"), "{}", error);
+}
+
+/// A copy of `one-dependency` with the given postinstall script.
+fn postinstall_project(script: &str) -> TempProject {
+    let project = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    )))
+    .unwrap();
+
+    let manifest_path = project.path().join("wally.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    fs::write(
+        &manifest_path,
+        format!("{}\n[scripts]\npostinstall = \"{}\"\n", manifest, script),
+    )
+    .unwrap();
+
+    project
+}
+
+#[test]
+fn no_dev_skips_dev_dependencies() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/dev-dependency-also-required-as-non-dev"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            no_dev: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    assert_dir_snapshot!(project.path());
+}
+
+#[test]
+fn no_dev_removes_installed_dev_packages() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/dev-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let dev_packages = project.path().join("DevPackages");
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    assert!(dev_packages.exists());
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            no_dev: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+    assert!(!dev_packages.exists(), "DevPackages should have been removed");
+
+    let lockfile = fs::read_to_string(project.path().join("wally.lock")).unwrap();
+    assert!(
+        lockfile.contains("biff/minimal"),
+        "Dev dependencies should stay in the lockfile"
+    );
+}
+
+#[test]
+fn aliased_dependency_is_linked_under_its_alias() {
+    let project = aliased_project(r#"Dep = { package = "biff/one-dependency@0.1.0" }"#);
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    let server_packages = project.path().join("ServerPackages");
+    assert!(server_packages.join("Dep.lua").exists());
+    assert!(!server_packages.join("OneDependency.lua").exists());
+    assert!(server_packages
+        .join("_Index/biff_one-dependency@0.1.0/one-dependency")
+        .exists());
+}
+
+#[test]
+fn colliding_aliases_fail() {
+    // Both links would be written to ServerPackages/Dep.lua on a
+    // case-insensitive filesystem.
+    let project = aliased_project(
+        "Dep = \"biff/one-dependency@0.1.0\"\ndep = \"biff/minimal@0.1.0\"",
+    );
+
+    let err = run_install(test_global(), install_args(project.path())).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The aliases Dep and dep only differ by case, so their links in the Server realm would \
+         collide. Give one of them a different alias."
+    );
+    assert!(!project.path().join("ServerPackages").exists());
+}
+
+/// A copy of the `transitive-dependency` project whose server dependencies
+/// are replaced with `dependencies`.
+fn aliased_project(dependencies: &str) -> TempProject {
+    let project = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    )))
+    .unwrap();
+
+    let manifest_path = project.path().join("wally.toml");
+    let manifest = fs::read_to_string(&manifest_path)
+        .unwrap()
+        .replace("OneDependency = \"biff/one-dependency@0.1.0\"", dependencies);
+    fs::write(&manifest_path, manifest).unwrap();
+
+    project
+}
+
+#[test]
+fn production_skips_dev_dependencies() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/dev-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    // Without a lockfile, there's nothing for --locked to check against.
+    let err = run_install(
+        test_global(),
+        InstallSubcommand {
+            production: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string().contains("--locked"),
+        "unexpected error: {}",
+        err
+    );
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    fs::remove_dir_all(project.path().join("DevPackages")).unwrap();
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            production: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+    assert!(!project.path().join("DevPackages").exists());
+    assert!(!project.path().join("ServerPackages").exists());
+
+    // A lockfile that no longer matches the manifest is an error.
+    let manifest_path = project.path().join("wally.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    fs::write(
+        &manifest_path,
+        format!(
+            "{}\n[server-dependencies]\nWithTests = \"biff/with-tests@0.1.0\"\n",
+            manifest
+        ),
+    )
+    .unwrap();
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            production: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap_err();
+    assert!(!project.path().join("ServerPackages").exists());
+}
+
+#[test]
+fn production_requires_locked_checksums() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    let lockfile_path = project.path().join("wally.lock");
+    let lockfile = fs::read_to_string(&lockfile_path).unwrap();
+    let without_checksums: String = lockfile
+        .lines()
+        .filter(|line| !line.starts_with("checksum"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    assert_ne!(lockfile, without_checksums);
+    fs::write(&lockfile_path, without_checksums).unwrap();
+
+    let err = run_install(
+        test_global(),
+        InstallSubcommand {
+            production: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "--production requires the lockfile to record the checksum of every package, but it has \
+         none for biff/minimal@0.1.0. Run `wally install` without --production to record them."
+    );
+}
+
+#[test]
+fn install_into_memory_sink() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let manifest = Manifest::load(project.path()).unwrap();
+
+    let package_sources = PackageSourceMap::new(Box::new(PackageSource::TestRegistry(
+        TestRegistry::new(&manifest.package.registry),
+    )));
+    let resolved = resolve(&manifest, &BTreeSet::new(), &package_sources).unwrap();
+
+    let sink = Arc::new(MemorySink::new());
+    InstallationContext::new(project.path(), &manifest.place)
+        .with_sink(sink.clone())
+        .install(package_sources, manifest.package_id(), resolved)
+        .unwrap();
+
+    let server_packages = project.path().join("ServerPackages");
+    assert!(!server_packages.exists(), "Nothing should be written to disk");
+
+    let files = sink.files();
+    assert_eq!(
+        String::from_utf8_lossy(&files[&server_packages.join("Minimal.lua")]),
+        "return require(script.Parent._Index[\"biff_minimal@0.1.0\"][\"minimal\"])\n"
+    );
+    assert!(files.contains_key(
+        &server_packages.join("_Index/biff_minimal@0.1.0/minimal/init.lua")
+    ));
+}
+
+#[derive(Default)]
+struct CountingObserver {
+    package_count: AtomicUsize,
+    downloads_started: AtomicUsize,
+    downloads_completed: AtomicUsize,
+    installed: AtomicUsize,
+    downloaded: AtomicUsize,
+    links_written: AtomicUsize,
+}
+
+impl InstallObserver for CountingObserver {
+    fn on_install_start(&self, package_count: usize) {
+        self.package_count.store(package_count, Ordering::SeqCst);
+    }
+
+    fn on_download_start(&self, _package_id: &PackageId) {
+        self.downloads_started.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_download_complete(&self, _package_id: &PackageId) {
+        self.downloads_completed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_package_installed(&self, _package_id: &PackageId, downloaded: bool) {
+        self.installed.fetch_add(1, Ordering::SeqCst);
+        if downloaded {
+            self.downloaded.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn on_link_written(&self, _path: &Path) {
+        self.links_written.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl CountingObserver {
+    fn counts(&self) -> [usize; 6] {
+        [
+            self.package_count.load(Ordering::SeqCst),
+            self.downloads_started.load(Ordering::SeqCst),
+            self.downloads_completed.load(Ordering::SeqCst),
+            self.installed.load(Ordering::SeqCst),
+            self.downloaded.load(Ordering::SeqCst),
+            self.links_written.load(Ordering::SeqCst),
+        ]
+    }
+}
+
+#[test]
+fn observer_is_told_about_progress() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let manifest = Manifest::load(project.path()).unwrap();
+    let sink = Arc::new(MemorySink::new());
+
+    let install = |observer: Arc<CountingObserver>| {
+        let package_sources = PackageSourceMap::new(Box::new(PackageSource::TestRegistry(
+            TestRegistry::new(&manifest.package.registry),
+        )));
+        let resolved = resolve(&manifest, &BTreeSet::new(), &package_sources).unwrap();
+
+        InstallationContext::new(project.path(), &manifest.place)
+            .with_sink(sink.clone())
+            .with_observer(observer)
+            .install(package_sources, manifest.package_id(), resolved)
+            .unwrap();
+    };
+
+    // Both packages are downloaded, and the root package and `one-dependency`
+    // each get a link.
+    let observer = Arc::new(CountingObserver::default());
+    install(observer.clone());
+    assert_eq!(observer.counts(), [2, 2, 2, 2, 2, 2]);
+
+    // Installing again reuses both packages, and every link is unchanged.
+    let observer = Arc::new(CountingObserver::default());
+    install(observer.clone());
+    assert_eq!(observer.counts(), [2, 0, 0, 2, 0, 0]);
+}
+
+/// Installs into a memory sink, except that writing anything inside of
+/// `failing_path` fails, as if the install was interrupted there.
+struct FailingSink {
+    inner: Arc<MemorySink>,
+    failing_path: String,
+}
+
+impl InstallSink for FailingSink {
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        if path.to_string_lossy().contains(&self.failing_path) {
+            return Err(io::Error::other("interrupted"));
+        }
+
+        self.inner.write_file(path, contents)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.inner.read_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.read_dir(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.inner.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.inner.is_file(path)
+    }
+}
+
+#[test]
+fn interrupted_install_resumes_where_it_stopped() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let manifest = Manifest::load(project.path()).unwrap();
+    let sink = Arc::new(MemorySink::new());
+
+    let install = |sink: Arc<dyn InstallSink>, observer: Arc<CountingObserver>| {
+        let package_sources = PackageSourceMap::new(Box::new(PackageSource::TestRegistry(
+            TestRegistry::new(&manifest.package.registry),
+        )));
+        let resolved = resolve(&manifest, &BTreeSet::new(), &package_sources).unwrap();
+
+        InstallationContext::new(project.path(), &manifest.place)
+            .with_sink(sink)
+            .with_observer(observer)
+            .install(package_sources, manifest.package_id(), resolved)
+    };
+
+    // The install stops partway through unpacking `minimal`, after
+    // `one-dependency` was installed.
+    let failing_sink = Arc::new(FailingSink {
+        inner: sink.clone(),
+        failing_path: "biff_minimal@0.1.0".to_owned(),
+    });
+    install(failing_sink, Arc::new(CountingObserver::default())).unwrap_err();
+
+    let sentinel = project.path().join(INSTALL_SENTINEL_FILE_NAME);
+    assert_eq!(
+        String::from_utf8_lossy(&sink.read_file(&sentinel).unwrap()),
+        "biff/one-dependency@0.1.0\n"
+    );
+
+    // Only the package that didn't finish is downloaded again.
+    let observer = Arc::new(CountingObserver::default());
+    install(sink.clone(), observer.clone()).unwrap();
+    assert_eq!(observer.counts()[..4], [2, 1, 1, 2]);
+    assert!(!sink.is_file(&sentinel), "A finished install should remove its sentinel");
+    assert!(sink.is_file(
+        &project
+            .path()
+            .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/init.lua")
+    ));
+}
+
+/// Remembers which lines were reported as requiring dev packages.
+#[derive(Default)]
+struct DevRequireObserver {
+    dev_requires: std::sync::Mutex<Vec<(String, usize)>>,
+}
+
+impl InstallObserver for DevRequireObserver {
+    fn on_dev_require(&self, dev_require: &DevRequire) {
+        let file_name = dev_require.path.file_name().unwrap().to_str().unwrap();
+        self.dev_requires
+            .lock()
+            .unwrap()
+            .push((file_name.to_owned(), dev_require.line));
+    }
+}
+
+#[test]
+fn dev_requires_outside_dev_packages_are_reported() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/dev-dependency-required"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let manifest = Manifest::load(project.path()).unwrap();
+    let package_sources = PackageSourceMap::new(Box::new(PackageSource::TestRegistry(
+        TestRegistry::new(&manifest.package.registry),
+    )));
+    let resolved = resolve(&manifest, &BTreeSet::new(), &package_sources).unwrap();
+    let observer = Arc::new(DevRequireObserver::default());
+
+    InstallationContext::new(project.path(), &manifest.place)
+        .with_check_dev_requires(true)
+        .with_observer(observer.clone())
+        .install(package_sources, manifest.package_id(), resolved)
+        .unwrap();
+
+    // The comment and the test file don't count, and neither do the links to
+    // the dev packages themselves.
+    assert_eq!(
+        *observer.dev_requires.lock().unwrap(),
+        [("Shipped.lua".to_owned(), 4)]
+    );
+}
+
+#[test]
+fn check_orphans_succeeds_after_install() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            check_orphans: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    assert!(project.path().join("ServerPackages/OneDependency.lua").exists());
+}
+
+/// Remembers which files were reported as orphaned.
+#[derive(Default)]
+struct OrphanObserver {
+    orphans: std::sync::Mutex<Vec<PathBuf>>,
+}
+
+impl InstallObserver for OrphanObserver {
+    fn on_orphaned_file(&self, path: &Path) {
+        self.orphans.lock().unwrap().push(path.to_owned());
+    }
+}
+
+#[test]
+fn check_orphans_reports_hand_written_links() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let stray_path = project.path().join("ServerPackages/Stray.lua");
+    fs::create_dir_all(stray_path.parent().unwrap()).unwrap();
+    fs::write(&stray_path, "return require(script.Parent.OneDependency)\n").unwrap();
+
+    let manifest = Manifest::load(project.path()).unwrap();
+    let package_sources = PackageSourceMap::new(Box::new(PackageSource::TestRegistry(
+        TestRegistry::new(&manifest.package.registry),
+    )));
+    let resolved = resolve(&manifest, &BTreeSet::new(), &package_sources).unwrap();
+    let observer = Arc::new(OrphanObserver::default());
+
+    InstallationContext::new(project.path(), &manifest.place)
+        .with_check_orphans(true)
+        .with_observer(observer.clone())
+        .install(package_sources, manifest.package_id(), resolved)
+        .unwrap();
+
+    assert_eq!(*observer.orphans.lock().unwrap(), [stray_path.as_path()]);
+    assert!(stray_path.exists(), "Orphans should be left for the user to look at");
+}
+
+#[test]
+fn verify_succeeds_for_cross_realm_links() {
+    for name in [
+        "cross-realm-dependency",
+        "cross-realm-explicit-dependency",
+        "server-depends-on-shared",
+        "combined-types",
+        "reexported-types",
+    ]
+    .iter()
+    {
+        let source_project =
+            Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects")).join(name);
+        let project = TempProject::new(&source_project).unwrap();
+
+        run_install(
+            test_global(),
+            InstallSubcommand {
+                verify: true,
+                ..install_args(project.path())
+            },
+        )
+        .unwrap_or_else(|err| panic!("verifying {} failed: {:#}", name, err));
+    }
+}
+
+#[test]
+fn report_describes_install() {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects/transitive-dependency"));
+    let project = TempProject::new(source_project).unwrap();
+    let report_path = project.path().join("report.json");
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            report: Some(report_path.clone()),
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    let manifest = Manifest::load(project.path()).unwrap();
+    let lockfile = Lockfile::load(project.path()).unwrap().unwrap();
+    let resolved = resolve_from_lockfile(&manifest, &lockfile).unwrap();
+
+    let report: InstallReport =
+        serde_json::from_slice(&fs::read(&report_path).unwrap()).unwrap();
+
+    assert_eq!(report.schema_version, REPORT_SCHEMA_VERSION);
+    assert_eq!(report.packages.len(), resolved.activated.len() - 1);
+
+    let direct: Vec<_> = report
+        .packages
+        .iter()
+        .filter(|package| package.resolved_from == ResolvedFrom::Direct)
+        .map(|package| package.id.to_string())
+        .collect();
+    assert_eq!(direct, vec!["biff/one-dependency@0.1.0"]);
+
+    assert!(report
+        .links
+        .contains(&Path::new("ServerPackages").join("OneDependency.lua")));
+}
+
+#[test]
+fn mirrored_scope_installs_from_mirror() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let config_dir = tempfile::tempdir().unwrap();
+    let config_path = config_dir.path().join("config.toml");
+    fs::write(
+        &config_path,
+        "[mirrors]\nbiff = \"test-registries/mirror-registry\"\n",
+    )
+    .unwrap();
+
+    run_install(
+        GlobalOptions {
+            global_config_path: Some(config_path),
+            ..test_global()
+        },
+        install_args(project.path()),
+    )
+    .unwrap();
+
+    let init = project
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/init.lua");
+    assert_eq!(fs::read_to_string(init).unwrap(), "return \"mirror\"");
+}
+
+#[test]
+fn registries_are_searched_in_order() {
+    let installed_minimal = |registries: &[&str]| {
+        let project = TempProject::new(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-projects/one-dependency"
+        )))
+        .unwrap();
+
+        let manifest_path = project.path().join("wally.toml");
+        let mut manifest = fs::read_to_string(&manifest_path).unwrap();
+        for registry in registries {
+            manifest.push_str(&format!("\n[[registries]]\nindex = \"{}\"\n", registry));
+        }
+        fs::write(&manifest_path, manifest).unwrap();
+
+        run_install(test_global(), install_args(project.path())).unwrap();
+
+        fs::read_to_string(
+            project
+                .path()
+                .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/init.lua"),
+        )
+        .unwrap()
+    };
+
+    // Both registries have biff/minimal@0.1.0, but only the primary registry
+    // has biff/one-dependency, so it's always found further down the list.
+    let mirror = "test-registries/mirror-registry";
+    let primary = "test-registries/primary-registry";
+
+    assert_eq!(installed_minimal(&[mirror]), "return \"mirror\"");
+    assert_eq!(installed_minimal(&[mirror, primary]), "return \"mirror\"");
+    assert_ne!(installed_minimal(&[primary, mirror]), "return \"mirror\"");
+    assert_ne!(installed_minimal(&[]), "return \"mirror\"");
+}
+
+#[test]
+fn manifest_outside_project_is_installed_into_project() {
+    let project = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    )))
+    .unwrap();
+
+    // Move the manifest somewhere else entirely, under a different name.
+    let manifest_dir = tempfile::tempdir().unwrap();
+    let manifest_path = manifest_dir.path().join("generated.toml");
+    fs::rename(project.path().join("wally.toml"), &manifest_path).unwrap();
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            manifest_path: Some(manifest_path),
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    assert!(project.path().join("wally.lock").exists());
+    assert!(project
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal")
+        .exists());
+    assert!(!manifest_dir.path().join("wally.lock").exists());
+    assert!(!manifest_dir.path().join("ServerPackages").exists());
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            dry_run: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    assert!(!project.path().join("ServerPackages").exists());
+    assert!(!project.path().join("wally.lock").exists());
+}
+
+#[test]
+fn incremental_install_reuses_packages() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let index = project.path().join("ServerPackages/_Index");
+    let marker = index.join("biff_minimal@0.1.0/minimal/marker.lua");
+    let stale = index.join("biff_stale@0.1.0");
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    fs::write(&marker, "return true").unwrap();
+    fs::create_dir_all(&stale).unwrap();
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    assert!(marker.exists(), "Installed package should have been reused");
+    assert!(!stale.exists(), "Stale index entry should have been removed");
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            clean: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+    assert!(!marker.exists(), "Package should have been reinstalled");
+}
+
+#[test]
+fn interrupted_install_is_recovered() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let sentinel = project.path().join(INSTALL_SENTINEL_FILE_NAME);
+    let module = project
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/init.lua");
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    assert!(!sentinel.exists(), "A finished install should remove its sentinel");
+
+    // Simulate an install that was cancelled partway through writing a
+    // package, which an incremental install would otherwise reuse as-is.
+    fs::write(&sentinel, "").unwrap();
+    fs::remove_file(&module).unwrap();
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    assert!(module.exists(), "The half-written package should have been reinstalled");
+    assert!(!sentinel.exists(), "A finished install should remove its sentinel");
+}
+
+#[test]
+fn targeted_install_only_reinstalls_target() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/latest"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let server_packages = project.path().join("ServerPackages");
+    let index = server_packages.join("_Index");
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    // A and B depend on incompatible versions of the indirect package, so A's
+    // version of it is only needed by A and is reinstalled along with it.
+    let target_marker = index.join("diamond-graph_direct-dependency-a@0.1.1/direct-dependency-a/marker.lua");
+    let subtree_marker = index.join("diamond-graph_indirect-dependency-a@0.1.1/indirect-dependency-a/marker.lua");
+    let other_marker = index.join("diamond-graph_direct-dependency-b@0.1.0/direct-dependency-b/marker.lua");
+    let other_subtree_marker = index.join("diamond-graph_indirect-dependency-a@0.2.1/indirect-dependency-a/marker.lua");
+    let stale = index.join("biff_stale@0.1.0");
+
+    for marker in &[&target_marker, &subtree_marker, &other_marker, &other_subtree_marker] {
+        fs::write(marker, "return true").unwrap();
+    }
+    fs::create_dir_all(&stale).unwrap();
+    fs::remove_file(server_packages.join("A.lua")).unwrap();
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            package: Some("diamond-graph/direct-dependency-a".parse().unwrap()),
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    assert!(!target_marker.exists(), "Target should have been reinstalled");
+    assert!(!subtree_marker.exists(), "Target's dependency should have been reinstalled");
+    assert!(other_marker.exists(), "Other packages should be untouched");
+    assert!(other_subtree_marker.exists(), "Other packages' dependencies should be untouched");
+    assert!(stale.exists(), "Other index entries should be untouched");
+    assert!(server_packages.join("A.lua").exists(), "Root link to the target should be rewritten");
+
+    let err = run_install(
+        test_global(),
+        InstallSubcommand {
+            package: Some("biff/minimal".parse().unwrap()),
+            ..install_args(project.path())
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "biff/minimal is not a dependency of diamond-graph/root@0.1.0"
+    );
+}
+
+#[test]
+fn unchanged_links_are_not_rewritten() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/latest"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let root_link = project.path().join("ServerPackages/A.lua");
+    let package_link = project
+        .path()
+        .join("ServerPackages/_Index/diamond-graph_direct-dependency-a@0.1.1/Indirect.lua");
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    let root_modified = fs::metadata(&root_link).unwrap().modified().unwrap();
+    let package_modified = fs::metadata(&package_link).unwrap().modified().unwrap();
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+    assert_eq!(
+        fs::metadata(&root_link).unwrap().modified().unwrap(),
+        root_modified,
+        "Root link should not have been rewritten"
+    );
+    assert_eq!(
+        fs::metadata(&package_link).unwrap().modified().unwrap(),
+        package_modified,
+        "Package link should not have been rewritten"
+    );
+}
+
+#[test]
+fn explain_resolution_describes_each_choice() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/latest"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let explanation_path = project.path().join("resolution.txt");
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            explain_resolution: Some(Some(explanation_path.clone())),
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    let explanation = fs::read_to_string(&explanation_path).unwrap();
+
+    // A and B both depend on the indirect package, and each requirement is
+    // described alongside the version that was chosen for it.
+    assert!(
+        explanation.contains("diamond-graph/direct-dependency-a@0.1.1 requires diamond-graph/indirect-dependency-a@"),
+        "{}",
+        explanation
+    );
+    assert!(
+        explanation.contains("diamond-graph/direct-dependency-b@0.1.0 requires diamond-graph/indirect-dependency-a@"),
+        "{}",
+        explanation
+    );
+    assert!(explanation.contains("Chose 0.2.1 because it is in the lockfile"), "{}", explanation);
+}
+
+#[test]
+fn frozen_installs_from_lockfile() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    run_install(test_global(), install_args(project.path())).unwrap();
+    let links = [
+        project.path().join("ServerPackages/OneDependency.lua"),
+        project
+            .path()
+            .join("ServerPackages/_Index/biff_one-dependency@0.1.0/Minimal.lua"),
+    ];
+    let installed: Vec<_> = links
+        .iter()
+        .map(|link| fs::read_to_string(link).unwrap())
+        .collect();
+
+    for link in &links {
+        fs::remove_file(link).unwrap();
+    }
+
+    run_install(
+        test_global(),
+        InstallSubcommand {
+            frozen: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap();
+
+    for (link, contents) in links.iter().zip(installed) {
+        assert_eq!(fs::read_to_string(link).unwrap(), contents);
+    }
+}
+
+#[test]
+fn frozen_fails_when_package_is_missing() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    fs::remove_dir_all(project.path().join("ServerPackages/_Index/biff_minimal@0.1.0")).unwrap();
+
+    let error = run_install(
+        test_global(),
+        InstallSubcommand {
+            frozen: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("biff/minimal@0.1.0"), "{}", error);
+    assert!(error.contains("--frozen"), "{}", error);
+}
+
+#[test]
+fn frozen_fails_without_lockfile() {
+    let project = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    )))
+    .unwrap();
+
+    let error = run_install(
+        test_global(),
+        InstallSubcommand {
+            frozen: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("requires a lockfile"), "{}", error);
+}
+
+#[test]
+fn frozen_fails_when_lockfile_is_out_of_date() {
+    let project = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/dated"
+    )))
+    .unwrap();
+
+    let manifest_path = project.path().join("wally.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    fs::write(&manifest_path, manifest.replace("direct-dependency-a@0.1.0", "direct-dependency-a@0.2.0")).unwrap();
+
+    let error = run_install(
+        test_global(),
+        InstallSubcommand {
+            frozen: true,
+            ..install_args(project.path())
+        },
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("out of date"), "{}", error);
+}
+
+#[test]
+fn quiet_install_prints_nothing() {
+    let project = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    )))
+    .unwrap();
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    // The binary can't use the test registry, but a frozen install never
+    // reads the index, so any registry URL will do.
+    let manifest_path = project.path().join("wally.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    fs::write(
+        &manifest_path,
+        manifest.replace("test-registries/primary-registry", "https://example.com/index"),
+    )
+    .unwrap();
+
+    let run_wally = |quiet: bool| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_wally"));
+        command
+            .args(["install", "--frozen", "--no-cache", "--project-path"])
+            .arg(project.path());
+        if quiet {
+            command.arg("--quiet");
+        }
+
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        output
+    };
+
+    let output = run_wally(false);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Verified"));
+
+    let output = run_wally(true);
+    assert!(output.stdout.is_empty(), "{:?}", output);
+    assert!(output.stderr.is_empty(), "{:?}", output);
+}
+
+#[test]
+fn package_cache_is_shared_between_projects() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let cache = tempfile::tempdir().unwrap();
+    let first = TempProject::new(source_project).unwrap();
+    let second = TempProject::new(source_project).unwrap();
+
+    run_install(
+        GlobalOptions {
+            package_cache_path: Some(cache.path().to_owned()),
+            ..test_global()
+        },
+        install_args(first.path()),
+    )
+    .unwrap();
+
+    // Mark the cached copy so that we can tell whether the second project
+    // was installed from the cache or downloaded from the registry.
+    let cached_package = cache.path().join("biff_minimal@0.1.0/contents");
+    assert!(cached_package.is_dir(), "Package should have been cached");
+    fs::write(cached_package.join("marker.lua"), "return true").unwrap();
+
+    run_install(
+        GlobalOptions {
+            package_cache_path: Some(cache.path().to_owned()),
+            ..test_global()
+        },
+        install_args(second.path()),
+    )
+    .unwrap();
+
+    assert!(second
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/marker.lua")
+        .exists());
+    assert_eq!(
+        fs::read_to_string(first.path().join("wally.lock")).unwrap(),
+        fs::read_to_string(second.path().join("wally.lock")).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn link_cache_links_packages_to_the_cache() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let cache = tempfile::tempdir().unwrap();
+    let first = TempProject::new(source_project).unwrap();
+    let second = TempProject::new(source_project).unwrap();
+    let cached_package = cache.path().join("biff_minimal@0.1.0/contents");
+
+    // The first project downloads the package, and the second installs it
+    // from the cache.
+    for project in [&first, &second] {
+        run_install(
+            GlobalOptions {
+                package_cache_path: Some(cache.path().to_owned()),
+                link_cache: true,
+                ..test_global()
+            },
+            install_args(project.path()),
+        )
+        .unwrap();
+
+        let installed = project
+            .path()
+            .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal");
+        assert!(fs::symlink_metadata(&installed).unwrap().file_type().is_symlink());
+        assert_eq!(
+            fs::canonicalize(&installed).unwrap(),
+            fs::canonicalize(&cached_package).unwrap()
+        );
+        assert!(installed.join("init.lua").is_file());
+        assert!(project.path().join("ServerPackages/Minimal.lua").is_file());
+    }
+
+    // Installing again reuses the linked package without touching the cache.
+    run_install(
+        GlobalOptions {
+            package_cache_path: Some(cache.path().to_owned()),
+            ..test_global()
+        },
+        install_args(first.path()),
+    )
+    .unwrap();
+    run_install(
+        GlobalOptions {
+            package_cache_path: Some(cache.path().to_owned()),
+            link_cache: true,
+            ..test_global()
+        },
+        install_args(second.path()),
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read_dir(&cached_package).unwrap().count(),
+        1,
+        "the cached package should be unchanged"
+    );
+}
+
+#[test]
+fn offline_install_uses_package_cache() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let cache = tempfile::tempdir().unwrap();
+    let online = TempProject::new(source_project).unwrap();
+    run_install(
+        GlobalOptions {
+            package_cache_path: Some(cache.path().to_owned()),
+            ..test_global()
+        },
+        install_args(online.path()),
+    )
+    .unwrap();
+
+    let offline = TempProject::new(source_project).unwrap();
+    run_install(
+        GlobalOptions {
+            offline: true,
+            package_cache_path: Some(cache.path().to_owned()),
+            ..test_global()
+        },
+        install_args(offline.path()),
+    )
+    .unwrap();
+    assert!(offline
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal")
+        .is_dir());
+
+    let empty_cache = tempfile::tempdir().unwrap();
+    let uncached = TempProject::new(source_project).unwrap();
+    let error = run_install(
+        GlobalOptions {
+            offline: true,
+            package_cache_path: Some(empty_cache.path().to_owned()),
+            ..test_global()
+        },
+        install_args(uncached.path()),
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("offline"), "{}", error);
+}
+
+#[test]
+fn tampered_package_fails_verification() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    let lockfile_path = project.path().join("wally.lock");
+    let lockfile = fs::read_to_string(&lockfile_path).unwrap();
+    let checksum_start = lockfile.find("checksum = \"").unwrap() + "checksum = \"".len();
+    let checksum_end = checksum_start + lockfile[checksum_start..].find('"').unwrap();
+    let tampered = format!(
+        "{}{}{}",
+        &lockfile[..checksum_start],
+        "0".repeat(checksum_end - checksum_start),
+        &lockfile[checksum_end..]
+    );
+    fs::write(&lockfile_path, tampered).unwrap();
+
+    let error = run_install(
+        test_global(),
+        InstallSubcommand {
+            clean: true,
+            ..install_args(project.path())
+        },
+    )
+    .expect_err("Install should have failed");
+    assert!(
+        error.to_string().contains("Checksum mismatch"),
+        "Expected a checksum mismatch error. Instead we got: {:#}",
+        error
+    );
+
+    let error = run_install(
+        test_global(),
+        InstallSubcommand {
+            locked: true,
+            clean: true,
+            ..install_args(project.path())
+        },
+    )
+    .expect_err("Install should have failed");
+    assert!(
+        error.to_string().contains("Checksum mismatch"),
+        "Expected a checksum mismatch error. Instead we got: {:#}",
+        error
+    );
+}
+
 #[test]
 fn private_with_public_dependency() {
     let project = run_install_test("private-with-public-dependency");
@@ -56,12 +1651,47 @@ fn cross_realm_explicit_dependency() {
     assert_dir_snapshot!(project.path());
 }
 
+#[test]
+fn cross_realm_dependency_without_place_names_packages() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/cross-realm-explicit-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+    let manifest_path = project.path().join("wally.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    let manifest = manifest.replace("shared-packages = \"game.ReplicatedStorage.Packages\"", "");
+    fs::write(&manifest_path, manifest).unwrap();
+
+    let err = run_install(test_global(), install_args(project.path())).unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref::<InstallError>(),
+        Some(InstallError::MissingPlacePath {
+            realm: Realm::Shared,
+            ..
+        })
+    ));
+
+    let message = err.to_string();
+    assert!(message.contains("biff/minimal-shared@0.1.0"), "{}", message);
+    assert!(message.contains("biff/server-depends-on-shared@0.1.0"), "{}", message);
+    assert!(message.contains("shared-packages = "), "{}", message);
+}
+
 #[test]
 fn manifest_links() {
     let project = run_install_test("manifest-links");
     assert_dir_snapshot!(project.path());
 }
 
+#[test]
+fn custom_package_dirs() {
+    let project = run_install_test("custom-package-dirs");
+    assert_dir_snapshot!(project.path());
+}
+
 #[test]
 fn locked_pass() {
     let result = run_locked_install("diamond-graph/root/latest");
@@ -71,8 +1701,21 @@ fn locked_pass() {
 
 #[test]
 fn locked_catches_dated_packages() {
-    let result = run_locked_install("diamond-graph/root/dated");
-    assert!(result.is_err(), "Should fail!");
+    let err = run_locked_install("diamond-graph/root/dated").unwrap_err();
+    let message = err.to_string();
+
+    for drifted in [
+        "diamond-graph/direct-dependency-a from v0.1.0 to v0.1.1",
+        "diamond-graph/indirect-dependency-a from v0.1.0 to v0.1.1",
+        "diamond-graph/indirect-dependency-a from v0.2.0 to v0.2.1",
+    ] {
+        assert!(
+            message.contains(drifted),
+            "Expected {} in the error. Instead we got: {}",
+            drifted,
+            message
+        );
+    }
 }
 
 fn run_locked_install(name: &str) -> Result<(), anyhow::Error> {
@@ -81,17 +1724,14 @@ fn run_locked_install(name: &str) -> Result<(), anyhow::Error> {
 
     let project = TempProject::new(&source_project).unwrap();
 
-    Args {
-        global: GlobalOptions {
-            test_registry: true,
-            ..Default::default()
-        },
-        subcommand: Subcommand::Install(InstallSubcommand {
-            project_path: project.path().to_owned(),
+    run_install(
+        test_global(),
+        InstallSubcommand {
             locked: true,
-        }),
-    }
-    .run()
+            clean: true,
+            ..install_args(project.path())
+        },
+    )
 }
 
 fn run_install_test(name: &str) -> TempProject {
@@ -100,18 +1740,7 @@ fn run_install_test(name: &str) -> TempProject {
 
     let project = TempProject::new(&source_project).unwrap();
 
-    let args = Args {
-        global: GlobalOptions {
-            test_registry: true,
-            ..Default::default()
-        },
-        subcommand: Subcommand::Install(InstallSubcommand {
-            project_path: project.path().to_owned(),
-            locked: false,
-        }),
-    };
-
-    args.run().unwrap();
+    run_install(test_global(), install_args(project.path())).unwrap();
     
     project
 }
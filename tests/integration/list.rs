@@ -0,0 +1,61 @@
+use crate::temp_project::TempProject;
+use libwally::{Args, GlobalOptions, ListSubcommand, ResolveSubcommand, Subcommand};
+use std::path::Path;
+
+#[test]
+fn list_after_resolve() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    run_resolve(project.path()).unwrap();
+    run_list(project.path(), false).unwrap();
+    run_list(project.path(), true).unwrap();
+}
+
+#[test]
+fn list_fails_without_lockfile() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    let error = run_list(project.path(), false).unwrap_err().to_string();
+    assert!(error.contains("has no lockfile yet"), "{}", error);
+}
+
+fn run_list(path: &Path, json: bool) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::List(ListSubcommand {
+            project_path: path.to_owned(),
+            json,
+            features: Default::default(),
+        }),
+    }
+    .run()
+}
+
+fn run_resolve(path: &Path) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Resolve(ResolveSubcommand {
+            project_path: path.to_owned(),
+            locked: false,
+            features: Default::default(),
+            message_format: Default::default(),
+        }),
+    }
+    .run()
+}
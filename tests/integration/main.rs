@@ -2,7 +2,15 @@
 mod util;
 mod temp_project;
 
+mod clean;
+mod git;
 mod install;
+mod list;
+mod outdated;
+mod path;
 mod publish;
 mod read_projects;
+mod resolve;
+mod tree;
 mod update;
+mod why;
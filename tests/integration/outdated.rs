@@ -0,0 +1,90 @@
+use crate::temp_project::TempProject;
+use libwally::{Args, GlobalOptions, OutdatedSubcommand, Subcommand, UpdateSubcommand};
+use std::path::Path;
+
+#[test]
+fn outdated_dependencies_fail_with_exit_code() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/dated"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    let result = run_outdated(project.path(), true);
+
+    assert!(
+        result.is_err(),
+        "direct-dependency-a@0.1.0 is locked but 0.1.1 is available"
+    );
+}
+
+#[test]
+fn outdated_dependencies_pass_without_exit_code() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/dated"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    run_outdated(project.path(), false).unwrap();
+}
+
+#[test]
+fn no_lockfile_is_an_error() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/fresh"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    let err = run_outdated(project.path(), false).unwrap_err();
+
+    assert!(
+        err.to_string().contains("has no lockfile yet"),
+        "unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn up_to_date_after_update() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/dated"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Update(UpdateSubcommand {
+            project_path: project.path().to_owned(),
+            package_specs: Vec::new(),
+            features: Default::default(),
+        }),
+    }
+    .run()
+    .unwrap();
+
+    run_outdated(project.path(), true).unwrap();
+}
+
+fn run_outdated(path: &Path, exit_code: bool) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Outdated(OutdatedSubcommand {
+            project_path: path.to_owned(),
+            exit_code,
+        }),
+    }
+    .run()
+}
@@ -0,0 +1,170 @@
+use crate::temp_project::TempProject;
+use crate::util::{install_args, run_install, test_global};
+use fs_err as fs;
+use libwally::lockfile::{LockPackage, Lockfile};
+use std::path::Path;
+
+#[test]
+fn path_dependency_with_registry_dependencies() {
+    let (library, project) = path_dependency_projects();
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    let server_packages = project.path().join("ServerPackages");
+    assert!(server_packages.join("Library.lua").exists());
+    assert!(server_packages
+        .join("_Index/biff_one-dependency@0.1.0/one-dependency/src/init.lua")
+        .exists());
+
+    // The library's own dependency still comes from the registry.
+    assert!(server_packages
+        .join("_Index/biff_one-dependency@0.1.0/Minimal.lua")
+        .exists());
+    assert!(server_packages
+        .join("_Index/biff_minimal@0.1.0/minimal")
+        .exists());
+
+    let lockfile = Lockfile::load(project.path()).unwrap().unwrap();
+    let locked_path = lockfile
+        .packages
+        .iter()
+        .find_map(|package| match package {
+            LockPackage::Path(package) => Some(package.path.clone()),
+            _ => None,
+        })
+        .expect("the lockfile has no path package");
+    assert_eq!(locked_path, relative_path(&library));
+    assert!(!lockfile
+        .checksums()
+        .keys()
+        .any(|package_id| package_id.name().name() == "one-dependency"));
+}
+
+#[test]
+fn path_dependency_changes_are_installed() {
+    let (library, project) = path_dependency_projects();
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    fs::write(library.path().join("src/init.lua"), "return \"changed\"\n").unwrap();
+
+    // The version didn't change, but the package is copied again anyway.
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    let installed = project
+        .path()
+        .join("ServerPackages/_Index/biff_one-dependency@0.1.0/one-dependency/src/init.lua");
+    assert_eq!(
+        fs::read_to_string(installed).unwrap(),
+        "return \"changed\"\n"
+    );
+}
+
+#[test]
+fn patch_replaces_transitive_dependency() {
+    let (patched, project) = patched_dependency_projects("0.1.0");
+
+    run_install(test_global(), install_args(project.path())).unwrap();
+
+    // `one-dependency` still asks for `biff/minimal@0.1.0`, but gets the copy
+    // next to the project instead of the one in the registry.
+    let installed = project
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/src/init.lua");
+    assert_eq!(
+        fs::read_to_string(installed).unwrap(),
+        "return \"patched\"\n"
+    );
+
+    let lockfile = Lockfile::load(project.path()).unwrap().unwrap();
+    let locked_path = lockfile
+        .packages
+        .iter()
+        .find_map(|package| match package {
+            LockPackage::Path(package) => Some(package.path.clone()),
+            _ => None,
+        })
+        .expect("the lockfile has no path package");
+    assert_eq!(locked_path, relative_path(&patched));
+}
+
+#[test]
+fn patch_must_satisfy_requirements() {
+    let (_patched, project) = patched_dependency_projects("0.2.0");
+
+    let err = run_install(test_global(), install_args(project.path())).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "biff/one-dependency@0.1.0 requires biff/minimal@>=0.1.0, <0.2.0, but biff/minimal is \
+         patched with version 0.2.0, which does not satisfy it"
+    );
+}
+
+/// A copy of the `minimal` package at `version`, along with a project next to
+/// it that depends on `one-dependency` from the registry and patches its
+/// dependency on `minimal` with the copy.
+fn patched_dependency_projects(version: &str) -> (TempProject, TempProject) {
+    let test_projects = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects"));
+    let patched = TempProject::new(&test_projects.join("minimal")).unwrap();
+    let project = TempProject::new(&test_projects.join("minimal")).unwrap();
+
+    let patched_manifest = fs::read_to_string(patched.path().join("wally.toml"))
+        .unwrap()
+        .replace("version = \"0.1.0\"", &format!("version = \"{}\"", version));
+    fs::write(patched.path().join("wally.toml"), patched_manifest).unwrap();
+    fs::write(patched.path().join("src/init.lua"), "return \"patched\"\n").unwrap();
+
+    let manifest = format!(
+        r#"[package]
+name = "biff/patch"
+version = "0.1.0"
+license = "MIT"
+realm = "server"
+registry = "test-registries/primary-registry"
+
+[server-dependencies]
+OneDependency = "biff/one-dependency@0.1.0"
+
+[patch]
+"biff/minimal" = {{ path = "{}" }}
+"#,
+        relative_path(&patched)
+    );
+    fs::write(project.path().join("wally.toml"), manifest).unwrap();
+
+    (patched, project)
+}
+
+/// A copy of the `one-dependency` package along with a project next to it
+/// that depends on it by path.
+fn path_dependency_projects() -> (TempProject, TempProject) {
+    let test_projects = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects"));
+    let library = TempProject::new(&test_projects.join("one-dependency")).unwrap();
+    let project = TempProject::new(&test_projects.join("minimal")).unwrap();
+
+    let manifest = format!(
+        r#"[package]
+name = "biff/path-dependency"
+version = "0.1.0"
+license = "MIT"
+realm = "server"
+registry = "test-registries/primary-registry"
+
+[server-dependencies]
+Library = {{ path = "{}" }}
+"#,
+        relative_path(&library)
+    );
+    fs::write(project.path().join("wally.toml"), manifest).unwrap();
+
+    (library, project)
+}
+
+/// The path to `library` from the project next to it, since both are kept in
+/// the same temporary directory.
+fn relative_path(library: &TempProject) -> String {
+    format!(
+        "../{}",
+        library.path().file_name().unwrap().to_str().unwrap()
+    )
+}
@@ -0,0 +1,272 @@
+use crate::temp_project::TempProject;
+use crate::util::{install_args, run_install, test_global};
+use fs_err as fs;
+use libwally::{
+    lockfile::Lockfile, Args, FeatureOptions, GlobalOptions, ResolveSubcommand, Subcommand,
+};
+use std::path::Path;
+
+#[test]
+fn resolve_matches_install_lockfile() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let installed = TempProject::new(source_project).unwrap();
+    let resolved = TempProject::new(source_project).unwrap();
+
+    run_install(test_global(), install_args(installed.path())).unwrap();
+    run_resolve(resolved.path(), false).unwrap();
+
+    assert!(!resolved.path().join("ServerPackages").exists());
+
+    // Resolving doesn't download anything, so it can't know the checksums of
+    // packages that weren't locked before. Everything else should match.
+    let without_checksums = |path: &Path| -> String {
+        fs::read_to_string(path.join("wally.lock"))
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with("checksum = "))
+            .map(|line| format!("{}\n", line))
+            .collect()
+    };
+    assert_eq!(
+        without_checksums(installed.path()),
+        without_checksums(resolved.path())
+    );
+
+    // Resolving an installed project keeps the checksums it already has.
+    let lockfile = fs::read_to_string(installed.path().join("wally.lock")).unwrap();
+    run_resolve(installed.path(), false).unwrap();
+    assert_eq!(
+        lockfile,
+        fs::read_to_string(installed.path().join("wally.lock")).unwrap()
+    );
+}
+
+#[test]
+fn locked_resolve_requires_current_lockfile() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    let error = run_resolve(project.path(), true).unwrap_err().to_string();
+    assert!(error.contains("out of date"), "{}", error);
+    assert!(!project.path().join("wally.lock").exists());
+
+    run_resolve(project.path(), false).unwrap();
+    run_resolve(project.path(), true).unwrap();
+}
+
+#[test]
+fn features_enable_optional_dependencies() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/optional-dependency"
+    ));
+
+    let resolved_packages = |features: &[&str], all_features: bool| -> Vec<String> {
+        let project = TempProject::new(source_project).unwrap();
+        let features = FeatureOptions {
+            features: features.iter().map(|feature| feature.to_string()).collect(),
+            all_features,
+        };
+        run_resolve_with_features(project.path(), features).unwrap();
+
+        Lockfile::load(project.path())
+            .unwrap()
+            .unwrap()
+            .as_ids()
+            .map(|id| id.to_string())
+            .filter(|id| !id.starts_with("biff/optional-dependency@"))
+            .collect()
+    };
+
+    assert!(resolved_packages(&[], false).is_empty());
+    assert_eq!(
+        resolved_packages(&["minimal"], false),
+        ["biff/minimal@0.1.0"]
+    );
+
+    // OneDependency requires Minimal unconditionally, so enabling Minimal as
+    // well unifies with it.
+    let everything = ["biff/minimal@0.1.0", "biff/one-dependency@0.1.0"];
+    assert_eq!(resolved_packages(&["extras"], false), everything);
+    assert_eq!(resolved_packages(&["minimal", "extras"], false), everything);
+    assert_eq!(resolved_packages(&[], true), everything);
+
+    let project = TempProject::new(source_project).unwrap();
+    let error = run_resolve_with_features(
+        project.path(),
+        FeatureOptions {
+            features: vec!["missing".to_owned()],
+            all_features: false,
+        },
+    )
+    .unwrap_err();
+    assert!(
+        error.to_string().contains("has no feature named missing"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn newer_wally_version_is_required() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/minimal"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    // A realm this version doesn't know about doesn't hide the requirement.
+    let manifest = fs::read_to_string(project.path().join("wally.toml"))
+        .unwrap()
+        .replace("[package]\n", "[package]\nwally-version = \">=999.0.0\"\n")
+        .replace("realm = \"server\"", "realm = \"client\"");
+    fs::write(project.path().join("wally.toml"), manifest).unwrap();
+
+    let error = run_resolve(project.path(), false).unwrap_err().to_string();
+    assert!(
+        error.starts_with("This project requires Wally >=999.0.0, but this is Wally "),
+        "{}",
+        error
+    );
+    assert!(!project.path().join("wally.lock").exists());
+}
+
+#[test]
+fn malformed_place_path_is_rejected() {
+    let project = dependency_project("0.1.0");
+
+    let manifest_path = project.path().join("wally.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    fs::write(
+        &manifest_path,
+        format!(
+            "{}\n[place]\nserver-packages = \"game.ServerScriptService.Packages;\"\n",
+            manifest
+        ),
+    )
+    .unwrap();
+
+    let error = format!("{:#}", run_resolve(project.path(), false).unwrap_err());
+    assert!(
+        error.contains(
+            "[place] server-packages = \"game.ServerScriptService.Packages;\" is not a path to a \
+             Roblox instance: unexpected `;` at character 34"
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn version_requirements_choose_expected_versions() {
+    // diamond-graph/indirect-dependency-a has versions 0.1.0, 0.1.1, 0.2.0 and
+    // 0.2.1 in the test registry.
+    let cases = [
+        ("0.1.0", "0.1.1"),
+        ("^0.2.0", "0.2.1"),
+        ("~0.1", "0.1.1"),
+        ("=0.2.0", "0.2.0"),
+        ("*", "0.2.1"),
+        ("0.1.*", "0.1.1"),
+        ("0.x", "0.2.1"),
+        (">=0.1.0, <0.2.0", "0.1.1"),
+        (">0.1.0, <=0.2.0", "0.2.0"),
+        ("<0.2.1", "0.2.0"),
+    ];
+
+    for (requirement, expected) in cases {
+        let project = dependency_project(requirement);
+        run_resolve(project.path(), false).unwrap();
+
+        let resolved: Vec<String> = Lockfile::load(project.path())
+            .unwrap()
+            .unwrap()
+            .as_ids()
+            .filter(|id| id.name().name() == "indirect-dependency-a")
+            .map(|id| id.version().to_string())
+            .collect();
+        assert_eq!(resolved, [expected], "for requirement {}", requirement);
+    }
+}
+
+#[test]
+fn ambiguous_version_requirements_are_rejected() {
+    for requirement in [">0.1.*", "0.1.0 - 0.2.0", "^0.1 || ^0.2"] {
+        let project = dependency_project(requirement);
+        let error = run_resolve(project.path(), false).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("invalid version requirement"),
+            "{:#}",
+            error
+        );
+        assert!(!project.path().join("wally.lock").exists());
+    }
+}
+
+/// A project that depends on diamond-graph/indirect-dependency-a with the
+/// given version requirement.
+fn dependency_project(requirement: &str) -> TempProject {
+    let project = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/minimal"
+    )))
+    .unwrap();
+
+    let manifest = format!(
+        r#"[package]
+name = "biff/version-requirements"
+version = "0.1.0"
+license = "MIT"
+realm = "server"
+registry = "test-registries/primary-registry"
+
+[server-dependencies]
+Indirect = "diamond-graph/indirect-dependency-a@{}"
+"#,
+        requirement
+    );
+    fs::write(project.path().join("wally.toml"), manifest).unwrap();
+
+    project
+}
+
+fn run_resolve(path: &Path, locked: bool) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Resolve(ResolveSubcommand {
+            project_path: path.to_owned(),
+            locked,
+            features: Default::default(),
+            message_format: Default::default(),
+        }),
+    }
+    .run()
+}
+
+fn run_resolve_with_features(path: &Path, features: FeatureOptions) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Resolve(ResolveSubcommand {
+            project_path: path.to_owned(),
+            locked: false,
+            features,
+            message_format: Default::default(),
+        }),
+    }
+    .run()
+}
@@ -0,0 +1,45 @@
+use crate::temp_project::TempProject;
+use libwally::{Args, GlobalOptions, Subcommand, TreeSubcommand};
+use std::path::Path;
+use std::str::FromStr;
+
+#[test]
+fn tree_of_diamond_graph() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/latest"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    run_tree(project.path(), None).unwrap();
+}
+
+#[test]
+fn tree_fails_for_package_outside_the_graph() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/latest"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    let result = run_tree(project.path(), Some("biff/not-a-dependency"));
+
+    assert!(result.is_err(), "biff/not-a-dependency is not in the graph");
+}
+
+fn run_tree(path: &Path, package: Option<&str>) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Tree(TreeSubcommand {
+            project_path: path.to_owned(),
+            depth: None,
+            package: package.map(|name| FromStr::from_str(name).unwrap()),
+        }),
+    }
+    .run()
+}
@@ -146,6 +146,7 @@ fn run_update_with_specs(project: &TempProject, specs: Vec<PackageSpec>) -> anyh
         subcommand: Subcommand::Update(UpdateSubcommand {
             project_path: project.path().to_owned(),
             package_specs: specs,
+            features: Default::default(),
         }),
     }
     .run()
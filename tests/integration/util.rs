@@ -1,6 +1,7 @@
 use std::collections::BTreeMap;
 use std::path::Path;
 
+use libwally::{Args, GlobalOptions, InstallSubcommand, Subcommand};
 use serde::{Deserialize, Serialize};
 
 #[macro_export]
@@ -38,3 +39,53 @@ pub fn read_path(path: &Path) -> anyhow::Result<Entry> {
         Ok(Entry::File(contents))
     }
 }
+
+/// Global options that point Wally at the test registries, for tests to
+/// override with the options they're about.
+pub fn test_global() -> GlobalOptions {
+    GlobalOptions {
+        test_registry: true,
+        ..Default::default()
+    }
+}
+
+/// Run `wally install` with `global` and `install`, which usually start from
+/// [`test_global`] and [`install_args`].
+pub fn run_install(global: GlobalOptions, install: InstallSubcommand) -> anyhow::Result<()> {
+    Args {
+        global,
+        subcommand: Subcommand::Install(install),
+    }
+    .run()
+}
+
+/// Arguments for `wally install` in the project at `project_path` with every
+/// flag left at its default, for tests to override with the flags they're
+/// about.
+pub fn install_args(project_path: &Path) -> InstallSubcommand {
+    InstallSubcommand {
+        project_path: project_path.to_owned(),
+        manifest_path: None,
+        locked: false,
+        dry_run: false,
+        clean: false,
+        frozen: false,
+        no_dev: false,
+        check_orphans: false,
+        check_dev_requires: false,
+        require_types: false,
+        verify: false,
+        fail_fast: false,
+        report: None,
+        features: Default::default(),
+        message_format: Default::default(),
+        package: None,
+        no_links: false,
+        gitignore: false,
+        force_gitignore: false,
+        production: false,
+        layout: None,
+        explain_resolution: None,
+        allow_scripts: false,
+    }
+}
@@ -0,0 +1,44 @@
+use crate::temp_project::TempProject;
+use libwally::{Args, GlobalOptions, Subcommand, WhySubcommand};
+use std::path::Path;
+use std::str::FromStr;
+
+#[test]
+fn why_explains_transitive_dependency() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/latest"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    run_why(project.path(), "diamond-graph/indirect-dependency-a").unwrap();
+}
+
+#[test]
+fn why_fails_for_package_outside_the_graph() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/latest"
+    ));
+
+    let project = TempProject::new(source_project).unwrap();
+
+    let result = run_why(project.path(), "biff/not-a-dependency");
+
+    assert!(result.is_err(), "biff/not-a-dependency is not in the graph");
+}
+
+fn run_why(path: &Path, package: &str) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Why(WhySubcommand {
+            project_path: path.to_owned(),
+            package: FromStr::from_str(package).unwrap(),
+        }),
+    }
+    .run()
+}
@@ -21,6 +21,7 @@ use figment::{
 };
 use libwally::{
     manifest::{Manifest, MANIFEST_FILE_NAME},
+    package_contents::PackageContents,
     package_id::PackageId,
     package_index::PackageIndex,
     package_name::PackageName,
@@ -152,7 +153,7 @@ async fn publish(
 
     index.update()?;
 
-    let manifest = get_manifest(&mut archive).status(Status::BadRequest)?;
+    let mut manifest = get_manifest(&mut archive).status(Status::BadRequest)?;
     let package_id = manifest.package_id();
 
     if !authorization.can_write_package(&package_id, &index)? {
@@ -183,8 +184,14 @@ async fn publish(
         }
     }
 
+    let contents = archive.into_inner().into_inner();
+
+    // Clients check that the package they download still matches the
+    // checksum in the index.
+    manifest.checksum = Some(PackageContents::from_buffer(contents.clone()).checksum());
+
     storage
-        .write(&manifest.package_id(), &archive.into_inner().into_inner())
+        .write(&manifest.package_id(), &contents)
         .await
         .context("could not write package to storage backend")?;
 